@@ -126,20 +126,21 @@ export class Sidecar {
     console.log('[SIDECAR] Rust sidecar started successfully')
   }
 
-  private createFriendlyError(errorMessage: string): Error {
+  private createFriendlyError(rpcError: { code: string; message: string }): Error {
     const error = new Error()
-    
-    // Determine error type and create user-friendly message
-    if (errorMessage.includes('API key not provided') || errorMessage.includes('You didn\'t provide an API key')) {
+    const { code, message: errorMessage } = rpcError
+
+    // Determine error type from the sidecar's error code (message text for the rest)
+    if (code === 'OPENAI_AUTH' && errorMessage.includes('not provided')) {
       error.name = 'API_KEY_MISSING'
       error.message = 'OpenAI API key is not configured. Add it in settings for better transcription quality.'
-    } else if (errorMessage.includes('401 Unauthorized') || errorMessage.includes('Unauthorized')) {
+    } else if (code === 'OPENAI_AUTH') {
       error.name = 'API_KEY_INVALID'  
       error.message = 'Invalid OpenAI API key. Please check the key in settings.'
-    } else if (errorMessage.includes('No whisper models found') || errorMessage.includes('No local whisper models available')) {
+    } else if (code === 'MODEL_MISSING') {
       error.name = 'NO_LOCAL_MODELS'
       error.message = 'Local models not found. Using online transcription via OpenAI API.'
-    } else if (errorMessage.includes('whisper.cpp binary not found') || errorMessage.includes('FFmpeg not found')) {
+    } else if (code === 'WHISPER_BINARY_NOT_FOUND' || code === 'FFMPEG_NOT_FOUND') {
       error.name = 'BINARY_NOT_FOUND'
       error.message = 'System components not found. Try reinstalling the application.'
     } else if (errorMessage.includes('Network') || errorMessage.includes('fetch') || errorMessage.includes('ENOTFOUND')) {
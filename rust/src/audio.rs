@@ -1,20 +1,36 @@
-use crate::rpc::RpcEvent;
-use crate::types::{ExtractAudioParams, ExtractAudioResult};
+use crate::rpc::{CapslapError, RpcEvent};
+use crate::types::{ExtractAudioParams, ExtractAudioResult, WaveformPeaks, WaveformResult};
 use crate::video::probe;
 use std::path::PathBuf;
+use std::process::Stdio;
+use tokio::io::AsyncReadExt;
 use tokio::process::Command as TokioCommand;
 
+// Waveform decoding rate: plenty for peak detection and cheap to stream
+const WAVEFORM_SAMPLE_RATE: u32 = 8000;
+const WAVEFORM_PNG_SIZE: &str = "1800x200";
+
 pub async fn extract_audio(id: &str, p: ExtractAudioParams, mut emit: impl FnMut(RpcEvent)) -> anyhow::Result<ExtractAudioResult> {
     let out = p.out.unwrap_or_else(|| {
         let mut pb = PathBuf::from(&p.input);
         pb.set_extension("m4a");
         pb.to_string_lossy().to_string()
     });
+    let out = crate::rpc::sanitize_output_path(&out)?.to_string_lossy().to_string();
 
     let target_codec = p.codec.unwrap_or_else(|| "aac".to_string());
+    if let Some(rate) = p.audio_sample_rate.filter(|r| !(8_000..=192_000).contains(r)) {
+        return Err(anyhow::anyhow!("audioSampleRate must be between 8000 and 192000 Hz, got {}", rate));
+    }
+    if let Some(channels) = p.audio_channels.filter(|c| !(1..=8).contains(c)) {
+        return Err(anyhow::anyhow!("audioChannels must be between 1 and 8, got {}", channels));
+    }
+    let resample = p.audio_sample_rate.is_some() || p.audio_channels.is_some();
 
-    // Probe input to determine if we can use stream copy
-    let use_copy = if let Ok(probe_result) = probe(id, &p.input, &mut emit).await {
+    // Probe input to determine if we can use stream copy (not when resampling or downmixing)
+    let use_copy = if resample {
+        false
+    } else if let Ok(probe_result) = probe(id, &p.input, &mut emit).await {
         if let Some(audio_codec) = &probe_result.audio_codec {
             let codec_lower = audio_codec.to_lowercase();
             match target_codec.as_str() {
@@ -39,7 +55,15 @@ pub async fn extract_audio(id: &str, p: ExtractAudioParams, mut emit: impl FnMut
     } else {
         emit(RpcEvent::Log {
             id: id.into(),
-            message: format!("Re-encoding audio to {}", target_codec).into()
+            message: match (p.audio_sample_rate, p.audio_channels) {
+                (None, None) => format!("Re-encoding audio to {}", target_codec),
+                (rate, channels) => format!(
+                    "Re-encoding audio to {} ({} Hz, {} channel(s))",
+                    target_codec,
+                    rate.map_or("source".to_string(), |r| r.to_string()),
+                    channels.map_or("source".to_string(), |c| c.to_string())
+                ),
+            }
         });
         &target_codec
     };
@@ -54,12 +78,106 @@ pub async fn extract_audio(id: &str, p: ExtractAudioParams, mut emit: impl FnMut
     if !use_copy && target_codec == "aac" {
         cmd.arg("-b:a").arg("160k");   // Explicit AAC bitrate for quality
     }
+    if let Some(rate) = p.audio_sample_rate {
+        cmd.arg("-ar").arg(rate.to_string());
+    }
+    if let Some(channels) = p.audio_channels {
+        cmd.arg("-ac").arg(channels.to_string());
+    }
 
     cmd.arg(&out);
+    // Stop ffmpeg when the caller drops this future (e.g. a concurrent probe failed)
+    cmd.kill_on_drop(true);
 
     let status = cmd.status().await?;
     if !status.success() {
-        return Err(anyhow::anyhow!("ffmpeg audio extraction failed"));
+        return Err(CapslapError::FfmpegFailed("ffmpeg audio extraction failed".into()).into());
+    }
+
+    let waveform = if p.write_waveform {
+        emit(RpcEvent::Log { id: id.into(), message: "Computing waveform peaks".into() });
+        Some(write_waveform(&out, p.peaks_per_second.unwrap_or(100)).await?)
+    } else {
+        None
+    };
+
+    emit(RpcEvent::Artifact { id: id.into(), kind: "audio".into(), path: out.clone() });
+    Ok(ExtractAudioResult { audio: out, waveform })
+}
+
+/// Write `<audio>.peaks.json` and `<audio>.waveform.png` next to the extracted audio.
+/// Peaks come from streaming mono 16-bit PCM out of ffmpeg; the PNG uses `showwavespic`.
+pub async fn write_waveform(audio: &str, peaks_per_second: u32) -> anyhow::Result<WaveformResult> {
+    let peaks_per_second = peaks_per_second.clamp(1, WAVEFORM_SAMPLE_RATE);
+    let samples_per_peak = (WAVEFORM_SAMPLE_RATE / peaks_per_second) as usize;
+
+    let mut child = TokioCommand::new("ffmpeg")
+        .args(["-v", "error", "-i", audio, "-ac", "1", "-ar", &WAVEFORM_SAMPLE_RATE.to_string(), "-f", "s16le", "-"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?;
+    let mut stdout = child.stdout.take().ok_or_else(|| anyhow::anyhow!("ffmpeg produced no PCM output"))?;
+
+    let mut peaks = Vec::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut carry: Option<u8> = None; // odd byte left over between reads
+    let (mut bucket_max, mut bucket_len, mut total_samples) = (0i32, 0usize, 0usize);
+    loop {
+        let n = stdout.read(&mut buf).await?;
+        if n == 0 { break; }
+        let mut bytes = &buf[..n];
+        if let Some(lo) = carry.take() {
+            let sample = i16::from_le_bytes([lo, bytes[0]]);
+            bytes = &bytes[1..];
+            bucket_max = bucket_max.max((sample as i32).abs());
+            bucket_len += 1;
+            total_samples += 1;
+        }
+        let mut chunks = bytes.chunks_exact(2);
+        for pair in &mut chunks {
+            let sample = i16::from_le_bytes([pair[0], pair[1]]);
+            bucket_max = bucket_max.max((sample as i32).abs());
+            bucket_len += 1;
+            total_samples += 1;
+            if bucket_len == samples_per_peak {
+                peaks.push(bucket_max as f32 / 32768.0);
+                bucket_max = 0;
+                bucket_len = 0;
+            }
+        }
+        carry = chunks.remainder().first().copied();
+    }
+    if bucket_len > 0 {
+        peaks.push(bucket_max as f32 / 32768.0);
+    }
+    if !child.wait().await?.success() {
+        return Err(CapslapError::FfmpegFailed("ffmpeg failed to decode audio for waveform".into()).into());
+    }
+
+    // Round to keep the JSON compact
+    let peaks: Vec<f32> = peaks.into_iter().map(|p| (p * 1000.0).round() / 1000.0).collect();
+    let count = peaks.len();
+    let data = WaveformPeaks {
+        peaks_per_second,
+        duration: total_samples as f64 / WAVEFORM_SAMPLE_RATE as f64,
+        peaks,
+    };
+
+    let peaks_json = PathBuf::from(audio).with_extension("peaks.json").to_string_lossy().to_string();
+    tokio::fs::write(&peaks_json, serde_json::to_string(&data)?).await?;
+
+    let png = PathBuf::from(audio).with_extension("waveform.png").to_string_lossy().to_string();
+    let status = TokioCommand::new("ffmpeg")
+        .args(["-y", "-v", "error", "-i", audio,
+               "-filter_complex", &format!("aformat=channel_layouts=mono,showwavespic=s={}:colors=white", WAVEFORM_PNG_SIZE),
+               "-frames:v", "1", &png])
+        .kill_on_drop(true)
+        .status()
+        .await?;
+    if !status.success() {
+        return Err(CapslapError::FfmpegFailed("ffmpeg waveform image rendering failed".into()).into());
     }
-    Ok(ExtractAudioResult { audio: out })
+
+    Ok(WaveformResult { peaks_json, png, peaks_per_second, count })
 }
@@ -1,11 +1,27 @@
-use core::rpc::{RpcRequest, RpcResponse, RpcError, RpcEvent, new_id};
+use core::rpc::{RpcRequest, RpcResponse, RpcError, RpcEvent, CapslapError, IdempotencyCache, IdempotencyClaim, new_id};
 use core::captions;
+use std::collections::HashMap;
 use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+use tokio::task::AbortHandle;
+use tokio_util::sync::CancellationToken;
+
+// Running requests by id, so `cancel` can abort them
+type Jobs = Arc<Mutex<HashMap<String, Job>>>;
+
+/// A running request: its task, plus where its final response goes
+struct Job {
+    handle: AbortHandle,
+    to_stdout: bool,
+    webhook_url: Option<String>,
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let stdin = io::stdin();
     let mut tasks = tokio::task::JoinSet::new();
+    let jobs: Jobs = Arc::default();
+    let idempotency = Arc::new(IdempotencyCache::default());
 
     for line in stdin.lock().lines() {
         let line = line?;
@@ -14,75 +30,257 @@ async fn main() -> anyhow::Result<()> {
         let req: Result<RpcRequest, _> = serde_json::from_str(&line);
         match req {
             Ok(r) => {
-                // Spawn each request as a concurrent task
-                tasks.spawn(async move {
-                    handle_request(r).await
+                // Spawn each request as a concurrent task; the lock is held until the handle
+                // is registered so a fast task can't deregister before it's inserted
+                let mut running = jobs.lock().unwrap();
+                let id = r.id.clone();
+                let to_stdout = !r.suppress_stdout;
+                let webhook_url = r.webhook_url.clone();
+                let task_jobs = jobs.clone();
+                let idempotency = idempotency.clone();
+                let handle = tasks.spawn(async move {
+                    handle_request(r, task_jobs, idempotency).await
                 });
+                running.insert(id, Job { handle, to_stdout, webhook_url });
             }
             Err(e) => {
-                let err = serde_json::json!({ "id": new_id(), "error": format!("Bad request: {}", e) });
-                println!("{}", err);
+                let err = RpcError::coded(new_id(), CapslapError::Generic(format!("Bad request: {}", e)));
+                println!("{}", serde_json::to_string(&err).unwrap());
                 let _ = io::stdout().flush();
             }
         }
     }
 
     // Wait for all tasks to complete (though this won't be reached in normal operation)
-    while let Some(_) = tasks.join_next().await {}
+    while tasks.join_next().await.is_some() {}
     Ok(())
 }
 
-async fn handle_request(r: RpcRequest) {
+async fn handle_request(r: RpcRequest, jobs: Jobs, idempotency: Arc<IdempotencyCache>) {
+    let id = r.id.clone();
+    let webhook_url = r.webhook_url.clone();
+    let to_stdout = !r.suppress_stdout;
+    let method = r.method.clone();
+    let key = r.idempotency_key.clone();
+
+    // A retry with a known key answers with the original job's result (under this request's id)
+    let payload = match key.as_deref().map(|key| idempotency.claim(&method, key)) {
+        Some(IdempotencyClaim::Done(previous)) => with_id(previous, &id),
+        Some(IdempotencyClaim::Running(rx)) => match core::rpc::wait_for_original(rx).await {
+            Some(previous) => with_id(previous, &id),
+            None => serde_json::to_value(RpcError::coded(&id, CapslapError::Cancelled("Original request with this idempotency key was cancelled".into()))).unwrap(),
+        },
+        Some(IdempotencyClaim::New(tx)) => {
+            let payload = dispatch(r, &jobs).await;
+            idempotency.finish(&method, key.as_deref().unwrap_or_default(), tx, &payload);
+            payload
+        }
+        None => dispatch(r, &jobs).await,
+    };
+
+    // Deregister, unless the id has since been reused by a newer request
+    {
+        let mut running = jobs.lock().unwrap();
+        if running.get(&id).is_some_and(|job| job.handle.id() == tokio::task::id()) {
+            running.remove(&id);
+        }
+    }
+
+    deliver(&payload, to_stdout, webhook_url.as_deref()).await;
+}
+
+/// Send a final response to stdout (unless suppressed) and to the request's webhook
+async fn deliver(payload: &serde_json::Value, to_stdout: bool, webhook_url: Option<&str>) {
+    if to_stdout {
+        println!("{}", payload);
+        let _ = io::stdout().flush();
+    }
+
+    if let Some(url) = webhook_url {
+        if let Err(e) = core::rpc::post_webhook(url, payload).await {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+fn with_id(mut payload: serde_json::Value, id: &str) -> serde_json::Value {
+    if let Some(obj) = payload.as_object_mut() {
+        obj.insert("id".into(), id.into());
+    }
+    payload
+}
+
+/// Run one request and return its final response/error payload
+async fn dispatch(r: RpcRequest, jobs: &Jobs) -> serde_json::Value {
     let id = r.id.clone();
+    let to_stdout = !r.suppress_stdout;
+
+    // Fires when this task is aborted (the future is dropped), stopping spawned ffmpeg encodes
+    let cancel = CancellationToken::new();
+    let _cancel_on_drop = cancel.clone().drop_guard();
 
     // Emit progress/log events — no captured stdout handle.
     let mut emit = |ev: RpcEvent| {
-        println!("{}", serde_json::to_string(&ev).unwrap());
-        let _ = io::stdout().flush();
+        if to_stdout {
+            println!("{}", serde_json::to_string(&ev).unwrap());
+            let _ = io::stdout().flush();
+        }
     };
 
-    let write_ok = |value: serde_json::Value| {
-        let resp = RpcResponse { id: id.clone(), result: value };
-        println!("{}", serde_json::to_string(&resp).unwrap());
-        let _ = io::stdout().flush();
+    let ok_response = |value: serde_json::Value| {
+        serde_json::to_value(RpcResponse { id: id.clone(), result: value }).unwrap()
     };
 
-    let write_err = |e: String| {
-        let err = RpcError { id: id.clone(), error: e };
-        println!("{}", serde_json::to_string(&err).unwrap());
-        let _ = io::stdout().flush();
+    let err_response = |e: anyhow::Error| {
+        serde_json::to_value(RpcError::new(&id, &e)).unwrap()
     };
 
+    // Every arm evaluates to the final response/error payload
     match r.method.as_str() {
-        "ping" => write_ok(serde_json::json!({"ok": true})),
+        "ping" => ok_response(serde_json::json!({"ok": true})),
         "generateCaptions" => {
             let p: core::types::GenerateCaptionsParams = serde_json::from_value(r.params).unwrap();
-            match captions::generate_captions(&id, p, &mut emit).await {
-                Ok(v) => write_ok(serde_json::to_value(v).unwrap()),
-                Err(e) => write_err(e.to_string()),
+            match captions::generate_captions(&id, p, cancel.clone(), &mut emit).await {
+                Ok(v) => ok_response(serde_json::to_value(v).unwrap()),
+                Err(e) => err_response(e),
+            }
+        }
+        "transcribeSegments" | "transcribeOnly" => {
+            // Transcription + segment JSON only, no video encode
+            let p: core::types::TranscribeSegmentsParams = serde_json::from_value(r.params).unwrap();
+            match core::whisper::transcribe_segments(&id, p, &mut emit).await {
+                Ok(v) => ok_response(serde_json::to_value(v).unwrap()),
+                Err(e) => err_response(e),
+            }
+        }
+        "burnCaptions" => {
+            let p: core::types::BurnCaptionsParams = serde_json::from_value(r.params).unwrap();
+            match captions::burn_captions(&id, p, cancel.clone(), &mut emit).await {
+                Ok(v) => ok_response(serde_json::to_value(v).unwrap()),
+                Err(e) => err_response(e),
             }
         }
         "downloadModel" => {
             let p: core::types::DownloadModelParams = serde_json::from_value(r.params).unwrap();
             match core::whisper::download_model_rpc(&id, p, &mut emit).await {
-                Ok(v) => write_ok(serde_json::to_value(v).unwrap()),
-                Err(e) => write_err(e.to_string()),
+                Ok(v) => ok_response(serde_json::to_value(v).unwrap()),
+                Err(e) => err_response(e),
             }
         }
         "checkModelExists" => {
             let model_name: String = serde_json::from_value(r.params).unwrap();
             match core::whisper::check_model_exists(&model_name) {
-                Ok(exists) => write_ok(serde_json::to_value(exists).unwrap()),
-                Err(e) => write_err(e.to_string()),
+                Ok(exists) => ok_response(serde_json::to_value(exists).unwrap()),
+                Err(e) => err_response(e),
             }
         }
         "deleteModel" => {
             let p: core::types::DeleteModelParams = serde_json::from_value(r.params).unwrap();
             match core::whisper::delete_model_rpc(&id, p, &mut emit).await {
-                Ok(v) => write_ok(serde_json::to_value(v).unwrap()),
-                Err(e) => write_err(e.to_string()),
+                Ok(v) => ok_response(serde_json::to_value(v).unwrap()),
+                Err(e) => err_response(e),
+            }
+        }
+        "verifyModel" => {
+            let p: core::types::VerifyModelParams = serde_json::from_value(r.params).unwrap();
+            match core::whisper::verify_model_rpc(&id, p, &mut emit).await {
+                Ok(v) => ok_response(serde_json::to_value(v).unwrap()),
+                Err(e) => err_response(e),
+            }
+        }
+        "exportCache" => {
+            let p: core::types::ExportCacheParams = serde_json::from_value(r.params).unwrap();
+            match core::whisper::export_cache_rpc(&id, p, &mut emit).await {
+                Ok(v) => ok_response(serde_json::to_value(v).unwrap()),
+                Err(e) => err_response(e),
+            }
+        }
+        "importCache" => {
+            let p: core::types::ImportCacheParams = serde_json::from_value(r.params).unwrap();
+            match core::whisper::import_cache_rpc(&id, p, &mut emit).await {
+                Ok(v) => ok_response(serde_json::to_value(v).unwrap()),
+                Err(e) => err_response(e),
+            }
+        }
+        "probeVideo" => {
+            let p: core::types::ProbeVideoParams = serde_json::from_value(r.params).unwrap();
+            match core::video::probe_video_rpc(&id, p, &mut emit).await {
+                Ok(v) => ok_response(serde_json::to_value(v).unwrap()),
+                Err(e) => err_response(e),
+            }
+        }
+        "exportVideo" => {
+            let p: core::video::ExportParams = serde_json::from_value(r.params).unwrap();
+            match core::video::export_video(&id, p, &mut emit).await {
+                Ok(v) => ok_response(serde_json::to_value(v).unwrap()),
+                Err(e) => err_response(e),
+            }
+        }
+        "detectLanguage" => {
+            let p: core::types::DetectLanguageParams = serde_json::from_value(r.params).unwrap();
+            match core::whisper::detect_language_rpc(&id, p, &mut emit).await {
+                Ok(v) => ok_response(serde_json::to_value(v).unwrap()),
+                Err(e) => err_response(e),
+            }
+        }
+        "clearCache" => {
+            match core::whisper::clear_cache_rpc(&id, &mut emit).await {
+                Ok(v) => ok_response(serde_json::to_value(v).unwrap()),
+                Err(e) => err_response(e),
+            }
+        }
+        "analyzeHighlights" => {
+            let p: core::types::AnalyzeHighlightsParams = serde_json::from_value(r.params).unwrap();
+            match core::captions::analyze_highlights_rpc(&id, p, &mut emit).await {
+                Ok(v) => ok_response(serde_json::to_value(v).unwrap()),
+                Err(e) => err_response(e),
+            }
+        }
+        "generateCaptionsBatch" => {
+            let p: core::types::GenerateCaptionsBatchParams = serde_json::from_value(r.params).unwrap();
+            match captions::generate_captions_batch(&id, p, cancel.clone(), &mut emit).await {
+                Ok(v) => ok_response(serde_json::to_value(v).unwrap()),
+                Err(e) => err_response(e),
+            }
+        }
+        "estimateCost" => {
+            let p: core::types::EstimateCostParams = serde_json::from_value(r.params).unwrap();
+            match core::whisper::estimate_cost_rpc(&id, p, &mut emit).await {
+                Ok(v) => ok_response(serde_json::to_value(v).unwrap()),
+                Err(e) => err_response(e),
+            }
+        }
+        "downloadRecommendedModels" => {
+            let p: core::types::DownloadModelsParams = serde_json::from_value(r.params).unwrap_or_default();
+            match core::whisper::download_recommended_models_rpc(&id, p, &mut emit).await {
+                Ok(v) => ok_response(serde_json::to_value(v).unwrap()),
+                Err(e) => err_response(e),
+            }
+        }
+        "listModels" => {
+            match core::whisper::list_models() {
+                Ok(v) => ok_response(serde_json::to_value(v).unwrap()),
+                Err(e) => err_response(e),
+            }
+        }
+        "deleteAllModels" => {
+            match core::whisper::delete_all_models_rpc(&id, &mut emit).await {
+                Ok(v) => ok_response(serde_json::to_value(v).unwrap()),
+                Err(e) => err_response(e),
+            }
+        }
+        "cancel" => {
+            let p: core::types::CancelParams = serde_json::from_value(r.params).unwrap();
+            let job = jobs.lock().unwrap().remove(&p.job_id);
+            if let Some(job) = &job {
+                job.handle.abort();
+                // The aborted job never answers, so report it on its behalf, honouring its
+                // suppressStdout/webhookUrl
+                let err = serde_json::to_value(RpcError::coded(&p.job_id, CapslapError::Cancelled("Cancelled".into()))).unwrap();
+                deliver(&err, job.to_stdout, job.webhook_url.as_deref()).await;
             }
+            ok_response(serde_json::to_value(core::types::CancelResult { cancelled: job.is_some() }).unwrap())
         }
-        _ => write_err("Unknown method".into()),
+        _ => err_response(anyhow::anyhow!("Unknown method")),
     }
 }
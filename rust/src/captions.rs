@@ -1,30 +1,134 @@
 use anyhow::{anyhow, Result};
-use crate::rpc::RpcEvent;
-use crate::types::{CaptionSegment, WordSpan, GenerateCaptionsParams, GenerateCaptionsResult, CaptionedVideoResult, ExtractAudioParams, TranscribeSegmentsParams};
+use crate::rpc::{CapslapError, RpcEvent};
+use crate::types::{BroadcastRules, BurnCaptionsParams, BurnCaptionsResult, CaptionSegment, WordSpan, GenerateCaptionsParams, GenerateCaptionsResult, GenerateCaptionsBatchParams, GenerateCaptionsBatchResult, BatchCaptionResult, CaptionedVideoResult, ExtractAudioParams, TranscribeSegmentsParams, AnalyzeHighlightsParams, AnalyzeHighlightsResult, HighlightLineAnalysis, HighlightScore};
 use crate::video::probe;
 use crate::{audio, whisper};
-use std::{fs, path::PathBuf, process::Command};
+use std::{fs, path::{Path, PathBuf}};
 use std::collections::{HashMap, HashSet, VecDeque};
+use tokio::io::AsyncReadExt;
+use tokio_util::sync::CancellationToken;
 
+/// `cancel` stops the job's ffmpeg encodes (they run as separate tasks, so aborting the
+/// caller alone would leave them running)
 pub async fn generate_captions(
     id: &str,
     params: GenerateCaptionsParams,
+    cancel: CancellationToken,
     emit: impl FnMut(RpcEvent)
 ) -> Result<GenerateCaptionsResult> {
-    generate_captions_single_pass(id, params, emit).await
+    generate_captions_single_pass(id, params, cancel, emit).await
 }
 
+/// Caption many videos with one shared template. Each file runs as `generateCaptions` under
+/// its own sub-id (`{id}_{index}`), so its progress events are tagged with that sub-id while
+/// the batch id reports overall progress. Failures are collected per file instead of aborting.
+///
+/// Files run in a `JoinSet`, so dropping this future (the job being aborted) aborts every
+/// file still running. `concurrency` limits this batch only: each batch is one client request,
+/// and the encodes inside each file are already bounded by `maxEncodeConcurrency`.
+pub async fn generate_captions_batch(
+    id: &str,
+    params: GenerateCaptionsBatchParams,
+    cancel: CancellationToken,
+    mut emit: impl FnMut(RpcEvent)
+) -> Result<GenerateCaptionsBatchResult> {
+    let template = match params.template {
+        serde_json::Value::Object(map) => map,
+        _ => return Err(anyhow!("Batch template must be an object of generateCaptions params")),
+    };
+    if params.inputs.is_empty() {
+        return Err(anyhow!("No input videos specified"));
+    }
+
+    let total = params.inputs.len();
+    let semaphore = std::sync::Arc::new(tokio::sync::Semaphore::new(params.concurrency.unwrap_or(1).max(1)));
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<(usize, RpcEvent)>();
+    let mut tasks = tokio::task::JoinSet::new();
+
+    for (idx, input) in params.inputs.into_iter().enumerate() {
+        let sub_id = format!("{}_{}", id, idx);
+
+        // Template, then this file's path, then its overrides
+        let mut merged = template.clone();
+        merged.insert("inputVideo".into(), serde_json::Value::String(input.input_video.clone()));
+        if let Some(serde_json::Value::Object(overrides)) = input.overrides {
+            merged.extend(overrides);
+        }
+        let file_params = serde_json::from_value::<GenerateCaptionsParams>(serde_json::Value::Object(merged))
+            .map_err(|e| anyhow!("Invalid params for {}: {}", input.input_video, e));
+
+        let semaphore = semaphore.clone();
+        let tx = tx.clone();
+        let input_video = input.input_video;
+        let cancel = cancel.clone();
+
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire().await.unwrap();
+            // Files still waiting for a slot when the batch is cancelled never start
+            let result = match file_params {
+                _ if cancel.is_cancelled() => Err(CapslapError::Cancelled(format!("Cancelled before {} started", sub_id)).into()),
+                Ok(p) => generate_captions(&sub_id, p, cancel, |ev| { let _ = tx.send((idx, ev)); }).await,
+                Err(e) => Err(e),
+            };
+            let _ = tx.send((idx, RpcEvent::Progress {
+                id: sub_id.clone(),
+                status: if result.is_ok() { "Done".into() } else { "Failed".into() },
+                progress: 1.0,
+            }));
+
+            let result = match result {
+                Ok(r) => BatchCaptionResult { input_video, sub_id, result: Some(r), error: None },
+                Err(e) => BatchCaptionResult { input_video, sub_id, result: None, error: Some(crate::rpc::ErrorBody::from_error(&e)) },
+            };
+            (idx, result)
+        });
+    }
+    drop(tx);
+
+    // Forward per-file events and report the batch's average progress
+    let mut file_progress = vec![0.0f32; total];
+    while let Some((idx, ev)) = rx.recv().await {
+        if let RpcEvent::Progress { progress, .. } = &ev {
+            file_progress[idx] = *progress;
+            let done = file_progress.iter().filter(|p| **p >= 1.0).count();
+            let overall = file_progress.iter().sum::<f32>() / total as f32;
+            emit(ev);
+            emit(RpcEvent::Progress {
+                id: id.into(),
+                status: format!("Captioned {}/{} videos", done, total),
+                progress: overall.min(1.0),
+            });
+        } else {
+            emit(ev);
+        }
+    }
+
+    let mut results = Vec::with_capacity(total);
+    while let Some(joined) = tasks.join_next().await {
+        results.push(joined.map_err(|e| anyhow!("Batch task failed: {}", e))?);
+    }
+    results.sort_by_key(|(idx, _)| *idx);
+    let results: Vec<BatchCaptionResult> = results.into_iter().map(|(_, r)| r).collect();
+    let succeeded = results.iter().filter(|r| r.error.is_none()).count();
+
+    Ok(GenerateCaptionsBatchResult { failed: total - succeeded, succeeded, results })
+}
+
+/// Sample rate of the audio extracted for transcription (whisper's native rate)
+const TRANSCRIBE_SAMPLE_RATE: u32 = 16_000;
+
 pub async fn generate_captions_single_pass(
     id: &str,
     params: GenerateCaptionsParams,
+    cancel: CancellationToken,
     mut emit: impl FnMut(RpcEvent)
 ) -> Result<GenerateCaptionsResult> {
 
     // Progress ranges for each step (0.0 to 1.0 overall)
+    // Probe and audio extraction run together over 0-15%; the probe finishing marks 5%
     const PROBE_START: f32 = 0.0;
-    const PROBE_END: f32 = 0.05;      // 0-5%
-    const AUDIO_START: f32 = 0.05;
-    const AUDIO_END: f32 = 0.15;      // 5-15%
+    const PROBE_END: f32 = 0.05;
+    const AUDIO_END: f32 = 0.15;
     const TRANSCRIBE_START: f32 = 0.15;
     const TRANSCRIBE_END: f32 = 0.65; // 15-65% (longest step)
     const ENCODE_START: f32 = 0.65;
@@ -36,44 +140,88 @@ pub async fn generate_captions_single_pass(
         progress: PROBE_START,
     });
 
-    let temp_dir = std::env::temp_dir().join(format!("capslap_captions_{}", id));
+    // Reject a bad scaler or rounding mode before spending time on transcription
+    let scale_algo = crate::video::resolve_scale_algo(params.scale_algo.as_deref())?;
+    let rounding = crate::video::EvenRounding::parse(params.round.as_deref())?;
+    let loudness = crate::video::LoudnessTarget::resolve(params.target_lufs, params.true_peak_db)?;
+
+    // "Next to the input" would be the job temp dir for a downloaded copy, which is never cleaned up
+    let remote_input = is_remote_input(&params.input_video);
+    if remote_input && params.output_dir.is_none() {
+        return Err(anyhow!("outputDir is required when inputVideo is a URL"));
+    }
+
+    let temp_dir = std::env::temp_dir().join(crate::rpc::job_scratch_name("capslap_captions", id));
     if let Err(e) = fs::create_dir_all(&temp_dir) {
         return Err(anyhow!("Failed to create temp directory: {}", e));
     }
 
-    // Step 1: Probe (0-5%)
-    emit(RpcEvent::Progress {
-        id: id.into(),
-        status: "Analyzing video...".into(),
-        progress: PROBE_START,
-    });
-    let probe_result = probe(id, &params.input_video, &mut emit).await?;
-    emit(RpcEvent::Progress {
-        id: id.into(),
-        status: "Video analyzed".into(),
-        progress: PROBE_END,
-    });
+    // Remote sources are fetched into the job temp dir; the copy is deleted when this guard
+    // drops, whether the job succeeds, fails or is aborted.
+    let mut params = params;
+    let _downloaded_input = if remote_input {
+        let path = download_input_video(id, &params.input_video, &temp_dir, &mut emit).await?;
+        params.input_video = path.to_string_lossy().to_string();
+        Some(path)
+    } else {
+        None
+    };
+
+    // Fail on a missing/empty/unreadable input here, not with a cryptic ffprobe error later
+    check_input_video(&params.input_video)?;
+    let transcribe_dir = resolve_work_dir(params.transcribe_dir.as_deref(), &temp_dir, "transcribeDir")?;
+    let scratch_dir = resolve_work_dir(params.scratch_dir.as_deref(), &temp_dir, "scratchDir")?;
 
-    // Step 2: Extract audio (5-15%)
+    // Steps 1-2: Probe and extract audio (0-15%). Both only read the input, so they run
+    // concurrently; the first error drops the other future, which kills its ffprobe/ffmpeg.
     emit(RpcEvent::Progress {
         id: id.into(),
-        status: "Extracting audio...".into(),
-        progress: AUDIO_START,
+        status: "Analyzing video and extracting audio...".into(),
+        progress: PROBE_START,
     });
     let audio_filename = format!("audio_{}.mp3", id);
-    let temp_audio_path = temp_dir.join(&audio_filename);
+    let temp_audio_path = transcribe_dir.join(&audio_filename);
     let audio_params = ExtractAudioParams {
         input: params.input_video.clone(),
         codec: Some("mp3".to_string()),
         out: Some(temp_audio_path.to_string_lossy().to_string()),
+        write_waveform: false,
+        peaks_per_second: None,
+        // Whisper works on 16kHz mono anyway; smaller audio also means smaller API uploads
+        audio_sample_rate: Some(TRANSCRIBE_SAMPLE_RATE),
+        audio_channels: Some(1),
+    };
+    let (probe_result, audio_result) = {
+        // Both tasks report through the one emitter; join! polls them on this task, so the
+        // lock is never contended (a Mutex rather than a RefCell keeps the job future Send)
+        let shared_emit = std::sync::Mutex::new(&mut emit);
+        let mut probe_emit = |ev| (shared_emit.lock().unwrap())(ev);
+        let probing = async {
+            let result = probe_source(id, &params, &mut probe_emit).await;
+            if result.is_ok() {
+                (shared_emit.lock().unwrap())(RpcEvent::Progress { id: id.into(), status: "Video analyzed".into(), progress: PROBE_END });
+            }
+            result
+        };
+        let extracting = audio::extract_audio(id, audio_params, |ev| (shared_emit.lock().unwrap())(ev));
+        tokio::try_join!(probing, extracting)?
     };
-    let audio_result = audio::extract_audio(id, audio_params, &mut emit).await?;
     emit(RpcEvent::Progress {
         id: id.into(),
         status: "Audio extracted".into(),
         progress: AUDIO_END,
     });
 
+    // Make sure each work volume can hold what this job will put there
+    let audio_estimate = probe_result.duration.map(|d| (d * AUDIO_BYTES_PER_SEC) as u64).unwrap_or(0);
+    ensure_free_space(id, &transcribe_dir, audio_estimate * 2, "transcribeDir", &mut emit)?;
+    if params.scratch_dir.is_some() {
+        let input_size = fs::metadata(&params.input_video).map(|m| m.len()).unwrap_or(0);
+        let per_format = 1 + params.clean_copy as u64 + params.qa_side_by_side as u64 * 2;
+        let outputs = params.export_formats.len().max(1) as u64 * per_format;
+        ensure_free_space(id, &scratch_dir, input_size * outputs, "scratchDir", &mut emit)?;
+    }
+
     // Step 3: Transcribe (15-65%)
     emit(RpcEvent::Progress {
         id: id.into(),
@@ -82,19 +230,150 @@ pub async fn generate_captions_single_pass(
     });
     let transcribe_params = TranscribeSegmentsParams {
         audio: audio_result.audio.clone(),
-        model: params.model,
-        language: params.language,
+        model: params.model.clone(),
+        language: params.language.clone(),
         split_by_words: params.split_by_words,
         api_key: params.api_key.clone(),
-        prompt: params.prompt,
+        api_base_url: params.api_base_url.clone(),
+        api_org: params.api_org.clone(),
+        prompt: params.prompt.clone(),
+        prompt_file: params.prompt_file.clone(),
+        transcription_mode: params.transcription_mode.clone(),
         video_file: Some(params.input_video.clone()),
+        max_audio_bytes: params.max_audio_bytes,
+        max_words_per_caption: params.max_words_per_caption,
+        max_segment_ms: params.max_segment_ms,
+        whisper_max_len: params.whisper_max_len,
+        min_word_confidence: params.min_word_confidence,
+        model_path: params.model_path.clone(),
+        auto_download_model: params.auto_download_model,
+        write_transcript_json: None,
+        export_subtitles: false,
+        word_level_subtitles: false,
+        stream_segments: false,
+        threads: None,
+        processors: None,
     };
-    let transcription = whisper::transcribe_segments_with_temp(id, transcribe_params, Some(&temp_dir), &mut emit).await?;
+    let mut transcription = whisper::transcribe_segments_with_temp(id, transcribe_params, Some(&transcribe_dir.path), &mut emit).await?;
+    if let Some(json_file) = transcription.json_file.take() {
+        transcription.json_file = Some(transcribe_dir.keep(&json_file, &temp_dir).await?);
+    }
     emit(RpcEvent::Progress {
         id: id.into(),
         status: "Transcription complete".into(),
         progress: TRANSCRIBE_END,
     });
+    if let Some(gap_ms) = params.speaker_turn_gap_ms {
+        assign_speakers_by_pauses(&mut transcription.segments, gap_ms);
+    }
+    let censored = censor_word_set(&params);
+    if !censored.is_empty() {
+        censor_segments(&mut transcription.segments, &censored);
+    }
+
+    // keepAudio: the extracted audio becomes a deliverable next to the other outputs
+    let audio_file = if params.keep_audio {
+        let kept = OutputNaming::resolve(&params, &params.input_video)?.sidecar("_audio", "mp3");
+        move_into_place(Path::new(&audio_result.audio), &kept).await?;
+        emit(RpcEvent::Artifact { id: id.into(), kind: "audio".into(), path: kept.clone() });
+        kept
+    } else {
+        transcribe_dir.keep(&audio_result.audio, &temp_dir).await?
+    };
+
+    // Audio-only inputs have nothing to burn captions into: hand back the transcript with
+    // subtitle sidecars next to the input instead of encoding
+    if !probe_result.video {
+        emit(RpcEvent::Log {
+            id: id.into(),
+            message: format!("{} has no video stream, writing subtitle files instead of captioned videos", params.input_video)
+        });
+        let naming = OutputNaming::resolve(&params, &params.input_video)?;
+        let srt_path = naming.sidecar("", "srt");
+        let vtt_path = naming.sidecar("", "vtt");
+        fs::write(&srt_path, build_srt(&transcription.segments))?;
+        fs::write(&vtt_path, build_segment_vtt(&transcription.segments, params.word_level_vtt))?;
+        emit(RpcEvent::Artifact { id: id.into(), kind: "srt".into(), path: srt_path.clone() });
+        emit(RpcEvent::Artifact { id: id.into(), kind: "vtt".into(), path: vtt_path.clone() });
+        transcription.srt_file = Some(srt_path);
+        transcription.vtt_file = Some(vtt_path);
+
+        emit(RpcEvent::Progress {
+            id: id.into(),
+            status: "Complete".into(),
+            progress: ENCODE_END,
+        });
+        return Ok(GenerateCaptionsResult {
+            probe_result,
+            audio_file,
+            transcription,
+            captioned_videos: Vec::new(),
+            existing_captions: None,
+            vtt_file: None,
+            fcpxml_file: None,
+            audio_only: true,
+        });
+    }
+
+    // Latin fonts render non-Latin scripts as tofu boxes: pick a default for the language
+    let language_font = transcription.language.as_deref()
+        .filter(|_| params.font_name.is_none())
+        .and_then(|lang| language_default_font(lang, params.language_fonts.as_ref()).map(|font| (lang, font)));
+    if let Some((lang, font)) = language_font {
+        emit(RpcEvent::Log { id: id.into(), message: format!("Using font \"{}\" for language {}", font, lang) });
+        params.font_name = Some(font);
+    }
+
+    // Opt-in check for captions already burned into the source
+    let existing_captions = if params.detect_existing_captions {
+        let mut report = crate::video::detect_existing_captions(&params.input_video, probe_result.duration).await?;
+        if report.likely {
+            emit(RpcEvent::Log {
+                id: id.into(),
+                message: format!(
+                    "Warning: the video likely already has burned-in captions ({}/{} sampled frames show text in the caption zone)",
+                    report.frames_flagged, report.frames_sampled
+                )
+            });
+            if params.avoid_existing_captions && params.position.as_deref().unwrap_or("bottom") == "bottom" {
+                params.position = Some("top".into());
+                report.moved_to_top = true;
+            }
+        }
+        Some(report)
+    } else {
+        None
+    };
+
+    let encode_settings = EncodeSettings::for_params(&params, &scratch_dir, scale_algo, rounding, loudness);
+    warn_if_font_missing(id, &params, &mut emit);
+
+    // Sidecars go next to the input, or into outputDir when the input is a temporary download
+    let sidecar_base = if remote_input {
+        OutputNaming::resolve(&params, &params.input_video)?.sidecar("", "mp4")
+    } else {
+        params.input_video.clone()
+    };
+
+    // Soft-subtitle karaoke track alongside the burned captions
+    let vtt_file = if params.word_level_vtt {
+        let path = write_word_level_vtt(&params, &sidecar_base, &transcription.segments, &probe_result, rounding)?;
+        emit(RpcEvent::Log { id: id.into(), message: format!("Wrote word-level WebVTT to {}", path) });
+        emit(RpcEvent::Artifact { id: id.into(), kind: "vtt".into(), path: path.clone() });
+        Some(path)
+    } else {
+        None
+    };
+
+    // Caption markers for NLE import (Final Cut Pro, Resolve, Premiere via FCPXML)
+    let fcpxml_file = if params.write_fcpxml {
+        let path = write_fcpxml(&params.input_video, &sidecar_base, &transcription.segments, &probe_result)?;
+        emit(RpcEvent::Log { id: id.into(), message: format!("Wrote FCPXML caption markers to {}", path) });
+        emit(RpcEvent::Artifact { id: id.into(), kind: "fcpxml".into(), path: path.clone() });
+        Some(path)
+    } else {
+        None
+    };
 
     // Step 4: Encode videos (65-100%)
     emit(RpcEvent::Progress {
@@ -104,18 +383,13 @@ pub async fn generate_captions_single_pass(
     });
     let captioned_videos = optimized_multi_format_encode(
         id,
-        &params.input_video,
+        &params,
         &transcription.segments,
-        &params.export_formats,
         &probe_result,
-        &temp_dir,
-        params.font_name,
-        params.text_color,
-        params.highlight_word_color,
-        params.outline_color,
-        params.glow_effect,
-        params.karaoke,
-        params.position,
+        &scratch_dir,
+        &encode_settings,
+        &cancel,
+        (ENCODE_START, ENCODE_END),
         &mut emit
     ).await?;
     emit(RpcEvent::Progress {
@@ -126,139 +400,1073 @@ pub async fn generate_captions_single_pass(
 
     Ok(GenerateCaptionsResult {
         probe_result,
-        audio_file: audio_result.audio,
+        audio_file,
         transcription,
         captioned_videos,
+        existing_captions,
+        vtt_file,
+        fcpxml_file,
+        audio_only: false,
     })
 }
 
+/// Re-render captions from existing segments (e.g. the transcript JSON) with new styling:
+/// no audio extraction or transcription, just the ASS build and encode per format.
+pub async fn burn_captions(
+    id: &str,
+    params: BurnCaptionsParams,
+    cancel: CancellationToken,
+    mut emit: impl FnMut(RpcEvent)
+) -> Result<BurnCaptionsResult> {
+    const PROBE_END: f32 = 0.05;
+    const ENCODE_END: f32 = 1.0;
+
+    let BurnCaptionsParams { segments, options: params } = params;
+    if segments.is_empty() {
+        return Err(anyhow!("No caption segments"));
+    }
+    check_input_video(&params.input_video)?;
+    let scale_algo = crate::video::resolve_scale_algo(params.scale_algo.as_deref())?;
+    let rounding = crate::video::EvenRounding::parse(params.round.as_deref())?;
+    let loudness = crate::video::LoudnessTarget::resolve(params.target_lufs, params.true_peak_db)?;
+
+    let temp_dir = std::env::temp_dir().join(crate::rpc::job_scratch_name("capslap_burn", id));
+    if let Err(e) = fs::create_dir_all(&temp_dir) {
+        return Err(anyhow!("Failed to create temp directory: {}", e));
+    }
+    let scratch_dir = resolve_work_dir(params.scratch_dir.as_deref(), &temp_dir, "scratchDir")?;
+
+    emit(RpcEvent::Progress {
+        id: id.into(),
+        status: "Analyzing video...".into(),
+        progress: 0.0,
+    });
+    let probe_result = probe_source(id, &params, &mut emit).await?;
+    if !probe_result.video {
+        return Err(anyhow!("Input has no video stream: {}", params.input_video));
+    }
+
+    let encode_settings = EncodeSettings::for_params(&params, &scratch_dir, scale_algo, rounding, loudness);
+    warn_if_font_missing(id, &params, &mut emit);
+
+    emit(RpcEvent::Progress {
+        id: id.into(),
+        status: "Encoding videos...".into(),
+        progress: PROBE_END,
+    });
+    let captioned_videos = optimized_multi_format_encode(
+        id,
+        &params,
+        &segments,
+        &probe_result,
+        &scratch_dir,
+        &encode_settings,
+        &cancel,
+        (PROBE_END, ENCODE_END),
+        &mut emit
+    ).await?;
+    emit(RpcEvent::Progress {
+        id: id.into(),
+        status: "Complete".into(),
+        progress: ENCODE_END,
+    });
+
+    Ok(BurnCaptionsResult { probe_result, captioned_videos })
+}
+
+/// Per-job ffmpeg output settings shared by every format encode
+#[derive(Clone, Default)]
+struct EncodeSettings {
+    metadata_title: Option<String>,   // Title tag; None disables the CapSlap provenance tags
+    fonts_dir: Option<String>,        // User fonts directory for the subtitles filter
+    clean_copy: bool,                 // Also write the reformatted video without captions
+    qa_side_by_side: bool,            // Also write clean | captioned stacked (needs the clean encode)
+    staging_dir: Option<PathBuf>,     // Encode here first, then move outputs next to the input
+    scale_algo: &'static str,         // swscale flags for the fit+pad scale
+    rounding: crate::video::EvenRounding, // Even-dimension snapping for canvas and scaled source
+    video_stream: usize,              // Which input video stream (0:v:<i>) gets captioned
+    loudness: Option<crate::video::LoudnessTarget>, // Normalize output audio (re-encodes instead of copying)
+}
+
+impl EncodeSettings {
+    fn for_params(
+        params: &GenerateCaptionsParams,
+        scratch_dir: &Path,
+        scale_algo: &'static str,
+        rounding: crate::video::EvenRounding,
+        loudness: Option<crate::video::LoudnessTarget>,
+    ) -> Self {
+        EncodeSettings {
+            // Provenance tags written into every output file
+            metadata_title: if params.write_tool_metadata.unwrap_or(true) {
+                Some(params.metadata_title.clone().unwrap_or_else(|| {
+                    std::path::Path::new(&params.input_video)
+                        .file_name()
+                        .map(|n| n.to_string_lossy().to_string())
+                        .unwrap_or_default()
+                }))
+            } else {
+                None
+            },
+            fonts_dir: params.fonts_dir.clone(),
+            clean_copy: params.clean_copy,
+            qa_side_by_side: params.qa_side_by_side,
+            staging_dir: params.scratch_dir.is_some().then(|| scratch_dir.to_path_buf()),
+            scale_algo,
+            rounding,
+            video_stream: params.video_stream_index.unwrap_or(0),
+            loudness,
+        }
+    }
+}
+
+/// Probe the input, sizing the canvases from the video stream that will actually be captioned
+async fn probe_source(id: &str, params: &GenerateCaptionsParams, emit: &mut impl FnMut(RpcEvent)) -> Result<crate::video::ProbeResult> {
+    let mut probe_result = probe(id, &params.input_video, &mut *emit).await?;
+    if let Some(index) = params.video_stream_index {
+        let stream = probe_result.video_streams.get(index).cloned().ok_or_else(|| anyhow!(
+            "videoStreamIndex {} is out of range: {} has {} video stream(s)",
+            index, params.input_video, probe_result.video_streams.len()
+        ))?;
+        probe_result.width = stream.width;
+        probe_result.height = stream.height;
+        probe_result.fps = stream.fps.or(probe_result.fps);
+    }
+    Ok(probe_result)
+}
+
+/// libass silently substitutes a default face for fonts it can't find
+fn warn_if_font_missing(id: &str, params: &GenerateCaptionsParams, emit: &mut impl FnMut(RpcEvent)) {
+    let font_name = params.font_name.as_deref().unwrap_or("Montserrat Black");
+    let fonts_dir = crate::video::resolve_fonts_dir(params.fonts_dir.as_deref());
+    if let Some(dir) = params.fonts_dir.as_deref().filter(|d| !Path::new(d).is_dir()) {
+        emit(RpcEvent::Log { id: id.into(), message: format!("Warning: fonts directory not found: {}", dir) });
+    }
+    if !crate::video::font_is_discoverable(font_name, fonts_dir.as_deref()) {
+        emit(RpcEvent::Log {
+            id: id.into(),
+            message: format!("Warning: font \"{}\" was not found in the fonts directory or system fonts; captions may render with a fallback font", font_name)
+        });
+    }
+}
+
+/// Rough upper bound for the extracted MP3 (~192 kbps)
+const AUDIO_BYTES_PER_SEC: f64 = 24_000.0;
+
+/// Pause-based speaker turns for transcripts without diarization: alternate between speakers
+/// 0 and 1 whenever the silence before a segment is at least `gap_ms`. Backend labels win.
+fn assign_speakers_by_pauses(segments: &mut [CaptionSegment], gap_ms: u64) {
+    if segments.iter().any(|s| s.speaker.is_some()) {
+        return;
+    }
+    let mut speaker = 0;
+    let mut prev_end_ms = None;
+    for seg in segments.iter_mut() {
+        if prev_end_ms.is_some_and(|end| seg.start_ms.saturating_sub(end) >= gap_ms) {
+            speaker = 1 - speaker;
+        }
+        prev_end_ms = Some(seg.end_ms);
+        seg.speaker = Some(speaker);
+        for word in &mut seg.words {
+            word.speaker = Some(speaker);
+        }
+    }
+}
+
+/// Built-in list behind `censorProfanity` (whole words, matched case-insensitively)
+const PROFANITY: [&str; 24] = [
+    "fuck", "fucks", "fucked", "fucker", "fuckers", "fucking", "motherfucker", "motherfucking",
+    "shit", "shits", "shitty", "bullshit", "bitch", "bitches", "bastard", "asshole",
+    "assholes", "dick", "dickhead", "cunt", "cunts", "piss", "pissed", "wanker",
+];
+
+/// Lowercased words to mask: the caller's `censorWords` plus the built-in list when enabled
+fn censor_word_set(params: &GenerateCaptionsParams) -> HashSet<String> {
+    let mut words: HashSet<String> = params.censor_words.iter().flatten()
+        .map(|w| w.trim().to_lowercase())
+        .filter(|w| !w.is_empty())
+        .collect();
+    if params.censor_profanity {
+        words.extend(PROFANITY.iter().map(|w| w.to_string()));
+    }
+    words
+}
+
+/// Mask a censored token with one `*` per character, keeping surrounding punctuation ("damn," -> "****,")
+fn mask_token(token: &str, censored: &HashSet<String>) -> Option<String> {
+    let not_word = |c: char| !c.is_alphanumeric();
+    let core = token.trim_matches(not_word);
+    if core.is_empty() || !censored.contains(&core.to_lowercase()) {
+        return None;
+    }
+    let start = token.len() - token.trim_start_matches(not_word).len();
+    let end = start + core.len();
+    Some(format!("{}{}{}", &token[..start], "*".repeat(core.chars().count()), &token[end..]))
+}
+
+/// Replace censored words in segment text and word spans. Timing is untouched, and masked
+/// words are never smart-highlighted.
+fn censor_segments(segments: &mut [CaptionSegment], censored: &HashSet<String>) {
+    for seg in segments.iter_mut() {
+        seg.text = seg.text.split(' ')
+            .map(|t| mask_token(t, censored).unwrap_or_else(|| t.to_string()))
+            .collect::<Vec<_>>()
+            .join(" ");
+        for word in &mut seg.words {
+            if let Some(masked) = mask_token(&word.text, censored) {
+                word.text = masked;
+                word.highlight = Some(false);
+            }
+        }
+    }
+}
+
+/// Built-in defaults for scripts the Latin default font can't render
+const LANGUAGE_FONTS: [(&str, &str); 17] = [
+    ("ja", "Noto Sans CJK JP"),
+    ("zh", "Noto Sans CJK SC"),
+    ("zh-hant", "Noto Sans CJK TC"),
+    ("yue", "Noto Sans CJK TC"),
+    ("ko", "Noto Sans CJK KR"),
+    ("hi", "Noto Sans Devanagari"),
+    ("mr", "Noto Sans Devanagari"),
+    ("ne", "Noto Sans Devanagari"),
+    ("bn", "Noto Sans Bengali"),
+    ("ta", "Noto Sans Tamil"),
+    ("te", "Noto Sans Telugu"),
+    ("ar", "Noto Sans Arabic"),
+    ("fa", "Noto Sans Arabic"),
+    ("ur", "Noto Sans Arabic"),
+    ("he", "Noto Sans Hebrew"),
+    ("th", "Noto Sans Thai"),
+    ("el", "Noto Sans"),
+];
+
+/// ISO 639-1 code for a language as reported by a backend: whisper.cpp gives codes
+/// ("ja"), the OpenAI API gives English names ("japanese"), clients may add a region ("zh-CN")
+pub(crate) fn language_code(lang: &str) -> String {
+    let lang = lang.trim().to_lowercase();
+    let base = lang.split(['-', '_']).next().unwrap_or_default();
+    match base {
+        "japanese" => "ja", "chinese" | "mandarin" => "zh", "cantonese" => "yue", "korean" => "ko",
+        "hindi" => "hi", "marathi" => "mr", "nepali" => "ne", "bengali" => "bn", "tamil" => "ta",
+        "telugu" => "te", "arabic" => "ar", "persian" => "fa", "urdu" => "ur", "hebrew" => "he",
+        "thai" => "th", "greek" => "el",
+        other => other,
+    }.to_string()
+}
+
+/// Default caption font for a transcript language: the caller's map (by code or as given),
+/// then the built-in table; None keeps the Latin default
+fn language_default_font(lang: &str, overrides: Option<&HashMap<String, String>>) -> Option<String> {
+    let code = language_code(lang);
+    let key = font_language_key(lang, &code);
+    overrides
+        .and_then(|map| map.get(&key).or_else(|| map.get(&code)).or_else(|| map.get(lang)))
+        .cloned()
+        .or_else(|| LANGUAGE_FONTS.iter().find(|(c, _)| *c == key).map(|(_, font)| font.to_string()))
+}
+
+/// Font table key for a language: its code, except that Traditional Chinese (`zh-Hant`,
+/// `zh-TW`, `zh-HK`, `zh-MO`) needs its own glyphs, so the script/region is kept as `zh-hant`
+fn font_language_key(lang: &str, code: &str) -> String {
+    let traditional = code == "zh" && lang.trim().to_lowercase()
+        .split(['-', '_'])
+        .skip(1)
+        .any(|subtag| matches!(subtag, "hant" | "tw" | "hk" | "mo"));
+    if traditional { "zh-hant".into() } else { code.to_string() }
+}
+
+const DEFAULT_FILENAME_TEMPLATE: &str = "{name}_{format}";
+
+/// Extensions dropped from a filenameTemplate: exports are always written as .mp4
+const TEMPLATE_VIDEO_EXTENSIONS: [&str; 4] = ["mp4", "mov", "mkv", "m4v"];
+
+/// Where exported videos go and what they're called (`outputDir`, `filenameTemplate`)
+struct OutputNaming {
+    dir: PathBuf,
+    name: String,     // input file name without extension
+    template: String,
+}
+
+impl OutputNaming {
+    fn resolve(params: &GenerateCaptionsParams, input_video: &str) -> Result<Self> {
+        // Only the directory written to is sanitized; the input is just read
+        let input = Path::new(input_video);
+        let dir = match params.output_dir.as_deref() {
+            Some(dir) => {
+                let dir = crate::rpc::sanitize_output_path(dir)?;
+                fs::create_dir_all(&dir)
+                    .map_err(|e| anyhow!("Failed to create output directory {}: {}", dir.display(), e))?;
+                dir
+            }
+            None => {
+                let parent = input.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+                crate::rpc::sanitize_output_path(&parent.to_string_lossy())?
+            }
+        };
+        let name = input.file_stem().unwrap_or_default().to_string_lossy().to_string();
+
+        let template = params.filename_template.as_deref().unwrap_or(DEFAULT_FILENAME_TEMPLATE);
+        let template = match template.rsplit_once('.') {
+            Some((stem, ext)) if TEMPLATE_VIDEO_EXTENSIONS.contains(&ext.to_lowercase().as_str()) => stem,
+            _ => template,
+        }.to_string();
+        if template.trim().is_empty() {
+            return Err(anyhow!("filenameTemplate is empty"));
+        }
+        let rendered = template.replace("{name}", "").replace("{format}", "").replace("{index}", "");
+        if rendered.contains(['{', '}']) {
+            return Err(anyhow!("Unknown placeholder in filenameTemplate: {}. Supported: {{name}}, {{format}}, {{index}}", template));
+        }
+        if template.contains(['/', '\\']) {
+            return Err(anyhow!("filenameTemplate must be a file name, not a path: {}", template));
+        }
+        // Every export needs its own file, or later formats would overwrite earlier ones
+        let distinct = template.contains("{format}") || template.contains("{index}");
+        if params.export_formats.len() > 1 && !distinct {
+            return Err(anyhow!("filenameTemplate must contain {{format}} or {{index}} when exporting several formats"));
+        }
+        Ok(OutputNaming { dir, name, template })
+    }
+
+    /// Output path for the export at `index` (0-based; `{index}` counts from 1), with a
+    /// `suffix` for its companions (`_clean`, `_qa`)
+    fn path(&self, format: &str, index: usize, suffix: &str) -> String {
+        let file = self.template
+            .replace("{name}", &self.name)
+            .replace("{format}", &format.replace(':', "x"))
+            .replace("{index}", &(index + 1).to_string());
+        self.dir.join(format!("{}{}.mp4", file, suffix)).to_string_lossy().to_string()
+    }
+
+    /// Path for a file named after the input plus `suffix`, with another extension
+    /// (subtitle sidecars, kept audio)
+    fn sidecar(&self, suffix: &str, extension: &str) -> String {
+        self.dir.join(format!("{}{}.{}", self.name, suffix, extension)).to_string_lossy().to_string()
+    }
+}
+
+/// Redirect hops followed when downloading a remote input video
+const INPUT_DOWNLOAD_MAX_REDIRECTS: usize = 10;
+
+fn is_remote_input(input: &str) -> bool {
+    input.starts_with("https://") || input.starts_with("http://")
+}
+
+/// Stream an http(s) input video into `dir`, keeping the URL's file name so output names stay
+/// recognizable. The returned path removes the file when dropped.
+async fn download_input_video(
+    id: &str,
+    url: &str,
+    dir: &Path,
+    emit: &mut impl FnMut(RpcEvent)
+) -> Result<tempfile::TempPath> {
+    use futures_util::StreamExt;
+    use tokio::io::AsyncWriteExt;
+
+    let parsed = reqwest::Url::parse(url).map_err(|e| anyhow!("Invalid input video URL: {}", e))?;
+    // Logs and errors leave out the query string and userinfo: presigned URLs carry credentials
+    let mut shown = parsed.clone();
+    shown.set_query(None);
+    shown.set_fragment(None);
+    let _ = shown.set_username("");
+    let _ = shown.set_password(None);
+    let display_url = shown.to_string();
+    let file_name: String = parsed.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .unwrap_or("")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+        .collect();
+    let file_name = match file_name.trim_matches('.') {
+        "" => "input_video".to_string(),
+        name => name.to_string(),
+    };
+
+    emit(RpcEvent::Log { id: id.into(), message: format!("Downloading input video from {}", display_url) });
+    let client = reqwest::Client::builder()
+        .user_agent("core/1.0.0")
+        .redirect(reqwest::redirect::Policy::limited(INPUT_DOWNLOAD_MAX_REDIRECTS))
+        .build()?;
+    let response = client.get(parsed).send().await
+        .map_err(|e| anyhow!("Failed to download input video {}: {}", display_url, e.without_url()))?;
+    if !response.status().is_success() {
+        return Err(anyhow!("Failed to download input video {}: HTTP {}", display_url, response.status()));
+    }
+
+    let path = tempfile::TempPath::from_path(dir.join(file_name));
+    let mut file = tokio::fs::File::create(&path).await?;
+    let mut stream = response.bytes_stream();
+    let mut downloaded = 0u64;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| anyhow!("Download of input video {} was interrupted: {}", display_url, e.without_url()))?;
+        file.write_all(&chunk).await?;
+        downloaded += chunk.len() as u64;
+    }
+    file.flush().await?;
+
+    emit(RpcEvent::Log {
+        id: id.into(),
+        message: format!("Downloaded input video ({:.1} MB)", downloaded as f64 / 1024.0 / 1024.0)
+    });
+    Ok(path)
+}
+
+/// The input video must be an existing, non-empty, readable file
+fn check_input_video(path: &str) -> Result<()> {
+    let meta = fs::metadata(path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => anyhow!("Input video not found: {}", path),
+        std::io::ErrorKind::PermissionDenied => anyhow!("Input video is not accessible (permission denied): {}", path),
+        _ => anyhow!("Cannot read input video {}: {}", path, e),
+    })?;
+    if !meta.is_file() {
+        return Err(anyhow!("Input video is not a file: {}", path));
+    }
+    if meta.len() == 0 {
+        return Err(anyhow!("Input video is empty (0 bytes), possibly an incomplete download: {}", path));
+    }
+    fs::File::open(path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::PermissionDenied => anyhow!("Input video is not readable (permission denied): {}", path),
+        _ => anyhow!("Cannot open input video {}: {}", path, e),
+    })?;
+    Ok(())
+}
+
+/// Where a job keeps its intermediate files. A per-job subdirectory of a user-chosen work
+/// volume is removed when this drops, whether the job succeeds, fails or is aborted.
+struct WorkDir {
+    path: PathBuf,
+    cleanup: Option<tempfile::TempDir>,
+}
+
+impl WorkDir {
+    /// Moves `file` out of a removable work dir into `job_temp_dir`, where it would have been
+    /// written without one, so returned paths outlive the job
+    async fn keep(&self, file: &str, job_temp_dir: &Path) -> Result<String> {
+        if self.cleanup.is_none() {
+            return Ok(file.to_string());
+        }
+        let kept = job_temp_dir.join(Path::new(file).file_name().unwrap_or_default()).to_string_lossy().to_string();
+        move_into_place(Path::new(file), &kept).await?;
+        Ok(kept)
+    }
+}
+
+impl std::ops::Deref for WorkDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Per-job subdirectory of a user-chosen work volume (prefixed like the job temp dir), or the
+/// job temp dir itself when unset
+fn resolve_work_dir(custom: Option<&str>, job_temp_dir: &Path, label: &str) -> Result<WorkDir> {
+    let Some(root) = custom else {
+        return Ok(WorkDir { path: job_temp_dir.to_path_buf(), cleanup: None });
+    };
+    let root = crate::rpc::sanitize_output_path(root)?;
+    if !root.is_dir() {
+        return Err(anyhow!("{} does not exist or is not a directory: {}", label, root.display()));
+    }
+    let dir = tempfile::Builder::new()
+        .prefix(job_temp_dir.file_name().unwrap_or_default())
+        .tempdir_in(&root)
+        .map_err(|e| anyhow!("Failed to create {} work directory in {}: {}", label, root.display(), e))?;
+    Ok(WorkDir { path: dir.path().to_path_buf(), cleanup: Some(dir) })
+}
+
+/// Fail early when `dir` clearly can't hold `needed` bytes
+fn ensure_free_space(id: &str, dir: &Path, needed: u64, label: &str, emit: &mut impl FnMut(RpcEvent)) -> Result<()> {
+    match crate::video::available_disk_space(dir) {
+        Some(available) if available < needed => Err(anyhow!(
+            "Not enough free space in {} ({}): need about {} MB, only {} MB available",
+            label, dir.display(), needed / (1024 * 1024), available / (1024 * 1024)
+        )),
+        Some(_) => Ok(()),
+        None => {
+            emit(RpcEvent::Log { id: id.into(), message: format!("Could not determine free space in {}, continuing", dir.display()) });
+            Ok(())
+        }
+    }
+}
+
+/// Move a finished encode from the staging volume to its final path (copying across filesystems)
+async fn move_into_place(from: &Path, to: &str) -> Result<()> {
+    if tokio::fs::rename(from, to).await.is_ok() {
+        return Ok(());
+    }
+    tokio::fs::copy(from, to).await.map_err(|e| anyhow!("Failed to move {} to {}: {}", from.display(), to, e))?;
+    tokio::fs::remove_file(from).await?;
+    Ok(())
+}
+
+/// Format encodes running at once when maxEncodeConcurrency is unset
+const DEFAULT_ENCODE_CONCURRENCY: usize = 2;
+
+#[allow(clippy::too_many_arguments)]
 async fn optimized_multi_format_encode(
     id: &str,
-    input_video: &str,
+    params: &GenerateCaptionsParams,
     segments: &[CaptionSegment],
-    export_formats: &[String],
     probe_result: &crate::video::ProbeResult,
-    temp_dir: &PathBuf,
-    font_name: Option<String>,
-    text_color: Option<String>,
-    highlight_word_color: Option<String>,
-    outline_color: Option<String>,
-    glow_effect: bool,
-    karaoke: bool,
-    position: Option<String>,
+    temp_dir: &Path,
+    settings: &EncodeSettings,
+    cancel: &CancellationToken,
+    (encode_start, encode_end): (f32, f32),  // Overall progress range for the encodes
     emit: &mut impl FnMut(RpcEvent)
 ) -> Result<Vec<CaptionedVideoResult>> {
-    // Progress ranges for encoding step (65-100% overall)
-    const ENCODE_START: f32 = 0.65;
-    const ENCODE_END: f32 = 1.0;
-    if export_formats.is_empty() {
-        return Err(anyhow!("No export formats specified"));
+    let input_video = params.input_video.as_str();
+    let export_targets = resolve_export_targets(&params.export_formats, probe_result, settings.rounding, params.max_output_height)?;
+
+    let typewriter = match params.reveal.as_deref() {
+        None | Some("word") => false,
+        Some("typewriter") => true,
+        Some(other) => return Err(anyhow!("Unknown reveal mode: {}. Supported: word, typewriter", other)),
+    };
+    TextCase::from_param(params.text_case.as_deref())?;
+    if params.broadcast_compliant && params.karaoke {
+        return Err(anyhow!("broadcastCompliant is not supported with karaoke captions"));
     }
+    let gap_hold_ms = match params.gap_behavior.as_deref() {
+        None | Some("blank") => None,
+        Some("hold") => Some(params.gap_hold_ms.unwrap_or(GAP_HOLD_MS)),
+        Some(other) => return Err(anyhow!("Unknown gap behavior: {}. Supported: blank, hold", other)),
+    };
 
-    let input_path = std::path::Path::new(input_video)
-        .with_extension("")
-        .to_string_lossy()
-        .to_string();
+    // Encodes use a fixed GOP (-g), so caption cuts can be pulled onto its keyframe grid
+    let snapped;
+    let segments = if params.snap_to_keyframes {
+        let gop = crate::video::gop_size_for_probe(probe_result) as f64;
+        let fps = probe_result.fps.filter(|f| f.is_finite() && *f > 0.0).unwrap_or(24.0);
+        let interval_ms = ((gop / fps) * 1000.0).round() as u64;
+        let tolerance_ms = params.keyframe_snap_tolerance_ms.unwrap_or(KEYFRAME_SNAP_TOLERANCE_MS);
+        snapped = snap_starts_to_keyframes(segments, interval_ms, tolerance_ms);
+        snapped.as_slice()
+    } else {
+        segments
+    };
+    let held;
+    let segments = match gap_hold_ms {
+        Some(hold_ms) => {
+            held = hold_short_gaps(segments, hold_ms);
+            held.as_slice()
+        }
+        None => segments,
+    };
+
+    // Outputs land next to the input (or in outputDir), so that location must be writable
+    let naming = std::sync::Arc::new(OutputNaming::resolve(params, input_video)?);
 
     // Pre-generate shared ASS files for each format (avoiding redundant subtitle processing)
     let mut format_ass_files = Vec::new();
-    for format in export_formats {
-        let target_ar = crate::video::parse_target_ar(format)?;
-        let src_w = probe_result.width.unwrap_or(1920) as u32;
-        let src_h = probe_result.height.unwrap_or(1080) as u32;
-        let (target_w, target_h) = crate::video::canvas_no_downscale(src_w, src_h, target_ar);
-
+    let mut verify_targets = Vec::new();
+    for (format, target_w, target_h) in export_targets {
         // Build ASS subtitle file optimized for this format
-        let style = default_ass_style(
-            target_w, target_h,
-            font_name.as_deref(),
-            text_color.as_deref(),
-            highlight_word_color.as_deref(),
-            outline_color.as_deref(),
-            glow_effect,
-            position.as_deref()
-        );
-        let ass_doc = build_ass_document(target_w, target_h, &style, segments, karaoke, glow_effect)?;
+        let mut style = caption_style(params, target_w, target_h);
+        style.typewriter = typewriter;
+        let mut ass_doc = build_ass_document(target_w, target_h, &style, segments, params.karaoke, params.glow_effect)?;
+        if let Some(base_ms) = params.caption_time_base_ms {
+            let duration_ms = probe_result.duration
+                .map(|d| (d * 1000.0) as u64)
+                .or_else(|| segments.last().map(|s| s.end_ms))
+                .unwrap_or(0);
+            ass_doc.push_str(&timecode_overlay_lines(base_ms, duration_ms, &style));
+        }
 
         let safe_format = format.replace(':', "x");
         let ass_filename = format!("captions_{}_{}.ass", id, safe_format);
         let ass_path = temp_dir.join(&ass_filename);
         fs::write(&ass_path, ass_doc)?;
 
-        format_ass_files.push((format.clone(), ass_path, target_w, target_h));
+        verify_targets.push((ass_path.clone(), caption_zone(&style, target_h)));
+        format_ass_files.push((format, ass_path, target_w, target_h));
     }
 
-    // Process formats with limited concurrency (2 at a time for optimal resource usage)
-    let semaphore = std::sync::Arc::new(tokio::sync::Semaphore::new(2));
-    let mut tasks = Vec::new();
+    // Encode tasks report per-format ffmpeg progress (0..1) through this channel
+    let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel::<(usize, f32)>();
 
-    for (idx, (format, ass_path, target_w, target_h)) in format_ass_files.into_iter().enumerate() {
-        let format = format.clone();
+    // Process formats with limited concurrency (2 at a time by default, maxEncodeConcurrency to tune)
+    let concurrency = params.max_encode_concurrency
+        .unwrap_or(DEFAULT_ENCODE_CONCURRENCY)
+        .clamp(1, format_ass_files.len().max(1));
+    emit(RpcEvent::Log { id: id.into(), message: format!("Encoding up to {} format(s) at once", concurrency) });
+    let semaphore = std::sync::Arc::new(tokio::sync::Semaphore::new(concurrency));
+    let spawn_encode = move |idx: usize, (format, ass_path, target_w, target_h): (String, PathBuf, u32, u32)| {
         let input_video = input_video.to_string();
         let probe_result = probe_result.clone();
         let semaphore = semaphore.clone();
         let task_id = format!("{}_{}", id, idx);
-        let input_path = input_path.clone();
+        let naming = naming.clone();
+        let settings = settings.clone();
+        let cancel = cancel.clone();
+        let progress_tx = progress_tx.clone();
 
-        let task = tokio::spawn(async move {
+        tokio::spawn(async move {
             // Acquire semaphore permit for bounded concurrency
             let _permit = semaphore.acquire().await.unwrap();
 
-            let safe_format = format.replace(':', "x");
-            let captioned_path = format!("{}_{}.mp4", input_path, safe_format);
+            let captioned_path = naming.path(&format, idx, "");
+            // The side-by-side QA video is stacked from the clean encode, so it's made even when
+            // the clean copy itself isn't kept
+            let clean_path = (settings.clean_copy || settings.qa_side_by_side)
+                .then(|| naming.path(&format, idx, "_clean"));
+            let qa_path = settings.qa_side_by_side.then(|| naming.path(&format, idx, "_qa"));
+
+            // With a scratch volume, ffmpeg writes there and finished files are moved into place
+            let staged = |path: &str| match &settings.staging_dir {
+                Some(dir) => dir.join(Path::new(path).file_name().unwrap_or_default()).to_string_lossy().to_string(),
+                None => path.to_string(),
+            };
+            let encode_path = staged(&captioned_path);
+            let encode_clean_path = clean_path.as_deref().map(staged);
+            let encode_qa_path = qa_path.as_deref().map(staged);
 
             // Single-pass format conversion + caption burning with hardware acceleration
             optimized_single_format_encode(
                 &task_id,
                 &input_video,
                 &ass_path,
-                &captioned_path,
+                &encode_path,
+                encode_clean_path.as_deref(),
                 target_w,
                 target_h,
                 &probe_result,
+                &settings,
+                &cancel,
+                &|fraction| { let _ = progress_tx.send((idx, fraction)); },
             ).await?;
 
+            if let (Some(clean), Some(qa)) = (&encode_clean_path, &encode_qa_path) {
+                render_side_by_side(&task_id, clean, &encode_path, qa, &cancel).await?;
+            }
+            let clean_path = if settings.clean_copy {
+                clean_path
+            } else {
+                if let Some(clean) = &encode_clean_path {
+                    let _ = fs::remove_file(clean);
+                }
+                None
+            };
+
+            if settings.staging_dir.is_some() {
+                move_into_place(Path::new(&encode_path), &captioned_path).await?;
+                if let (Some(from), Some(to)) = (&encode_clean_path, &clean_path) {
+                    move_into_place(Path::new(from), to).await?;
+                }
+                if let (Some(from), Some(to)) = (&encode_qa_path, &qa_path) {
+                    move_into_place(Path::new(from), to).await?;
+                }
+            }
+
             Ok::<CaptionedVideoResult, anyhow::Error>(CaptionedVideoResult {
                 format,
-                raw_video: "".to_string(),
+                raw_video: clean_path.unwrap_or_default(),
                 captioned_video: captioned_path,
+                qa_video: qa_path,
                 width: target_w,
                 height: target_h,
             })
+        })
+    };
+
+    let tracker = EncodeProgress::new(id, format_ass_files.len(), encode_start, encode_end);
+    let jobs: Vec<_> = format_ass_files.into_iter().enumerate().collect();
+    let mut finished = run_encode_jobs(
+        jobs,
+        params.primary_format.as_deref(),
+        |(format, ..)| format,
+        spawn_encode,
+        tracker,
+        &mut progress_rx,
+        emit
+    ).await?;
+
+    // Report in the requested format order regardless of which finished first
+    finished.sort_by_key(|(idx, _)| *idx);
+    let captioned_videos: Vec<CaptionedVideoResult> = finished.into_iter().map(|(_, result)| result).collect();
+
+    if params.verify_captions {
+        verify_burned_captions(id, params, segments, &captioned_videos, &verify_targets, emit).await?;
+    }
+
+    Ok(captioned_videos)
+}
+
+/// Start the format encodes with `spawn` and collect them as they finish. The primary format
+/// is encoded first and alone, so it's deliverable as early as possible; the rest start after.
+async fn run_encode_jobs<J>(
+    mut jobs: Vec<(usize, J)>,
+    primary: Option<&str>,
+    format_of: impl Fn(&J) -> &str,
+    spawn: impl Fn(usize, J) -> tokio::task::JoinHandle<Result<CaptionedVideoResult>>,
+    mut tracker: EncodeProgress<'_>,
+    progress_rx: &mut tokio::sync::mpsc::UnboundedReceiver<(usize, f32)>,
+    emit: &mut impl FnMut(RpcEvent)
+) -> Result<Vec<(usize, CaptionedVideoResult)>> {
+    let id = tracker.id;
+    let mut finished = Vec::new();
+
+    if let Some((idx, job)) = take_primary_job(&mut jobs, primary, format_of)? {
+        let result = tracker.await_task(spawn(idx, job), progress_rx, emit).await??;
+        emit(RpcEvent::FormatReady {
+            id: id.into(),
+            format: result.format.clone(),
+            captioned_video: result.captioned_video.clone(),
         });
+        emit_video_artifacts(id, &result, emit);
+        tracker.format_done(idx, emit);
+        finished.push((idx, result));
+    }
+
+    let tasks: Vec<_> = jobs.into_iter().map(|(idx, job)| (idx, spawn(idx, job))).collect();
+
+    // Wait for all tasks to complete and collect results (`spawn` holds the last progress sender)
+    drop(spawn);
+    for (idx, task) in tasks {
+        let result = tracker.await_task(task, progress_rx, emit).await??;
+        emit_video_artifacts(id, &result, emit);
+        tracker.format_done(idx, emit);
+        finished.push((idx, result));
+    }
+    Ok(finished)
+}
+
+/// Remove the `primaryFormat` job from `jobs`, leaving the others in requested order
+fn take_primary_job<J>(jobs: &mut Vec<(usize, J)>, primary: Option<&str>, format_of: impl Fn(&J) -> &str) -> Result<Option<(usize, J)>> {
+    let Some(primary) = primary else { return Ok(None) };
+    let pos = jobs.iter().position(|(_, job)| format_of(job) == primary)
+        .ok_or_else(|| anyhow!("primaryFormat {} is not one of the requested export formats", primary))?;
+    Ok(Some(jobs.remove(pos)))
+}
+
+/// Artifact events for every file one format's encode produced
+fn emit_video_artifacts(id: &str, result: &CaptionedVideoResult, emit: &mut impl FnMut(RpcEvent)) {
+    let mut artifact = |kind: &str, path: &str| emit(RpcEvent::Artifact { id: id.into(), kind: kind.into(), path: path.into() });
+    if !result.raw_video.is_empty() {
+        artifact("cleanVideo", &result.raw_video);
+    }
+    artifact("video", &result.captioned_video);
+    if let Some(qa) = &result.qa_video {
+        artifact("qaVideo", qa);
+    }
+}
+
+/// Fraction-of-height band (top, bottom) where a style's captions are drawn, with room for
+/// two rows and the bounce animation
+fn caption_zone(style: &AssStyle, frame_h: u32) -> (f32, f32) {
+    const BAND: f32 = 0.2;
+    let y = style.pos_y(frame_h) as f32 / frame_h.max(1) as f32;
+    let (top, bottom) = match style.align {
+        7..=9 => (y, y + BAND),                     // anchored at the top edge of the text
+        4..=6 => (y - BAND / 2.0, y + BAND / 2.0),
+        _ => (y - BAND, y),                         // anchored at the bottom edge
+    };
+    (top.max(0.0), bottom.min(1.0))
+}
+
+/// Post-encode checks that captions were actually burned in: the ASS file renders without
+/// libass warnings, and a frame from the longest caption shows text in the caption zone.
+/// Both are heuristics, so findings are logged (or fail the job with failOnMissingCaptions).
+async fn verify_burned_captions(
+    id: &str,
+    params: &GenerateCaptionsParams,
+    segments: &[CaptionSegment],
+    results: &[CaptionedVideoResult],
+    targets: &[(PathBuf, (f32, f32))],
+    emit: &mut impl FnMut(RpcEvent)
+) -> Result<()> {
+    let sample_s = segments.iter()
+        .max_by_key(|s| s.end_ms.saturating_sub(s.start_ms))
+        .map(|s| (s.start_ms + s.end_ms) as f64 / 2000.0);
+
+    let mut missing = Vec::new();
+    for (result, (ass_path, zone)) in results.iter().zip(targets) {
+        for warning in ass_render_warnings(ass_path, result.width, result.height, params.fonts_dir.as_deref()).await? {
+            emit(RpcEvent::Log { id: id.into(), message: format!("Warning: subtitles filter reported for {}: {}", result.format, warning) });
+        }
+        let Some(t) = sample_s else { continue };
+        match crate::video::frame_has_text_at(&result.captioned_video, t, *zone).await? {
+            Some(false) => missing.push(format!("{} (no caption text at {:.1}s)", result.format, t)),
+            Some(true) => {}
+            None => emit(RpcEvent::Log { id: id.into(), message: format!("Could not sample a frame from {} to verify captions", result.captioned_video) }),
+        }
+    }
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let message = format!("Captions may be missing from: {}", missing.join(", "));
+    if params.fail_on_missing_captions {
+        return Err(CapslapError::FfmpegFailed(message).into());
+    }
+    emit(RpcEvent::Log { id: id.into(), message: format!("Warning: {}", message) });
+    Ok(())
+}
+
+/// Render an ASS file alone over a short blank canvas and collect what the subtitles filter
+/// (libass) warns about: parse errors, missing fonts and glyphs
+async fn ass_render_warnings(ass_path: &Path, w: u32, h: u32, fonts_dir: Option<&str>) -> Result<Vec<String>> {
+    let ffmpeg_path = crate::whisper::find_ffmpeg_binary()
+        .await
+        .map_err(|e| CapslapError::FfmpegNotFound(format!("FFmpeg not found: {}", e)))?;
+    let canvas = format!("color=c=black:s={}x{}:d=0.1", w, h);
+    let vf = crate::video::subtitles_filter(&ass_path.to_string_lossy(), fonts_dir);
+    let output = tokio::process::Command::new(&ffmpeg_path)
+        .args(["-hide_banner", "-v", "warning", "-f", "lavfi", "-i", &canvas, "-vf", &vf, "-f", "null", "-"])
+        .kill_on_drop(true)
+        .output()
+        .await?;
+    Ok(subtitle_filter_warnings(&String::from_utf8_lossy(&output.stderr), output.status.success()))
+}
+
+/// Lines logged by the subtitles filter instance (`[Parsed_subtitles_0 @ 0x...] ...`), or the
+/// last line of a failed run that logged none
+fn subtitle_filter_warnings(stderr: &str, success: bool) -> Vec<String> {
+    let warnings: Vec<String> = stderr.lines()
+        .filter(|line| line.contains("Parsed_subtitles"))
+        .filter_map(|line| line.split_once("] ").map(|(_, msg)| msg.trim().to_string()))
+        .filter(|msg| !msg.is_empty())
+        .collect();
+    // e.g. an ffmpeg build without libass: the filter never gets to log anything itself
+    if !success && warnings.is_empty() {
+        return stderr.lines().last().map(|line| line.trim().to_string()).into_iter().collect();
+    }
+    warnings
+}
+
+/// Overall encode progress from each format's ffmpeg progress, mapped into `start..end`
+struct EncodeProgress<'a> {
+    id: &'a str,
+    fractions: Vec<f32>,   // per format, 0..1
+    done: usize,
+    start: f32,
+    end: f32,
+    last_sent: f32,
+}
+
+impl<'a> EncodeProgress<'a> {
+    // Don't flood the UI: ffmpeg reports several times a second per encode
+    const MIN_STEP: f32 = 0.005;
+
+    fn new(id: &'a str, formats: usize, start: f32, end: f32) -> Self {
+        Self { id, fractions: vec![0.0; formats.max(1)], done: 0, start, end, last_sent: start }
+    }
 
-        tasks.push(task);
+    fn overall(&self) -> f32 {
+        let mean = self.fractions.iter().sum::<f32>() / self.fractions.len() as f32;
+        (self.start + mean * (self.end - self.start)).min(self.end)
     }
 
-    // Wait for all tasks to complete and collect results
-    let total_formats = tasks.len();
-    let mut captioned_videos = Vec::new();
-    for (idx, task) in tasks.into_iter().enumerate() {
-        let result = task.await.map_err(|e| anyhow!("Concurrent task failed: {}", e))??;
-        captioned_videos.push(result);
-        
-        // Emit progress for encoding step (65-100% overall)
-        // Each format completion moves us forward in the encoding range
-        let encode_progress = ENCODE_START + ((idx + 1) as f32 / total_formats as f32) * (ENCODE_END - ENCODE_START);
+    fn update(&mut self, idx: usize, fraction: f32, emit: &mut dyn FnMut(RpcEvent)) {
+        let Some(slot) = self.fractions.get_mut(idx) else { return };
+        *slot = fraction.clamp(0.0, 1.0);
+        let progress = self.overall();
+        if (progress - self.last_sent).abs() >= Self::MIN_STEP {
+            self.last_sent = progress;
+            emit(RpcEvent::Progress {
+                id: self.id.into(),
+                status: format!("Encoding format {}/{}...", (self.done + 1).min(self.fractions.len()), self.fractions.len()),
+                progress,
+            });
+        }
+    }
+
+    fn format_done(&mut self, idx: usize, emit: &mut dyn FnMut(RpcEvent)) {
+        if let Some(slot) = self.fractions.get_mut(idx) {
+            *slot = 1.0;
+        }
+        self.done += 1;
+        self.last_sent = self.overall();
         emit(RpcEvent::Progress {
-            id: id.into(),
-            status: format!("Encoding format {}/{}...", idx + 1, total_formats),
-            progress: encode_progress.min(ENCODE_END),
+            id: self.id.into(),
+            status: format!("Encoding format {}/{}...", self.done, self.fractions.len()),
+            progress: self.last_sent,
         });
     }
 
-    Ok(captioned_videos)
+    /// Wait for one encode task, forwarding progress from every running encode meanwhile
+    async fn await_task<T>(
+        &mut self,
+        mut task: tokio::task::JoinHandle<T>,
+        progress_rx: &mut tokio::sync::mpsc::UnboundedReceiver<(usize, f32)>,
+        emit: &mut impl FnMut(RpcEvent)
+    ) -> Result<T> {
+        loop {
+            tokio::select! {
+                result = &mut task => return result.map_err(|e| anyhow!("Concurrent task failed: {}", e)),
+                Some((idx, fraction)) = progress_rx.recv() => self.update(idx, fraction, emit),
+            }
+        }
+    }
+}
+
+/// Seconds of output written so far, from an ffmpeg stats line (`... time=00:01:02.50 ...`)
+fn ffmpeg_progress_time(line: &str) -> Option<f64> {
+    let value = line.split("time=").nth(1)?.split_whitespace().next()?;
+    let mut secs = 0.0;
+    for part in value.split(':') {
+        secs = secs * 60.0 + part.parse::<f64>().ok()?;
+    }
+    Some(secs)
+}
+
+/// Caption style for one export canvas from the job's styling params
+fn caption_style(params: &GenerateCaptionsParams, target_w: u32, target_h: u32) -> AssStyle {
+    let mut style = default_ass_style(
+        target_w, target_h,
+        params.font_name.as_deref(),
+        params.text_color.as_deref(),
+        params.highlight_word_color.as_deref(),
+        params.outline_color.as_deref(),
+        params.glow_effect,
+        params.position.as_deref(),
+        params.text_align.as_deref()
+    );
+    style.rotation_deg = params.rotation_deg.unwrap_or(0.0).clamp(-45.0, 45.0);
+    style.lead_in_ms = params.lead_in_ms.unwrap_or(0);
+    style.broadcast = params.broadcast_compliant.then(|| params.broadcast_rules.clone().unwrap_or_default());
+    style.hook = params.hook_duration_ms
+        .filter(|ms| *ms > 0)
+        .map(|ms| (ms, params.hook_scale.unwrap_or(HOOK_DEFAULT_SCALE).clamp(1.0, 2.0)));
+    style.max_chars_per_line = params.max_chars_per_line.filter(|n| *n > 0);
+    style.max_lines = params.max_lines.unwrap_or(1).clamp(1, MAX_CAPTION_LINES);
+    style.karaoke_fill = params.karaoke_fill;
+    style.text_case = TextCase::from_param(params.text_case.as_deref()).unwrap_or_default();
+    style.speaker_colors = params.speaker_colors.iter().flatten()
+        .map(|hex| bgr_from_aa_bgrr(&hex_to_ass_color(hex)))
+        .collect();
+    style.max_highlights = params.max_highlights;
+    style.highlight_intensity = params.highlight_intensity.unwrap_or(HL_DEFAULT_INTENSITY).clamp(0.0, 1.0);
+    let karaoke_bgr = |hex: &Option<String>| hex.as_deref().map(|h| bgr_from_aa_bgrr(&hex_to_ass_color(h)));
+    style.background_box = params.background_box.as_ref().map(|b| {
+        let bgr = bgr_from_aa_bgrr(&hex_to_ass_color(b.color.as_deref().unwrap_or("#000000")));
+        let alpha = ((1.0 - b.opacity.unwrap_or(BOX_DEFAULT_OPACITY).clamp(0.0, 1.0)) * 255.0).round() as u8;
+        let padding = b.padding.unwrap_or(style.font_size / 4);
+        (format!("&H{:02X}{}", alpha, bgr), padding)
+    });
+    style.fade_ms = params.fade_ms.unwrap_or(0);
+    style.karaoke_colors = (
+        karaoke_bgr(&params.karaoke_past_color),
+        karaoke_bgr(&params.karaoke_active_color),
+        karaoke_bgr(&params.karaoke_future_color),
+    );
+    style.apply_safe_area(
+        target_w, target_h,
+        params.safe_area_top_pct,
+        params.safe_area_bottom_pct,
+        params.safe_area_horizontal_pct
+    );
+    style.apply_margin_pct(target_w, target_h, params.margin_v_pct, params.margin_h_pct);
+    style
+}
+
+/// Default distance a caption start may move to land on a keyframe
+const KEYFRAME_SNAP_TOLERANCE_MS: u64 = 200;
+
+/// Move caption starts within `tolerance_ms` of a keyframe (multiples of `interval_ms`) onto it.
+/// Approximate: encoders may add scene-cut keyframes, and this shifts timing slightly.
+fn snap_starts_to_keyframes(segments: &[CaptionSegment], interval_ms: u64, tolerance_ms: u64) -> Vec<CaptionSegment> {
+    if interval_ms == 0 {
+        return segments.to_vec();
+    }
+    let mut prev_start = 0;
+    segments.iter().map(|seg| {
+        let mut seg = seg.clone();
+        let nearest = ((seg.start_ms as f64 / interval_ms as f64).round() as u64) * interval_ms;
+        // Never reorder cues or collapse one to zero length
+        if nearest.abs_diff(seg.start_ms) <= tolerance_ms && nearest >= prev_start && nearest < seg.end_ms {
+            seg.start_ms = nearest;
+            for (i, word) in seg.words.iter_mut().enumerate() {
+                if i == 0 || word.start_ms < nearest {
+                    word.start_ms = nearest;
+                    word.end_ms = word.end_ms.max(nearest);
+                }
+            }
+        }
+        prev_start = seg.start_ms;
+        seg
+    }).collect()
+}
+
+/// Default longest pause bridged by gapBehavior "hold"
+const GAP_HOLD_MS: u64 = 500;
+
+/// Keep each caption up through a pause shorter than `hold_ms` instead of blanking until the
+/// next one: the segment (and its last word) ends where the next segment starts.
+fn hold_short_gaps(segments: &[CaptionSegment], hold_ms: u64) -> Vec<CaptionSegment> {
+    let mut held = segments.to_vec();
+    for i in 1..held.len() {
+        let next_start = held[i].start_ms;
+        let seg = &mut held[i - 1];
+        let gap = next_start.saturating_sub(seg.end_ms);
+        if gap == 0 || gap >= hold_ms {
+            continue;
+        }
+        seg.end_ms = next_start;
+        if let Some(last) = seg.words.last_mut() {
+            last.end_ms = last.end_ms.max(next_start);
+        }
+    }
+    held
+}
+
+/// Label used for the default export that keeps the source dimensions
+const ORIGINAL_FORMAT: &str = "original";
+
+/// Resolve requested aspect ratios into (format, width, height) canvases.
+/// An empty list means "caption the video as-is": a single export at the source's own
+/// dimensions, labeled "original". That default needs probed dimensions, so it still
+/// errors when the source size is unknown. `max_output_height` caps every canvas (the source
+/// is then scaled down to fit).
+fn resolve_export_targets(
+    export_formats: &[String],
+    probe_result: &crate::video::ProbeResult,
+    rounding: crate::video::EvenRounding,
+    max_output_height: Option<u32>
+) -> Result<Vec<(String, u32, u32)>> {
+    let cap = |(w, h): (u32, u32)| match max_output_height {
+        Some(max_h) => crate::video::cap_canvas_height(w, h, max_h, rounding),
+        None => (w, h),
+    };
+    if export_formats.is_empty() {
+        return match (probe_result.width, probe_result.height) {
+            (Some(w), Some(h)) if w > 0 && h > 0 => {
+                let (w, h) = cap((rounding.apply(w as f64), rounding.apply(h as f64)));
+                Ok(vec![(ORIGINAL_FORMAT.to_string(), w, h)])
+            }
+            _ => Err(anyhow!("No export formats specified and source dimensions are unknown")),
+        };
+    }
+
+    let src_w = probe_result.width.unwrap_or(1920) as u32;
+    let src_h = probe_result.height.unwrap_or(1080) as u32;
+    export_formats.iter().map(|format| {
+        let (target_ar, exact_size) = crate::video::parse_export_format(format)?;
+        let (target_w, target_h) = match exact_size {
+            Some(size) => size, // "9:16@1080x1920": exact sizes skip the canvas math and maxOutputHeight
+            None => cap(crate::video::canvas_no_downscale_with(src_w, src_h, target_ar, rounding)),
+        };
+        Ok((format.clone(), target_w, target_h))
+    }).collect()
 }
 
 /// Optimized single format encoding with hardware acceleration and modern FFmpeg flags
+#[allow(clippy::too_many_arguments)]
 async fn optimized_single_format_encode(
     id: &str,
     input_video: &str,
-    ass_path: &PathBuf,
+    ass_path: &Path,
     output_path: &str,
+    clean_path: Option<&str>,
     target_w: u32,
     target_h: u32,
     probe_result: &crate::video::ProbeResult,
+    settings: &EncodeSettings,
+    cancel: &CancellationToken,
+    on_progress: &(dyn Fn(f32) + Send + Sync),
 ) -> Result<()> {
     // Determine the best available hardware encoder for H.264 first (for filter optimization)
     let hardware_encoder = crate::video::get_best_hardware_encoder().await;
@@ -269,23 +1477,31 @@ async fn optimized_single_format_encode(
         input_video,
         ass_path,
         output_path,
+        clean_path,
         target_w,
         target_h,
         probe_result,
         hardware_encoder,
+        settings,
+        cancel,
+        on_progress,
     ).await;
 
-    // If hardware encoder failed, try software fallback
-    if result.is_err() && !matches!(hardware_encoder, crate::video::HardwareEncoder::Software) {
+    // If hardware encoder failed, try software fallback (not when the job was cancelled)
+    if result.is_err() && !cancel.is_cancelled() && !matches!(hardware_encoder, crate::video::HardwareEncoder::Software) {
         return try_encode_with_encoder(
             id,
             input_video,
             ass_path,
             output_path,
+            clean_path,
             target_w,
             target_h,
             probe_result,
             crate::video::HardwareEncoder::Software,
+            settings,
+            cancel,
+            on_progress,
         ).await;
     }
 
@@ -293,101 +1509,200 @@ async fn optimized_single_format_encode(
 }
 
 /// Helper function to try encoding with a specific encoder
+#[allow(clippy::too_many_arguments)]
 async fn try_encode_with_encoder(
     id: &str,
     input_video: &str,
-    ass_path: &PathBuf,
+    ass_path: &Path,
     output_path: &str,
+    clean_path: Option<&str>,
     target_w: u32,
     target_h: u32,
     probe_result: &crate::video::ProbeResult,
     hardware_encoder: crate::video::HardwareEncoder,
+    settings: &EncodeSettings,
+    cancel: &CancellationToken,
+    on_progress: &(dyn Fn(f32) + Send + Sync),
 ) -> Result<()> {
     // Build optimized filter with format conversion AND subtitles in one pass
     // Use encoder-specific format optimization (NV12 for VideoToolbox/NVENC, yuv420p for software)
     let ass = ass_path.to_string_lossy().to_string();
-    let vf = crate::video::build_fitpad_filter_with_format(target_w, target_h, Some(&ass), hardware_encoder);
-
-    // Determine optimal audio codec and settings
-    let (audio_codec, audio_args) = crate::video::determine_audio_codec(Some(probe_result));
+    let source = probe_result.width.zip(probe_result.height).map(|(w, h)| (w as u32, h as u32));
+    let fit = crate::video::FitPad::new(source, target_w, target_h, settings.rounding);
+    let vf = crate::video::build_fitpad_filter_with_format(&fit, Some(&ass), settings.fonts_dir.as_deref(), settings.scale_algo, hardware_encoder);
+    let split_graph = clean_path.map(|_| {
+        crate::video::build_fitpad_split_graph(settings.video_stream, &fit, &ass, settings.fonts_dir.as_deref(), settings.scale_algo, hardware_encoder)
+    });
 
-    // Calculate GOP size based on original video FPS for better seeking
-    let gop_size = if let Some(fps) = probe_result.fps {
-        (fps * 2.0).round() as u32
-    } else {
-        48 // Default for 24fps content
+    // Determine optimal audio codec and settings; loudness normalization can't stream-copy
+    let loudness_filter = settings.loudness.filter(|_| probe_result.audio).map(|l| l.filter());
+    let (audio_codec, audio_args) = match crate::video::determine_audio_codec(Some(probe_result)) {
+        ("copy", _) if loudness_filter.is_some() => ("aac", Vec::new()),
+        codec => codec,
     };
+
+    // Calculate GOP size based on original video FPS for better seeking (VFR-corrected)
+    let gop_size = crate::video::gop_size_for_probe(probe_result);
     let gop_size_str = gop_size.to_string();
 
+    // Provenance metadata tags (title = source name unless overridden)
+    let metadata_args: Vec<String> = match &settings.metadata_title {
+        Some(title) => vec![
+            "-metadata".into(), format!("title={}", title),
+            "-metadata".into(), "comment=Captioned by CapSlap".into(),
+        ],
+        None => Vec::new(),
+    };
+    // The clean copy keeps the title but isn't captioned
+    let clean_metadata_args: Vec<String> = match &settings.metadata_title {
+        Some(title) => vec!["-metadata".into(), format!("title={}", title)],
+        None => Vec::new(),
+    };
+
+    // Per-output encoding options (repeated for the clean copy when one is requested)
+    let mut encode_args: Vec<&str> = vec![
+        "-fps_mode", "passthrough",       // Modern replacement for -vsync
+        "-threads", "0",                  // Use all available CPU cores
+    ];
+
+    // Add hardware-optimized encoding parameters
+    match hardware_encoder {
+        crate::video::HardwareEncoder::VideoToolbox => {
+            // VideoToolbox uses -q:v (0-100 scale) instead of CRF
+            // CRF 16 is very high quality, so use q:v ~70-75 (higher is better for VideoToolbox)
+            // Note: pix_fmt is already set in the filter (format=nv12), no need to duplicate
+            encode_args.extend_from_slice(&[
+                "-c:v", "h264_videotoolbox",
+                "-q:v", "72",                 // Quality setting (0-100, higher=better)
+                "-allow_sw", "1",             // Allow software fallback
+                "-g", &gop_size_str,
+            ]);
+        },
+        crate::video::HardwareEncoder::Nvenc => {
+            // Note: pix_fmt is already set in the filter (format=nv12), no need to duplicate
+            encode_args.extend_from_slice(&[
+                "-c:v", "h264_nvenc",
+                "-cq", "16",
+                "-preset", "p5",
+                "-tune", "hq",
+                "-rc", "vbr",
+                "-g", &gop_size_str,
+            ]);
+        },
+        crate::video::HardwareEncoder::Software => {
+            // Note: pix_fmt is already set in the filter (format=yuv420p), no need to duplicate
+            encode_args.extend_from_slice(&[
+                "-c:v", "libx264",
+                "-preset", "medium",
+                "-crf", "16",
+                "-g", &gop_size_str,
+            ]);
+        }
+    }
+
+    encode_args.push("-c:a");
+    encode_args.push(audio_codec);
+
+    // Add audio-specific args
+    encode_args.extend(audio_args.iter().copied());
+
+    // Add explicit bitrate for re-encoded audio if not using copy
+    if audio_codec != "copy" && audio_codec == "aac" && audio_args.is_empty() {
+        encode_args.extend_from_slice(&["-b:a", "160k"]);
+    }
+
+    if let Some(filter) = &loudness_filter {
+        encode_args.extend_from_slice(&["-af", filter]);
+    }
+
     // Resolve FFmpeg path using unified async detector (bundled > project > system)
     let ffmpeg_path = crate::whisper::find_ffmpeg_binary()
         .await
-        .map_err(|e| anyhow!("FFmpeg not found: {}", e))?;
+        .map_err(|e| CapslapError::FfmpegNotFound(format!("FFmpeg not found: {}", e)))?;
 
-    let status = Command::new(&ffmpeg_path)
+    let video_map = format!("0:v:{}", settings.video_stream);
+    let mut child = tokio::process::Command::new(&ffmpeg_path)
         .args({
-            let mut args = vec![
-                "-y", "-i", input_video,
-                "-vf", &vf,
-                "-fps_mode", "passthrough",       // Modern replacement for -vsync
-                "-threads", "0",                  // Use all available CPU cores
-                "-map", "0:v:0",                  // Map first video stream
-                "-map", "0:a?",                   // Map audio if present (optional)
-            ];
-
-            // Add hardware-optimized encoding parameters
-            match hardware_encoder {
-                crate::video::HardwareEncoder::VideoToolbox => {
-                    // VideoToolbox uses -q:v (0-100 scale) instead of CRF
-                    // CRF 16 is very high quality, so use q:v ~70-75 (higher is better for VideoToolbox)
-                    // Note: pix_fmt is already set in the filter (format=nv12), no need to duplicate
-                    args.extend_from_slice(&[
-                        "-c:v", "h264_videotoolbox",
-                        "-q:v", "72",                 // Quality setting (0-100, higher=better)
-                        "-allow_sw", "1",             // Allow software fallback
-                        "-g", &gop_size_str,
-                    ]);
-                },
-                crate::video::HardwareEncoder::Nvenc => {
-                    // Note: pix_fmt is already set in the filter (format=nv12), no need to duplicate
+            let mut args = vec!["-y", "-i", input_video];
+
+            match (clean_path, &split_graph) {
+                (Some(clean_path), Some(graph)) => {
+                    // One decode + fit/pad feeding both outputs
+                    args.extend_from_slice(&["-filter_complex", graph]);
+
+                    args.extend_from_slice(&["-map", "[captioned]", "-map", "0:a?"]);
+                    args.extend(encode_args.iter().copied());
+                    args.extend(metadata_args.iter().map(String::as_str));
+                    args.extend_from_slice(&["-movflags", "+faststart", output_path]);
+
+                    args.extend_from_slice(&["-map", "[clean]", "-map", "0:a?"]);
+                    args.extend(encode_args.iter().copied());
+                    args.extend(clean_metadata_args.iter().map(String::as_str));
+                    args.extend_from_slice(&["-movflags", "+faststart", clean_path]);
+                }
+                _ => {
                     args.extend_from_slice(&[
-                        "-c:v", "h264_nvenc",
-                        "-cq", "16",
-                        "-preset", "p5",
-                        "-tune", "hq",
-                        "-rc", "vbr",
-                        "-g", &gop_size_str,
+                        "-vf", &vf,
+                        "-map", &video_map,               // Map the selected video stream
+                        "-map", "0:a?",                   // Map audio if present (optional)
                     ]);
-                },
-                crate::video::HardwareEncoder::Software => {
-                    // Note: pix_fmt is already set in the filter (format=yuv420p), no need to duplicate
+                    args.extend(encode_args.iter().copied());
+                    args.extend(metadata_args.iter().map(String::as_str));
                     args.extend_from_slice(&[
-                        "-c:v", "libx264",
-                        "-preset", "medium",
-                        "-crf", "16",
-                        "-g", &gop_size_str,
+                        "-movflags", "+faststart",       // Fast web playback
+                        output_path
                     ]);
                 }
             }
-
-            args.push("-c:a");
-            args.push(&audio_codec);
-
-            // Add audio-specific args
-            args.extend(audio_args.iter().copied());
-
-            // Add explicit bitrate for re-encoded audio if not using copy
-            if audio_codec != "copy" && audio_codec == "aac" && audio_args.is_empty() {
-                args.extend_from_slice(&["-b:a", "160k"]);
-            }
-
-            args.extend_from_slice(&[
-                "-movflags", "+faststart",       // Fast web playback
-                output_path
-            ]);
             args
         })
-        .status()?;
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    // Follow ffmpeg's `time=` stats (\r-separated) for progress; keep the last few other
+    // lines for the error message
+    let duration = probe_result.duration.filter(|d| *d > 0.0);
+    let mut stderr = child.stderr.take();
+    let mut stderr_tail: VecDeque<String> = VecDeque::new();
+    let run = async {
+        if let Some(stderr) = stderr.as_mut() {
+            let mut buf = [0u8; 4096];
+            let mut pending = String::new();
+            loop {
+                let n = stderr.read(&mut buf).await?;
+                if n == 0 { break; }
+                pending.push_str(&String::from_utf8_lossy(&buf[..n]));
+                while let Some(pos) = pending.find(['\r', '\n']) {
+                    let line: String = pending.drain(..=pos).collect();
+                    let line = line.trim();
+                    match (ffmpeg_progress_time(line), duration) {
+                        (Some(t), Some(d)) => on_progress((t / d) as f32),
+                        (None, _) if !line.is_empty() => {
+                            if stderr_tail.len() == 5 { stderr_tail.pop_front(); }
+                            stderr_tail.push_back(line.to_string());
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        child.wait().await
+    };
+
+    let outcome = tokio::select! {
+        status = run => Some(status),
+        _ = cancel.cancelled() => None,
+    };
+    let Some(status) = outcome else {
+        let _ = child.kill().await;
+        let _ = fs::remove_file(output_path);
+        if let Some(clean_path) = clean_path {
+            let _ = fs::remove_file(clean_path);
+        }
+        return Err(CapslapError::Cancelled(format!("Encoding cancelled for {}", id)).into());
+    };
+    let status = status?;
 
     if !status.success() {
         let encoder_name = match hardware_encoder {
@@ -395,7 +1710,56 @@ async fn try_encode_with_encoder(
             crate::video::HardwareEncoder::Nvenc => "h264_nvenc",
             crate::video::HardwareEncoder::Software => "libx264",
         };
-        return Err(anyhow!("FFmpeg failed to encode format for {} with encoder {}", id, encoder_name));
+        return Err(CapslapError::FfmpegFailed(format!(
+            "FFmpeg failed to encode format for {} with encoder {}: {}",
+            id, encoder_name, Vec::from(stderr_tail).join(" | ")
+        )).into());
+    }
+
+    Ok(())
+}
+
+/// Width of the divider between the two halves of the QA video
+const QA_DIVIDER_PX: u32 = 8;
+
+/// Stack the clean and captioned encodes left | right with a thin white divider for review.
+/// Both come from the same fit/pad so heights match; the output is twice as wide (plus divider).
+async fn render_side_by_side(id: &str, clean_path: &str, captioned_path: &str, output_path: &str, cancel: &CancellationToken) -> Result<()> {
+    let ffmpeg_path = crate::whisper::find_ffmpeg_binary()
+        .await
+        .map_err(|e| CapslapError::FfmpegNotFound(format!("FFmpeg not found: {}", e)))?;
+
+    let graph = format!("[0:v]pad=iw+{}:ih:0:0:color=white[left];[left][1:v]hstack=inputs=2[qa]", QA_DIVIDER_PX);
+    let run = tokio::process::Command::new(&ffmpeg_path)
+        .args([
+            "-y",
+            "-i", clean_path,
+            "-i", captioned_path,
+            "-filter_complex", &graph,
+            "-map", "[qa]",
+            "-map", "1:a?",               // Audio from the captioned encode, as delivered
+            "-c:v", "libx264",
+            "-preset", "veryfast",        // Review copy: speed over size
+            "-crf", "20",
+            "-pix_fmt", "yuv420p",
+            "-c:a", "copy",
+            "-movflags", "+faststart",
+            output_path,
+        ])
+        .kill_on_drop(true)
+        .output();
+
+    let output = tokio::select! {
+        output = run => output?,
+        _ = cancel.cancelled() => {
+            let _ = fs::remove_file(output_path);
+            return Err(CapslapError::Cancelled(format!("Encoding cancelled for {}", id)).into());
+        }
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let tail: Vec<&str> = stderr.lines().rev().take(5).collect::<Vec<_>>().into_iter().rev().collect();
+        return Err(CapslapError::FfmpegFailed(format!("FFmpeg failed to render side-by-side QA video for {}: {}", id, tail.join(" | "))).into());
     }
 
     Ok(())
@@ -407,6 +1771,7 @@ const STRETCH_X_PEAK: f32 = 1.03;  // 1.08–1.15 looks right
 const STRETCH_UP_MIN_MS: i64 = 0;
 const STRETCH_UP_MAX_MS: i64 = 150;
 const BIG_FONT_SIZE_MULTIPLIER: f32 = 1.1;
+const HOOK_DEFAULT_SCALE: f32 = 1.25;     // opening-hook captions when hook_scale isn't given
 
 // ---- Constants for bounce animation (non-karaoke) ----
 const BOUNCE_START: f32 = 0.85;   // 95%
@@ -416,12 +1781,44 @@ const BOUNCE_UP_MS: i64 = 100;    // Time to reach peak
 const BOUNCE_DOWN_MS: i64 = 66;  // Time to settle
 
 // ---- Smart highlight tuning (non-karaoke) ----
-const HL_BASE_T: f32 = 2.5;         // base threshold
+const HL_BASE_T: f32 = 2.5;         // base threshold (at the default intensity)
 const HL_HYSTERESIS: f32 = 0.7;     // make back-to-back highlights harder
 const HL_MIN_GAP_MS: u64 = 1200;    // min time between highlights
-const HL_MAX_RATIO: f32 = 0.35;     // cap ~35% of phrases highlighted
+const HL_MAX_RATIO: f32 = 0.35;     // cap ~35% of phrases highlighted (at the default intensity)
+const HL_DEFAULT_INTENSITY: f32 = 0.5; // `highlightIntensity` the two values above are tuned for
 const HL_RECENT_WINDOW_MS: u64 = 5000; // window for repetition penalty
 
+// Default opacity of `backgroundBox`
+const BOX_DEFAULT_OPACITY: f32 = 0.6;
+
+/// `TikTokBox` style line for the background box: BorderStyle 3 draws an opaque box (in the
+/// outline color, padded by the outline width) around each rendered line instead of a stroke.
+/// libass can't round its corners.
+fn box_style_line(style: &AssStyle) -> String {
+    match &style.background_box {
+        Some((color, padding)) => format!(
+            "Style: TikTokBox,{font},{size},&HFF000000,&HFF000000,{color},{color},0,0,0,0,100,100,0,0,3,{pad},0,{al},{mh},{mh},{mv},1\n",
+            font = style.font_name, size = style.font_size, color = color, pad = padding,
+            al = style.align, mh = style.margin_h, mv = style.margin_v
+        ),
+        None => String::new(),
+    }
+}
+
+/// Box layer under a caption: same anchor as the text, glyphs hidden so only the box shows. `text_body` must lay out like the visible layers so the box matches their width.
+#[allow(clippy::too_many_arguments)]
+fn push_box_layer(lines: &mut String, start: &str, end: &str, style: &AssStyle, x: i32, y: i32, rotation: &str, fade: &str, text_body: &str) {
+    if style.background_box.is_none() {
+        return;
+    }
+    // Emitted before the text on layer 0: same-layer events are drawn in file order
+    lines.push_str(&format!(
+        "Dialogue: 0,{},{},TikTokBox,,0,0,0,,{{\\an{}\\q2\\pos({},{}){}{}\\1a&HFF\\2a&HFF\\blur0\\shad0}}{}\n",
+        start, end, style.align, x, y, rotation, fade, text_body
+    ));
+}
+
+#[allow(clippy::too_many_arguments)]
 fn push_glow_and_stroke(
     lines: &mut String,
     start: &str, end: &str,
@@ -431,8 +1828,10 @@ fn push_glow_and_stroke(
     enable_glow: bool,    // whether to apply glow effect
     glow_w: f32, glow_blur: f32, glow_alpha_hex: &str, // e.g. "&H80" ~ 50% opacity
     alignment: u32,       // ASS alignment value (2 = bottom center, 5 = middle center)
+    rotation: &str,       // \org + \frz tags (empty when not rotated)
+    fade: &str,           // \fad tag shared by every layer so glow and stroke fade together (empty = none)
 ) {
-    let common = format!("{{\\an{}\\q2\\pos({},{})\\be0}}", alignment, x, y);
+    let common = format!("{{\\an{}\\q2\\pos({},{}){}{}\\be0}}", alignment, x, y, rotation, fade);
 
     // LAYER 0 — soft WHITE GLOW (outline only) - only if enabled
     if enable_glow {
@@ -444,10 +1843,11 @@ fn push_glow_and_stroke(
         lines.push_str(&format!("Dialogue: 0,{},{},TikTok,,0,0,0,,{}{}\n", start, end, glow, text_body));
     }
 
-    // LAYER 1 (or 0 if no glow) — sharp black stroke + visible fill
+    // LAYER 1 (or 0 if no glow) — sharp black stroke + visible fill (fill alpha comes from
+    // the style's PrimaryColour, so #RRGGBBAA text colors stay translucent)
     let layer = if enable_glow { 1 } else { 0 };
     let stroke_fill = format!(
-        "{}{{\\1a&H00\\bord{:.2}\\3c&H000000&\\3a&H00\\blur0\\shad0}}",
+        "{}{{\\bord{:.2}\\3c&H000000&\\3a&H00\\blur0\\shad0}}",
         common, stroke_w
     );
     lines.push_str(&format!("Dialogue: {},{},{},TikTok,,0,0,0,,{}{}\n", layer, start, end, stroke_fill, text_body));
@@ -463,12 +1863,13 @@ struct Phrase {
 }
 
 // Heuristics: new phrase if punctuation on previous token or gap > 350ms or length > 3 words
+// (or the speaker changes, so a line never mixes speaker colors)
 fn coalesce_phrases(segments: &[CaptionSegment]) -> Vec<Phrase> {
     let mut all: Vec<WordSpan> = Vec::new();
     for s in segments {
         for w in &s.words {
             let t = w.text.trim();
-            if !t.is_empty() { all.push(WordSpan { start_ms: w.start_ms, end_ms: w.end_ms, text: t.to_string() }); }
+            if !t.is_empty() { all.push(WordSpan { start_ms: w.start_ms, end_ms: w.end_ms, text: t.to_string(), speaker: w.speaker.or(s.speaker), highlight: w.highlight.or(s.highlight) }); }
         }
         // Fallback: if a segment has text but no words, split evenly so nothing gets dropped
         if s.words.is_empty() && !s.text.trim().is_empty() {
@@ -478,7 +1879,7 @@ fn coalesce_phrases(segments: &[CaptionSegment]) -> Vec<Phrase> {
             let mut t = s.start_ms;
             for tok in toks {
                 let s0 = t; let e0 = (t + per).min(s.end_ms); t = e0;
-                all.push(WordSpan { start_ms: s0, end_ms: e0, text: tok.to_string() });
+                all.push(WordSpan { start_ms: s0, end_ms: e0, text: tok.to_string(), speaker: s.speaker, highlight: s.highlight });
             }
         }
     }
@@ -489,7 +1890,8 @@ fn coalesce_phrases(segments: &[CaptionSegment]) -> Vec<Phrase> {
         if cur.is_empty() { cur.push(w); continue; }
         let prev = cur.last().unwrap();
         let gap = w.start_ms.saturating_sub(prev.end_ms);
-        let hard_break = [".","!","?"].iter().any(|p| prev.text.ends_with(p)) || gap > 350 || cur.len() >= 3;
+        let hard_break = [".","!","?"].iter().any(|p| prev.text.ends_with(p)) || gap > 350 || cur.len() >= 3
+            || w.speaker != prev.speaker;
         if hard_break {
             let tokens = cur.iter().map(|x| x.text.clone()).collect::<Vec<_>>();
             out.push(Phrase{ start_ms: cur.first().unwrap().start_ms, end_ms: cur.last().unwrap().end_ms, tokens, spans: cur.clone() });
@@ -548,39 +1950,159 @@ fn bounce_tag() -> String {
             BOUNCE_UP_MS, BOUNCE_UP_MS, BOUNCE_UP_MS + BOUNCE_DOWN_MS)
 }
 
-// Uppercase + sanitize tokens (keeps punctuation)
-fn normalize_tokens(words: &[WordSpan]) -> Vec<String> {
+/// Display casing for caption tokens (`textCase`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum TextCase {
+    #[default]
+    Upper,
+    Lower,
+    Sentence, // capitalize each phrase's first word, keep the transcript's casing elsewhere
+    Preserve,
+}
+
+impl TextCase {
+    fn from_param(value: Option<&str>) -> Result<Self> {
+        match value {
+            None | Some("upper") => Ok(TextCase::Upper),
+            Some("lower") => Ok(TextCase::Lower),
+            Some("sentence") => Ok(TextCase::Sentence),
+            Some("preserve") => Ok(TextCase::Preserve),
+            Some(other) => Err(anyhow!("Unknown text case: {}. Supported: upper, lower, sentence, preserve", other)),
+        }
+    }
+
+    /// Apply to one token; `phrase_start` marks the first word of a phrase (sentence case)
+    fn apply(self, text: &str, phrase_start: bool) -> String {
+        match self {
+            TextCase::Upper => text.to_uppercase(),
+            TextCase::Lower => text.to_lowercase(),
+            TextCase::Preserve => text.to_string(),
+            TextCase::Sentence if phrase_start => {
+                let mut chars = text.chars();
+                chars.next().map(|c| c.to_uppercase().chain(chars).collect()).unwrap_or_default()
+            }
+            TextCase::Sentence => text.to_string(),
+        }
+    }
+}
+
+// Display tokens for a phrase. Highlight scoring works on `original_tokens` instead, so its
+// case-based heuristics (proper nouns) see the transcript as-is whatever the display casing
+fn normalize_tokens(words: &[WordSpan], case: TextCase) -> Vec<String> {
     words.iter()
         .map(|w| w.text.trim())
         .filter(|t| !t.is_empty())
-        .map(|t| t.to_uppercase())
+        .enumerate()
+        .map(|(i, t)| case.apply(t, i == 0))
         .collect()
 }
 
-// Simple width check for karaoke - split long phrases into single-line segments
-fn split_phrase_for_width(tokens: &[String], spans: &[WordSpan], frame_w: u32, font_px: u32) -> Vec<(Vec<String>, Vec<WordSpan>)> {
-    let est_char_width = (font_px as f32 * 0.56).max(1.0);
-    let max_chars = ((frame_w as f32 * 0.85) / est_char_width).floor() as usize; // Use 85% of width for safety
+// Approximate advance width of a glyph in em for a regular sans face. Capitals and digits
+// are noticeably wider than the lowercase average, which matters since tokens are uppercased.
+fn glyph_width_em(c: char) -> f32 {
+    match c {
+        ' ' => 0.28,
+        'I' | 'J' | 'i' | 'j' | 'l' | '!' | '\'' | '.' | ',' | ':' | ';' | '|' => 0.30,
+        'M' | 'W' | 'm' | 'w' | '@' | '%' => 0.90,
+        'A'..='Z' | '0'..='9' | '#' | '$' | '&' => 0.68,
+        'a'..='z' => 0.54,
+        c if c.is_uppercase() => 0.70,
+        c if c.is_alphabetic() => 0.56,
+        c if (c as u32) >= 0x2E80 => 1.0, // CJK and other full-width scripts
+        _ => 0.56,
+    }
+}
+
+// Heavier and condensed faces change every glyph's advance roughly proportionally
+fn font_width_factor(font_name: &str) -> f32 {
+    let name = font_name.to_ascii_lowercase();
+    if name.contains("condensed") || name.contains("narrow") || name.contains("compressed") {
+        0.85
+    } else if name.contains("black") || name.contains("heavy") || name.contains("extrabold") {
+        1.10
+    } else if name.contains("bold") {
+        1.05
+    } else {
+        1.0
+    }
+}
+
+// Safety margin on top of the estimate (kerning, outline, renderer differences)
+const WIDTH_SAFETY_FACTOR: f32 = 1.06;
+
+// Estimated rendered width of `text` in pixels
+fn estimate_text_width(text: &str, font_px: u32, font_factor: f32) -> f32 {
+    text.chars().map(glyph_width_em).sum::<f32>() * font_px as f32 * font_factor * WIDTH_SAFETY_FACTOR
+}
+
+// Width check for karaoke - split long phrases into single-line segments.
+// With `max_chars_per_line`, lines are wrapped by character count instead (for fonts the
+// width estimate doesn't know, e.g. CJK)
+fn split_phrase_for_width(tokens: &[String], spans: &[WordSpan], frame_w: u32, font_px: u32, font_name: &str, max_chars_per_line: Option<usize>, case: TextCase) -> Vec<(Vec<String>, Vec<WordSpan>)> {
+    let font_factor = font_width_factor(font_name);
+    let max_width = frame_w as f32 * 0.85; // Use 85% of width for safety
+    let space_width = estimate_text_width(" ", font_px, font_factor);
+    // Character budget for breaking a single over-long token, sized for its widest glyph
+    // (at least a capital) so a piece of "WWW..." fits as well as one of "AAA..."
+    let max_chars_for = |token: &str| max_chars_per_line.unwrap_or_else(|| {
+        let widest_em = token.chars().map(glyph_width_em).fold(glyph_width_em('A'), f32::max);
+        let est_char_width = (font_px as f32 * widest_em * font_factor * WIDTH_SAFETY_FACTOR).max(1.0);
+        (max_width / (est_char_width * BIG_FONT_SIZE_MULTIPLIER)).floor() as usize
+    });
+    // Any word on a line may end up highlighted at the bigger size (always in karaoke, and the
+    // smart pick happens after wrapping), so each line reserves room for its widest word to grow
+    let grow = BIG_FONT_SIZE_MULTIPLIER - 1.0;
 
     let mut segments = Vec::new();
     let mut current_tokens = Vec::new();
     let mut current_spans = Vec::new();
-    let mut current_length = 0;
+    let mut current_width = 0.0_f32;
+    let mut current_widest = 0.0_f32;
+    let mut current_chars = 0_usize;
+
+    // Tokens wider than a whole line (URLs, long compounds) are broken into pieces first
+    let mut pieces: Vec<(String, WordSpan)> = Vec::with_capacity(tokens.len());
+    for (i, (token, span)) in tokens.iter().zip(spans.iter()).enumerate() {
+        let too_long = match max_chars_per_line {
+            Some(limit) => token.chars().count() > limit,
+            None => estimate_text_width(token, font_px, font_factor) * BIG_FONT_SIZE_MULTIPLIER > max_width,
+        };
+        if too_long {
+            pieces.extend(break_long_token(span, max_chars_for(token)).into_iter().enumerate()
+                .map(|(k, s)| (case.apply(&s.text, i == 0 && k == 0), s)));
+        } else {
+            pieces.push((token.clone(), span.clone()));
+        }
+    }
 
-    for (token, span) in tokens.iter().zip(spans.iter()) {
-        let token_length = token.len() + if current_length == 0 { 0 } else { 1 }; // Add space
+    for (token, span) in pieces.iter() {
+        let token_width = estimate_text_width(token, font_px, font_factor);
+        let token_chars = token.chars().count();
 
-        if current_length > 0 && current_length + token_length > max_chars {
+        let widest = current_widest.max(token_width);
+        let overflows = match max_chars_per_line {
+            Some(limit) => current_chars + 1 + token_chars > limit,
+            None => current_width + space_width + token_width + widest * grow > max_width,
+        };
+        if !current_tokens.is_empty() && overflows {
             // Current segment is full, start a new one
             segments.push((current_tokens.clone(), current_spans.clone()));
             current_tokens.clear();
             current_spans.clear();
-            current_length = 0;
+            current_width = 0.0;
+            current_widest = 0.0;
+            current_chars = 0;
         }
 
+        if !current_tokens.is_empty() {
+            current_width += space_width; // Add space
+            current_chars += 1;
+        }
         current_tokens.push(token.clone());
         current_spans.push(span.clone());
-        current_length += token_length;
+        current_width += token_width;
+        current_widest = current_widest.max(token_width);
+        current_chars += token_chars;
     }
 
     // Add the last segment if it has content
@@ -596,15 +2118,94 @@ fn split_phrase_for_width(tokens: &[String], spans: &[WordSpan], frame_w: u32, f
     segments
 }
 
+// Upper bound for `maxLines`; taller stacks cover too much of the frame
+const MAX_CAPTION_LINES: usize = 4;
+
+/// Stack consecutive width-fitted rows into captions of up to `max_lines` rows each.
+/// Returns each caption's tokens and spans plus the token indices where a new row starts.
+fn stack_rows(rows: Vec<(Vec<String>, Vec<WordSpan>)>, max_lines: usize) -> Vec<(Vec<String>, Vec<WordSpan>, Vec<usize>)> {
+    let mut out: Vec<(Vec<String>, Vec<WordSpan>, Vec<usize>)> = Vec::new();
+    for (i, (tokens, spans)) in rows.into_iter().enumerate() {
+        if i % max_lines.max(1) == 0 {
+            out.push((tokens, spans, Vec::new()));
+        } else if let Some((all_tokens, all_spans, breaks)) = out.last_mut() {
+            breaks.push(all_tokens.len());
+            all_tokens.extend(tokens);
+            all_spans.extend(spans);
+        }
+    }
+    out
+}
+
+/// Break a single over-long word into pieces of at most `max_chars`, preferring to cut after
+/// `/`, `-` or `.` and hyphenating otherwise. The word's time span is shared out by length.
+fn break_long_token(span: &WordSpan, max_chars: usize) -> Vec<WordSpan> {
+    let chars: Vec<char> = span.text.trim().chars().collect();
+    if max_chars < 4 || chars.len() <= max_chars {
+        return vec![span.clone()];
+    }
+
+    let mut texts = Vec::new(); // (piece text, end offset into the original word)
+    let mut pos = 0;
+    while chars.len() - pos > max_chars {
+        let window = &chars[pos..pos + max_chars];
+        // Don't cut so early that we leave a tiny fragment behind
+        let natural = window.iter()
+            .rposition(|c| matches!(c, '/' | '-' | '.'))
+            .filter(|&j| j + 1 >= max_chars / 3);
+        match natural {
+            Some(j) => {
+                pos += j + 1;
+                texts.push((window[..=j].iter().collect::<String>(), pos));
+            }
+            None => {
+                let mut piece: String = window[..max_chars - 1].iter().collect();
+                piece.push('-');
+                pos += max_chars - 1;
+                texts.push((piece, pos));
+            }
+        }
+    }
+    texts.push((chars[pos..].iter().collect(), chars.len()));
+
+    let total_chars = chars.len() as u64;
+    let dur = span.end_ms.saturating_sub(span.start_ms);
+    let mut start = span.start_ms;
+    texts.into_iter().map(|(text, offset)| {
+        let end = span.start_ms + dur * offset as u64 / total_chars;
+        let piece = WordSpan { start_ms: start, end_ms: end, text, speaker: span.speaker, highlight: span.highlight };
+        start = end;
+        piece
+    }).collect()
+}
+
+// Brightness kept for already-sung karaoke words when no past color is given
+const KARAOKE_PAST_DIM: f32 = 0.6;
+
+// Scale each BBGGRR channel by `factor` (invalid input is returned unchanged)
+fn dim_bgr(bgr: &str, factor: f32) -> String {
+    if bgr.len() != 6 || !bgr.chars().all(|c| c.is_ascii_hexdigit()) {
+        return bgr.to_string();
+    }
+    (0..3)
+        .map(|k| u8::from_str_radix(&bgr[k * 2..k * 2 + 2], 16).unwrap_or(0))
+        .map(|c| format!("{:02X}", (c as f32 * factor).round().clamp(0.0, 255.0) as u8))
+        .collect()
+}
+
 // Color tags use BBGGRR (no alpha) for \1c
 fn bgr_from_aa_bgrr(aa_bgrr: &str) -> String {
     aa_bgrr.trim_start_matches("&H").chars().skip(2).collect() // drop AA
 }
 
-fn assemble_colored_two_lines(
+/// Colored caption body; `hi` indexes the whole token list and a `\N` row break goes
+/// before every index in `breaks`. With `past_bgr` (karaoke), tokens before `hi` use it
+#[allow(clippy::too_many_arguments)]
+fn assemble_colored_lines(
     tokens: &[String], hi: usize,
     white_bgr: &str, hi_bgr: &str,
-    line1_count: usize,
+    past_bgr: Option<&str>,
+    breaks: &[usize],
     header: &str,
     font_size: u32
 ) -> String {
@@ -618,19 +2219,106 @@ fn assemble_colored_two_lines(
         format!("{{\\1c&H{}&\\fs{}}}", hi_bgr, font_size) // Same size, just different color
     };
 
+    let past = past_bgr.map(|bgr| format!("{{\\1c&H{}&\\fs{}}}", bgr, font_size));
+
     let mut s = String::from(header); // will include \an2 \pos \q2 and stretch
     for i in 0..tokens.len() {
-        if i == line1_count { s.push_str(r"\N"); }
+        if i > 0 && breaks.contains(&i) { s.push_str(r"\N"); }
         // Only highlight if hi is a valid index (not usize::MAX)
         let should_highlight = has_highlighting && i == hi;
-        s.push_str(if should_highlight { &hi_style } else { &white });
-        let t = tokens[i].replace('\\', r"\\").replace('{', r"\{").replace('}', r"\}");
-        s.push_str(&t);
+        let style = match &past {
+            _ if should_highlight => &hi_style,
+            Some(past) if has_highlighting && i < hi => past,
+            _ => &white,
+        };
+        s.push_str(style);
+        s.push_str(&escape_ass_text(&tokens[i]));
+        if i + 1 < tokens.len() && !breaks.contains(&(i + 1)) { s.push(' '); }
+    }
+    s
+}
+
+// Escape characters that would otherwise be parsed as ASS override blocks
+fn escape_ass_text(text: &str) -> String {
+    text.replace('\\', r"\\").replace('{', r"\{").replace('}', r"\}")
+}
+
+// ---- Typewriter reveal (non-karaoke) ----
+const TYPEWRITER_MAX_STEPS_PER_WORD: usize = 12; // longer words reveal in chunks to bound tag count
+
+/// Typewriter body: every character (or chunk, for long words) is a `\ko` syllable whose
+/// fill stays fully transparent (`\2a&HFF&`) until its turn, so the phrase keeps its final
+/// layout while letters appear one by one. Each word's letters are spread across its own
+/// contiguous timing window, and the segment stays a single Dialogue line per layer.
+/// Tradeoff: one override tag per character makes lines longer and libass slightly slower.
+#[allow(clippy::too_many_arguments)]
+fn assemble_typewriter_line(
+    tokens: &[String], spans: &[WordSpan], hi: usize,
+    white_bgr: &str, hi_bgr: &str,
+    header: &str,
+    font_size: u32,
+    lead_cs: i64
+) -> String {
+    let white = format!("{{\\1c&H{}&\\fs{}}}", white_bgr, font_size);
+    let big_font_size = (font_size as f32 * BIG_FONT_SIZE_MULTIPLIER) as u32;
+    let hi_style = format!("{{\\1c&H{}&\\fs{}}}", hi_bgr, big_font_size);
+    let windows = contiguous_cs_windows(spans);
+
+    let mut s = String::from(header);
+    s.push_str(r"{\2a&HFF&}");
+    if lead_cs > 0 {
+        // Line shown early (lead-in): hold the reveal until the first word is spoken
+        s.push_str(&format!("{{\\ko{}}}", lead_cs));
+    }
+    for (i, token) in tokens.iter().enumerate() {
+        s.push_str(if i == hi { &hi_style } else { &white });
+
+        let chars: Vec<char> = token.chars().collect();
+        let (cs0, cs1) = windows.get(i).copied().unwrap_or((0, 1));
+        let chunk_len = chars.len().div_ceil(TYPEWRITER_MAX_STEPS_PER_WORD).max(1);
+        let steps = chars.len().div_ceil(chunk_len).max(1);
+        let per_step = (cs1 - cs0) / steps as i64;
+
+        for (step, chunk) in chars.chunks(chunk_len).enumerate() {
+            // Last chunk absorbs the rounding remainder so windows stay contiguous
+            let dur = if step + 1 == steps {
+                (cs1 - cs0) - per_step * step as i64
+            } else {
+                per_step
+            };
+            let text: String = chunk.iter().collect();
+            s.push_str(&format!("{{\\ko{}}}{}", dur.max(0), escape_ass_text(&text)));
+        }
         if i + 1 < tokens.len() { s.push(' '); }
     }
     s
 }
 
+/// Karaoke fill body: each token is a `\kf` syllable timed by its contiguous window, so the
+/// active color (`\1c`) sweeps over the upcoming color (`\2c`) word by word. `lead_cs` holds
+/// the sweep while the line is shown early. `\N` goes before every index in `breaks`.
+#[allow(clippy::too_many_arguments)]
+fn assemble_karaoke_fill_line(
+    tokens: &[String], spans: &[WordSpan],
+    active_bgr: &str, future_bgr: &str,
+    breaks: &[usize],
+    header: &str,
+    font_size: u32,
+    lead_cs: i64
+) -> String {
+    let mut s = String::from(header);
+    s.push_str(&format!("{{\\1c&H{}&\\2c&H{}&\\fs{}}}", active_bgr, future_bgr, font_size));
+    if lead_cs > 0 {
+        s.push_str(&format!("{{\\k{}}}", lead_cs));
+    }
+    for (i, (token, (cs0, cs1))) in tokens.iter().zip(contiguous_cs_windows(spans)).enumerate() {
+        if i > 0 && breaks.contains(&i) { s.push_str(r"\N"); }
+        s.push_str(&format!("{{\\kf{}}}{}", cs1 - cs0, escape_ass_text(token)));
+        if i + 1 < tokens.len() && !breaks.contains(&(i + 1)) { s.push(' '); }
+    }
+    s
+}
+
 struct AssStyle {
     font_name: String,
     font_size: u32,
@@ -640,15 +2328,166 @@ struct AssStyle {
     outline_w: u32,
     shadow: u32,
     align: u32,    // 1..9 grid; 2 = bottom-center
+    margin_h: u32, // pixels (MarginL/MarginR, anchor for left/right aligned text)
     margin_v: u32, // pixels
     highlight: String,   // green for current word
+    typewriter: bool,    // reveal non-karaoke captions letter by letter
+    rotation_deg: f32,   // \frz angle (0 = upright)
+    safe_top: u32,       // pixels kept clear at the top (middle row centers in the safe band)
+    safe_bottom: u32,    // pixels kept clear at the bottom for the middle row
+    safe_h: u32,         // pixels kept clear on each side
+    lead_in_ms: u64,     // captions appear this much before their first word
+    broadcast: Option<BroadcastRules>, // subtitle timing rules (non-karaoke)
+    hook: Option<(u64, f32)>, // (window ms, size multiplier) for captions starting in the opening hook
+    max_chars_per_line: Option<usize>, // fixed wrap width in characters (None = estimate from font metrics)
+    max_lines: usize,    // wrapped rows stacked per caption (1 = single line)
+    karaoke_fill: bool,  // karaoke words fill progressively (\kf) within one line per segment
+    text_case: TextCase, // casing applied to displayed tokens
+    speaker_colors: Vec<String>, // BBGGRR fill per speaker index, cycled (empty = primary for everyone)
+    max_highlights: Option<u32>, // absolute smart-highlight budget for the whole video
+    highlight_intensity: f32,    // 0..1 smart-highlight aggressiveness (scales threshold and ratio cap)
+    karaoke_colors: (Option<String>, Option<String>, Option<String>), // BBGGRR (past, active, future) overrides
+    background_box: Option<(String, u32)>, // (&HAABBGGRR box color, padding px) behind the text
+    fade_ms: u32,        // \fad in/out duration per caption (0 = hard cut)
+}
+
+impl AssStyle {
+    /// \pos X for the alignment column: left/right text anchors at the horizontal margin
+    fn pos_x(&self, frame_w: u32) -> i32 {
+        match self.align % 3 {
+            1 => self.margin_h as i32,                        // left column (1/4/7)
+            0 => frame_w as i32 - self.margin_h as i32,       // right column (3/6/9)
+            _ => (frame_w / 2) as i32,                        // center column (2/5/8)
+        }
+    }
+
+    /// \pos Y for the alignment row
+    fn pos_y(&self, frame_h: u32) -> i32 {
+        match self.align {
+            4..=6 => ((self.safe_top + frame_h.saturating_sub(self.safe_bottom)) / 2) as i32, // Middle row - center of safe band
+            7..=9 => self.margin_v as i32,                                // Top row - margin from the top
+            _ => (frame_h as i32 - self.margin_v as i32).max(0),         // Bottom row - use margin
+        }
+    }
+
+    /// Fill color (BBGGRR) for a line: its speaker's palette entry, or the primary color
+    fn fill_bgr(&self, spans: &[WordSpan]) -> String {
+        match spans.iter().find_map(|s| s.speaker) {
+            Some(speaker) if !self.speaker_colors.is_empty() => {
+                self.speaker_colors[speaker as usize % self.speaker_colors.len()].clone()
+            }
+            _ => bgr_from_aa_bgrr(&self.primary),
+        }
+    }
+
+    /// Karaoke (past, active, future) colors for a line: sung words dim to 60% of the fill
+    /// unless overridden, the active word uses the highlight color
+    fn karaoke_bgr(&self, spans: &[WordSpan]) -> (String, String, String) {
+        let (past, active, future) = &self.karaoke_colors;
+        let future = future.clone().unwrap_or_else(|| self.fill_bgr(spans));
+        let past = past.clone().unwrap_or_else(|| dim_bgr(&future, KARAOKE_PAST_DIM));
+        let active = active.clone().unwrap_or_else(|| bgr_from_aa_bgrr(&self.highlight));
+        (past, active, future)
+    }
+
+    /// Base font size for a caption starting at `start_ms` (boosted inside the hook window)
+    fn font_size_at(&self, start_ms: u64) -> u32 {
+        match self.hook {
+            Some((window_ms, scale)) if start_ms < window_ms => (self.font_size as f32 * scale).round() as u32,
+            _ => self.font_size,
+        }
+    }
+
+    /// Width available for a caption line; left/right text starts at a margin so it gets less room
+    /// Rotated text also needs room for its tilted extent (L·cosθ + H·sinθ must fit)
+    fn line_width(&self, frame_w: u32) -> u32 {
+        let base = if self.align % 3 == 2 { frame_w.saturating_sub(2 * self.safe_h).max(1) } else { frame_w.saturating_sub(2 * self.margin_h).max(1) };
+        if self.rotation_deg == 0.0 {
+            return base;
+        }
+        let theta = self.rotation_deg.abs().to_radians();
+        let fitted = (base as f32 - self.font_size as f32 * theta.sin()) / theta.cos();
+        fitted.min(base as f32).max(self.font_size as f32) as u32
+    }
+
+    /// Rotation tags pivoting around the caption's visual center rather than its anchor point
+    fn rotation_tag(&self, x: i32, y: i32) -> String {
+        if self.rotation_deg == 0.0 {
+            return String::new();
+        }
+        let half = (self.font_size / 2) as i32;
+        let center_y = match self.align {
+            4..=6 => y,         // middle row: \pos is already the center
+            7..=9 => y + half,  // top row: anchor is the top edge
+            _ => y - half,      // bottom row: anchor is the baseline edge
+        };
+        format!("\\org({},{})\\frz{:.1}", x, center_y, self.rotation_deg)
+    }
+
+    /// Earlier start for a caption line (in cs) so it can be read before it's spoken.
+    /// Only the start moves: never before 0 or the end of the previous caption line.
+    fn lead_in_start_cs(&self, start_cs: i64, prev_end_cs: i64) -> i64 {
+        let lead_cs = ms_to_cs(self.lead_in_ms);
+        (start_cs - lead_cs).max(prev_end_cs).max(0).min(start_cs)
+    }
+
+    /// \fad tag for an event lasting `dur_cs`, fading in and/or out. Fades are shortened to fit
+    /// the event (half of it each when both apply) so the text is fully visible at some point.
+    fn fade_tag(&self, fade_in: bool, fade_out: bool, dur_cs: i64) -> String {
+        if self.fade_ms == 0 || !(fade_in || fade_out) {
+            return String::new();
+        }
+        let dur_ms = (dur_cs.max(0) * 10) as u32;
+        let limit = if fade_in && fade_out { dur_ms / 2 } else { dur_ms };
+        let ms = self.fade_ms.min(limit);
+        format!("\\fad({},{})", if fade_in { ms } else { 0 }, if fade_out { ms } else { 0 })
+    }
+
+    /// Apply platform safe-area insets (percent of frame) on top of the position defaults.
+    /// Bottom-row captions move up to clear the bottom inset; centered ones center in the band.
+    fn apply_safe_area(&mut self, frame_w: u32, frame_h: u32, top_pct: Option<f32>, bottom_pct: Option<f32>, horizontal_pct: Option<f32>) {
+        let px = |total: u32, pct: f32| (total as f32 * pct.clamp(0.0, 45.0) / 100.0).round() as u32;
+        if let Some(top) = top_pct {
+            self.safe_top = px(frame_h, top);
+            if (7..=9).contains(&self.align) {
+                self.margin_v = self.margin_v.max(self.safe_top);
+            }
+        }
+        if let Some(bottom) = bottom_pct {
+            self.safe_bottom = px(frame_h, bottom);
+            if (1..=3).contains(&self.align) {
+                self.margin_v = pct_to_margin_v(frame_h, 100.0 - bottom.clamp(0.0, 45.0));
+            }
+        }
+        if let Some(horizontal) = horizontal_pct {
+            self.safe_h = px(frame_w, horizontal);
+            self.margin_h = self.margin_h.max(self.safe_h);
+        }
+    }
+
+    /// Explicit placement in percent of the frame; wins over the position keyword and safe areas.
+    /// Bottom-row captions sit on the line `v_pct` down, top-row ones hang from it (middle-row
+    /// captions stay centered, as ASS ignores MarginV for them).
+    fn apply_margin_pct(&mut self, frame_w: u32, frame_h: u32, v_pct: Option<f32>, h_pct: Option<f32>) {
+        if let Some(v) = v_pct.map(|v| v.clamp(0.0, 100.0)) {
+            match self.align {
+                1..=3 => self.margin_v = pct_to_margin_v(frame_h, v),
+                7..=9 => self.margin_v = (frame_h as f32 * v / 100.0).round() as u32,
+                _ => {}
+            }
+        }
+        if let Some(h) = h_pct.map(|h| h.clamp(0.0, 45.0)) {
+            self.margin_h = (frame_w as f32 * h / 100.0).round() as u32;
+            // centered lines wrap within the side insets
+            self.safe_h = self.margin_h;
+        }
+    }
 }
 
 fn pct_to_margin_v(frame_h: u32, y_pct_from_top: f32) -> u32 {
     // bottom-aligned: margin_v measured from bottom
     let y = (frame_h as f32 * (y_pct_from_top / 100.0)).round() as i32;
-    let margin_from_bottom = (frame_h as i32 - y).max(0) as u32;
-    margin_from_bottom
+    (frame_h as i32 - y).max(0) as u32
 }
 
 fn stopwords() -> &'static HashSet<&'static str> {
@@ -724,10 +2563,16 @@ struct HighlightState {
     last_hl_phrase: Option<usize>,
     phrases_done: u32,
     phrases_hl: u32,
+    max_highlights: Option<u32>,      // once reached, nothing else is highlighted
+    base_threshold: f32,              // score a word needs before any penalties
+    max_ratio: f32,                   // share of phrases highlighted before the threshold rises
 }
 
 impl HighlightState {
-    fn new(segments: &[CaptionSegment]) -> Self {
+    /// `intensity` maps linearly onto the threshold and ratio cap: 0 gives a high bar and no
+    /// ratio allowance, the default reproduces `HL_BASE_T`/`HL_MAX_RATIO`, 1 lets most lines win
+    fn new(segments: &[CaptionSegment], max_highlights: Option<u32>, intensity: f32) -> Self {
+        let scale = intensity.clamp(0.0, 1.0) / HL_DEFAULT_INTENSITY;
         Self {
             tf: build_global_tf(segments),
             recent: VecDeque::new(),
@@ -735,9 +2580,16 @@ impl HighlightState {
             last_hl_phrase: None,
             phrases_done: 0,
             phrases_hl: 0,
+            max_highlights,
+            base_threshold: HL_BASE_T * (2.0 - scale),
+            max_ratio: HL_MAX_RATIO * scale,
         }
     }
 
+    fn budget_spent(&self) -> bool {
+        self.max_highlights.is_some_and(|max| self.phrases_hl >= max)
+    }
+
     fn push_recent_phrase(&mut self, tokens: &[String], end_ms: u64) {
         // drop old
         while let Some((_, t)) = self.recent.front().cloned() {
@@ -760,30 +2612,45 @@ fn choose_highlight_idx(
     tokens_orig: &[String],
     spans: &[WordSpan],
     phrase_idx: usize,
-    st: &mut HighlightState
+    st: &mut HighlightState,
+    mut explain: Option<&mut HighlightLineAnalysis>
 ) -> Option<usize> {
     let sw = stopwords();
     let pw = power_words();
 
     // rarity controls
-    let mut threshold = HL_BASE_T;
+    let mut threshold = st.base_threshold;
     let phrase_start = spans.first().map(|w| w.start_ms).unwrap_or(0);
     let phrase_end   = spans.last().map(|w| w.end_ms).unwrap_or(0);
 
+    // Editor overrides: an explicitly flagged word wins outright (one highlight per line, so the first)
+    if let Some(idx) = spans.iter().position(|w| w.highlight == Some(true)) {
+        st.phrases_done += 1;
+        st.phrases_hl += 1;
+        st.last_hl_ms = Some(phrase_end);
+        st.last_hl_phrase = Some(phrase_idx);
+        st.push_recent_phrase(tokens_orig, phrase_end);
+        return Some(idx);
+    }
+
     if let Some(last) = st.last_hl_ms {
         if phrase_start.saturating_sub(last) < HL_MIN_GAP_MS { threshold += 1.0; }
     }
     if st.last_hl_phrase.map(|p| p + 1 == phrase_idx).unwrap_or(false) {
         threshold += HL_HYSTERESIS; // avoid back-to-back
     }
-    if st.phrases_done > 0 && (st.phrases_hl as f32) / (st.phrases_done as f32) >= HL_MAX_RATIO {
+    if st.phrases_done > 0 && (st.phrases_hl as f32) / (st.phrases_done as f32) >= st.max_ratio {
         threshold += 0.8; // too many already
     }
+    if st.budget_spent() {
+        threshold = f32::MAX; // absolute budget used up: scores are still reported, nothing wins
+    }
+    if let Some(ex) = explain.as_deref_mut() { ex.threshold = threshold; }
 
-    // candidates
+    // candidates (words flagged highlight=false are never picked)
     let cand: Vec<usize> = (0..tokens_orig.len()).filter(|&i| {
         let t = tokens_orig[i].trim();
-        if t.is_empty() { return false; }
+        if t.is_empty() || spans.get(i).is_some_and(|w| w.highlight == Some(false)) { return false; }
         let low = t.to_lowercase();
         if sw.contains(low.as_str()) { return false; }
         t.len() >= 3 || has_digit_or_currency(t)
@@ -808,59 +2675,464 @@ fn choose_highlight_idx(
     for &i in &cand {
         let t = tokens_orig[i].trim();
         let low = t.to_lowercase();
-        let mut s = 0.0;
+        let mut sc = HighlightScore { token_index: i, token: t.to_string(), ..Default::default() };
+
+        if has_digit_or_currency(t) { sc.digit_currency = 3.0; }
+        if st.tf.get(&low).copied().unwrap_or(0) <= 2 { sc.rarity = 2.0; }
+        if looks_proper_noun(t, i) { sc.proper_noun = 1.5; }
+        if pw.contains(low.as_str()) { sc.power_word = 1.5; }
+        if ends_with_content_suffix(t) { sc.content_suffix = 1.0; }
+        if (t.len() as f32) > med_len { sc.length = 1.0; }
+
+        if std_dur > 0.0 {
+            let z = (durs[i] - mean_dur) / std_dur;
+            sc.duration = 0.5 * z.max(0.0); // only reward longer-than-avg
+        }
+
+        // pause / phrase-final emphasis
+        if i + 1 == spans.len() { sc.pause = 0.5; }
+        else {
+            let gap = spans[i+1].start_ms.saturating_sub(spans[i].end_ms);
+            if gap >= 250 { sc.pause = 0.5; }
+        }
+
+        // penalties
+        if st.recent_count(&low, phrase_end) > 3 { sc.repetition_penalty = -2.0; }
+        if t.chars().all(|c| c.is_uppercase()) && !tokens_orig.iter().all(|w| w.chars().all(|c| c.is_uppercase())) {
+            sc.caps_penalty = -1.0;
+        }
+
+        let s = sc.digit_currency + sc.rarity + sc.proper_noun + sc.power_word + sc.content_suffix
+            + sc.length + sc.duration + sc.pause + sc.repetition_penalty + sc.caps_penalty;
+        sc.total = s;
+        if let Some(ex) = explain.as_deref_mut() { ex.scores.push(sc); }
+
+        // tie-breakers inline
+        if s >= threshold {
+            match best {
+                None => best = Some((i,s)),
+                Some((bi,bs)) => {
+                    if (s > bs)
+                        || (s == bs && i > bi)              // later in phrase
+                        || (s == bs && durs[i] > durs[bi])   // longer held
+                        || (s == bs && st.tf.get(&low).unwrap_or(&u32::MAX) < st.tf.get(&tokens_orig[bi].to_lowercase()).unwrap_or(&u32::MAX))
+                    { best = Some((i,s)); }
+                }
+            }
+        }
+    }
+
+    st.phrases_done += 1;
+    st.push_recent_phrase(tokens_orig, phrase_end);
+
+    if let Some((idx,_)) = best {
+        st.phrases_hl += 1;
+        st.last_hl_ms = Some(phrase_end);
+        st.last_hl_phrase = Some(phrase_idx);
+        Some(idx)
+    } else {
+        None
+    }
+}
+
+/// One non-karaoke caption line with its smart-highlight decision
+struct PlannedLine {
+    tokens: Vec<String>,        // normalized tokens for display
+    spans: Vec<WordSpan>,       // timings per token
+    highlight: Option<usize>,   // token to emphasize
+    analysis: Option<HighlightLineAnalysis>,
+    start_ms: u64,              // cue on-screen time (defaults to the word span)
+    end_ms: u64,
+    breaks: Vec<usize>,         // token indices starting each extra row (empty = single row)
+}
+
+/// Coalesce segments into phrases, split them to fit the line width and pick highlights.
+/// Shared by rendering and `analyzeHighlights`, so given the same caption params and canvas a
+/// preview splits lines and picks highlights exactly as the burned output does.
+fn plan_highlighted_lines(segments: &[CaptionSegment], style: &AssStyle, frame_w: u32, explain: bool) -> Vec<PlannedLine> {
+    let phrases = coalesce_phrases(segments);
+
+    // state for smart highlighting
+    let mut hl_state = HighlightState::new(segments, style.max_highlights, style.highlight_intensity);
+    let mut out = Vec::new();
+
+    for (p_idx, phrase) in phrases.iter().enumerate() {
+        let tokens_display = normalize_tokens(&phrase.spans, style.text_case);
+
+        // Split phrase into width-fitted rows and stack them, same as karaoke mode
+        let rows = split_phrase_for_width(&tokens_display, &phrase.spans, style.line_width(frame_w), style.font_size_at(phrase.start_ms), &style.font_name, style.max_chars_per_line, style.text_case);
+
+        for (line_tokens, line_spans, breaks) in stack_rows(rows, style.max_lines) {
+            let line_tokens_orig = original_tokens(&line_spans);
+            let mut analysis = explain.then(|| HighlightLineAnalysis {
+                phrase_index: p_idx,
+                start_ms: line_spans.first().map(|w| w.start_ms).unwrap_or(0),
+                end_ms: line_spans.last().map(|w| w.end_ms).unwrap_or(0),
+                tokens: line_tokens_orig.clone(),
+                ..Default::default()
+            });
+
+            // Decide which single word (if any) to highlight in this line
+            let highlight = choose_highlight_idx(&line_tokens_orig, &line_spans, p_idx, &mut hl_state, analysis.as_mut());
+            if let Some(a) = analysis.as_mut() { a.highlight_index = highlight; }
+
+            let start_ms = line_spans.first().map(|w| w.start_ms).unwrap_or(0);
+            let end_ms = line_spans.last().map(|w| w.end_ms).unwrap_or(0);
+            out.push(PlannedLine {
+                tokens: line_tokens, spans: line_spans, highlight, analysis,
+                start_ms, end_ms, breaks,
+            });
+        }
+    }
+    out
+}
+
+/// Post-process planned cues to broadcast subtitle rules, in order: split cues longer than the
+/// max duration at word boundaries, merge too-short cues into the next one as an extra row
+/// (up to `max_lines`, not for typewriter), stretch remaining short cues toward the min duration,
+/// then trim ends so consecutive cues keep the minimum gap.
+fn apply_broadcast_rules(lines: Vec<PlannedLine>, rules: &BroadcastRules, allow_merge: bool) -> Vec<PlannedLine> {
+    let max_ms = rules.max_duration_ms.max(rules.min_duration_ms).max(1);
+
+    // 1. Split too-long cues into time-balanced parts
+    let mut split = Vec::with_capacity(lines.len());
+    for line in lines {
+        let dur = line.end_ms.saturating_sub(line.start_ms);
+        if dur <= max_ms || line.tokens.len() < 2 {
+            let mut line = line;
+            line.end_ms = line.end_ms.min(line.start_ms + max_ms);
+            split.push(line);
+            continue;
+        }
+        let parts = (dur.div_ceil(max_ms) as usize).min(line.tokens.len());
+        let mut from = 0;
+        for part in 1..=parts {
+            let boundary = line.start_ms + dur * part as u64 / parts as u64;
+            // Cut before the first word starting after this part's time budget
+            let mut to = if part == parts { line.tokens.len() } else {
+                line.spans.iter().position(|w| w.start_ms >= boundary).unwrap_or(line.tokens.len())
+            };
+            to = to.max(from + 1).min(line.tokens.len() - (parts - part));
+            if to <= from { continue; }
+            let spans = line.spans[from..to].to_vec();
+            let highlight = line.highlight.filter(|&h| h >= from && h < to).map(|h| h - from);
+            split.push(PlannedLine {
+                tokens: line.tokens[from..to].to_vec(),
+                start_ms: if from == 0 { line.start_ms } else { spans[0].start_ms },
+                end_ms: if to == line.tokens.len() { line.end_ms } else { line.spans[to].start_ms },
+                spans,
+                highlight,
+                analysis: None,
+                breaks: line.breaks.iter().filter(|&&b| b > from && b < to).map(|b| b - from).collect(),
+            });
+            from = to;
+        }
+    }
+
+    // 2. Merge too-short cues with the following one as an extra row
+    let mut merged: Vec<PlannedLine> = Vec::with_capacity(split.len());
+    for line in split {
+        if let Some(prev) = merged.last_mut() {
+            let prev_short = prev.end_ms.saturating_sub(prev.start_ms) < rules.min_duration_ms;
+            let rows = prev.breaks.len() + 1 + line.breaks.len() + 1;
+            let fits = line.end_ms.saturating_sub(prev.start_ms) <= max_ms;
+            if allow_merge && prev_short && rows <= rules.max_lines as usize && fits {
+                let offset = prev.tokens.len();
+                prev.breaks.push(offset);
+                prev.breaks.extend(line.breaks.iter().map(|b| b + offset));
+                if prev.highlight.is_none() {
+                    prev.highlight = line.highlight.map(|h| h + offset);
+                }
+                prev.tokens.extend(line.tokens);
+                prev.spans.extend(line.spans);
+                prev.end_ms = line.end_ms;
+                continue;
+            }
+        }
+        merged.push(line);
+    }
+
+    // 3. Stretch short (or too fast to read) cues, 4. keep the minimum gap before the next cue
+    for i in 0..merged.len() {
+        let next_start = merged.get(i + 1).map(|n| n.start_ms);
+        let line = &mut merged[i];
+        let reading_ms = rules.max_chars_per_second
+            .filter(|cps| *cps > 0.0)
+            .map(|cps| (line.tokens.join(" ").chars().count() as f32 / cps * 1000.0).ceil() as u64)
+            .unwrap_or(0);
+        let min_ms = rules.min_duration_ms.max(reading_ms).min(max_ms);
+        if line.end_ms.saturating_sub(line.start_ms) < min_ms {
+            let wanted = line.start_ms + min_ms;
+            line.end_ms = match next_start {
+                Some(next) => wanted.min(next.saturating_sub(rules.min_gap_ms)).max(line.end_ms),
+                None => wanted,
+            };
+        }
+        if let Some(next) = next_start {
+            if line.end_ms + rules.min_gap_ms > next {
+                line.end_ms = next.saturating_sub(rules.min_gap_ms).max(line.start_ms + 10);
+            }
+        }
+    }
+    merged
+}
+
+/// Public RPC method to preview smart-highlight decisions (and their score breakdown) without rendering
+pub async fn analyze_highlights_rpc(
+    id: &str,
+    params: AnalyzeHighlightsParams,
+    emit: impl FnMut(RpcEvent)
+) -> Result<AnalyzeHighlightsResult> {
+    // Styling is parsed up front so a typo fails before transcription
+    let caption_params = caption_params_for_analysis(params.caption_params)?;
+
+    let segments = match (params.segments, params.transcription) {
+        (Some(segments), _) => segments,
+        (None, Some(mut transcription)) => {
+            // Only the segments are needed; don't leave a JSON export next to the audio
+            transcription.write_transcript_json = Some(false);
+            whisper::transcribe_segments(id, transcription, emit).await?.segments
+        }
+        (None, None) => return Err(anyhow!("Either segments or transcription params must be provided")),
+    };
+
+    // Karaoke captions color every word in turn and never pick a smart highlight
+    if caption_params.karaoke {
+        return Ok(AnalyzeHighlightsResult { lines: Vec::new() });
+    }
+
+    let w = params.width.unwrap_or(1080);
+    let h = params.height.unwrap_or(1920);
+    let mut style = caption_style(&caption_params, w, h);
+    if params.max_highlights.is_some() {
+        style.max_highlights = params.max_highlights;
+    }
+    if let Some(intensity) = params.highlight_intensity {
+        style.highlight_intensity = intensity.clamp(0.0, 1.0);
+    }
+
+    let lines = plan_highlighted_lines(&segments, &style, w, true)
+        .into_iter()
+        .filter_map(|line| line.analysis)
+        .collect();
+
+    Ok(AnalyzeHighlightsResult { lines })
+}
+
+/// `analyzeHighlights`' `captionParams` as generateCaptions params; the input video and the
+/// required flags are optional here since nothing is transcribed or rendered
+fn caption_params_for_analysis(value: Option<serde_json::Value>) -> Result<GenerateCaptionsParams> {
+    let mut map = match value {
+        Some(serde_json::Value::Object(map)) => map,
+        None | Some(serde_json::Value::Null) => serde_json::Map::new(),
+        Some(_) => return Err(anyhow!("captionParams must be an object of generateCaptions params")),
+    };
+    map.entry("inputVideo").or_insert_with(|| "".into());
+    map.entry("karaoke").or_insert(false.into());
+    map.entry("splitByWords").or_insert(true.into());
+    serde_json::from_value(serde_json::Value::Object(map)).map_err(|e| anyhow!("Invalid captionParams: {}", e))
+}
 
-        if has_digit_or_currency(t) { s += 3.0; }
-        if st.tf.get(&low).copied().unwrap_or(0) <= 2 { s += 2.0; }
-        if looks_proper_noun(t, i) { s += 1.5; }
-        if pw.contains(low.as_str()) { s += 1.5; }
-        if ends_with_content_suffix(t) { s += 1.0; }
-        if (t.len() as f32) > med_len { s += 1.0; }
+/// WebVTT timestamp (HH:MM:SS.mmm)
+fn ms_to_vtt(ms: u64) -> String {
+    format!("{:02}:{:02}:{:02}.{:03}", ms / 3_600_000, (ms % 3_600_000) / 60_000, (ms % 60_000) / 1000, ms % 1000)
+}
 
-        if std_dur > 0.0 {
-            let z = (durs[i] - mean_dur) / std_dur;
-            s += 0.5 * z.max(0.0); // only reward longer-than-avg
-        }
+/// SRT timestamp (HH:MM:SS,mmm)
+fn ms_to_srt(ms: u64) -> String {
+    ms_to_vtt(ms).replacen('.', ",", 1)
+}
 
-        // pause / phrase-final emphasis
-        if i + 1 == spans.len() { s += 0.5; }
-        else {
-            let gap = spans[i+1].start_ms.saturating_sub(spans[i].end_ms);
-            if gap >= 250 { s += 0.5; }
-        }
+/// Plain SRT document, one cue per segment
+pub(crate) fn build_srt(segments: &[CaptionSegment]) -> String {
+    let mut out = String::new();
+    for (n, seg) in segments.iter().enumerate() {
+        out.push_str(&format!("{}\n{} --> {}\n{}\n\n", n + 1, ms_to_srt(seg.start_ms), ms_to_srt(seg.end_ms), seg.text.trim()));
+    }
+    out
+}
 
-        // penalties
-        if st.recent_count(&low, phrase_end) > 3 { s -= 2.0; }
-        if t.chars().all(|c| c.is_uppercase()) && !tokens_orig.iter().all(|w| w.chars().all(|c| c.is_uppercase())) {
-            s -= 1.0;
+/// WebVTT document, one cue per segment; with `word_level`, segments that carry word
+/// timing get `<timestamp><c>word</c>` tags so players can follow along word by word
+pub(crate) fn build_segment_vtt(segments: &[CaptionSegment], word_level: bool) -> String {
+    let mut out = String::from("WEBVTT\n\n");
+    for (n, seg) in segments.iter().enumerate() {
+        out.push_str(&format!("{}\n{} --> {}\n", n + 1, ms_to_vtt(seg.start_ms), ms_to_vtt(seg.end_ms)));
+        if word_level && !seg.words.is_empty() {
+            let mut last_ms = seg.start_ms;
+            for (i, word) in seg.words.iter().enumerate() {
+                if i > 0 {
+                    out.push(' ');
+                    let at = word.start_ms.clamp(last_ms, seg.end_ms);
+                    if at > seg.start_ms && at < seg.end_ms {
+                        out.push_str(&format!("<{}>", ms_to_vtt(at)));
+                    }
+                    last_ms = at;
+                }
+                out.push_str(&format!("<c>{}</c>", escape_vtt(word.text.trim())));
+            }
+        } else {
+            out.push_str(&escape_vtt(seg.text.trim()));
         }
+        out.push_str("\n\n");
+    }
+    out
+}
 
-        // tie-breakers inline
-        if s >= threshold {
-            match best {
-                None => best = Some((i,s)),
-                Some((bi,bs)) => {
-                    if (s > bs)
-                        || (s == bs && i > bi)              // later in phrase
-                        || (s == bs && durs[i] > durs[bi])   // longer held
-                        || (s == bs && st.tf.get(&low).unwrap_or(&u32::MAX) < st.tf.get(&tokens_orig[bi].to_lowercase()).unwrap_or(&u32::MAX))
-                    { best = Some((i,s)); }
+/// Soft-subtitle version of the highlighted captions: one cue per planned line, with
+/// `<timestamp><c>word</c>` karaoke tags and the smart-highlight word in `<c.highlight>`
+fn build_word_level_vtt(lines: &[PlannedLine], highlight_bgr: &str) -> String {
+    let mut out = String::from("WEBVTT\n\n");
+    if highlight_bgr.len() == 6 {
+        let (b, g, r) = (&highlight_bgr[0..2], &highlight_bgr[2..4], &highlight_bgr[4..6]);
+        out.push_str(&format!("STYLE\n::cue(.highlight) {{ color: #{}{}{}; }}\n\n", r, g, b));
+    }
+
+    for (n, line) in lines.iter().enumerate() {
+        out.push_str(&format!("{}\n{} --> {}\n", n + 1, ms_to_vtt(line.start_ms), ms_to_vtt(line.end_ms)));
+        // Inline timestamps must increase and fall strictly inside the cue
+        let mut last_ms = line.start_ms;
+        for (i, (token, span)) in line.tokens.iter().zip(&line.spans).enumerate() {
+            if i > 0 {
+                out.push(if line.breaks.contains(&i) { '\n' } else { ' ' });
+                let at = span.start_ms.clamp(last_ms, line.end_ms);
+                if at > line.start_ms && at < line.end_ms {
+                    out.push_str(&format!("<{}>", ms_to_vtt(at)));
                 }
+                last_ms = at;
             }
+            let class = if line.highlight == Some(i) { "c.highlight" } else { "c" };
+            out.push_str(&format!("<{}>{}</c>", class, escape_vtt(token)));
         }
+        out.push_str("\n\n");
     }
+    out
+}
 
-    st.phrases_done += 1;
-    st.push_recent_phrase(tokens_orig, phrase_end);
+fn escape_vtt(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
 
-    if let Some((idx,_)) = best {
-        st.phrases_hl += 1;
-        st.last_hl_ms = Some(phrase_end);
-        st.last_hl_phrase = Some(phrase_idx);
-        Some(idx)
+/// Write `{sidecar_base}.vtt` with word-level karaoke tags, planned for the first export canvas
+fn write_word_level_vtt(
+    params: &GenerateCaptionsParams,
+    sidecar_base: &str,
+    segments: &[CaptionSegment],
+    probe_result: &crate::video::ProbeResult,
+    rounding: crate::video::EvenRounding
+) -> Result<String> {
+    let (_, target_w, target_h) = resolve_export_targets(&params.export_formats, probe_result, rounding, params.max_output_height)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("No export formats to plan captions for"))?;
+    let style = caption_style(params, target_w, target_h);
+    let mut lines = plan_highlighted_lines(segments, &style, target_w, false);
+    if let Some(rules) = &style.broadcast {
+        lines = apply_broadcast_rules(lines, rules, true);
+    }
+
+    let vtt_path = input_sidecar_path(sidecar_base, "vtt")?;
+    fs::write(&vtt_path, build_word_level_vtt(&lines, &bgr_from_aa_bgrr(&style.highlight)))?;
+    Ok(vtt_path.to_string_lossy().to_string())
+}
+
+/// FCPXML frame duration as a rational `num/den` seconds; NTSC rates use the exact 1001 forms
+fn fcpxml_frame_duration(fps: f64) -> (u64, u64) {
+    for (nominal, den) in [(23.976, 24000), (29.97, 30000), (47.952, 48000), (59.94, 60000)] {
+        if (fps - nominal).abs() < 0.01 {
+            return (1001, den);
+        }
+    }
+    if (fps - fps.round()).abs() < 0.01 {
+        (100, (fps.round() as u64).max(1) * 100)
     } else {
-        None
+        (100, ((fps * 100.0).round() as u64).max(1))
+    }
+}
+
+/// FCPXML time for `ms`, snapped to a whole frame of the given frame duration
+fn fcpxml_time(ms: u64, (num, den): (u64, u64)) -> String {
+    let frames = ((ms as f64 / 1000.0) * den as f64 / num as f64).round() as u64;
+    if frames == 0 {
+        "0s".to_string()
+    } else {
+        format!("{}/{}s", frames * num, den)
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// file:// URL with everything but unreserved path characters percent-encoded
+fn file_url(path: &Path) -> String {
+    let mut url = String::from("file://");
+    for b in path.to_string_lossy().bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => url.push(b as char),
+            _ => url.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    url
+}
+
+/// Minimal FCPXML 1.9: the source as one asset clip with a marker per caption segment
+fn build_fcpxml(input_video: &Path, segments: &[CaptionSegment], probe_result: &crate::video::ProbeResult) -> String {
+    let frame = fcpxml_frame_duration(probe_result.fps.filter(|f| f.is_finite() && *f > 0.0).unwrap_or(30.0));
+    let duration_ms = probe_result.duration
+        .map(|d| (d * 1000.0) as u64)
+        .or_else(|| segments.last().map(|s| s.end_ms))
+        .unwrap_or(0);
+    let duration = fcpxml_time(duration_ms, frame);
+    let name = escape_xml(&input_video.file_stem().map(|n| n.to_string_lossy().to_string()).unwrap_or_default());
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE fcpxml>\n<fcpxml version=\"1.9\">\n");
+    xml.push_str("  <resources>\n");
+    xml.push_str(&format!(
+        "    <format id=\"r1\" frameDuration=\"{}/{}s\" width=\"{}\" height=\"{}\"/>\n",
+        frame.0, frame.1, probe_result.width.unwrap_or(1920), probe_result.height.unwrap_or(1080)
+    ));
+    xml.push_str(&format!(
+        "    <asset id=\"r2\" name=\"{}\" start=\"0s\" duration=\"{}\" hasVideo=\"1\" hasAudio=\"{}\" format=\"r1\">\n",
+        name, duration, if probe_result.audio { 1 } else { 0 }
+    ));
+    xml.push_str(&format!("      <media-rep kind=\"original-media\" src=\"{}\"/>\n", escape_xml(&file_url(input_video))));
+    xml.push_str("    </asset>\n  </resources>\n");
+    xml.push_str(&format!("  <library>\n    <event name=\"CapSlap Captions\">\n      <project name=\"{} captions\">\n", name));
+    xml.push_str(&format!(
+        "        <sequence format=\"r1\" duration=\"{}\" tcStart=\"0s\" tcFormat=\"NDF\">\n          <spine>\n",
+        duration
+    ));
+    xml.push_str(&format!(
+        "            <asset-clip ref=\"r2\" offset=\"0s\" name=\"{}\" start=\"0s\" duration=\"{}\" format=\"r1\">\n",
+        name, duration
+    ));
+    for seg in segments {
+        // Markers are at least one frame long
+        let length_ms = seg.end_ms.saturating_sub(seg.start_ms).max((frame.0 * 1000).div_ceil(frame.1));
+        xml.push_str(&format!(
+            "              <marker start=\"{}\" duration=\"{}\" value=\"{}\"/>\n",
+            fcpxml_time(seg.start_ms, frame),
+            fcpxml_time(length_ms, frame),
+            escape_xml(seg.text.trim())
+        ));
     }
+    xml.push_str("            </asset-clip>\n          </spine>\n        </sequence>\n      </project>\n    </event>\n  </library>\n</fcpxml>\n");
+    xml
+}
+
+/// Write `{sidecar_base}.fcpxml` with markers on `input_video`
+fn write_fcpxml(input_video: &str, sidecar_base: &str, segments: &[CaptionSegment], probe_result: &crate::video::ProbeResult) -> Result<String> {
+    let path = input_sidecar_path(sidecar_base, "fcpxml")?;
+    // The media reference needs an absolute path; the source exists, so canonicalize resolves it
+    let input = fs::canonicalize(input_video).unwrap_or_else(|_| PathBuf::from(input_video));
+    fs::write(&path, build_fcpxml(&input, segments, probe_result))?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// `{base}.{extension}` (base: the source video or its stand-in), validated as a write destination
+fn input_sidecar_path(base: &str, extension: &str) -> Result<PathBuf> {
+    crate::rpc::sanitize_output_path(&Path::new(base).with_extension(extension).to_string_lossy())
 }
 
 fn build_ass_document(
@@ -884,8 +3156,8 @@ ScaledBorderAndShadow: yes
 
 [V4+ Styles]
 Format: Name,Fontname,Fontsize,PrimaryColour,SecondaryColour,OutlineColour,BackColour,Bold,Italic,Underline,StrikeOut,ScaleX,ScaleY,Spacing,Angle,BorderStyle,Outline,Shadow,Alignment,MarginL,MarginR,MarginV,Encoding
-Style: TikTok,{font},{size},{pri},{sec},{out},&H64000000,0,0,0,0,100,100,0,0,1,{ow},{sh},{al},60,60,{mv},1
-
+Style: TikTok,{font},{size},{pri},{sec},{out},&H64000000,0,0,0,0,100,100,0,0,1,{ow},{sh},{al},{mh},{mh},{mv},1
+{box_style}
 [Events]
 Format: Layer,Start,End,Style,Name,MarginL,MarginR,MarginV,Effect,Text
 "#,
@@ -893,141 +3165,220 @@ Format: Layer,Start,End,Style,Name,MarginL,MarginR,MarginV,Effect,Text
         font = style.font_name, size = style.font_size,
         pri = style.primary, sec = style.secondary,
         out = style.outline, ow = style.outline_w, sh = style.shadow,
-        al = style.align, mv = style.margin_v
+        al = style.align, mh = style.margin_h, mv = style.margin_v,
+        box_style = box_style_line(style)
     );
 
     let mut lines = String::new();
 
     if karaoke {
         let phrases = coalesce_phrases(segments);
-        let white_bgr = bgr_from_aa_bgrr(&style.primary);
-        let hi_bgr    = bgr_from_aa_bgrr(&style.highlight);
 
-        // Simple single-line karaoke: split phrases that are too wide, then process each segment
+        let mut prev_end_cs = 0;
+
+        // Karaoke: split phrases that are too wide into rows, stack up to maxLines rows per
+        // segment, then process each segment
         for ph in phrases {
-            let tokens_upper = normalize_tokens(&ph.spans);
-            let segments = split_phrase_for_width(&tokens_upper, &ph.spans, w, style.font_size);
+            let tokens_display = normalize_tokens(&ph.spans, style.text_case);
+            let font_size = style.font_size_at(ph.start_ms);
+            let rows = split_phrase_for_width(&tokens_display, &ph.spans, style.line_width(w), font_size, &style.font_name, style.max_chars_per_line, style.text_case);
+            let segments = stack_rows(rows, style.max_lines);
 
-            // Calculate Y position based on alignment
-            let y_pos = match style.align {
-                5 => (h / 2) as i32, // Middle center
-                _ => (h as i32 - style.margin_v as i32).max(0), // Bottom center
-            };
+            // Calculate position based on alignment
+            let x_pos = style.pos_x(w);
+            let y_pos = style.pos_y(h);
+            let rotation = style.rotation_tag(x_pos, y_pos);
 
             // Process each width-appropriate segment
-            for (segment_tokens, segment_spans) in segments {
-                let windows = contiguous_cs_windows(&segment_spans);
+            for (segment_tokens, segment_spans, breaks) in segments {
+                let (past_bgr, active_bgr, future_bgr) = style.karaoke_bgr(&segment_spans);
+                let mut windows: Vec<(usize, i64, i64)> = contiguous_cs_windows(&segment_spans)
+                    .into_iter().enumerate().map(|(i, (cs0, cs1))| (i, cs0, cs1)).collect();
+
+                // Lead-in: show the line un-highlighted before its first word
+                let first_cs = windows[0].1;
+                let lead_cs = style.lead_in_start_cs(first_cs, prev_end_cs);
+                if lead_cs < first_cs {
+                    windows.insert(0, (usize::MAX, lead_cs, first_cs));
+                }
+                prev_end_cs = windows.last().map(|w| w.2).unwrap_or(prev_end_cs);
+
+                if style.karaoke_fill {
+                    // One event per layer for the whole segment; the \kf sweep does the timing
+                    let (start, end) = (cs_to_ass(windows[0].1), cs_to_ass(prev_end_cs));
+                    let lead = first_cs - windows[0].1;
+                    let fade = style.fade_tag(true, true, prev_end_cs - windows[0].1);
+                    let box_body = assemble_karaoke_fill_line(&segment_tokens, &segment_spans, &active_bgr, &future_bgr, &breaks, "", font_size, lead);
+                    push_box_layer(&mut lines, &start, &end, style, x_pos, y_pos, &rotation, &fade, &box_body);
+                    if glow_effect {
+                        let glow_header = format!(
+                            "{{\\an{}\\q2\\pos({},{}){}{}\\1a&HFF\\2a&HFF\\bord{}\\3c&HFFFFFF&\\3a&H80\\blur{:.1}\\shad0}}",
+                            style.align, x_pos, y_pos, rotation, fade,
+                            style.outline_w as f32 * 2.0,
+                            6.0
+                        );
+                        let glow_text = assemble_karaoke_fill_line(&segment_tokens, &segment_spans, &active_bgr, &future_bgr, &breaks, &glow_header, font_size, lead);
+                        lines.push_str(&format!("Dialogue: 0,{},{},TikTok,,0,0,0,,{}\n", start, end, glow_text));
+                    }
+                    let header = format!(
+                        "{{\\an{}\\q2\\pos({},{}){}{}\\bord{}{}}}",
+                        style.align, x_pos, y_pos, rotation, fade,
+                        style.outline_w,
+                        if glow_effect { "\\blur0\\shad0" } else { "\\blur2.0" }
+                    );
+                    let text = assemble_karaoke_fill_line(&segment_tokens, &segment_spans, &active_bgr, &future_bgr, &breaks, &header, font_size, lead);
+                    lines.push_str(&format!("Dialogue: {},{},{},TikTok,,0,0,0,,{}\n", u8::from(glow_effect), start, end, text));
+                    continue;
+                }
 
-                for (i, (cs0, cs1)) in windows.iter().enumerate() {
+                let window_count = windows.len();
+                for (w_idx, &(i, cs0, cs1)) in windows.iter().enumerate() {
                 let dur_ms = (cs1 - cs0) * 10;
                 let blur_value = if glow_effect { 6.0 } else { 2.0 };
+                // Each word is its own event: fade in on the segment's first, out on its last
+                let fade = style.fade_tag(w_idx == 0, w_idx + 1 == window_count, cs1 - cs0);
 
                 let header = format!(
-                    "{{\\an{}\\q2\\pos({},{})\\bord{}\\blur{:.1}}}{}",
-                    style.align, (w/2), y_pos,
+                    "{{\\an{}\\q2\\pos({},{}){}{}\\bord{}\\blur{:.1}}}{}",
+                    style.align, x_pos, y_pos, rotation, fade,
                     style.outline_w,
                     blur_value,
                     stretch_tag_ms(dur_ms)
                 );
 
+                let box_body = assemble_colored_lines(&segment_tokens, i, &future_bgr, &active_bgr, None, &breaks, &stretch_tag_ms(dur_ms), font_size);
+                push_box_layer(&mut lines, &cs_to_ass(cs0), &cs_to_ass(cs1), style, x_pos, y_pos, &rotation, &fade, &box_body);
+
                 if glow_effect {
                     // Glow layer
                     let glow_header = format!(
-                        "{{\\an{}\\q2\\pos({},{})\\1a&HFF\\bord{}\\3c&HFFFFFF&\\3a&H80\\blur{:.1}\\shad0}}{}",
-                        style.align, (w/2), y_pos,
+                        "{{\\an{}\\q2\\pos({},{}){}{}\\1a&HFF\\bord{}\\3c&HFFFFFF&\\3a&H80\\blur{:.1}\\shad0}}{}",
+                        style.align, x_pos, y_pos, rotation, fade,
                         style.outline_w as f32 * 2.0,
                         6.0,
                         stretch_tag_ms(dur_ms)
                     );
-                    let glow_text = assemble_colored_two_lines(&segment_tokens, i, &white_bgr, &hi_bgr, usize::MAX, &glow_header, style.font_size);
+                    let glow_text = assemble_colored_lines(&segment_tokens, i, &future_bgr, &active_bgr, Some(&past_bgr), &breaks, &glow_header, font_size);
                     lines.push_str(&format!(
                         "Dialogue: 0,{},{},TikTok,,0,0,0,,{}\n",
-                        cs_to_ass(*cs0), cs_to_ass(*cs1), glow_text
+                        cs_to_ass(cs0), cs_to_ass(cs1), glow_text
                     ));
 
                     // Main text layer
                     let main_header = format!(
-                        "{{\\an{}\\q2\\pos({},{})\\bord{}\\blur0\\shad0}}{}",
-                        style.align, (w/2), y_pos,
+                        "{{\\an{}\\q2\\pos({},{}){}{}\\bord{}\\blur0\\shad0}}{}",
+                        style.align, x_pos, y_pos, rotation, fade,
                         style.outline_w,
                         stretch_tag_ms(dur_ms)
                     );
-                    let main_text = assemble_colored_two_lines(&segment_tokens, i, &white_bgr, &hi_bgr, usize::MAX, &main_header, style.font_size);
+                    let main_text = assemble_colored_lines(&segment_tokens, i, &future_bgr, &active_bgr, Some(&past_bgr), &breaks, &main_header, font_size);
                     lines.push_str(&format!(
                         "Dialogue: 1,{},{},TikTok,,0,0,0,,{}\n",
-                        cs_to_ass(*cs0), cs_to_ass(*cs1), main_text
+                        cs_to_ass(cs0), cs_to_ass(cs1), main_text
                     ));
                 } else {
                     // Single layer
-                    let text = assemble_colored_two_lines(&segment_tokens, i, &white_bgr, &hi_bgr, usize::MAX, &header, style.font_size);
+                    let text = assemble_colored_lines(&segment_tokens, i, &future_bgr, &active_bgr, Some(&past_bgr), &breaks, &header, font_size);
                     lines.push_str(&format!(
                         "Dialogue: 0,{},{},TikTok,,0,0,0,,{}\n",
-                        cs_to_ass(*cs0), cs_to_ass(*cs1), text
+                        cs_to_ass(cs0), cs_to_ass(cs1), text
                     ));
                 }
             }
             }
         }
     } else {
-        let white_bgr = bgr_from_aa_bgrr(&style.primary);
         let hi_bgr    = bgr_from_aa_bgrr(&style.highlight);
-        let x = (w/2) as i32;
-        // Calculate Y position based on alignment
-        let y = match style.align {
-            5 => (h / 2) as i32, // Middle center - use actual center of frame
-            _ => (h as i32 - style.margin_v as i32).max(0), // Bottom center - use margin
-        };
-
-        let phrases = coalesce_phrases(segments);
-
-        // NEW: state for smart highlighting
-        let mut hl_state = HighlightState::new(segments);
-
-        for (p_idx, phrase) in phrases.iter().enumerate() {
-            let tokens_upper = normalize_tokens(&phrase.spans);
-
-            // Split phrase into single-line segments, same as karaoke mode
-            let segments = split_phrase_for_width(&tokens_upper, &phrase.spans, w, style.font_size);
-
-            for (segment_tokens, segment_spans) in segments {
-                let segment_tokens_orig = original_tokens(&segment_spans);
-
-                let start = cs_to_ass(ms_to_cs(segment_spans.first().unwrap().start_ms));
-                let end   = cs_to_ass(ms_to_cs(segment_spans.last().unwrap().end_ms));
-
-                // Decide which single word (if any) to highlight in this segment
-                let hi_opt = choose_highlight_idx(&segment_tokens_orig, &segment_spans, p_idx, &mut hl_state);
-                let hi_idx = hi_opt.unwrap_or(usize::MAX); // usize::MAX => no highlight
+        // Calculate position based on alignment
+        let x = style.pos_x(w);
+        let y = style.pos_y(h);
+        let rotation = style.rotation_tag(x, y);
+
+        let mut prev_end_cs = 0;
+        let mut planned = plan_highlighted_lines(segments, style, w, false);
+        if let Some(rules) = &style.broadcast {
+            planned = apply_broadcast_rules(planned, rules, !style.typewriter);
+        }
 
-                // Build a ONE-LINE body: only colors/sizes + entrance animation
-                // (no \pos/\bord/\shad in here; those are added by the glow/stroke layers)
-                let text_body = assemble_colored_two_lines(
-                    &segment_tokens, hi_idx, &white_bgr, &hi_bgr,
-                    usize::MAX,               // no line break
+        for line in planned {
+            let (segment_tokens, segment_spans) = (line.tokens, line.spans);
+            let white_bgr = style.fill_bgr(&segment_spans);
+
+            let first_cs = ms_to_cs(line.start_ms);
+            let start_cs = style.lead_in_start_cs(first_cs, prev_end_cs);
+            let end_cs = ms_to_cs(line.end_ms);
+            prev_end_cs = end_cs;
+            let start = cs_to_ass(start_cs);
+            let end   = cs_to_ass(end_cs);
+
+            let hi_idx = line.highlight.unwrap_or(usize::MAX); // usize::MAX => no highlight
+            let font_size = style.font_size_at(line.start_ms);
+
+            // Build a ONE-LINE body: only colors/sizes + entrance animation
+            // (no \pos/\bord/\shad in here; those are added by the glow/stroke layers)
+            let text_body = if style.typewriter {
+                assemble_typewriter_line(
+                    &segment_tokens, &segment_spans, hi_idx, &white_bgr, &hi_bgr,
+                    &bounce_tag(),
+                    font_size,
+                    first_cs - start_cs
+                )
+            } else {
+                assemble_colored_lines(
+                    &segment_tokens, hi_idx, &white_bgr, &hi_bgr, None,
+                    &line.breaks,             // stacked rows (maxLines, merged broadcast cues)
                     &bounce_tag(),            // entrance scale
-                    style.font_size
-                );
+                    font_size
+                )
+            };
 
-                // Your layered renderer (glow + black stroke + fill)
-                let glow_w    = style.outline_w as f32 * 2.0;
-                let glow_blur = 6.0;
-                let stroke_w  = style.outline_w as f32;
-
-                push_glow_and_stroke(
-                    &mut lines, &start, &end, &text_body,
-                    x, y,
-                    stroke_w,
-                    glow_effect,  // Use the parameter to control glow
-                    glow_w, glow_blur, "&H80",  // ~50% white glow
-                    style.align   // Pass the alignment from style
-                );
-            }
+            // Your layered renderer (glow + black stroke + fill)
+            let glow_w    = style.outline_w as f32 * 2.0;
+            let glow_blur = 6.0;
+            let stroke_w  = style.outline_w as f32;
+
+            let fade = style.fade_tag(true, true, end_cs - start_cs);
+            push_box_layer(&mut lines, &start, &end, style, x, y, &rotation, &fade, &text_body);
+            push_glow_and_stroke(
+                &mut lines, &start, &end, &text_body,
+                x, y,
+                stroke_w,
+                glow_effect,  // Use the parameter to control glow
+                glow_w, glow_blur, "&H80",  // ~50% white glow
+                style.align,  // Pass the alignment from style
+                &rotation,
+                &fade
+            );
         }
     }
 
     Ok(header + &lines)
 }
 
+/// Review overlay: a top-left timecode showing the *source* timeline (`base_ms` + playback time).
+/// This only labels frames with where they came from in the longer original; it never shifts
+/// when captions appear. One event per displayed second keeps the script small.
+fn timecode_overlay_lines(base_ms: u64, duration_ms: u64, style: &AssStyle) -> String {
+    let size = (style.font_size / 2).max(12);
+    let margin = style.margin_h.max(size);
+    let mut lines = String::new();
+
+    let mut t = 0u64;
+    while t < duration_ms {
+        let source_ms = base_ms + t;
+        // Hold until the displayed second changes
+        let next = (t + 1000 - source_ms % 1000).min(duration_ms);
+        let secs = source_ms / 1000;
+        let label = format!("{:02}:{:02}:{:02}", secs / 3600, (secs % 3600) / 60, secs % 60);
+        lines.push_str(&format!(
+            "Dialogue: 10,{},{},TikTok,,0,0,0,,{{\\an7\\pos({},{})\\fs{}\\bord2\\shad0\\1c&HFFFFFF&\\3c&H000000&}}{}\n",
+            cs_to_ass(ms_to_cs(t)), cs_to_ass(ms_to_cs(next)), margin, margin, size, label
+        ));
+        t = next;
+    }
+    lines
+}
+
 /// Calculate proportional font size that maintains consistent appearance across different aspect ratios
 /// Uses 9:16 format (608x1080) as the reference size
 /// Formula: font_size = reference_font_size * sqrt(current_area / reference_area)
@@ -1054,6 +3405,8 @@ fn calculate_proportional_font_size(frame_w: u32, frame_h: u32) -> u32 {
 /// Uses 9:16 format as reference to maintain consistent caption size across all formats
 /// Accepts optional color parameters - if None, uses defaults (white text, black outline, yellow highlight)
 /// Position parameter controls vertical alignment: "bottom" (default) or "center"
+/// Text align parameter picks the alignment column: "center" (default), "left" or "right"
+#[allow(clippy::too_many_arguments)]
 fn default_ass_style(
     frame_w: u32,
     frame_h: u32,
@@ -1062,7 +3415,8 @@ fn default_ass_style(
     highlight_color: Option<&str>,
     outline_color: Option<&str>,
     _glow_effect: bool,
-    position: Option<&str>
+    position: Option<&str>,
+    text_align: Option<&str>
 ) -> AssStyle {
     // Convert hex colors to ASS format (AABBGGRR), use defaults if None
     let primary = text_color.map(hex_to_ass_color).unwrap_or_else(|| "&H00FFFFFF".into());
@@ -1072,9 +3426,17 @@ fn default_ass_style(
     // Determine vertical position and alignment based on position parameter
     let (align, margin_v) = match position.unwrap_or("bottom") {
         "center" => (5, 0), // Alignment 5 = middle center, margin_v 0 for center
+        "top" => (8, (frame_h as f32 * 0.12).round() as u32), // Alignment 8 = top center, 12% from the top
         _ => (2, pct_to_margin_v(frame_h, 88.0)), // Alignment 2 = bottom center (default)
     };
 
+    // Shift to the left (1/4) or right (3/6) column of the same row
+    let align = match text_align.unwrap_or("center") {
+        "left" => align - 1,
+        "right" => align + 1,
+        _ => align,
+    };
+
     AssStyle {
         font_name: font_name.unwrap_or("Montserrat Black").into(),
         font_size: calculate_proportional_font_size(frame_w, frame_h),
@@ -1084,21 +3446,845 @@ fn default_ass_style(
         outline_w: 4,
         shadow: 0,
         align,
+        margin_h: 60,
         margin_v,
         highlight,
+        typewriter: false,
+        rotation_deg: 0.0,
+        safe_top: 0,
+        safe_bottom: 0,
+        safe_h: 0,
+        lead_in_ms: 0,
+        broadcast: None,
+        hook: None,
+        max_chars_per_line: None,
+        max_lines: 1,
+        karaoke_fill: false,
+        text_case: TextCase::Upper,
+        speaker_colors: Vec::new(),
+        max_highlights: None,
+        highlight_intensity: HL_DEFAULT_INTENSITY,
+        karaoke_colors: (None, None, None),
+        background_box: None,
+        fade_ms: 0,
     }
 }
 
-/// Convert hex color string (e.g., "#ffffff") to ASS color format (e.g., "&H00FFFFFF")
+/// Convert hex color string ("#ffffff", or "#ffffff80" with alpha) to ASS color format
+/// (e.g., "&H00FFFFFF", "&H7FFFFFFF"). ASS alpha is inverted: 00 = opaque, FF = transparent.
 fn hex_to_ass_color(hex: &str) -> String {
     let hex = hex.trim_start_matches('#');
-    if hex.len() == 6 {
-        // Convert RGB hex to BGR hex for ASS format
-        let r = &hex[0..2];
-        let g = &hex[2..4];
-        let b = &hex[4..6];
-        format!("&H00{}{}{}", b, g, r) // ASS uses AABBGGRR format
-    } else {
-        "&H00FFFFFF".into() // Default to white if invalid hex
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return "&H00FFFFFF".into(); // Default to white if invalid hex
+    }
+    match hex.len() {
+        // Convert RGB(A) hex to BGR hex for ASS format
+        6 | 8 => {
+            let r = &hex[0..2];
+            let g = &hex[2..4];
+            let b = &hex[4..6];
+            let alpha = match hex.get(6..8) {
+                Some(a) => 255 - u8::from_str_radix(a, 16).unwrap_or(255),
+                None => 0,
+            };
+            format!("&H{:02X}{}{}{}", alpha, b, g, r).to_ascii_uppercase() // ASS uses AABBGGRR format
+        }
+        _ => "&H00FFFFFF".into(), // Default to white if invalid hex
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Back-to-back 300ms words
+    fn spans(text: &str) -> Vec<WordSpan> {
+        text.split_whitespace().enumerate().map(|(i, w)| WordSpan {
+            start_ms: i as u64 * 300,
+            end_ms: (i as u64 + 1) * 300,
+            text: w.into(),
+            speaker: None,
+            highlight: None,
+        }).collect()
+    }
+
+    fn segment(text: &str) -> CaptionSegment {
+        let words = spans(text);
+        CaptionSegment { start_ms: 0, end_ms: words.last().map(|w| w.end_ms).unwrap_or(0), text: text.into(), words, speaker: None, highlight: None }
+    }
+
+    fn analyze_params(caption_params: Option<serde_json::Value>) -> AnalyzeHighlightsParams {
+        AnalyzeHighlightsParams {
+            segments: Some(vec![segment("we spent $500 building the fastest rocket engine in Texas")]),
+            transcription: None,
+            width: None,
+            height: None,
+            max_highlights: None,
+            highlight_intensity: None,
+            caption_params,
+        }
+    }
+
+    #[tokio::test]
+    async fn analyze_highlights_uses_caption_params_for_line_splitting() {
+        let default = analyze_highlights_rpc("t", analyze_params(None), |_| {}).await.unwrap();
+        let stacked = analyze_highlights_rpc("t", analyze_params(Some(serde_json::json!({ "maxLines": 2 }))), |_| {}).await.unwrap();
+        assert!(stacked.lines.len() < default.lines.len(), "{} vs {}", stacked.lines.len(), default.lines.len());
+    }
+
+    #[tokio::test]
+    async fn analyze_highlights_is_empty_for_karaoke() {
+        let result = analyze_highlights_rpc("t", analyze_params(Some(serde_json::json!({ "karaoke": true }))), |_| {}).await.unwrap();
+        assert!(result.lines.is_empty());
+    }
+
+    #[test]
+    fn output_naming_reads_the_name_from_the_raw_input() {
+        let dir = std::env::temp_dir().canonicalize().unwrap();
+        let input = dir.join("clips/../My Clip.final.mov");
+        let params = caption_params_for_analysis(None).unwrap();
+        let naming = OutputNaming::resolve(&params, &input.to_string_lossy()).unwrap();
+        assert_eq!(naming.name, "My Clip.final");
+        assert_eq!(naming.dir, dir);
+        assert_eq!(naming.sidecar("", "srt"), dir.join("My Clip.final.srt").to_string_lossy());
+
+        let vtt = input_sidecar_path(&input.to_string_lossy(), "vtt").unwrap();
+        assert_eq!(vtt, dir.join("My Clip.final.vtt"));
+    }
+
+    /// Minimal HTTP server on loopback: `/video.mp4` serves a body, `/moved` redirects there,
+    /// `/loop` redirects to itself and anything else is a 404
+    async fn serve_downloads() -> String {
+        use tokio::io::AsyncWriteExt;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = vec![0u8; 4096];
+                let n = socket.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                let target = request.split_whitespace().nth(1).unwrap_or("/");
+                let path = target.split('?').next().unwrap_or("/").to_string();
+                let response = match path.as_str() {
+                    "/video.mp4" => "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nvideo".to_string(),
+                    "/moved" => "HTTP/1.1 302 Found\r\nLocation: /video.mp4\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+                    "/loop" => "HTTP/1.1 302 Found\r\nLocation: /loop\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+                    _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+                };
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn download_input_video_follows_redirects() {
+        let base = serve_downloads().await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = download_input_video("t", &format!("{}/moved?sig=secret", base), dir.path(), &mut |_| {}).await.unwrap();
+        assert_eq!(path.file_name().unwrap(), "moved");
+        assert_eq!(fs::read(&path).unwrap(), b"video");
+    }
+
+    #[tokio::test]
+    async fn download_input_video_reports_http_errors() {
+        let base = serve_downloads().await;
+        let dir = tempfile::tempdir().unwrap();
+        let err = download_input_video("t", &format!("{}/missing.mp4?sig=secret", base), dir.path(), &mut |_| {}).await.unwrap_err();
+        assert!(err.to_string().contains("HTTP 404"), "{}", err);
+        assert!(!err.to_string().contains("secret"), "{}", err);
+
+        let err = download_input_video("t", &format!("{}/loop", base), dir.path(), &mut |_| {}).await.unwrap_err();
+        assert!(err.to_string().contains("Failed to download input video"), "{}", err);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn remote_input_requires_output_dir() {
+        let params = serde_json::from_value::<GenerateCaptionsParams>(serde_json::json!({
+            "inputVideo": "https://example.com/clip.mp4", "karaoke": false, "splitByWords": true
+        })).unwrap();
+        let err = generate_captions("t", params, CancellationToken::new(), |_| {}).await.unwrap_err();
+        assert!(err.to_string().contains("outputDir"), "{}", err);
+    }
+
+    #[test]
+    fn all_caps_rows_stay_within_the_width_budget() {
+        let text = "WOW THIS IS ABSOLUTELY MASSIVE WHAT WE MANAGED TO BUILD WITH WORLDWIDE MOMENTUM \
+                    SUPERCALIFRAGILISTICEXPIALIDOCIOUS MMMMMMMMMMMMMMMMMMMMMMMMMMMMMM";
+        let spans = spans(text);
+        let tokens: Vec<String> = spans.iter().map(|s| s.text.clone()).collect();
+        for (frame_w, font_px) in [(1080, 80), (720, 64), (1920, 96)] {
+            for font in ["Montserrat", "Montserrat Black", "Roboto Condensed"] {
+                let budget = frame_w as f32 * 0.85;
+                let factor = font_width_factor(font);
+                for (row, _) in split_phrase_for_width(&tokens, &spans, frame_w, font_px, font, None, TextCase::Upper) {
+                    let width = estimate_text_width(&row.join(" "), font_px, factor);
+                    let widest = row.iter().map(|t| estimate_text_width(t, font_px, factor)).fold(0.0, f32::max);
+                    assert!(width + widest * (BIG_FONT_SIZE_MULTIPLIER - 1.0) <= budget, "{:?} at {}px/{} in {}", row, font_px, font, frame_w);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rows_reserve_room_for_the_highlighted_word_to_grow() {
+        let spans = spans("a line with one extraordinarily long word");
+        let tokens: Vec<String> = spans.iter().map(|s| s.text.clone()).collect();
+        let factor = font_width_factor("Montserrat");
+        let width = estimate_text_width(&tokens.join(" "), 80, factor);
+        let widest = tokens.iter().map(|t| estimate_text_width(t, 80, factor)).fold(0.0, f32::max);
+        let rows_at = |budget: f32| split_phrase_for_width(&tokens, &spans, (budget / 0.85).ceil() as u32, 80, "Montserrat", None, TextCase::Preserve).len();
+
+        // Fits at the base size but not once its widest word is enlarged: wraps
+        assert!(rows_at(width * 1.01) > 1);
+        assert_eq!(rows_at((width + widest * (BIG_FONT_SIZE_MULTIPLIER - 1.0)) * 1.01), 1);
+    }
+
+    fn word(text: &str, start_ms: u64, end_ms: u64) -> WordSpan {
+        WordSpan { start_ms, end_ms, text: text.into(), speaker: None, highlight: None }
+    }
+
+    #[test]
+    fn break_long_token_cuts_urls_after_separators() {
+        let pieces = break_long_token(&word("https://example.com/products/launch-day", 1000, 2000), 16);
+        let texts: Vec<&str> = pieces.iter().map(|p| p.text.as_str()).collect();
+        assert_eq!(texts.concat(), "https://example.com/products/launch-day");
+        assert!(texts.iter().all(|t| t.chars().count() <= 16), "{:?}", texts);
+        assert!(texts[..texts.len() - 1].iter().all(|t| t.ends_with(['/', '-', '.'])), "{:?}", texts);
+    }
+
+    #[test]
+    fn break_long_token_hyphenates_plain_words_and_shares_time() {
+        let pieces = break_long_token(&word("Donaudampfschifffahrtsgesellschaft", 1000, 4400), 12);
+        let texts: Vec<&str> = pieces.iter().map(|p| p.text.as_str()).collect();
+        assert_eq!(texts, ["Donaudampfs-", "chifffahrts-", "gesellschaft"]);
+        assert_eq!(pieces.first().unwrap().start_ms, 1000);
+        assert_eq!(pieces.last().unwrap().end_ms, 4400);
+        assert!(pieces.windows(2).all(|w| w[0].end_ms == w[1].start_ms && w[0].start_ms < w[0].end_ms));
+    }
+
+    #[test]
+    fn break_long_token_keeps_short_words_whole() {
+        assert_eq!(break_long_token(&word("caption", 0, 500), 12).len(), 1);
+        assert_eq!(break_long_token(&word("unbreakable", 0, 500), 3).len(), 1); // budget too small to cut sensibly
+    }
+
+    /// A planned line of `text` starting at `start_ms`, one word every `word_ms`
+    fn planned(text: &str, start_ms: u64, word_ms: u64) -> PlannedLine {
+        let spans: Vec<WordSpan> = text.split_whitespace().enumerate()
+            .map(|(i, w)| word(w, start_ms + i as u64 * word_ms, start_ms + (i as u64 + 1) * word_ms))
+            .collect();
+        PlannedLine {
+            tokens: spans.iter().map(|s| s.text.clone()).collect(),
+            start_ms,
+            end_ms: spans.last().map(|s| s.end_ms).unwrap_or(start_ms),
+            spans,
+            highlight: None,
+            analysis: None,
+            breaks: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn broadcast_splits_cues_over_the_max_duration() {
+        let rules = BroadcastRules::default();
+        let lines = apply_broadcast_rules(vec![planned("one two three four five six seven eight nine ten", 0, 1500)], &rules, true);
+        assert!(lines.len() >= 3);
+        assert!(lines.iter().all(|l| l.end_ms - l.start_ms <= rules.max_duration_ms), "{:?}", lines.iter().map(|l| (l.start_ms, l.end_ms)).collect::<Vec<_>>());
+        assert_eq!(lines.iter().map(|l| l.tokens.len()).sum::<usize>(), 10);
+    }
+
+    #[test]
+    fn broadcast_merges_short_cues_up_to_the_line_cap() {
+        let rules = BroadcastRules::default();
+        let short = vec![planned("hi", 0, 300), planned("there", 300, 300), planned("you", 600, 300), planned("all", 900, 300)];
+        let lines = apply_broadcast_rules(short, &rules, true);
+        assert!(lines.iter().all(|l| l.breaks.len() < rules.max_lines as usize));
+        assert_eq!(lines[0].tokens, ["hi", "there"]);
+        assert_eq!(lines[0].breaks, [1]);
+        // Typewriter mode can't stack rows, so nothing is merged
+        let unmerged = apply_broadcast_rules(vec![planned("hi", 0, 300), planned("there", 300, 300)], &rules, false);
+        assert_eq!(unmerged.len(), 2);
+    }
+
+    #[test]
+    fn broadcast_stretches_short_cues_and_keeps_the_gap() {
+        let rules = BroadcastRules::default();
+        let lines = apply_broadcast_rules(vec![planned("quick", 0, 400), planned("next cue", 5000, 400)], &rules, false);
+        assert_eq!((lines[0].start_ms, lines[0].end_ms), (0, 1000));
+        // A following cue too close for the minimum: end at the gap before it instead
+        let lines = apply_broadcast_rules(vec![planned("quick", 0, 400), planned("next", 600, 1000)], &rules, false);
+        assert_eq!(lines[0].end_ms, 600 - rules.min_gap_ms);
+    }
+
+    #[test]
+    fn broadcast_holds_fast_cues_for_the_reading_speed() {
+        let rules = BroadcastRules { max_chars_per_second: Some(10.0), ..Default::default() };
+        // 28 characters at 10 cps need 2.8s on screen (29 with the "!")
+        let lines = apply_broadcast_rules(vec![planned("extraordinary communications", 0, 600), planned("later", 9000, 500)], &rules, false);
+        assert_eq!(lines[0].end_ms, 2800);
+        let lines = apply_broadcast_rules(vec![planned("extraordinary communications!", 0, 600), planned("later", 9000, 500)], &rules, false);
+        assert_eq!(lines[0].end_ms, 2900);
+        // Still never past the next cue's gap or the max duration
+        let lines = apply_broadcast_rules(vec![planned("extraordinary communications!", 0, 600), planned("soon", 2000, 500)], &rules, false);
+        assert_eq!(lines[0].end_ms, 2000 - rules.min_gap_ms);
+    }
+
+    #[test]
+    fn hex_to_ass_color_handles_rgb_and_rgba() {
+        assert_eq!(hex_to_ass_color("#FF8800"), "&H000088FF");
+        assert_eq!(hex_to_ass_color("ff8800"), "&H000088FF");
+        // ASS alpha is inverted: FF in the hex (opaque) becomes 00, 00 (clear) becomes FF
+        assert_eq!(hex_to_ass_color("#FF8800FF"), "&H000088FF");
+        assert_eq!(hex_to_ass_color("#FF880000"), "&HFF0088FF");
+        assert_eq!(hex_to_ass_color("#11223380"), "&H7F332211");
+    }
+
+    #[test]
+    fn hex_to_ass_color_defaults_to_white_on_bad_input() {
+        for bad in ["", "#FFF", "#GG0000", "#FF00000", "#FF0000FF00", "red"] {
+            assert_eq!(hex_to_ass_color(bad), "&H00FFFFFF", "{:?}", bad);
+        }
+    }
+
+    /// Centiseconds of every `{tag}N` override in an ASS line
+    fn tag_durations(line: &str, tag: &str) -> Vec<i64> {
+        regex::Regex::new(&format!(r"\\{}(\d+)\}}", tag)).unwrap()
+            .captures_iter(line)
+            .map(|c| c[1].parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn typewriter_reveals_each_letter_within_its_word_window() {
+        let spans = vec![word("hey", 1000, 1300), word("you", 1300, 1700)];
+        let tokens = vec!["HEY".to_string(), "YOU".to_string()];
+        let line = assemble_typewriter_line(&tokens, &spans, 1, "FFFFFF", "00FFFF", "HEADER", 80, 0);
+        assert!(line.starts_with(r"HEADER{\2a&HFF&}"));
+        assert_eq!(tag_durations(&line, "ko"), [10, 10, 10, 13, 13, 14]);
+        assert!(line.contains(r"{\1c&H00FFFF&\fs88}{\ko13}Y"), "{}", line);
+    }
+
+    #[test]
+    fn typewriter_holds_for_the_lead_in_and_chunks_long_words() {
+        let long = "abcdefghijklmnopqrstuvwxyz";
+        let line = assemble_typewriter_line(&[long.to_string()], &[word(long, 0, 2600)], usize::MAX, "FFFFFF", "00FFFF", "", 80, 25);
+        let steps = tag_durations(&line, "ko");
+        assert_eq!(steps[0], 25);
+        assert!(steps.len() - 1 <= TYPEWRITER_MAX_STEPS_PER_WORD);
+        assert_eq!(steps[1..].iter().sum::<i64>(), 260);
+    }
+
+    fn style_at(position: Option<&str>, text_align: Option<&str>) -> AssStyle {
+        default_ass_style(1080, 1920, None, None, None, None, false, position, text_align)
+    }
+
+    #[test]
+    fn pos_x_anchors_left_and_right_text_at_the_margin() {
+        assert_eq!(style_at(None, Some("left")).pos_x(1080), 60);
+        assert_eq!(style_at(None, Some("center")).pos_x(1080), 540);
+        assert_eq!(style_at(None, Some("right")).pos_x(1080), 1020);
+        assert_eq!(style_at(Some("top"), Some("right")).align, 9);
+        assert_eq!(style_at(Some("center"), Some("left")).align, 4);
+        // Off-center text only gets the room between the margins
+        assert_eq!(style_at(None, Some("left")).line_width(1080), 960);
+        assert_eq!(style_at(None, None).line_width(1080), 1080);
+    }
+
+    #[test]
+    fn rotation_pivots_around_the_visual_center() {
+        let mut style = style_at(None, None);
+        assert_eq!(style.rotation_tag(540, 1690), "");
+        style.rotation_deg = -8.0;
+        let half = (style.font_size / 2) as i32;
+        assert_eq!(style.rotation_tag(540, 1690), format!("\\org(540,{})\\frz-8.0", 1690 - half));
+        let mut top = style_at(Some("top"), None);
+        top.rotation_deg = 5.0;
+        assert_eq!(top.rotation_tag(540, 230), format!("\\org(540,{})\\frz5.0", 230 + half));
+    }
+
+    #[test]
+    fn rotated_lines_keep_their_tilted_extent_inside_the_frame() {
+        let mut style = style_at(None, None);
+        let upright = style.line_width(1080);
+        for (deg, font_size) in [(15.0, 80), (-45.0, 300), (30.0, 400)] {
+            style.rotation_deg = deg;
+            style.font_size = font_size;
+            let tilted = style.line_width(1080);
+            let theta = deg.abs().to_radians();
+            assert!(tilted <= upright);
+            assert!(tilted as f32 * theta.cos() + font_size as f32 * theta.sin() <= upright as f32 + 1.0, "{}deg {}px -> {}", deg, font_size, tilted);
+        }
+        assert!(style.line_width(1080) < upright); // 400px text at 30 degrees must wrap sooner
+    }
+
+    #[test]
+    fn safe_area_moves_captions_clear_of_platform_ui() {
+        let mut bottom = style_at(None, None);
+        bottom.apply_safe_area(1080, 1920, None, Some(20.0), Some(5.0));
+        assert_eq!(bottom.margin_v, 384); // bottom row sits on the inset's edge
+        assert_eq!(bottom.safe_h, 54);
+        assert_eq!(bottom.margin_h, 60); // the default margin is already wider
+        assert_eq!(bottom.line_width(1080), 972);
+
+        let mut top = style_at(Some("top"), None);
+        top.apply_safe_area(1080, 1920, Some(15.0), None, None);
+        assert_eq!(top.margin_v, 288);
+
+        let mut middle = style_at(Some("center"), None);
+        middle.apply_safe_area(1080, 1920, Some(10.0), Some(30.0), None);
+        assert_eq!(middle.pos_y(1920), (192 + 1920 - 576) / 2); // centered in the safe band
+    }
+
+    #[test]
+    fn safe_area_insets_are_clamped() {
+        let mut style = style_at(None, None);
+        style.apply_safe_area(1080, 1920, Some(-5.0), Some(90.0), Some(80.0));
+        assert_eq!(style.safe_top, 0);
+        assert_eq!(style.safe_bottom, 864);
+        assert_eq!(style.safe_h, 486);
+    }
+
+    #[test]
+    fn lead_in_moves_only_the_start_and_never_overlaps() {
+        let mut style = style_at(None, None);
+        assert_eq!(style.lead_in_start_cs(500, 0), 500);
+        style.lead_in_ms = 300;
+        assert_eq!(style.lead_in_start_cs(500, 0), 470);
+        assert_eq!(style.lead_in_start_cs(500, 480), 480); // not before the previous line ends
+        assert_eq!(style.lead_in_start_cs(20, 0), 0);      // not before the video starts
+        assert_eq!(style.lead_in_start_cs(500, 600), 500); // never later than the word itself
+    }
+
+    #[test]
+    fn word_level_vtt_tags_each_word_and_the_highlight() {
+        let mut line = planned("big news today", 1000, 500);
+        line.highlight = Some(1);
+        line.end_ms = 2600;
+        let vtt = build_word_level_vtt(&[line], "00FFFF");
+        assert!(vtt.starts_with("WEBVTT\n\nSTYLE\n::cue(.highlight) { color: #FFFF00; }\n\n"), "{}", vtt);
+        assert!(vtt.contains("1\n00:00:01.000 --> 00:00:02.600\n<c>big</c> <00:00:01.500><c.highlight>news</c> <00:00:02.000><c>today</c>\n"), "{}", vtt);
+    }
+
+    #[test]
+    fn word_level_vtt_keeps_timestamps_inside_the_cue_and_escapes_text() {
+        let mut line = planned("a<b c&d", 1000, 500);
+        line.spans[1].start_ms = 900; // earlier than the cue start: no timestamp allowed
+        line.breaks = vec![1];
+        let vtt = build_word_level_vtt(&[line], "");
+        assert!(!vtt.contains("STYLE"));
+        assert!(vtt.contains("<c>a&lt;b</c>\n<c>c&amp;d</c>"), "{}", vtt);
+    }
+
+    fn timed(text: &str, start_ms: u64, end_ms: u64) -> CaptionSegment {
+        let mut seg = segment(text);
+        for w in seg.words.iter_mut() {
+            w.start_ms += start_ms;
+            w.end_ms += start_ms;
+        }
+        seg.start_ms = start_ms;
+        seg.end_ms = end_ms;
+        seg
+    }
+
+    #[test]
+    fn keyframe_snap_moves_nearby_starts_onto_the_grid() {
+        let segments = vec![timed("one", 1900, 2500), timed("two", 3300, 4000), timed("three", 6150, 7000)];
+        let snapped = snap_starts_to_keyframes(&segments, 2000, KEYFRAME_SNAP_TOLERANCE_MS);
+        let starts: Vec<u64> = snapped.iter().map(|s| s.start_ms).collect();
+        assert_eq!(starts, [2000, 3300, 6000]); // 3300 is 700ms from a keyframe: left alone
+        assert_eq!(snapped[0].words[0].start_ms, 2000);
+        assert_eq!(snapped[2].words[0].start_ms, 6000);
+        assert_eq!(snap_starts_to_keyframes(&segments, 0, 200)[0].start_ms, 1900);
+    }
+
+    #[test]
+    fn keyframe_snap_never_reorders_or_empties_cues() {
+        // "a" is too far from 2000 to move, so "b" can't jump back before it
+        let segments = vec![timed("a", 2450, 2500), timed("b", 2350, 2600)];
+        let starts: Vec<u64> = snap_starts_to_keyframes(&segments, 2000, 400).iter().map(|s| s.start_ms).collect();
+        assert_eq!(starts, [2450, 2350]);
+        // Moving "c" forward to 4000 would put its start past its end
+        let segments = vec![timed("c", 3900, 3950)];
+        assert_eq!(snap_starts_to_keyframes(&segments, 2000, 400)[0].start_ms, 3900);
+        // Ties with the previous cue are fine
+        let segments = vec![timed("d", 2100, 2400), timed("e", 2150, 2600)];
+        let starts: Vec<u64> = snap_starts_to_keyframes(&segments, 2000, 200).iter().map(|s| s.start_ms).collect();
+        assert_eq!(starts, [2000, 2000]);
+    }
+
+    #[test]
+    fn fcpxml_times_snap_to_whole_frames() {
+        assert_eq!(fcpxml_frame_duration(29.97), (1001, 30000));
+        assert_eq!(fcpxml_frame_duration(25.0), (100, 2500));
+        assert_eq!(fcpxml_time(0, (100, 2500)), "0s");
+        assert_eq!(fcpxml_time(1000, (100, 2500)), "2500/2500s");
+        assert_eq!(fcpxml_time(1010, (100, 2500)), "2500/2500s"); // 1.01s rounds to frame 25
+        assert_eq!(fcpxml_time(1000, (1001, 30000)), "30030/30000s");
+    }
+
+    #[test]
+    fn fcpxml_has_one_marker_per_caption() {
+        let probe: crate::video::ProbeResult = serde_json::from_value(serde_json::json!({
+            "duration": 4.0, "width": 1080, "height": 1920, "fps": 25.0, "audio": true, "video": true
+        })).unwrap();
+        let segments = vec![timed("Tom & Jerry", 0, 1000), timed("<b>loud</b>", 2000, 2010)];
+        let xml = build_fcpxml(Path::new("/videos/my clip.mp4"), &segments, &probe);
+        assert!(xml.contains(r#"<format id="r1" frameDuration="100/2500s" width="1080" height="1920"/>"#));
+        assert!(xml.contains(r#"src="file:///videos/my%20clip.mp4""#), "{}", xml);
+        assert!(xml.contains(r#"<asset-clip ref="r2" offset="0s" name="my clip" start="0s" duration="10000/2500s" format="r1">"#), "{}", xml);
+        assert_eq!(xml.matches("<marker ").count(), 2);
+        assert!(xml.contains(r#"<marker start="0s" duration="2500/2500s" value="Tom &amp; Jerry"/>"#), "{}", xml);
+        // A 10ms caption still gets a one-frame marker
+        assert!(xml.contains(r#"<marker start="5000/2500s" duration="100/2500s" value="&lt;b&gt;loud&lt;/b&gt;"/>"#), "{}", xml);
+    }
+
+    #[test]
+    fn hook_boosts_only_captions_starting_in_the_window() {
+        let mut style = style_at(None, None);
+        style.font_size = 80;
+        assert_eq!(style.font_size_at(0), 80);
+        style.hook = Some((3000, 1.25));
+        assert_eq!(style.font_size_at(0), 100);
+        assert_eq!(style.font_size_at(2999), 100);
+        assert_eq!(style.font_size_at(3000), 80);
+    }
+
+    #[test]
+    fn hook_captions_wrap_for_their_bigger_size() {
+        let mut style = style_at(None, None);
+        style.hook = Some((3000, 2.0));
+        let text = "this opening line is pretty long and gets bigger";
+        let early = plan_highlighted_lines(&[timed(text, 0, 3000)], &style, 1080, false);
+        let late = plan_highlighted_lines(&[timed(text, 5000, 8000)], &style, 1080, false);
+        assert!(early.len() > late.len(), "{} vs {}", early.len(), late.len());
+    }
+
+    #[test]
+    fn hold_bridges_only_short_pauses() {
+        let segments = vec![timed("one", 0, 900), timed("two", 1200, 2000), timed("three", 3000, 3600)];
+        let held = hold_short_gaps(&segments, GAP_HOLD_MS);
+        assert_eq!(held[0].end_ms, 1200);          // 300ms pause: held
+        assert_eq!(held[0].words[0].end_ms, 1200); // its last word too
+        assert_eq!(held[1].end_ms, 2000);          // 1s pause: blank
+        assert_eq!(held[2].end_ms, 3600);          // nothing after the last caption
+    }
+
+    #[test]
+    fn hold_leaves_touching_and_overlapping_cues_alone() {
+        let segments = vec![timed("one", 0, 1000), timed("two", 1000, 1800), timed("three", 1700, 2500)];
+        let held = hold_short_gaps(&segments, GAP_HOLD_MS);
+        let ends: Vec<u64> = held.iter().map(|s| s.end_ms).collect();
+        assert_eq!(ends, [1000, 1800, 2500]);
+    }
+
+    fn pick(spans: &[WordSpan]) -> Option<usize> {
+        let seg = CaptionSegment { start_ms: 0, end_ms: 0, text: String::new(), words: spans.to_vec(), speaker: None, highlight: None };
+        let mut st = HighlightState::new(&[seg], None, HL_DEFAULT_INTENSITY);
+        choose_highlight_idx(&original_tokens(spans), spans, 0, &mut st, None)
+    }
+
+    #[test]
+    fn explicit_highlight_beats_the_heuristic() {
+        let mut words = spans("we spent $500 building the fastest rocket");
+        let heuristic = pick(&words).expect("heuristic pick");
+        assert_ne!(heuristic, 0);
+        words[0].highlight = Some(true); // a stopword the heuristic would never choose
+        assert_eq!(pick(&words), Some(0));
+    }
+
+    #[test]
+    fn words_flagged_off_are_never_highlighted() {
+        let mut words = spans("we spent $500 building the fastest rocket");
+        let heuristic = pick(&words).expect("heuristic pick");
+        words[heuristic].highlight = Some(false);
+        assert_ne!(pick(&words), Some(heuristic));
+        for w in words.iter_mut() { w.highlight = Some(false); }
+        assert_eq!(pick(&words), None);
+    }
+
+    #[test]
+    fn stack_rows_groups_rows_into_multi_line_events() {
+        let rows: Vec<(Vec<String>, Vec<WordSpan>)> = ["a b", "c", "d e f", "g", "h"].iter().map(|r| {
+            let words = spans(r);
+            (words.iter().map(|w| w.text.clone()).collect(), words)
+        }).collect();
+
+        let stacked = stack_rows(rows.clone(), 2);
+        let shape: Vec<(usize, Vec<usize>)> = stacked.iter().map(|(t, s, b)| { assert_eq!(t.len(), s.len()); (t.len(), b.clone()) }).collect();
+        assert_eq!(shape, [(3, vec![2]), (4, vec![3]), (1, vec![])]);
+
+        // One line (or a bogus zero) leaves every row on its own
+        assert_eq!(stack_rows(rows.clone(), 1).len(), 5);
+        assert!(stack_rows(rows, 0).iter().all(|(_, _, b)| b.is_empty()));
+    }
+
+    #[test]
+    fn dim_bgr_scales_each_channel() {
+        assert_eq!(dim_bgr("FFFFFF", KARAOKE_PAST_DIM), "999999");
+        assert_eq!(dim_bgr("0080FF", 0.5), "004080");
+        assert_eq!(dim_bgr("FFFFFF", 2.0), "FFFFFF");
+        assert_eq!(dim_bgr("&H00FFFFFF", 0.5), "&H00FFFFFF"); // not bare BBGGRR: left alone
+    }
+
+    #[test]
+    fn karaoke_colors_fall_back_to_the_style() {
+        let mut style = style_at(None, None);
+        style.primary = "&H00FFFFFF".into();
+        style.highlight = "&H0000FFFF".into();
+        let words = spans("sing along");
+        assert_eq!(style.karaoke_bgr(&words), ("999999".into(), "00FFFF".into(), "FFFFFF".into()));
+
+        // A future override drives the dimmed past color too
+        style.karaoke_colors = (None, None, Some("0000FF".into()));
+        assert_eq!(style.karaoke_bgr(&words), ("000099".into(), "00FFFF".into(), "0000FF".into()));
+
+        style.karaoke_colors = (Some("111111".into()), Some("222222".into()), Some("333333".into()));
+        assert_eq!(style.karaoke_bgr(&words), ("111111".into(), "222222".into(), "333333".into()));
+    }
+
+    #[test]
+    fn karaoke_fill_times_each_word_by_its_window() {
+        let words = vec![word("one", 0, 400), word("two", 600, 900), word("{x}", 900, 1000)];
+        let tokens: Vec<String> = words.iter().map(|w| w.text.clone()).collect();
+        let line = assemble_karaoke_fill_line(&tokens, &words, "00FFFF", "FFFFFF", &[], "", 80, 0);
+        // A pause is folded into the word before it, so the next word starts on time
+        assert_eq!(line, r"{\1c&H00FFFF&\2c&HFFFFFF&\fs80}{\kf60}one {\kf30}two {\kf10}\{x\}");
+        assert_eq!(tag_durations(&line, "kf").iter().sum::<i64>(), 100);
+    }
+
+    #[test]
+    fn karaoke_fill_holds_the_lead_in_and_breaks_rows() {
+        let words = spans("one two three");
+        let tokens: Vec<String> = words.iter().map(|w| w.text.clone()).collect();
+        let line = assemble_karaoke_fill_line(&tokens, &words, "00FFFF", "FFFFFF", &[2], r"{\an2}", 80, 25);
+        assert_eq!(line, r"{\an2}{\1c&H00FFFF&\2c&HFFFFFF&\fs80}{\k25}{\kf30}one {\kf30}two\N{\kf30}three");
+    }
+
+    #[test]
+    fn primary_format_is_taken_out_ahead_of_the_rest() {
+        let formats = ["9:16", "16:9", "1:1"];
+        let mut jobs: Vec<(usize, String)> = formats.iter().map(|f| f.to_string()).enumerate().collect();
+        let primary = take_primary_job(&mut jobs, Some("16:9"), |f| f.as_str()).unwrap();
+        assert_eq!(primary, Some((1, "16:9".to_string())));
+        let rest: Vec<usize> = jobs.iter().map(|(idx, _)| *idx).collect();
+        assert_eq!(rest, [0, 2]);
+
+        assert_eq!(take_primary_job(&mut jobs, None, |f| f.as_str()).unwrap(), None);
+        assert_eq!(jobs.len(), 2);
+        let err = take_primary_job(&mut jobs, Some("4:5"), |f| f.as_str()).unwrap_err();
+        assert_eq!(err.to_string(), "primaryFormat 4:5 is not one of the requested export formats");
+        assert_eq!(jobs.len(), 2);
+    }
+
+    #[test]
+    fn check_input_video_explains_what_is_wrong() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = |path: &Path| check_input_video(&path.to_string_lossy()).unwrap_err().to_string();
+
+        let missing = dir.path().join("missing.mp4");
+        assert_eq!(err(&missing), format!("Input video not found: {}", missing.display()));
+        assert!(err(dir.path()).starts_with("Input video is not a file:"));
+
+        let empty = dir.path().join("empty.mp4");
+        fs::write(&empty, b"").unwrap();
+        assert!(err(&empty).starts_with("Input video is empty (0 bytes)"));
+
+        let video = dir.path().join("clip.mp4");
+        fs::write(&video, b"\0\0\0\x18ftypmp42").unwrap();
+        assert!(check_input_video(&video.to_string_lossy()).is_ok());
+    }
+
+    /// Twenty short phrases, each with a standout word, four seconds apart
+    fn punchy_segments() -> Vec<CaptionSegment> {
+        let words = ["$500", "rocket", "volcano", "billion", "Tokyo", "diamond", "tornado", "$9000", "glacier", "meteor",
+                     "python", "castle", "jaguar", "$750", "saturn", "nitrogen", "harbor", "zeppelin", "falcon", "quartz"];
+        words.iter().enumerate()
+            .map(|(i, w)| timed(&format!("we saw the {}", w), i as u64 * 4000, i as u64 * 4000 + 1200))
+            .collect()
+    }
+
+    #[test]
+    fn max_highlights_caps_the_whole_video() {
+        let segments = punchy_segments();
+        let mut style = style_at(None, None);
+        style.highlight_intensity = 1.0;
+        let count = |style: &AssStyle| plan_highlighted_lines(&segments, style, 1080, false).iter().filter(|l| l.highlight.is_some()).count();
+
+        let uncapped = count(&style);
+        assert!(uncapped > 3, "{}", uncapped);
+        style.max_highlights = Some(3);
+        assert_eq!(count(&style), 3);
+        style.max_highlights = Some(0);
+        assert_eq!(count(&style), 0);
+    }
+
+    #[test]
+    fn explicit_and_heuristic_highlights_mix_across_lines() {
+        let mut style = style_at(None, None);
+        style.highlight_intensity = 1.0;
+        let highlighted_in = |segments: &[CaptionSegment]| -> Vec<String> {
+            plan_highlighted_lines(segments, &style, 1080, false).iter()
+                .filter_map(|l| l.highlight.map(|h| l.spans[h].text.clone()))
+                .collect()
+        };
+        let mut segments = punchy_segments();
+        assert!(highlighted_in(&segments).iter().any(|w| w == "$500"));
+
+        segments[0].words[3].highlight = Some(false); // "$500" vetoed
+        segments[5].words[0].highlight = Some(true);  // "we" forced
+        let highlighted = highlighted_in(&segments);
+        assert!(!highlighted.iter().any(|w| w == "$500"));
+        assert!(highlighted.iter().any(|w| w == "we"));
+        // Words without flags are still left to the heuristic
+        assert!(highlighted.iter().any(|w| w == "rocket" || w == "$9000"), "{:?}", highlighted);
+    }
+
+    #[test]
+    fn language_default_font_normalizes_codes_and_names() {
+        assert_eq!(language_default_font("ja", None).as_deref(), Some("Noto Sans CJK JP"));
+        assert_eq!(language_default_font("zh-Hant", None).as_deref(), Some("Noto Sans CJK TC"));
+        assert_eq!(language_default_font("zh_TW", None).as_deref(), Some("Noto Sans CJK TC"));
+        assert_eq!(language_default_font("zh-Hant-HK", None).as_deref(), Some("Noto Sans CJK TC"));
+        assert_eq!(language_default_font("zh-CN", None).as_deref(), Some("Noto Sans CJK SC"));
+        assert_eq!(language_default_font("chinese", None).as_deref(), Some("Noto Sans CJK SC"));
+        assert_eq!(language_default_font(" Hindi ", None).as_deref(), Some("Noto Sans Devanagari"));
+        assert_eq!(language_default_font("pt_BR", None), None);
+        assert_eq!(language_default_font("en", None), None);
+    }
+
+    #[test]
+    fn language_default_font_prefers_the_caller_map() {
+        let map: HashMap<String, String> = [("ja".to_string(), "Hiragino Sans".to_string()), ("Klingon".to_string(), "pIqaD".to_string())].into();
+        assert_eq!(language_default_font("japanese", Some(&map)).as_deref(), Some("Hiragino Sans"));
+        assert_eq!(language_default_font("Klingon", Some(&map)).as_deref(), Some("pIqaD"));
+        assert_eq!(language_default_font("ko", Some(&map)).as_deref(), Some("Noto Sans CJK KR"));
+    }
+
+    #[test]
+    fn subtitle_warnings_come_from_the_filter_instance() {
+        let stderr = "[Parsed_subtitles_0 @ 0x7f8] fontselect: failed to find any fallback with glyph 0x1F600 for font: (Montserrat, 700, 0)\n\
+                      [Parsed_subtitles_0 @ 0x7f8] Glyph 0x1F600 not found, selecting one more font for (Montserrat, 700, 0)\n\
+                      [out#0/null @ 0x600] Output file is empty, nothing was encoded\n";
+        let warnings = subtitle_filter_warnings(stderr, true);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[1].starts_with("Glyph 0x1F600 not found"));
+        assert!(subtitle_filter_warnings("", true).is_empty());
+    }
+
+    #[test]
+    fn failed_render_without_filter_output_reports_the_last_line() {
+        let stderr = "[AVFilterGraph @ 0x1] No such filter: 'subtitles'\nError opening output files: Filter not found\n";
+        assert_eq!(subtitle_filter_warnings(stderr, false), ["Error opening output files: Filter not found"]);
+        assert!(subtitle_filter_warnings(stderr, true).is_empty());
+        assert!(subtitle_filter_warnings("", false).is_empty());
+    }
+
+    #[tokio::test]
+    async fn work_dirs_on_a_custom_volume_are_removed_with_the_job() {
+        let volume = tempfile::tempdir().unwrap();
+        let job_temp = tempfile::tempdir().unwrap();
+        let root = volume.path().to_string_lossy().to_string();
+
+        // dropped on an error/abort path: nothing is left on the volume
+        let dir = resolve_work_dir(Some(&root), job_temp.path(), "scratchDir").unwrap();
+        fs::write(dir.join("scratch.ass"), "x").unwrap();
+        drop(dir);
+        assert_eq!(fs::read_dir(volume.path()).unwrap().count(), 0);
+
+        // deliverables are moved out before the dir goes
+        let dir = resolve_work_dir(Some(&root), job_temp.path(), "transcribeDir").unwrap();
+        let audio = dir.join("audio_t.mp3");
+        fs::write(&audio, "mp3").unwrap();
+        let kept = dir.keep(&audio.to_string_lossy(), job_temp.path()).await.unwrap();
+        drop(dir);
+        assert_eq!(fs::read_dir(volume.path()).unwrap().count(), 0);
+        assert_eq!(Path::new(&kept), job_temp.path().join("audio_t.mp3"));
+        assert_eq!(fs::read_to_string(&kept).unwrap(), "mp3");
+
+        // without a custom volume the job temp dir is used and left in place
+        let dir = resolve_work_dir(None, job_temp.path(), "scratchDir").unwrap();
+        assert_eq!(&*dir, job_temp.path());
+        assert_eq!(dir.keep(&kept, job_temp.path()).await.unwrap(), kept);
+        drop(dir);
+        assert!(job_temp.path().is_dir());
+    }
+
+    #[tokio::test]
+    async fn primary_format_finishes_before_the_others_start() {
+        let log = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let spawn = |idx: usize, format: String| {
+            let log = log.clone();
+            tokio::spawn(async move {
+                log.lock().unwrap().push(format!("start {}", format));
+                tokio::task::yield_now().await;
+                log.lock().unwrap().push(format!("finish {}", format));
+                Ok(CaptionedVideoResult {
+                    format: format.clone(),
+                    raw_video: String::new(),
+                    captioned_video: format!("out_{}.mp4", idx),
+                    qa_video: None,
+                    width: 1080,
+                    height: 1920,
+                })
+            })
+        };
+        let jobs: Vec<(usize, String)> = ["9:16", "16:9", "1:1"].iter().map(|f| f.to_string()).enumerate().collect();
+        let (_progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut ready = Vec::new();
+        let mut emit = |ev| if let RpcEvent::FormatReady { format, .. } = ev { ready.push(format) };
+
+        let tracker = EncodeProgress::new("t", jobs.len(), 0.0, 1.0);
+        let finished = run_encode_jobs(jobs, Some("16:9"), |f| f.as_str(), spawn, tracker, &mut progress_rx, &mut emit).await.unwrap();
+
+        let log = log.lock().unwrap();
+        let primary_done = log.iter().position(|e| e == "finish 16:9").unwrap();
+        for other in ["start 9:16", "start 1:1"] {
+            assert!(log.iter().position(|e| e == other).unwrap() > primary_done, "{:?}", log);
+        }
+        assert_eq!(ready, ["16:9"]);
+        let order: Vec<usize> = finished.iter().map(|(idx, _)| *idx).collect();
+        assert_eq!(order, [1, 0, 2]);
+    }
+
+    #[tokio::test]
+    async fn clean_copy_keeps_an_uncaptioned_encode_next_to_the_captioned_one() {
+        let (Ok(ffmpeg), Ok(_)) = (whisper::find_ffmpeg_binary().await, whisper::find_ffprobe_binary().await) else {
+            eprintln!("skipping: ffmpeg/ffprobe not found");
+            return;
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.mp4");
+        let status = std::process::Command::new(&ffmpeg)
+            .args(["-y", "-f", "lavfi", "-i", "color=c=gray:size=180x320:rate=10", "-f", "lavfi", "-i", "sine=frequency=440",
+                   "-t", "1", "-c:v", "libx264", "-pix_fmt", "yuv420p", "-c:a", "aac"])
+            .arg(&input)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "ffmpeg could not render the test clip");
+
+        let params: BurnCaptionsParams = serde_json::from_value(serde_json::json!({
+            "segments": [timed("hello world", 0, 1000)],
+            "inputVideo": input, "exportFormats": ["9:16"], "outputDir": dir.path().join("out"),
+            "cleanCopy": true, "karaoke": false, "splitByWords": true
+        })).unwrap();
+        let result = burn_captions("t", params, CancellationToken::new(), |_| {}).await.unwrap();
+
+        let video = &result.captioned_videos[0];
+        let (raw, captioned) = (fs::read(&video.raw_video).unwrap(), fs::read(&video.captioned_video).unwrap());
+        assert!(!raw.is_empty() && !captioned.is_empty());
+        assert_ne!(raw, captioned);
+    }
+
+    #[test]
+    fn caption_params_for_analysis_validates_input() {
+        assert!(caption_params_for_analysis(None).is_ok());
+        assert!(caption_params_for_analysis(Some(serde_json::json!("Inter"))).is_err());
+        assert!(caption_params_for_analysis(Some(serde_json::json!({ "maxLines": "two" }))).is_err());
+        let parsed = caption_params_for_analysis(Some(serde_json::json!({ "textCase": "upper" }))).unwrap();
+        assert_eq!(parsed.text_case.as_deref(), Some("upper"));
+        assert!(!parsed.karaoke);
     }
 }
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use unicode_segmentation::UnicodeSegmentation;
 use uuid::Uuid;
 
 
@@ -8,6 +9,12 @@ pub struct RpcRequest {
     pub method: String,                // What operation to perform (e.g., "extractAudio", "probe")
     #[serde(default)]                  // If params is missing in JSON, use default (empty JSON object)
     pub params: serde_json::Value,     // The input data needed for the operation
+    #[serde(default, rename = "webhookUrl")]
+    pub webhook_url: Option<String>,   // POST the final response/error here when the job finishes
+    #[serde(default, rename = "suppressStdout")]
+    pub suppress_stdout: bool,         // Don't write this request's events/response to stdout (webhook-only jobs)
+    #[serde(default, rename = "idempotencyKey")]
+    pub idempotency_key: Option<String>, // Retries with the same key get the first request's result instead of a new job
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -18,10 +25,76 @@ pub struct RpcResponse<T> {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RpcError {
-    pub id: String,      // Same ID as the request that failed
-    pub error: String,   // Human-readable error message explaining what went wrong
+    pub id: String,          // Same ID as the request that failed
+    pub error: ErrorBody,    // What went wrong
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ErrorBody {
+    pub code: String,        // Stable error category (e.g. "FFMPEG_FAILED"), see CapslapError
+    pub message: String,     // Human-readable error message explaining what went wrong
+}
+
+impl ErrorBody {
+    /// The code comes from the first CapslapError in the chain (Generic when there is none)
+    pub fn from_error(e: &anyhow::Error) -> Self {
+        let code = e.chain()
+            .find_map(|cause| cause.downcast_ref::<CapslapError>())
+            .map_or(CapslapError::GENERIC, CapslapError::code);
+        Self { code: code.into(), message: e.to_string() }
+    }
+}
+
+impl RpcError {
+    pub fn new(id: impl Into<String>, e: &anyhow::Error) -> Self {
+        Self { id: id.into(), error: ErrorBody::from_error(e) }
+    }
+
+    pub fn coded(id: impl Into<String>, e: CapslapError) -> Self {
+        Self { id: id.into(), error: ErrorBody { code: e.code().into(), message: e.to_string() } }
+    }
+}
+
+/// Failure categories the frontend can act on without matching message text. Raise them
+/// through anyhow (`Err(CapslapError::FfmpegFailed(msg).into())`); anything else is Generic.
+#[derive(Debug)]
+pub enum CapslapError {
+    WhisperBinaryNotFound(String),  // No whisper.cpp executable in any known location
+    FfmpegNotFound(String),         // No ffmpeg/ffprobe executable in any known location
+    ModelMissing(String),           // No local Whisper model, or modelPath doesn't exist
+    OpenAiAuth(String),             // OpenAI API key missing or rejected
+    FfmpegFailed(String),           // ffmpeg/ffprobe ran but failed
+    Cancelled(String),              // Stopped by a cancel request
+    Generic(String),
+}
+
+impl CapslapError {
+    const GENERIC: &'static str = "GENERIC";
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::WhisperBinaryNotFound(_) => "WHISPER_BINARY_NOT_FOUND",
+            Self::FfmpegNotFound(_) => "FFMPEG_NOT_FOUND",
+            Self::ModelMissing(_) => "MODEL_MISSING",
+            Self::OpenAiAuth(_) => "OPENAI_AUTH",
+            Self::FfmpegFailed(_) => "FFMPEG_FAILED",
+            Self::Cancelled(_) => "CANCELLED",
+            Self::Generic(_) => Self::GENERIC,
+        }
+    }
+}
+
+impl std::fmt::Display for CapslapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::WhisperBinaryNotFound(m) | Self::FfmpegNotFound(m) | Self::ModelMissing(m)
+            | Self::OpenAiAuth(m) | Self::FfmpegFailed(m) | Self::Cancelled(m) | Self::Generic(m) => f.write_str(m),
+        }
+    }
+}
+
+impl std::error::Error for CapslapError {}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "event", rename_all = "camelCase")]  // JSON will have an "event" field indicating the type
 pub enum RpcEvent {
@@ -36,8 +109,334 @@ pub enum RpcEvent {
         id: String,       // ID of the operation
         message: String   // The log message content
     },
+    // A transcribed segment as the backend produces it (streamSegments); segment-level timing,
+    // before merging/splitting, so the final result's segments can differ
+    Segment {
+        id: String,
+        segment: crate::types::CaptionSegment,
+    },
+    // One export is finished and usable while the others keep rendering
+    FormatReady {
+        id: String,
+        format: String,           // Export format label ("9:16", "original", ...)
+        captioned_video: String,  // Path of the finished captioned file
+    },
+    // An output file was written and can be shown to the user before the job finishes
+    Artifact {
+        id: String,
+        kind: String,             // "audio", "json", "srt", "vtt", "fcpxml", "video", "cleanVideo" or "qaVideo"
+        path: String,             // Path of the written file
+    },
 }
 
 pub fn new_id() -> String {
     Uuid::new_v4().to_string()
 }
+
+/// Recent results by idempotency key (per method), so a retried request joins or reuses
+/// the original job instead of starting a second one. Entries expire after
+/// CAPSLAP_IDEMPOTENCY_TTL_SECS (default 10 minutes) once finished.
+#[derive(Default)]
+pub struct IdempotencyCache {
+    entries: std::sync::Mutex<std::collections::HashMap<String, IdempotencyEntry>>,
+}
+
+enum IdempotencyEntry {
+    Running(tokio::sync::watch::Receiver<Option<serde_json::Value>>),
+    Done(serde_json::Value, std::time::Instant),
+}
+
+pub enum IdempotencyClaim {
+    /// First request with this key: run the job, then hand the payload to `finish`
+    New(tokio::sync::watch::Sender<Option<serde_json::Value>>),
+    /// The original is still running: `wait_for_original` yields its payload
+    Running(tokio::sync::watch::Receiver<Option<serde_json::Value>>),
+    /// The original already finished within the TTL
+    Done(serde_json::Value),
+}
+
+const DEFAULT_IDEMPOTENCY_TTL_SECS: u64 = 600;
+
+fn idempotency_ttl() -> std::time::Duration {
+    let secs = std::env::var("CAPSLAP_IDEMPOTENCY_TTL_SECS").ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .unwrap_or(DEFAULT_IDEMPOTENCY_TTL_SECS);
+    std::time::Duration::from_secs(secs)
+}
+
+impl IdempotencyCache {
+    pub fn claim(&self, method: &str, key: &str) -> IdempotencyClaim {
+        self.claim_with_ttl(method, key, idempotency_ttl())
+    }
+
+    fn claim_with_ttl(&self, method: &str, key: &str, ttl: std::time::Duration) -> IdempotencyClaim {
+        let mut entries = self.entries.lock().unwrap();
+        // Drop expired results and jobs that died without finishing (aborted/cancelled)
+        entries.retain(|_, entry| match entry {
+            IdempotencyEntry::Running(rx) => rx.has_changed().is_ok(),
+            IdempotencyEntry::Done(_, at) => at.elapsed() < ttl,
+        });
+
+        let key = format!("{}:{}", method, key);
+        match entries.get(&key) {
+            Some(IdempotencyEntry::Running(rx)) => IdempotencyClaim::Running(rx.clone()),
+            Some(IdempotencyEntry::Done(payload, _)) => IdempotencyClaim::Done(payload.clone()),
+            None => {
+                let (tx, rx) = tokio::sync::watch::channel(None);
+                entries.insert(key, IdempotencyEntry::Running(rx));
+                IdempotencyClaim::New(tx)
+            }
+        }
+    }
+
+    pub fn finish(&self, method: &str, key: &str, tx: tokio::sync::watch::Sender<Option<serde_json::Value>>, payload: &serde_json::Value) {
+        let key = format!("{}:{}", method, key);
+        self.entries.lock().unwrap()
+            .insert(key, IdempotencyEntry::Done(payload.clone(), std::time::Instant::now()));
+        tx.send_replace(Some(payload.clone()));
+    }
+}
+
+/// Payload of the original job, or None when it was cancelled before finishing
+pub async fn wait_for_original(mut rx: tokio::sync::watch::Receiver<Option<serde_json::Value>>) -> Option<serde_json::Value> {
+    rx.wait_for(|payload| payload.is_some()).await.ok().and_then(|payload| payload.clone())
+}
+
+const WEBHOOK_ATTEMPTS: u32 = 3;
+const WEBHOOK_TIMEOUT_SECS: u64 = 15;
+
+/// POST a finished job's `RpcResponse`/`RpcError` payload to a callback URL.
+/// Network errors and non-2xx replies are retried with exponential backoff (1s, 2s, ...).
+pub async fn post_webhook(url: &str, payload: &serde_json::Value) -> anyhow::Result<()> {
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        return Err(anyhow::anyhow!("Webhook URL must be http(s): {}", url));
+    }
+    let client = reqwest::Client::builder()
+        .user_agent("core/1.0.0")
+        .timeout(std::time::Duration::from_secs(WEBHOOK_TIMEOUT_SECS))
+        .build()?;
+
+    let mut last_error = String::new();
+    for attempt in 0..WEBHOOK_ATTEMPTS {
+        if attempt > 0 {
+            tokio::time::sleep(std::time::Duration::from_secs(1 << (attempt - 1))).await;
+        }
+        match client.post(url).json(payload).send().await {
+            Ok(resp) if resp.status().is_success() => return Ok(()),
+            Ok(resp) => last_error = format!("HTTP {}", resp.status()),
+            Err(e) => last_error = e.to_string(),
+        }
+    }
+    Err(anyhow::anyhow!("Webhook {} failed after {} attempts: {}", url, WEBHOOK_ATTEMPTS, last_error))
+}
+
+/// Unique name for a job's scratch directory or file. Request ids can be reused by clients
+/// (or across runs), so the id is combined with the process id and a random tag.
+pub fn job_scratch_name(prefix: &str, id: &str) -> String {
+    let safe_id: String = id.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .take(64)
+        .collect();
+    let tag = Uuid::new_v4().simple().to_string();
+    format!("{}_{}_{}_{}", prefix, safe_id, std::process::id(), &tag[..8])
+}
+
+/// Verbosity of tool output echoed into Log events, from CAPSLAP_LOG_LEVEL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    Quiet,   // Never echo tool output (it can contain transcript text), only its size
+    Info,    // Truncated previews (default)
+    Debug,   // Full output, untruncated
+}
+
+pub fn log_level() -> LogLevel {
+    match std::env::var("CAPSLAP_LOG_LEVEL").ok().as_deref().map(str::trim) {
+        Some(l) if l.eq_ignore_ascii_case("quiet") => LogLevel::Quiet,
+        Some(l) if l.eq_ignore_ascii_case("debug") => LogLevel::Debug,
+        _ => LogLevel::Info,
+    }
+}
+
+/// Preview of `s` for log messages: at most `n` graphemes plus "…", respecting the log level
+pub fn preview(s: &str, n: usize) -> String {
+    preview_at(s, n, log_level())
+}
+
+fn preview_at(s: &str, n: usize, level: LogLevel) -> String {
+    match level {
+        LogLevel::Quiet => format!("<{} bytes hidden>", s.len()),
+        LogLevel::Debug => s.to_string(),
+        LogLevel::Info => truncate_graphemes(s, n),
+    }
+}
+
+/// Cut `s` after `n` grapheme clusters (never inside an emoji or combining sequence)
+pub fn truncate_graphemes(s: &str, n: usize) -> String {
+    match s.grapheme_indices(true).nth(n) {
+        Some((cut, _)) => format!("{}…", &s[..cut]),
+        None => s.to_string(),
+    }
+}
+
+/// Validate a destination the RPC caller asked us to write to.
+///
+/// The path is made absolute, `.`/`..` are resolved and symlinks in the existing part are
+/// followed. When CAPSLAP_ALLOWED_OUTPUT_ROOT is set (sandboxed deployments), the result must
+/// stay inside that root or the system temp dir (where job scratch files live).
+pub fn sanitize_output_path(path: &str) -> anyhow::Result<std::path::PathBuf> {
+    let root = std::env::var_os("CAPSLAP_ALLOWED_OUTPUT_ROOT").filter(|r| !r.is_empty());
+    sanitize_output_path_in(path, root.as_deref())
+}
+
+fn sanitize_output_path_in(path: &str, allowed_root: Option<&std::ffi::OsStr>) -> anyhow::Result<std::path::PathBuf> {
+    use std::path::{Component, PathBuf};
+
+    if path.trim().is_empty() || path.contains('\0') {
+        return Err(anyhow::anyhow!("Invalid output path: {:?}", path));
+    }
+    let raw = PathBuf::from(path);
+    let absolute = if raw.is_absolute() { raw } else { std::env::current_dir()?.join(raw) };
+
+    let mut normalized = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => normalized.push(component),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return Err(anyhow::anyhow!("Output path escapes the filesystem root: {}", path));
+                }
+            }
+            Component::Normal(part) => normalized.push(part),
+        }
+    }
+
+    // Follow symlinks in the part that already exists so a link can't smuggle writes elsewhere
+    let mut existing = normalized.as_path();
+    let mut rest = Vec::new();
+    while !existing.exists() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name.to_os_string());
+                existing = parent;
+            }
+            _ => break,
+        }
+    }
+    let mut resolved = existing.canonicalize().unwrap_or_else(|_| existing.to_path_buf());
+    resolved.extend(rest.iter().rev());
+
+    if let Some(root) = allowed_root {
+        let root = PathBuf::from(root).canonicalize()
+            .map_err(|e| anyhow::anyhow!("CAPSLAP_ALLOWED_OUTPUT_ROOT is not accessible: {}", e))?;
+        let temp = std::env::temp_dir().canonicalize().unwrap_or_else(|_| std::env::temp_dir());
+        if !resolved.starts_with(&root) && !resolved.starts_with(&temp) {
+            return Err(anyhow::anyhow!("Output path {} is outside the allowed root {}", path, root.display()));
+        }
+    }
+
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::time::Duration;
+
+    const TTL: Duration = Duration::from_secs(600);
+
+    #[tokio::test]
+    async fn duplicate_key_joins_then_reuses_the_original() {
+        let cache = IdempotencyCache::default();
+        let IdempotencyClaim::New(tx) = cache.claim_with_ttl("generateCaptions", "k1", TTL) else { panic!("first claim must run") };
+        let IdempotencyClaim::Running(rx) = cache.claim_with_ttl("generateCaptions", "k1", TTL) else { panic!("retry must join") };
+        // Keys are per method
+        assert!(matches!(cache.claim_with_ttl("transcribeSegments", "k1", TTL), IdempotencyClaim::New(_)));
+
+        let payload = serde_json::json!({ "id": "a", "result": { "ok": true } });
+        cache.finish("generateCaptions", "k1", tx, &payload);
+        assert_eq!(wait_for_original(rx).await, Some(payload.clone()));
+        match cache.claim_with_ttl("generateCaptions", "k1", TTL) {
+            IdempotencyClaim::Done(previous) => assert_eq!(previous, payload),
+            _ => panic!("finished job must be reused"),
+        }
+    }
+
+    #[test]
+    fn finished_results_expire_after_the_ttl() {
+        let cache = IdempotencyCache::default();
+        let IdempotencyClaim::New(tx) = cache.claim_with_ttl("m", "k", TTL) else { panic!() };
+        cache.finish("m", "k", tx, &serde_json::json!({}));
+        assert!(matches!(cache.claim_with_ttl("m", "k", TTL), IdempotencyClaim::Done(_)));
+        assert!(matches!(cache.claim_with_ttl("m", "k", Duration::ZERO), IdempotencyClaim::New(_)));
+    }
+
+    #[tokio::test]
+    async fn cancelled_original_frees_the_key() {
+        let cache = IdempotencyCache::default();
+        let IdempotencyClaim::New(tx) = cache.claim_with_ttl("m", "k", TTL) else { panic!() };
+        let IdempotencyClaim::Running(rx) = cache.claim_with_ttl("m", "k", TTL) else { panic!() };
+        drop(tx); // the job was aborted before finishing
+        assert_eq!(wait_for_original(rx).await, None);
+        assert!(matches!(cache.claim_with_ttl("m", "k", TTL), IdempotencyClaim::New(_)));
+    }
+
+    #[test]
+    fn sanitize_resolves_parent_components() {
+        let temp = std::env::temp_dir().canonicalize().unwrap();
+        let path = temp.join("capslap_out/../capslap_other/./clip.mp4");
+        let sanitized = sanitize_output_path_in(&path.to_string_lossy(), None).unwrap();
+        assert_eq!(sanitized, temp.join("capslap_other/clip.mp4"));
+    }
+
+    #[test]
+    fn sanitize_rejects_traversal_out_of_allowed_root() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let inside = root.join("out/../clip.mp4");
+        assert!(sanitize_output_path_in(&inside.to_string_lossy(), Some(root.as_os_str())).is_ok());
+
+        let escaping = root.join("out/../../capslap_escape.mp4");
+        let err = sanitize_output_path_in(&escaping.to_string_lossy(), Some(root.as_os_str())).unwrap_err();
+        assert!(err.to_string().contains("outside the allowed root"), "{}", err);
+    }
+
+    #[test]
+    fn sanitize_rejects_escaping_the_filesystem_root_and_bad_input() {
+        assert!(sanitize_output_path_in("/../etc/passwd", None).is_err());
+        assert!(sanitize_output_path_in("", None).is_err());
+        assert!(sanitize_output_path_in("out\0.mp4", None).is_err());
+    }
+
+    #[test]
+    fn truncate_graphemes_never_splits_a_cluster() {
+        assert_eq!(truncate_graphemes("hello", 10), "hello");
+        assert_eq!(truncate_graphemes("hello", 5), "hello");
+        assert_eq!(truncate_graphemes("hello", 2), "he…");
+        // Family emoji (ZWJ sequence) and a decomposed "é" each count as one
+        assert_eq!(truncate_graphemes("👨‍👩‍👧 hi", 1), "👨‍👩‍👧…");
+        assert_eq!(truncate_graphemes("e\u{301}te\u{301}", 1), "e\u{301}…");
+        assert_eq!(truncate_graphemes("日本語のテキスト", 3), "日本語…");
+    }
+
+    #[test]
+    fn preview_follows_the_log_level() {
+        assert_eq!(preview_at("transcript text", 4, LogLevel::Info), "tran…");
+        assert_eq!(preview_at("transcript text", 4, LogLevel::Debug), "transcript text");
+        assert_eq!(preview_at("transcript text", 4, LogLevel::Quiet), "<15 bytes hidden>");
+    }
+
+    #[test]
+    fn job_scratch_names_are_safe_and_unique() {
+        let name = job_scratch_name("capslap_job", "../req 1/é");
+        let prefix = format!("capslap_job____req_1___{}_", std::process::id());
+        assert!(name.starts_with(&prefix), "{}", name);
+        let tag = &name[prefix.len()..];
+        assert!(tag.len() == 8 && tag.chars().all(|c| c.is_ascii_hexdigit()), "{}", name);
+        assert!(!name.contains('/') && !name.contains('.'));
+        assert_ne!(job_scratch_name("capslap_job", "same"), job_scratch_name("capslap_job", "same"));
+
+        let long = job_scratch_name("p", &"x".repeat(500));
+        assert!(long.contains(&format!("_{}_", "x".repeat(64))) && !long.contains(&"x".repeat(65)));
+    }
+}
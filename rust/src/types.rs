@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -10,6 +11,12 @@ pub struct CaptionSegment {
     // Optional word-level timing (used when split_by_words = true)
     #[serde(default)]
     pub words: Vec<WordSpan>,
+    // Speaker index in order of first appearance (None = no diarization info)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speaker: Option<u32>,
+    // Editor override for all of this segment's words: true = emphasize, false = never (None = heuristic)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub highlight: Option<bool>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -18,6 +25,19 @@ pub struct WordSpan {
     pub start_ms: u64,
     pub end_ms: u64,
     pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speaker: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub highlight: Option<bool>,          // Editor override (wins over the segment's); None = heuristic
+}
+
+// Transcription prompt: either a single string or a map keyed by language tag ("pt-BR", "es"),
+// detected when not given; "default" is used when the language has no entry or is unknown
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum PromptSpec {
+    Text(String),
+    PerLanguage(HashMap<String, String>),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -28,8 +48,29 @@ pub struct TranscribeSegmentsParams {
     pub language: Option<String>,                 // Language hint for better accuracy
     pub split_by_words: bool,                     // Whether to split by words or segments
     pub api_key: Option<String>,                  // OpenAI API key
-    pub prompt: Option<String>,                   // Context prompt to improve accuracy
+    pub api_base_url: Option<String>,             // OpenAI-compatible API root for proxies/Azure (default: https://api.openai.com/v1)
+    pub api_org: Option<String>,                  // Sent as the OpenAI-Organization header
+    pub prompt: Option<PromptSpec>,               // Context prompt to improve accuracy
+    pub prompt_file: Option<String>,              // File whose contents are used as the prompt
+    pub transcription_mode: Option<String>,       // "auto" (default), "local" (never use API), "openai" (skip local)
     pub video_file: Option<String>,               // Original video file path (for JSON output location)
+    pub max_audio_bytes: Option<u64>,             // Refuse larger audio files (falls back to CAPSLAP_MAX_AUDIO_BYTES)
+    pub max_words_per_caption: Option<usize>,     // Split longer segment-level captions into timed chunks
+    pub max_segment_ms: Option<u64>,              // Split captions lasting longer than this at word boundaries
+    pub whisper_max_len: Option<u32>,             // whisper.cpp --max-len: max characters per segment (default: 0 = unlimited)
+    pub min_word_confidence: Option<f32>,         // Drop words below this probability (0-1) when splitting by words; unscored words are kept
+    pub model_path: Option<String>,               // Custom whisper.cpp model file (ggml/gguf); bypasses the built-in model lookup
+    #[serde(default)]
+    pub auto_download_model: bool,                // Download a missing local model instead of falling back to OpenAI
+    pub write_transcript_json: Option<bool>,      // Write the JSON export to disk (default: true); false = in-memory only
+    #[serde(default)]
+    pub export_subtitles: bool,                   // Also write .srt and .vtt sidecars next to the JSON export
+    #[serde(default)]
+    pub word_level_subtitles: bool,               // VTT sidecar gets per-word <c> cues when segments carry word timing
+    #[serde(default)]
+    pub stream_segments: bool,                    // Emit backend segments as `segment` events while transcribing, before the result
+    pub threads: Option<u32>,                     // whisper.cpp -t: threads per processor (default: whisper.cpp's own)
+    pub processors: Option<u32>,                  // whisper.cpp -p: parallel processors (default: whisper.cpp's own)
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -38,7 +79,26 @@ pub struct TranscribeSegmentsResult {
     pub segments: Vec<CaptionSegment>,            // Caption segments with timing
     pub full_text: String,                        // Complete transcription text
     pub duration: Option<f64>,                    // Total audio duration
-    pub json_file: String,                        // Path to saved JSON captions file
+    pub json_file: Option<String>,                // Path to saved JSON captions file (None when writeTranscriptJson is false)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub srt_file: Option<String>,                 // Path to SRT sidecar (only with exportSubtitles)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vtt_file: Option<String>,                 // Path to WebVTT sidecar (only with exportSubtitles)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,                 // Requested language, else the one the backend detected
+}
+
+// On-disk JSON export written next to the source (or in the job temp dir)
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TranscriptionExport {
+    pub segments: Vec<CaptionSegment>,            // Caption segments with timing
+    pub full_text: String,                        // Complete transcription text
+    pub duration: Option<f64>,                    // Total audio duration
+    pub split_by_words: bool,                     // Whether segments are per-word
+    pub model: String,                            // Model used for transcription
+    pub language: Option<String>,                 // Requested language (None = auto-detect)
+    pub generated_at: u64,                        // Unix timestamp (seconds)
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -62,6 +122,57 @@ pub struct WhisperCacheIndex {
     pub entries: Vec<WhisperCacheEntry>,
 }
 
+// Portable whisper cache archive: index entries with their responses inlined
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WhisperCacheArchive {
+    pub version: u32,                             // archive format version
+    pub entries: Vec<WhisperCacheArchiveEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WhisperCacheArchiveEntry {
+    pub audio_hash: String,                       // blake3 hash of audio file content
+    pub params_hash: String,                      // blake3 hash of transcription parameters
+    pub timestamp: u64,                           // unix timestamp from the exporting machine
+    pub response: WhisperResponse,                // cached transcription response
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportCacheParams {
+    pub out: String,                              // Path of the archive file to write
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportCacheResult {
+    pub path: String,                             // Path of the written archive
+    pub entries: usize,                           // Number of cache entries exported
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportCacheParams {
+    pub input: String,                            // Path of the archive file to import
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportCacheResult {
+    pub imported: usize,                          // Entries added or refreshed from the archive
+    pub skipped: usize,                           // Entries older than the local copy
+    pub total: usize,                             // Entries in the local cache after merging (LRU-capped)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ClearCacheResult {
+    pub entries_removed: usize,                   // Index entries dropped
+    pub bytes_freed: u64,                         // Size of the deleted cache files
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct WhisperSegment {
@@ -69,6 +180,8 @@ pub struct WhisperSegment {
     pub start: f64,
     pub end: f64,
     pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speaker: Option<String>,  // Speaker label from diarizing backends (e.g. "A", "B")
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -77,6 +190,8 @@ pub struct WhisperWord {
     pub word: String,
     pub start: f64,
     pub end: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f32>,  // Token probability from whisper.cpp (lowest piece for multi-token words)
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -95,37 +210,180 @@ pub struct WhisperResponse {
 pub struct ExtractAudioParams {
     pub input: String,            // Path to input video file
     pub codec: Option<String>,    // Audio codec to use (default: "aac")
-    pub out: Option<String>       // Output path (default: input filename with .m4a extension)
+    pub out: Option<String>,      // Output path (default: input filename with .m4a extension)
+    #[serde(default)]
+    pub write_waveform: bool,     // Also write peak data (JSON) and a waveform PNG for UI scrubbing
+    pub peaks_per_second: Option<u32>, // Waveform peak resolution (default: 100)
+    pub audio_sample_rate: Option<u32>, // Resample to this rate in Hz, e.g. 16000 for whisper (default: keep the source rate)
+    pub audio_channels: Option<u32>,  // Downmix to this many channels, e.g. 1 (default: keep the source layout)
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct ExtractAudioResult {
-    pub audio: String             // Path to the extracted audio file
+    pub audio: String,            // Path to the extracted audio file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub waveform: Option<WaveformResult>, // Set when write_waveform was requested
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
+pub struct WaveformResult {
+    pub peaks_json: String,       // Path to JSON: { peaksPerSecond, duration, peaks: [0.0..1.0] }
+    pub png: String,              // Path to rendered waveform image
+    pub peaks_per_second: u32,
+    pub count: usize,             // Number of peaks written
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WaveformPeaks {
+    pub peaks_per_second: u32,
+    pub duration: f64,            // Seconds of audio covered by the peaks
+    pub peaks: Vec<f32>,          // Max absolute amplitude per bucket, normalized to 0..1
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct GenerateCaptionsParams {
-    pub input_video: String,              // Path to input video file
-    pub export_formats: Vec<String>,      // List of aspect ratios to export (e.g., ["9:16", "16:9"])
+    pub input_video: String,              // Path to input video file, or an http(s) URL to download first
+    #[serde(default)]
+    pub export_formats: Vec<String>,      // List of aspect ratios to export (e.g., ["9:16", "16:9@1920x1080"] for an exact size); empty = one "original"-sized export
+    pub primary_format: Option<String>,   // Export encoded first and alone; announced with a formatReady event
+    pub output_dir: Option<String>,       // Directory for the exported videos, created if missing (default: next to the input; required for URL inputs)
+    pub filename_template: Option<String>, // Output file name with {name}, {format} and {index} placeholders (default: "{name}_{format}")
     pub karaoke: bool,                    // Whether to use karaoke-style highlighting
+    #[serde(default)]
+    pub karaoke_fill: bool,               // Karaoke sweeps the active color across each word as it's spoken (\kf) instead of swapping whole words
     pub font_name: Option<String>,        // Font name for captions (defaults to "Montserrat Black")
+    pub fonts_dir: Option<String>,        // Extra directory searched for font files (passed to libass)
+    pub transcribe_dir: Option<String>,   // Volume for extracted audio and transcription JSON (default: job temp dir)
+    pub scratch_dir: Option<String>,      // Volume for encode intermediates; outputs are moved into place (default: job temp dir)
+    pub scale_algo: Option<String>,       // ffmpeg scaler: "lanczos" (default), "bicubic", "spline", ...
+    pub round: Option<String>,            // Even-dimension rounding: "up" (default), "down" or "nearest"
+    pub max_output_height: Option<u32>,   // Downscale canvases taller than this many pixels (default: never downscale the source)
+    pub max_encode_concurrency: Option<usize>, // Formats encoded in parallel (default 2, clamped to 1..number of formats)
+    pub video_stream_index: Option<usize>, // Video stream to caption in multi-track inputs (default: 0)
+    pub target_lufs: Option<f32>,         // Integrated loudness target, e.g. -14 (forces an audio re-encode; default: off)
+    pub true_peak_db: Option<f32>,        // True-peak ceiling in dBTP, e.g. -1 (forces an audio re-encode; default: off)
+    #[serde(default)]
+    pub word_level_vtt: bool,             // Also write {input}.vtt with per-word <c> timing tags (in outputDir for URL inputs)
+    #[serde(default)]
+    pub write_fcpxml: bool,               // Also write {input}.fcpxml with one marker per caption for NLE import (in outputDir for URL inputs)
+    #[serde(default)]
+    pub snap_to_keyframes: bool,          // Shift caption starts slightly onto the encode's keyframe grid
+    pub keyframe_snap_tolerance_ms: Option<u64>, // Max shift when snapping (default: 200)
+    pub gap_behavior: Option<String>,     // Pauses between captions: "blank" (default) or "hold" (keep the previous caption up)
+    pub gap_hold_ms: Option<u64>,         // With "hold": only pauses shorter than this are bridged (default: 500)
     pub split_by_words: bool,             // Whether to split transcription by words or segments
     pub model: Option<String>,            // Whisper model to use (default: "whisper-1")
     pub language: Option<String>,         // Language hint for better accuracy
-    pub prompt: Option<String>,           // Context prompt to improve accuracy
+    pub prompt: Option<PromptSpec>,       // Context prompt (string or per-language map)
+    pub prompt_file: Option<String>,      // File whose contents are used as the prompt
+    pub transcription_mode: Option<String>, // "auto" (default), "local" or "openai"
+    pub max_audio_bytes: Option<u64>,     // Refuse larger extracted audio (falls back to CAPSLAP_MAX_AUDIO_BYTES)
+    pub max_words_per_caption: Option<usize>, // Cap words per caption when transcribing by segments
+    pub max_segment_ms: Option<u64>,      // Split captions lasting longer than this (prefers pauses between words)
+    pub whisper_max_len: Option<u32>,     // whisper.cpp --max-len for shorter local segments (default: 0 = unlimited)
+    pub min_word_confidence: Option<f32>, // Drop words whisper.cpp scored below this probability (0-1, word-level captions only)
+    pub model_path: Option<String>,       // Custom whisper.cpp model file (ggml/gguf) instead of a built-in model
+    #[serde(default)]
+    pub auto_download_model: bool,        // Download the requested whisper model if it isn't installed yet
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub text_color: Option<String>,       // Text color as hex string (e.g., "#ffffff")
+    pub text_color: Option<String>,       // Text color as hex string (e.g., "#ffffff", or "#ffffff80" for translucent)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub highlight_word_color: Option<String>, // Highlight word color as hex string
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub karaoke_past_color: Option<String>, // Karaoke: already-sung words as hex (default: dimmed text color)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub karaoke_active_color: Option<String>, // Karaoke: word being sung as hex (default: highlightWordColor)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub karaoke_future_color: Option<String>, // Karaoke: upcoming words as hex (default: text color)
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub outline_color: Option<String>,    // Outline color as hex string
     #[serde(default)]
     pub glow_effect: bool,                // Whether to apply glow effect
+    #[serde(default)]
+    pub broadcast_compliant: bool,        // Enforce subtitle timing rules (min/max duration, cue gaps, line cap); non-karaoke only
+    pub broadcast_rules: Option<BroadcastRules>, // Thresholds for broadcast mode (defaults: 1s / 7s / 80ms / 2 lines, no reading-speed limit)
+    pub background_box: Option<BackgroundBox>, // Solid or translucent box behind each caption (default: none)
+    pub fade_ms: Option<u32>,             // Fade each caption in and out over this many ms (default: hard cut)
+    #[serde(default)]
+    pub detect_existing_captions: bool,   // Heuristically check the source for burned-in captions (warns only)
+    #[serde(default)]
+    pub avoid_existing_captions: bool,    // With detection: move captions to the top when existing ones are likely
+    #[serde(default)]
+    pub clean_copy: bool,                 // Also export each format without captions (returned as rawVideo)
+    #[serde(default)]
+    pub keep_audio: bool,                 // Move the extracted audio next to the outputs as {name}_audio.mp3 (returned as audioFile)
+    #[serde(default)]
+    pub verify_captions: bool,            // After encoding, check each output really shows captions (logs warnings)
+    #[serde(default)]
+    pub fail_on_missing_captions: bool,   // With verifyCaptions: fail the job instead of only logging
+    #[serde(default)]
+    pub qa_side_by_side: bool,            // Also export clean | captioned stacked side by side (doubles the output width; returned as qaVideo)
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub position: Option<String>,         // Caption position: "bottom" or "center"
+    pub position: Option<String>,         // Caption position: "bottom" (default), "center" or "top"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text_align: Option<String>,       // Horizontal alignment: "center" (default), "left" or "right"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotation_deg: Option<f32>,        // Caption tilt in degrees (counter-clockwise, clamped to ±45, default 0)
+    pub safe_area_bottom_pct: Option<f32>, // Keep bottom captions above this % of height (default 12 = 88% placement)
+    pub safe_area_top_pct: Option<f32>,   // Top inset in % of height (centered captions sit within the safe band)
+    pub safe_area_horizontal_pct: Option<f32>, // Left/right inset in % of width (e.g. clear platform side buttons)
+    pub margin_v_pct: Option<f32>,        // Exact vertical placement, 0-100 % from the top (bottom/top rows; overrides position's default)
+    pub margin_h_pct: Option<f32>,        // Left/right margin in % of width (MarginL/MarginR)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lead_in_ms: Option<u64>,          // Show each caption this much before its first word (starts only; never overlaps the previous caption)
+    pub hook_duration_ms: Option<u64>,    // Captions starting in the first N ms get an extra size boost (default: off)
+    pub hook_scale: Option<f32>,          // Size multiplier for hook captions (default: 1.25, max 2.0)
+    pub max_chars_per_line: Option<usize>, // Wrap captions at this many characters instead of estimating from font metrics
+    pub max_lines: Option<usize>,         // Rows stacked per caption before a new one starts (default 1, max 4)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text_case: Option<String>,        // Caption casing: "upper" (default), "lower", "sentence" or "preserve" (as transcribed)
+    pub speaker_colors: Option<Vec<String>>, // Text color per speaker index as hex (cycled); unset = everyone uses textColor
+    pub speaker_turn_gap_ms: Option<u64>, // Without diarization info, alternate between two speakers at pauses this long
+    pub censor_words: Option<Vec<String>>, // Words masked with asterisks (whole words, case-insensitive); timing is kept
+    #[serde(default)]
+    pub censor_profanity: bool,           // Also mask a built-in list of common profanity
+    pub max_highlights: Option<u32>,      // Absolute cap on smart-highlighted words per video (default: ratio cap only)
+    pub highlight_intensity: Option<f32>, // Smart-highlight aggressiveness, 0 (almost never) to 1 (very often); default 0.5
+    pub language_fonts: Option<HashMap<String, String>>, // Default font per language code (e.g. {"ja": "Noto Sans JP"}) when fontName is unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caption_time_base_ms: Option<u64>, // Burn a source-timeline timecode overlay starting at this time (off by default; caption timing is unchanged)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reveal: Option<String>,           // Non-karaoke reveal: "word" (default) or "typewriter" (letter by letter)
     pub api_key: Option<String>,         // OpenAI API key
+    pub api_base_url: Option<String>,    // OpenAI-compatible API root (default: https://api.openai.com/v1)
+    pub api_org: Option<String>,         // OpenAI-Organization header
+    pub write_tool_metadata: Option<bool>, // Tag outputs with CapSlap comment + source title (default: true)
+    pub metadata_title: Option<String>,   // Override the output title tag (default: source file name)
+}
+
+// Broadcast/accessibility subtitle timing rules
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct BroadcastRules {
+    pub min_duration_ms: u64,             // Shortest time a cue stays on screen
+    pub max_duration_ms: u64,             // Longer cues are split
+    pub min_gap_ms: u64,                  // Gap kept between consecutive cues (~2 frames)
+    pub max_lines: u32,                   // Rows per cue when short cues are merged
+    pub max_chars_per_second: Option<f32>, // Reading speed: cues are held longer (up to the next cue) until they read at this rate
+}
+
+// Box drawn behind the caption text
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BackgroundBox {
+    pub color: Option<String>,            // Box color as hex (default "#000000")
+    pub opacity: Option<f32>,             // 0 (invisible) to 1 (solid), default 0.6
+    pub padding: Option<u32>,             // Pixels between the text and the box edge (default: a quarter of the font size)
+}
+
+impl Default for BroadcastRules {
+    fn default() -> Self {
+        BroadcastRules { min_duration_ms: 1000, max_duration_ms: 7000, min_gap_ms: 80, max_lines: 2, max_chars_per_second: None }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -135,14 +393,75 @@ pub struct GenerateCaptionsResult {
     pub audio_file: String,               // Path to extracted audio file
     pub transcription: TranscribeSegmentsResult,  // Transcription results and segments
     pub captioned_videos: Vec<CaptionedVideoResult>, // List of generated videos with captions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub existing_captions: Option<crate::video::ExistingCaptionsReport>, // Set when detect_existing_captions was requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vtt_file: Option<String>,         // Word-level WebVTT path when word_level_vtt was requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fcpxml_file: Option<String>,      // FCPXML marker export when write_fcpxml was requested
+    #[serde(default)]
+    pub audio_only: bool,                 // Input had no video: no captioned videos, subtitles are in transcription.srtFile/vttFile
+}
+
+// Re-burn existing segments with new styling (no transcription)
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BurnCaptionsParams {
+    pub segments: Vec<CaptionSegment>,    // Segments to render (e.g. loaded from the transcript JSON)
+    #[serde(flatten)]
+    pub options: GenerateCaptionsParams,  // Input, formats and styling, as for generateCaptions (transcription fields are ignored)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BurnCaptionsResult {
+    pub probe_result: crate::video::ProbeResult,  // Original video information
+    pub captioned_videos: Vec<CaptionedVideoResult>, // List of generated videos with captions
+}
+
+// Batch captioning: one shared template applied to many input videos
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerateCaptionsBatchParams {
+    pub inputs: Vec<BatchCaptionInput>,           // Videos to caption, in order
+    pub template: serde_json::Value,              // Shared generateCaptions params (inputVideo is filled per file)
+    pub concurrency: Option<usize>,               // Files processed at once (default: 1)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchCaptionInput {
+    pub input_video: String,                      // Path to input video file
+    pub overrides: Option<serde_json::Value>,     // Per-file params merged over the template
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerateCaptionsBatchResult {
+    pub results: Vec<BatchCaptionResult>,         // One entry per input, in input order
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchCaptionResult {
+    pub input_video: String,
+    pub sub_id: String,                           // Id used to tag this file's progress events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<GenerateCaptionsResult>,   // Set on success
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<crate::rpc::ErrorBody>,     // Set on failure ({ code, message })
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct CaptionedVideoResult {
     pub format: String,                   // The aspect ratio format (e.g., "9:16")
-    pub raw_video: String,                // Path to reformatted video without captions
+    pub raw_video: String,                // Path to reformatted video without captions (empty unless clean_copy)
     pub captioned_video: String,          // Path to final video with captions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qa_video: Option<String>,         // Clean and captioned side by side (qaSideBySide only)
     pub width: u32,                       // Video width
     pub height: u32,                      // Video height
 }
@@ -174,3 +493,166 @@ pub struct DeleteModelResult {
     pub model: String,                    // Model name that was deleted
     pub path: String,                     // Path where model was deleted from
 }
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyModelParams {
+    pub model: String,                    // Model name: "tiny", "base", "small", "medium", "large"
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyModelResult {
+    pub model: String,                    // Model name that was checked
+    pub path: String,                     // Path of the checked model file
+    pub valid: bool,                      // Header is ggml and the SHA-1 matches the published one
+    pub checksum: Option<String>,         // SHA-1 of the file (None when it couldn't be read)
+    pub expected_checksum: Option<String>, // Published SHA-1 for the model file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,            // Why the model is invalid
+}
+
+// Bulk model management types
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadModelsParams {
+    pub models: Option<Vec<String>>,      // Models to fetch (default: the recommended "base" + "small")
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelOperationResult {
+    pub model: String,
+    pub success: bool,
+    pub bytes: u64,                       // Bytes downloaded or freed for this model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkModelResult {
+    pub results: Vec<ModelOperationResult>, // Per-model outcome, in processing order
+    pub total_bytes: u64,                 // Total downloaded or freed
+}
+
+// Entry of listModels
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelInfo {
+    pub name: String,                     // Model name: "tiny", "base", "small", "medium", "large"
+    pub installed: bool,                  // Model file is present in the models directory
+    pub path: String,                     // Where the model is (or will be saved when downloaded)
+    pub size_bytes: u64,                  // Size on disk when installed, else the approximate download size
+    pub download_url: String,             // Where downloadModel fetches it from
+}
+
+// Media metadata for a file, without starting a job
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ProbeVideoParams {
+    pub input: String,                    // Video (or audio) file to inspect
+}
+
+// Language detection on a short clip, before committing to a full transcription
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DetectLanguageParams {
+    pub input: String,                    // Audio or video file
+    pub model: Option<String>,            // Local whisper model (default: tiny)
+    pub model_path: Option<String>,       // Custom whisper.cpp model file (overrides model)
+    pub api_key: Option<String>,          // OpenAI fallback when local whisper isn't available
+    pub api_base_url: Option<String>,     // OpenAI-compatible API root (default: https://api.openai.com/v1)
+    pub api_org: Option<String>,          // OpenAI-Organization header
+    pub clip_seconds: Option<u32>,        // Length analyzed from the start (default: 30)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DetectLanguageResult {
+    pub language: String,                 // ISO 639-1 code, e.g. "en"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f32>,          // whisper.cpp's probability (0-1); the OpenAI API doesn't report one
+    pub source: String,                   // "whisper.cpp" or "openai"
+}
+
+// OpenAI cost estimate types
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EstimateCostParams {
+    pub input: String,                    // Audio or video file to estimate
+    pub model: Option<String>,            // Same meaning as in transcribeSegments
+    pub transcription_mode: Option<String>, // "auto" (default), "local" or "openai"
+    pub rate_per_minute: Option<f64>,     // USD per minute override (falls back to CAPSLAP_OPENAI_USD_PER_MINUTE)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EstimateCostResult {
+    pub duration_seconds: f64,            // Probed media duration
+    pub minutes: f64,                     // Billable audio minutes
+    pub rate_per_minute: f64,             // Rate used for the estimate
+    pub uses_openai: bool,                // Whether this mode/model would reach the API at all
+    pub estimated_usd: f64,               // minutes x rate, or 0 when transcription stays local
+}
+
+// Highlight analysis types (preview of smart-highlight decisions without rendering)
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalyzeHighlightsParams {
+    pub segments: Option<Vec<CaptionSegment>>,    // Segments to analyze (e.g. loaded from the JSON export)
+    pub transcription: Option<TranscribeSegmentsParams>, // Transcribe first when no segments are given
+    pub width: Option<u32>,                       // Canvas width used for line splitting (default: 1080)
+    pub height: Option<u32>,                      // Canvas height used for font sizing (default: 1920)
+    pub max_highlights: Option<u32>,              // Same absolute highlight cap as generateCaptions
+    pub highlight_intensity: Option<f32>,         // Same highlight aggressiveness as generateCaptions
+    pub caption_params: Option<serde_json::Value>, // generateCaptions styling (fontName, textCase, maxCharsPerLine, maxLines, karaoke, safe areas, margins...) so lines split as rendered
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalyzeHighlightsResult {
+    pub lines: Vec<HighlightLineAnalysis>,        // One entry per rendered caption line
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HighlightLineAnalysis {
+    pub phrase_index: usize,                      // Index of the coalesced phrase this line belongs to
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub tokens: Vec<String>,                      // Original (un-normalized) tokens
+    pub highlight_index: Option<usize>,           // Chosen token, None when nothing cleared the threshold
+    pub threshold: f32,                           // Effective score threshold for this line
+    pub scores: Vec<HighlightScore>,              // Score breakdown for each candidate token
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HighlightScore {
+    pub token_index: usize,
+    pub token: String,
+    pub digit_currency: f32,                      // Contains digits or $, %, #
+    pub rarity: f32,                              // Rare within the whole transcript
+    pub proper_noun: f32,                         // Capitalized mid-phrase
+    pub power_word: f32,                          // In the power-word list
+    pub content_suffix: f32,                      // -ing / -ed / -ly
+    pub length: f32,                              // Longer than the line's median token
+    pub duration: f32,                            // Held longer than average
+    pub pause: f32,                               // Followed by a pause or ends the line
+    pub repetition_penalty: f32,                  // Repeated too often recently
+    pub caps_penalty: f32,                        // Shouted token in mixed-case text
+    pub total: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelParams {
+    pub job_id: String,                           // Id of the running request to abort
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelResult {
+    pub cancelled: bool,                          // False when no job with that id was running
+}
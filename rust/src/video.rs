@@ -1,4 +1,4 @@
-use crate::rpc::RpcEvent;
+use crate::rpc::{CapslapError, RpcEvent};
 use crate::whisper::{find_ffmpeg_binary, find_ffprobe_binary};
 use serde::{Deserialize, Serialize};
 use tokio::process::Command as TokioCommand;
@@ -43,9 +43,28 @@ fn get_ffmpeg_path_sync() -> String {
     "ffmpeg".to_string() // Fallback
 }
 
+/// Fonts directory for this job: a user-supplied directory wins over the discovered ones
+pub fn resolve_fonts_dir(user_dir: Option<&str>) -> Option<std::path::PathBuf> {
+    if let Some(dir) = user_dir {
+        let dir = std::path::PathBuf::from(dir);
+        if dir.is_dir() {
+            return Some(dir);
+        }
+    }
+    get_fonts_dir()
+}
+
 /// Get the fonts directory path for subtitle rendering
 /// Returns None if fonts directory cannot be found (libass will use system fonts)
 fn get_fonts_dir() -> Option<std::path::PathBuf> {
+    // Allow override via environment
+    if let Ok(dir) = std::env::var("CAPSLAP_FONTS_DIR") {
+        let dir = std::path::PathBuf::from(dir);
+        if dir.is_dir() {
+            return Some(dir);
+        }
+    }
+
     // Priority 1: Development environment
     let dev_fonts = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/fonts");
     if dev_fonts.exists() && dev_fonts.is_dir() {
@@ -70,10 +89,80 @@ fn get_fonts_dir() -> Option<std::path::PathBuf> {
         }
     }
 
+    // Priority 3: Project-local fonts/ folder (working directory)
+    if let Ok(cwd) = std::env::current_dir() {
+        let project_fonts = cwd.join("fonts");
+        if project_fonts.is_dir() {
+            return Some(project_fonts);
+        }
+    }
+
     // No custom fonts directory found - libass will use system fonts
     None
 }
 
+/// System font locations libass/fontconfig search on each platform
+fn get_system_fonts_dirs() -> Vec<std::path::PathBuf> {
+    let mut dirs = Vec::new();
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")).map(std::path::PathBuf::from);
+
+    #[cfg(target_os = "macos")]
+    {
+        dirs.push(std::path::PathBuf::from("/System/Library/Fonts"));
+        dirs.push(std::path::PathBuf::from("/Library/Fonts"));
+        if let Some(home) = &home { dirs.push(home.join("Library/Fonts")); }
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        dirs.push(std::path::PathBuf::from(r"C:\Windows\Fonts"));
+        if let Some(home) = &home { dirs.push(home.join(r"AppData\Local\Microsoft\Windows\Fonts")); }
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        dirs.push(std::path::PathBuf::from("/usr/share/fonts"));
+        dirs.push(std::path::PathBuf::from("/usr/local/share/fonts"));
+        if let Some(home) = &home {
+            dirs.push(home.join(".fonts"));
+            dirs.push(home.join(".local/share/fonts"));
+        }
+    }
+
+    dirs
+}
+
+/// Best-effort check that a font file for `font_name` exists in the fonts dir or system font
+/// folders. Matches on file names with case, spaces, dashes and underscores ignored
+/// ("Montserrat Black" matches "Montserrat-Black.ttf").
+pub fn font_is_discoverable(font_name: &str, fonts_dir: Option<&std::path::Path>) -> bool {
+    fn normalize(s: &str) -> String {
+        s.chars().filter(|c| c.is_alphanumeric()).flat_map(|c| c.to_lowercase()).collect()
+    }
+    fn search(dir: &std::path::Path, wanted: &str, depth: u32) -> bool {
+        let Ok(entries) = std::fs::read_dir(dir) else { return false; };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                if depth > 0 && search(&path, wanted, depth - 1) { return true; }
+                continue;
+            }
+            let is_font = path.extension()
+                .map(|e| matches!(e.to_string_lossy().to_lowercase().as_str(), "ttf" | "otf" | "ttc"))
+                .unwrap_or(false);
+            if is_font && path.file_stem().map(|n| normalize(&n.to_string_lossy()) == wanted).unwrap_or(false) {
+                return true;
+            }
+        }
+        false
+    }
+
+    let wanted = normalize(font_name);
+    fonts_dir.into_iter().map(|d| d.to_path_buf())
+        .chain(get_system_fonts_dirs())
+        .any(|dir| search(&dir, &wanted, 3))
+}
+
 /// Properly escape subtitle file paths for FFmpeg subtitle filter
 /// Handles Windows paths with drive letters and special characters
 pub fn escape_subtitle_path(path: &str) -> String {
@@ -95,8 +184,80 @@ pub enum TargetAR {
     AR1x1
 }
 
-fn round_even(x: u32) -> u32 {
-    (x & !1) + (x & 1) // ensure even for yuv420
+pub fn round_even(x: u32) -> u32 {
+    (x + 1) & !1 // next even number (yuv420 needs even sizes)
+}
+
+/// How odd (or fractional) dimensions are snapped to the even sizes yuv420 needs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvenRounding {
+    Down,     // Never grow: may crop the canvas by 1px
+    #[default]
+    Up,       // Never shrink: may add a 1px pad (historical behaviour)
+    Nearest,  // Closest even size to the exact value
+}
+
+impl EvenRounding {
+    pub fn parse(mode: Option<&str>) -> anyhow::Result<Self> {
+        match mode {
+            None | Some("up") => Ok(Self::Up),
+            Some("down") => Ok(Self::Down),
+            Some("nearest") => Ok(Self::Nearest),
+            Some(other) => Err(anyhow::anyhow!("Unknown rounding mode: {}. Supported: down, up, nearest", other)),
+        }
+    }
+
+    /// Snap an exact size to an even pixel count (at least 2)
+    pub fn apply(self, x: f64) -> u32 {
+        // The epsilons keep float noise on exact sizes (1079.9999999 or 1080.0000001) from
+        // moving the result by 2px
+        let even = match self {
+            // A real floor: 1081.6 -> 1080
+            Self::Down => ((x + 1e-6).floor().max(0.0) as u32) & !1,
+            // A real ceiling: 1920.4 -> 1922
+            Self::Up => round_even((x - 1e-6).ceil().max(0.0) as u32),
+            Self::Nearest => ((x / 2.0).round().max(0.0) as u32) * 2,
+        };
+        even.max(2)
+    }
+}
+
+/// Exact scale + pad placement of a source inside an even canvas
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FitPad {
+    pub canvas_w: u32,
+    pub canvas_h: u32,
+    pub scaled: Option<(u32, u32)>,  // Even scaled source size; None = let ffmpeg fit it (unknown source)
+}
+
+impl FitPad {
+    pub fn new(source: Option<(u32, u32)>, canvas_w: u32, canvas_h: u32, rounding: EvenRounding) -> Self {
+        let scaled = source.filter(|&(w, h)| w > 0 && h > 0).map(|(src_w, src_h)| {
+            let ratio = (canvas_w as f64 / src_w as f64).min(canvas_h as f64 / src_h as f64);
+            (
+                rounding.apply(src_w as f64 * ratio).min(canvas_w),
+                rounding.apply(src_h as f64 * ratio).min(canvas_h),
+            )
+        });
+        Self { canvas_w, canvas_h, scaled }
+    }
+
+    /// `scale=...,pad=...` pair; both sizes are even so the centering offsets are exact
+    fn filters(&self, scale_algo: &str) -> (String, String) {
+        match self.scaled {
+            Some((w, h)) => (
+                format!("scale={}:{}:flags={}", w, h, scale_algo),
+                format!("pad={}:{}:{}:{}:black", self.canvas_w, self.canvas_h, (self.canvas_w - w) / 2, (self.canvas_h - h) / 2),
+            ),
+            None => (
+                format!(
+                    "scale={}:{}:flags={}:force_original_aspect_ratio=decrease:force_divisible_by=2",
+                    self.canvas_w, self.canvas_h, scale_algo
+                ),
+                format!("pad={}:{}:(ow-iw)/2:(oh-ih)/2:black", self.canvas_w, self.canvas_h),
+            ),
+        }
+    }
 }
 
 fn ar_wh(ar: TargetAR) -> (u32, u32) {
@@ -111,17 +272,22 @@ fn ar_wh(ar: TargetAR) -> (u32, u32) {
 /// Choose a canvas that does NOT require scaling the source frame.
 /// Strategy: pick the variant (keep-width or keep-height) where canvas >= source on *both* axes.
 pub fn canvas_no_downscale(src_w: u32, src_h: u32, ar: TargetAR) -> (u32, u32) {
+    canvas_no_downscale_with(src_w, src_h, ar, EvenRounding::Up)
+}
+
+/// `canvas_no_downscale` with an explicit even-rounding direction
+pub fn canvas_no_downscale_with(src_w: u32, src_h: u32, ar: TargetAR, rounding: EvenRounding) -> (u32, u32) {
     let (aw, ah) = ar_wh(ar);
     // candidate A: keep HEIGHT (canvas_h = src_h)
-    let cand_a_w = ((src_h as f32) * (aw as f32) / (ah as f32)).round() as u32;
-    let cand_a_h = src_h;
+    let cand_a_w = (src_h as f64) * (aw as f64) / (ah as f64);
+    let cand_a_h = src_h as f64;
 
     // candidate B: keep WIDTH (canvas_w = src_w)
-    let cand_b_w = src_w;
-    let cand_b_h = ((src_w as f32) * (ah as f32) / (aw as f32)).round() as u32;
+    let cand_b_w = src_w as f64;
+    let cand_b_h = (src_w as f64) * (ah as f64) / (aw as f64);
 
-    let (a_w, a_h) = (round_even(cand_a_w.max(2)), round_even(cand_a_h.max(2)));
-    let (b_w, b_h) = (round_even(cand_b_w.max(2)), round_even(cand_b_h.max(2)));
+    let (a_w, a_h) = (rounding.apply(cand_a_w), rounding.apply(cand_a_h));
+    let (b_w, b_h) = (rounding.apply(cand_b_w), rounding.apply(cand_b_h));
 
     // Pick the one that doesn't force downscale; if both qualify, take the smaller area.
     let a_ok = a_w >= src_w && a_h >= src_h;
@@ -141,6 +307,17 @@ pub fn canvas_no_downscale(src_w: u32, src_h: u32, ar: TargetAR) -> (u32, u32) {
     (out_w, out_h)
 }
 
+/// Scale a canvas down so its height is at most `max_h`, keeping the aspect ratio.
+/// Overrides the no-downscale policy; canvases already within the cap are returned as-is.
+pub fn cap_canvas_height(w: u32, h: u32, max_h: u32, rounding: EvenRounding) -> (u32, u32) {
+    if max_h == 0 || h <= max_h {
+        return (w, h);
+    }
+    // Height rounds down so an odd cap is never exceeded
+    let capped_h = EvenRounding::Down.apply(max_h as f64);
+    (rounding.apply(w as f64 * capped_h as f64 / h as f64), capped_h)
+}
+
 /// Build a vf that keeps full source, centers it, and pads to target canvas.
 /// NOTE: No scaling! (video stays native pixels)
 fn vf_fit_pad_no_scale(src_w: u32, src_h: u32, ar: TargetAR, pad_color: &str) -> String {
@@ -174,20 +351,66 @@ pub fn parse_target_ar(format: &str) -> anyhow::Result<TargetAR> {
     }
 }
 
+/// Largest width or height accepted as an exact export size
+const MAX_EXPORT_DIMENSION: u32 = 8192;
+
+/// Split an export format into its aspect ratio and optional exact size: "9:16" or
+/// "9:16@1080x1920". The size must be even (yuv420) and match the aspect ratio within 1%.
+pub fn parse_export_format(format: &str) -> anyhow::Result<(TargetAR, Option<(u32, u32)>)> {
+    let Some((ar, size)) = format.split_once('@') else {
+        return Ok((parse_target_ar(format)?, None));
+    };
+    let target_ar = parse_target_ar(ar)?;
+    let (w, h) = size.split_once(['x', 'X'])
+        .and_then(|(w, h)| Some((w.trim().parse::<u32>().ok()?, h.trim().parse::<u32>().ok()?)))
+        .ok_or_else(|| anyhow::anyhow!("Invalid size in export format {}: expected WIDTHxHEIGHT (e.g. 9:16@1080x1920)", format))?;
+    if !(2..=MAX_EXPORT_DIMENSION).contains(&w) || !(2..=MAX_EXPORT_DIMENSION).contains(&h) {
+        return Err(anyhow::anyhow!("Export size {}x{} is out of range (2 to {} pixels per side)", w, h, MAX_EXPORT_DIMENSION));
+    }
+    if w % 2 != 0 || h % 2 != 0 {
+        return Err(anyhow::anyhow!("Export size {}x{} must have even width and height", w, h));
+    }
+    let (ref_w, ref_h) = maybe_scale_to_standard(target_ar, true).unwrap_or((1, 1));
+    let mismatch = (w as f64 / h as f64) / (ref_w as f64 / ref_h as f64) - 1.0;
+    if mismatch.abs() > 0.01 {
+        return Err(anyhow::anyhow!("Export size {}x{} does not match the {} aspect ratio", w, h, ar));
+    }
+    Ok((target_ar, Some((w, h))))
+}
+
+/// Scaler used for fit+pad when none is requested (sharp on downscale)
+pub const DEFAULT_SCALE_ALGO: &str = "lanczos";
+
+/// Validate a user-chosen swscale algorithm against the flags ffmpeg accepts
+pub fn resolve_scale_algo(algo: Option<&str>) -> anyhow::Result<&'static str> {
+    const KNOWN: [&str; 11] = [
+        "fast_bilinear", "bilinear", "bicubic", "experimental", "neighbor",
+        "area", "bicublin", "gauss", "sinc", "lanczos", "spline",
+    ];
+    match algo.map(|a| a.trim().to_ascii_lowercase()) {
+        None => Ok(DEFAULT_SCALE_ALGO),
+        Some(a) => KNOWN.iter().copied().find(|k| *k == a).ok_or_else(|| anyhow::anyhow!(
+            "Unknown scale algorithm: {}. Supported: {}", a, KNOWN.join(", ")
+        )),
+    }
+}
+
 /// Build a unified video filter for fit+pad operations with high-quality scaling
 /// This creates a single filtergraph that handles scaling and padding efficiently
 /// Optimized for hardware encoders (VideoToolbox prefers NV12, others use yuv420p)
 pub fn build_fitpad_filter(target_w: u32, target_h: u32, subtitle_path: Option<&str>) -> String {
-    build_fitpad_filter_with_format(target_w, target_h, subtitle_path, HardwareEncoder::Software)
+    let fit = FitPad::new(None, target_w, target_h, EvenRounding::default());
+    build_fitpad_filter_with_format(&fit, subtitle_path, None, DEFAULT_SCALE_ALGO, HardwareEncoder::Software)
 }
 
 /// Build optimized video filter with encoder-specific format optimization
 /// VideoToolbox: ends with NV12 to avoid hidden swscale conversions
 /// Others: ends with yuv420p for broad compatibility
 pub fn build_fitpad_filter_with_format(
-    target_w: u32,
-    target_h: u32,
+    fit: &FitPad,
     subtitle_path: Option<&str>,
+    fonts_dir: Option<&str>,
+    scale_algo: &str,
     encoder: HardwareEncoder
 ) -> String {
     // Pre-calculate approximate capacity to avoid reallocations
@@ -215,38 +438,98 @@ pub fn build_fitpad_filter_with_format(
         add_filter("format=yuv444p");
     }
 
-    // High-quality scaling with letterboxing - BEFORE subtitles for final resolution text
-    add_filter(&format!(
-        "scale={}:{}:flags=lanczos:force_original_aspect_ratio=decrease",
-        target_w, target_h
-    ));
-
-    // Pad to exact target dimensions with black bars - BEFORE subtitles
-    add_filter(&format!(
-        "pad={}:{}:(ow-iw)/2:(oh-ih)/2:black",
-        target_w, target_h
-    ));
+    // High-quality scaling with letterboxing, then pad to the exact target with black bars -
+    // both BEFORE subtitles for final resolution text
+    let (scale, pad) = fit.filters(scale_algo);
+    add_filter(&scale);
+    add_filter(&pad);
 
     if let Some(subtitle_path) = subtitle_path {
-        let escaped_path = escape_subtitle_path(subtitle_path);
-        // Get fonts directory (development or bundled)
-        if let Some(fonts_dir) = get_fonts_dir() {
-            add_filter(&format!("subtitles={}:fontsdir={}", escaped_path, fonts_dir.display()));
-        } else {
-            // No fontsdir specified - libass will use system fonts
-            add_filter(&format!("subtitles={}", escaped_path));
-        }
+        add_filter(&subtitles_filter(subtitle_path, fonts_dir));
     }
 
     // End with encoder-optimized format to avoid hidden conversions
-    let final_format = match encoder {
+    add_filter(&format!("format={}", final_pix_fmt(encoder)));
+
+    result
+}
+
+/// Filtergraph for one decode feeding two outputs: `[captioned]` with burned subtitles and
+/// `[clean]` without, sharing the same fit+pad work. Use with `-filter_complex`.
+pub fn build_fitpad_split_graph(
+    video_stream: usize,
+    fit: &FitPad,
+    subtitle_path: &str,
+    fonts_dir: Option<&str>,
+    scale_algo: &str,
+    encoder: HardwareEncoder
+) -> String {
+    let final_format = final_pix_fmt(encoder);
+    let (scale, pad) = fit.filters(scale_algo);
+    format!(
+        "[0:v:{stream}]format=yuv444p,{scale},{pad},split=2[fit_cap][fit_clean];\
+         [fit_cap]{subs},format={fmt}[captioned];\
+         [fit_clean]format={fmt}[clean]",
+        stream = video_stream,
+        scale = scale, pad = pad,
+        subs = subtitles_filter(subtitle_path, fonts_dir),
+        fmt = final_format
+    )
+}
+
+/// `subtitles=` filter with the resolved fonts directory (if any)
+pub(crate) fn subtitles_filter(subtitle_path: &str, fonts_dir: Option<&str>) -> String {
+    let escaped_path = escape_subtitle_path(subtitle_path);
+    // Get fonts directory (user-supplied, development, bundled or project-local)
+    if let Some(fonts_dir) = resolve_fonts_dir(fonts_dir) {
+        let escaped_fonts = escape_subtitle_path(&fonts_dir.to_string_lossy());
+        format!("subtitles={}:fontsdir={}", escaped_path, escaped_fonts)
+    } else {
+        // No fontsdir specified - libass will use system fonts
+        format!("subtitles={}", escaped_path)
+    }
+}
+
+/// Encoder-optimized output pixel format to avoid hidden conversions
+fn final_pix_fmt(encoder: HardwareEncoder) -> &'static str {
+    match encoder {
         HardwareEncoder::VideoToolbox => "nv12",  // VideoToolbox optimization
         HardwareEncoder::Nvenc => "nv12",        // NVENC also prefers NV12
         HardwareEncoder::Software => "yuv420p",  // libx264 broad compatibility
-    };
-    add_filter(&format!("format={}", final_format));
+    }
+}
 
-    result
+/// Output loudness normalization (ffmpeg `loudnorm`, single pass)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoudnessTarget {
+    pub lufs: f32,          // Integrated loudness (I)
+    pub true_peak_db: f32,  // True-peak ceiling (TP)
+}
+
+impl LoudnessTarget {
+    const DEFAULT_LUFS: f32 = -14.0;
+    const DEFAULT_TRUE_PEAK_DB: f32 = -1.0;
+
+    /// Off unless either value is given; the other falls back to common platform targets
+    pub fn resolve(lufs: Option<f32>, true_peak_db: Option<f32>) -> anyhow::Result<Option<Self>> {
+        if lufs.is_none() && true_peak_db.is_none() {
+            return Ok(None);
+        }
+        let lufs = lufs.unwrap_or(Self::DEFAULT_LUFS);
+        let true_peak_db = true_peak_db.unwrap_or(Self::DEFAULT_TRUE_PEAK_DB);
+        if !(-70.0..=-5.0).contains(&lufs) {
+            return Err(anyhow::anyhow!("targetLufs must be between -70 and -5, got {}", lufs));
+        }
+        if !(-9.0..=0.0).contains(&true_peak_db) {
+            return Err(anyhow::anyhow!("truePeakDb must be between -9 and 0, got {}", true_peak_db));
+        }
+        Ok(Some(Self { lufs, true_peak_db }))
+    }
+
+    /// Audio filter; loudnorm upsamples internally, so the output rate is pinned back to 48k
+    pub fn filter(&self) -> String {
+        format!("loudnorm=I={:.1}:TP={:.1}:LRA=11,aresample=48000", self.lufs, self.true_peak_db)
+    }
 }
 
 /// Determine the best audio codec and settings based on input analysis
@@ -300,6 +583,31 @@ pub fn determine_audio_codec(probe_result: Option<&crate::video::ProbeResult>) -
 }
 
 
+/// Free space in bytes on the filesystem holding `path`, if it can be determined
+pub fn available_disk_space(path: &std::path::Path) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        let output = std::process::Command::new("df").arg("-Pk").arg(path).output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        parse_df_available(&String::from_utf8_lossy(&output.stdout))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// Available bytes from `df -Pk` output
+/// POSIX format: header line, then "fs blocks used available capacity mount"
+fn parse_df_available(stdout: &str) -> Option<u64> {
+    let line = stdout.lines().nth(1)?;
+    let available_kb: u64 = line.split_whitespace().nth(3)?.parse().ok()?;
+    Some(available_kb * 1024)
+}
+
 /// Check if the current platform is macOS
 pub fn is_macos() -> bool {
     cfg!(target_os = "macos")
@@ -346,28 +654,37 @@ pub async fn is_nvenc_available() -> bool {
 /// Check if whisper.cpp CLI is available (preferred method)
 pub async fn is_whisper_cpp_available() -> bool {
     // Use the new cross-platform whisper binary detection from whisper.rs
-    match crate::whisper::find_whisper_binary().await {
-        Ok(_) => true,
-        Err(_) => false,
-    }
+    crate::whisper::find_whisper_binary().await.is_ok()
 }
 
 /// Check if FFmpeg has built-in Whisper support (requires FFmpeg 8.0+)
 /// This function tests if ffmpeg supports the whisper audio filter
 pub async fn is_ffmpeg_whisper_available() -> bool {
-    // Test if ffmpeg has whisper filter available
-    let result = Command::new(get_ffmpeg_path_sync())
-        .args(["-hide_banner", "-filters"])
-        .output();
+    // Checked once per process: the ffmpeg binary doesn't change under us
+    static AVAILABLE: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        let ffmpeg = get_ffmpeg_path_sync();
+        let listed = Command::new(&ffmpeg)
+            .args(["-hide_banner", "-filters"])
+            .output()
+            .map(|o| filter_list_has(&String::from_utf8_lossy(&o.stdout), "whisper"))
+            .unwrap_or(false);
+        // Builds without the filter answer `-h filter=whisper` with "Unknown filter"
+        listed && Command::new(&ffmpeg)
+            .args(["-hide_banner", "-h", "filter=whisper"])
+            .output()
+            .map(|o| o.status.success() && String::from_utf8_lossy(&o.stdout).trim_start().starts_with("Filter whisper"))
+            .unwrap_or(false)
+    })
+}
 
-    match result {
-        Ok(output) => {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            // Look for whisper filter in the audio filters list
-            stdout.contains("whisper")
-        }
-        Err(_) => false,
-    }
+/// Whether `ffmpeg -filters` output lists `name` as a filter (name is the second column:
+/// ` ... whisper           A->A       Transcribe audio using whisper.cpp.`), not just mentions it
+fn filter_list_has(filters_output: &str, name: &str) -> bool {
+    filters_output.lines().any(|line| {
+        let mut cols = line.split_whitespace();
+        matches!((cols.next(), cols.next(), cols.next()), (Some(_), Some(n), Some(io)) if n == name && io.contains("->"))
+    })
 }
 
 /// Get FFmpeg version to check if it's 8.0+ for Whisper support
@@ -423,7 +740,7 @@ fn crf_to_videotoolbox_quality(crf: &str) -> String {
     if let Ok(crf_value) = crf.parse::<i32>() {
         // Invert and scale: CRF 18 -> ~75, CRF 23 -> ~60, CRF 28 -> ~45
         let quality = 100 - ((crf_value as f32 * 100.0) / 51.0) as i32;
-        quality.max(0).min(100).to_string()
+        quality.clamp(0, 100).to_string()
     } else {
         "65".to_string() // Fallback to medium-high quality
     }
@@ -550,6 +867,7 @@ pub struct ExportParams {
     pub height: Option<i32>,              // Output height (exact dimensions, will letterbox to fit)
     pub format: Option<String>,           // Aspect ratio format ("16:9", "9:16", "1:1", "4:5")
     pub use_standard_sizes: Option<bool>, // Whether to scale to standard social media sizes after padding
+    pub copy_video: Option<bool>,         // Stream-copy video when nothing needs re-encoding (default: auto, off when crf/preset/tune is set; false forces re-encode)
     pub out: String                       // Path for output video
 }
 
@@ -564,6 +882,24 @@ pub struct ProbeResult {
     pub video: bool,              // True if file has video track
     pub audio_codec: Option<String>, // Audio codec name (e.g., "aac", "mp3", "pcm_s16le")
     pub audio_bitrate: Option<i32>,  // Audio bitrate in bits/sec (e.g., 128000)
+    #[serde(default)]
+    pub audio_sample_rate: Option<u32>, // Audio sample rate in Hz (e.g., 48000)
+    #[serde(default)]
+    pub vfr: bool,                // True if avg_frame_rate and r_frame_rate disagree (variable frame rate)
+    #[serde(default)]
+    pub video_streams: Vec<VideoStreamInfo>, // Every video stream, in `0:v:<i>` order
+    #[serde(default)]
+    pub video_codec: Option<String>, // Codec of the first video stream (e.g., "h264", "hevc")
+    #[serde(default)]
+    pub rotation: i32,            // Display rotation of the first video stream (0, 90, 180 or 270); width/height are already post-rotation
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoStreamInfo {
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub fps: Option<f64>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -597,13 +933,19 @@ fn detect_content_type(probe_result: Option<&ProbeResult>) -> &'static str {
     "film" // Default to film tuning for live-action
 }
 
-pub async fn export_video(id: &str, p: ExportParams, mut emit: impl FnMut(RpcEvent)) -> anyhow::Result<ExportResult> {
+pub async fn export_video(id: &str, mut p: ExportParams, mut emit: impl FnMut(RpcEvent)) -> anyhow::Result<ExportResult> {
+    p.out = crate::rpc::sanitize_output_path(&p.out)?.to_string_lossy().to_string();
     let pr = probe(id, &p.input, &mut emit).await.ok();
     let crf = p.crf.unwrap_or(18).to_string(); // Default to CRF 18 for balanced quality/size
     let preset = p.preset.as_deref().unwrap_or("slow"); // Default to slow for final exports
     let tune = p.tune.as_deref().unwrap_or_else(|| detect_content_type(pr.as_ref()));
     let use_standard_sizes = p.use_standard_sizes.unwrap_or(false);
 
+    // Nothing to do to the picture: copy video and only fix up the audio
+    if use_copy_video(&p, pr.as_ref()) {
+        return export_copy_video(id, p, pr.as_ref(), emit).await;
+    }
+
     // Determine the best available hardware encoder for H.264
     let hardware_encoder = if p.codec == "h264" {
         get_best_hardware_encoder().await
@@ -611,7 +953,7 @@ pub async fn export_video(id: &str, p: ExportParams, mut emit: impl FnMut(RpcEve
         HardwareEncoder::Software
     };
 
-    let ffmpeg_path = find_ffmpeg_binary().await.map_err(|e| anyhow::anyhow!("FFmpeg not found: {}", e))?;
+    let ffmpeg_path = find_ffmpeg_binary().await.map_err(|e| CapslapError::FfmpegNotFound(format!("FFmpeg not found: {}", e)))?;
     let mut cmd = TokioCommand::new(ffmpeg_path);
     cmd.arg("-y").arg("-i").arg(&p.input);
 
@@ -681,11 +1023,7 @@ pub async fn export_video(id: &str, p: ExportParams, mut emit: impl FnMut(RpcEve
        .arg("-threads").arg("0");            // Use all available CPU cores
 
     // Calculate GOP size based on frame rate (2x fps for good seeking)
-    let gop_size = if let Some(fps) = pr.as_ref().and_then(|p| p.fps) {
-        (fps * 2.0).round() as u32
-    } else {
-        48 // Default for 24fps content
-    };
+    let gop_size = pr.as_ref().map(gop_size_for_probe).unwrap_or(48); // Default for 24fps content
 
         match p.codec.as_str() {
         "h264" => {
@@ -761,6 +1099,7 @@ pub async fn export_video(id: &str, p: ExportParams, mut emit: impl FnMut(RpcEve
                         crf, encoder_info, preset, tune, audio_codec)
     });
 
+    cmd.kill_on_drop(true);
     let status = cmd.status().await?;
 
     // If hardware encoder failed, try falling back to software encoding
@@ -807,12 +1146,13 @@ pub async fn export_video(id: &str, p: ExportParams, mut emit: impl FnMut(RpcEve
             message: "Retrying with software encoder (libx264)...".into()
         });
 
+        fallback_cmd.kill_on_drop(true);
         let fallback_status = fallback_cmd.status().await?;
         if !fallback_status.success() {
-            return Err(anyhow::anyhow!("ffmpeg export failed with both hardware and software encoders"));
+            return Err(CapslapError::FfmpegFailed("ffmpeg export failed with both hardware and software encoders".into()).into());
         }
     } else if !status.success() {
-        return Err(anyhow::anyhow!("ffmpeg export failed"));
+        return Err(CapslapError::FfmpegFailed("ffmpeg export failed".into()).into());
     }
 
     emit(RpcEvent::Log {
@@ -823,6 +1163,84 @@ pub async fn export_video(id: &str, p: ExportParams, mut emit: impl FnMut(RpcEve
     Ok(ExportResult { video: p.out })
 }
 
+/// The export path `export_video` takes: copy unless the caller forces a re-encode or the source doesn't fit
+fn use_copy_video(p: &ExportParams, probe: Option<&ProbeResult>) -> bool {
+    p.copy_video != Some(false) && can_copy_video(p, probe)
+}
+
+/// Whether an export can keep the source video stream as-is: same codec, no quality settings
+/// asked for, and the requested size/aspect ratio already matches the source so no scale or
+/// pad is needed
+fn can_copy_video(p: &ExportParams, probe: Option<&ProbeResult>) -> bool {
+    // crf/preset/tune only mean something to an encoder, so setting one asks for a re-encode
+    if p.crf.is_some() || p.preset.is_some() || p.tune.is_some() {
+        return false;
+    }
+    let Some(probe) = probe else { return false };
+    let (Some(src_w), Some(src_h)) = (probe.width, probe.height) else { return false };
+
+    let same_codec = matches!(
+        (p.codec.as_str(), probe.video_codec.as_deref()),
+        ("h264", Some("h264")) | ("hevc" | "h265", Some("hevc")) | ("prores", Some("prores"))
+    );
+    if !same_codec {
+        return false;
+    }
+
+    match (p.width, p.height, p.format.as_deref()) {
+        (Some(w), Some(h), _) => w == src_w && h == src_h,
+        (_, _, Some(format)) => {
+            let Ok(target_ar) = parse_target_ar(format) else { return false };
+            let (src_w, src_h) = (src_w as u32, src_h as u32);
+            match maybe_scale_to_standard(target_ar, p.use_standard_sizes.unwrap_or(false)) {
+                Some(standard) => standard == (src_w, src_h),
+                None => canvas_no_downscale(src_w, src_h, target_ar) == (src_w, src_h),
+            }
+        }
+        _ => true,
+    }
+}
+
+/// Fast export path: `-c:v copy` with only the audio re-encoded (when needed)
+async fn export_copy_video(
+    id: &str,
+    p: ExportParams,
+    probe: Option<&ProbeResult>,
+    mut emit: impl FnMut(RpcEvent)
+) -> anyhow::Result<ExportResult> {
+    let (audio_codec, audio_args) = determine_audio_codec(probe);
+    emit(RpcEvent::Log {
+        id: id.into(),
+        message: format!("Source already matches the requested output, copying video stream (audio: {})", audio_codec)
+    });
+
+    let ffmpeg_path = find_ffmpeg_binary().await.map_err(|e| CapslapError::FfmpegNotFound(format!("FFmpeg not found: {}", e)))?;
+    let mut cmd = TokioCommand::new(ffmpeg_path);
+    cmd.arg("-y").arg("-i").arg(&p.input)
+       .arg("-c:v").arg("copy")
+       .arg("-c:a").arg(audio_codec);
+    if audio_codec == "aac" && audio_args.is_empty() {
+        cmd.arg("-b:a").arg("160k");
+    }
+    for arg in &audio_args {
+        cmd.arg(arg);
+    }
+    cmd.arg("-map_metadata").arg("0")
+       .arg("-map").arg("0:v:0")
+       .arg("-map").arg("0:a?")
+       .arg("-movflags").arg("+faststart")
+       .arg(&p.out);
+
+    cmd.kill_on_drop(true);
+    let status = cmd.status().await?;
+    if !status.success() {
+        return Err(CapslapError::FfmpegFailed("ffmpeg export (video stream copy) failed".into()).into());
+    }
+
+    emit(RpcEvent::Log { id: id.into(), message: "Export completed (video copied, audio processed)".into() });
+    Ok(ExportResult { video: p.out })
+}
+
 // PROBE OPERATION - Analyze media file to get technical information
 // This is typically the first operation run on any video/audio file
 // Uses bundled ffprobe to extract metadata without processing the file
@@ -830,7 +1248,7 @@ pub async fn probe(id: &str, input: &str, mut emit: impl FnMut(RpcEvent)) -> any
     emit(RpcEvent::Progress { id: id.into(), status: "Probing…".into(), progress: 0.05 });
 
     // Get bundled ffprobe path
-    let ffprobe_path = find_ffprobe_binary().await.map_err(|e| anyhow::anyhow!("ffprobe not found: {}", e))?;
+    let ffprobe_path = find_ffprobe_binary().await.map_err(|e| CapslapError::FfmpegNotFound(format!("ffprobe not found: {}", e)))?;
 
     emit(RpcEvent::Log {
         id: id.into(),
@@ -851,6 +1269,7 @@ pub async fn probe(id: &str, input: &str, mut emit: impl FnMut(RpcEvent)) -> any
         .arg(input)                          // The file to analyze
         .stdout(std::process::Stdio::piped()) // Capture the output
         .stderr(std::process::Stdio::piped()) // Capture stderr for debugging
+        .kill_on_drop(true)                  // Don't leave ffprobe running when the caller gives up
         .spawn()?;
 
     emit(RpcEvent::Log {
@@ -870,7 +1289,7 @@ pub async fn probe(id: &str, input: &str, mut emit: impl FnMut(RpcEvent)) -> any
         let stderr = String::from_utf8_lossy(&out.stderr);
         emit(RpcEvent::Log {
             id: id.into(),
-            message: format!("ffprobe stderr: {}", stderr)
+            message: format!("ffprobe stderr: {}", crate::rpc::preview(&stderr, 500))
         });
     }
 
@@ -878,13 +1297,13 @@ pub async fn probe(id: &str, input: &str, mut emit: impl FnMut(RpcEvent)) -> any
         let stdout_preview = String::from_utf8_lossy(&out.stdout);
         emit(RpcEvent::Log {
             id: id.into(),
-            message: format!("ffprobe stdout preview: {}", stdout_preview.chars().take(200).collect::<String>())
+            message: format!("ffprobe stdout preview: {}", crate::rpc::preview(&stdout_preview, 200))
         });
     }
 
     if !out.status.success() {
         let stderr = String::from_utf8_lossy(&out.stderr);
-        return Err(anyhow::anyhow!("ffprobe failed with status {}: {}", out.status, stderr));
+        return Err(CapslapError::FfmpegFailed(format!("ffprobe failed with status {}: {}", out.status, stderr)).into());
     }
 
     // Parse the JSON output from ffprobe
@@ -904,6 +1323,11 @@ pub async fn probe(id: &str, input: &str, mut emit: impl FnMut(RpcEvent)) -> any
     let mut video = false;
     let mut audio_codec = None;
     let mut audio_bitrate = None;
+    let mut audio_sample_rate = None;
+    let mut vfr = false;
+    let mut video_streams = Vec::new();
+    let mut video_codec = None;
+    let mut rotation = 0;
 
     // Analyze each stream in the file
     if let Some(arr) = v.get("streams").and_then(|s| s.as_array()) {
@@ -912,15 +1336,34 @@ pub async fn probe(id: &str, input: &str, mut emit: impl FnMut(RpcEvent)) -> any
                 match codec_type {
                     "video" => {
                         video = true;
-                        // Extract video dimensions
-                        width = st.get("width").and_then(|x| x.as_i64()).map(|x| x as i32);
-                        height = st.get("height").and_then(|x| x.as_i64()).map(|x| x as i32);
+                        if video_codec.is_none() {
+                            video_codec = st.get("codec_name").and_then(|x| x.as_str()).map(|s| s.to_string());
+                        }
+                        // Extract video dimensions as displayed: ffmpeg autorotates on decode,
+                        // so filters see the rotated frame, not the coded one
+                        let stream_rotation = stream_rotation(st);
+                        let coded_w = st.get("width").and_then(|x| x.as_i64()).map(|x| x as i32);
+                        let coded_h = st.get("height").and_then(|x| x.as_i64()).map(|x| x as i32);
+                        (width, height) = display_dimensions(coded_w, coded_h, stream_rotation);
+                        if video_streams.is_empty() {
+                            rotation = stream_rotation;
+                        }
 
                         // Extract frame rate (can be in fraction format)
                         if let Some(fr) = st.get("avg_frame_rate").and_then(|x| x.as_str()) {
                             fps = parse_fps(fr).or(fps);
                         }
 
+                        // VFR sources report an average that differs from the nominal rate
+                        let r_fps = st.get("r_frame_rate").and_then(|x| x.as_str()).and_then(parse_fps);
+                        vfr = is_variable_frame_rate(fps, r_fps);
+
+                        video_streams.push(VideoStreamInfo {
+                            width,
+                            height,
+                            fps: st.get("avg_frame_rate").and_then(|x| x.as_str()).and_then(parse_fps),
+                        });
+
                         // Fallback: try to get duration from video stream if format didn't have it
                         if duration.is_none() {
                             duration = st.get("duration")
@@ -936,6 +1379,9 @@ pub async fn probe(id: &str, input: &str, mut emit: impl FnMut(RpcEvent)) -> any
                         audio_bitrate = st.get("bit_rate")
                             .and_then(|x| x.as_str())
                             .and_then(|s| s.parse::<i32>().ok());
+                        audio_sample_rate = st.get("sample_rate")
+                            .and_then(|x| x.as_str())
+                            .and_then(|s| s.parse::<u32>().ok());
                     }
                     _ => {} // Ignore other stream types (subtitles, data, etc.)
                 }
@@ -944,10 +1390,181 @@ pub async fn probe(id: &str, input: &str, mut emit: impl FnMut(RpcEvent)) -> any
     }
 
     emit(RpcEvent::Progress { id: id.into(), status: "Probe complete".into(), progress: 1.0 });
-    Ok(ProbeResult { duration, width, height, fps, audio, video, audio_codec, audio_bitrate })
+    Ok(ProbeResult { duration, width, height, fps, audio, video, audio_codec, audio_bitrate, audio_sample_rate, vfr, video_streams, video_codec, rotation })
+}
+
+/// Public RPC method: ffprobe metadata for a file, e.g. to show it before starting a job
+pub async fn probe_video_rpc(
+    id: &str,
+    params: crate::types::ProbeVideoParams,
+    emit: impl FnMut(RpcEvent)
+) -> anyhow::Result<ProbeResult> {
+    let path = std::path::Path::new(&params.input);
+    if !path.is_file() {
+        return Err(anyhow::anyhow!("Input file not found: {}", params.input));
+    }
+    probe(id, &params.input, emit).await.map_err(|e| {
+        if matches!(e.downcast_ref::<CapslapError>(), Some(CapslapError::FfmpegNotFound(_))) {
+            return e;
+        }
+        let message = format!("ffprobe could not read {}: {}", params.input, e);
+        e.context(message)
+    })
+}
+
+/// Display rotation of a stream, normalized to 0/90/180/270 clockwise.
+/// Newer ffprobe reports it as display-matrix side data (counter-clockwise, e.g. -90),
+/// older builds as a `rotate` tag (clockwise, e.g. "90").
+fn stream_rotation(st: &serde_json::Value) -> i32 {
+    let from_side_data = st.get("side_data_list")
+        .and_then(|l| l.as_array())
+        .and_then(|l| l.iter().find_map(|sd| sd.get("rotation").and_then(|r| r.as_f64())))
+        .map(|r| -r);
+    let from_tag = || st.get("tags")
+        .and_then(|t| t.get("rotate"))
+        .and_then(|r| r.as_str())
+        .and_then(|r| r.parse::<f64>().ok());
+    let degrees = from_side_data.or_else(from_tag).unwrap_or(0.0);
+    (((degrees / 90.0).round() as i32 * 90) % 360 + 360) % 360
+}
+
+/// Width/height as displayed after applying a 90/270 rotation to the coded frame
+fn display_dimensions(width: Option<i32>, height: Option<i32>, rotation: i32) -> (Option<i32>, Option<i32>) {
+    if rotation % 180 == 90 {
+        (height, width)
+    } else {
+        (width, height)
+    }
+}
+
+
+
+// Frame rates used to correct a misleading average on variable-frame-rate sources
+const STANDARD_FPS: [f64; 8] = [23.976, 24.0, 25.0, 29.97, 30.0, 48.0, 50.0, 60.0];
+
+/// Detect VFR by comparing avg_frame_rate with r_frame_rate (more than 1% apart)
+fn is_variable_frame_rate(avg_fps: Option<f64>, r_fps: Option<f64>) -> bool {
+    match (avg_fps, r_fps) {
+        (Some(avg), Some(r)) if avg > 0.0 && r > 0.0 => ((avg - r).abs() / r) > 0.01,
+        _ => false,
+    }
+}
+
+/// Snap an average fps to the closest standard frame rate
+fn snap_to_standard_fps(fps: f64) -> f64 {
+    STANDARD_FPS.iter()
+        .copied()
+        .min_by(|a, b| (a - fps).abs().partial_cmp(&(b - fps).abs()).unwrap())
+        .unwrap_or(fps)
 }
 
+/// GOP size (2x fps) for a probed source
+/// For VFR sources the average fps is snapped to a standard rate so keyframe intervals stay sane
+pub fn gop_size_for_probe(probe: &ProbeResult) -> u32 {
+    let Some(fps) = probe.fps.filter(|f| f.is_finite() && *f > 0.0) else {
+        return 48; // Default for 24fps content
+    };
+    let fps = if probe.vfr { snap_to_standard_fps(fps) } else { fps };
+    ((fps * 2.0).round() as u32).max(1)
+}
 
+// ---- Existing (burned-in) caption detection ----
+// Heuristic only: dense rows of sharp horizontal luma transitions in the lower caption zone
+// look like text. Busy textures (fences, foliage, UI overlays, lower thirds) can trigger it and
+// low-contrast or stylized captions can slip through, so it only ever warns or moves captions.
+const EXISTING_CAPTION_SAMPLES: usize = 6;
+const EXISTING_CAPTION_ZONE: (f32, f32) = (0.60, 0.92);  // fraction of height (top, bottom)
+const EDGE_DELTA: i16 = 60;                               // luma step counted as a sharp edge
+const TEXT_ROW_EDGE_RATIO: f32 = 0.08;                    // edges per pixel for a "text-like" row
+const TEXT_BAND_MIN_HEIGHT: f32 = 0.03;                   // consecutive text rows, fraction of height
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ExistingCaptionsReport {
+    pub likely: bool,             // Text-like band found in at least half of the sampled frames
+    pub frames_sampled: usize,
+    pub frames_flagged: usize,
+    pub moved_to_top: bool,       // New captions were moved to the top to avoid the collision
+}
+
+/// Sample frames across the video and look for persistent text in the bottom caption zone
+pub async fn detect_existing_captions(input: &str, duration: Option<f64>) -> anyhow::Result<ExistingCaptionsReport> {
+    let ffmpeg_path = find_ffmpeg_binary().await?;
+    let duration = duration.unwrap_or(0.0);
+
+    let mut frames_sampled = 0;
+    let mut frames_flagged = 0;
+    for i in 0..EXISTING_CAPTION_SAMPLES {
+        // Spread samples over the middle 80% to skip intros/outros
+        let t = duration * (0.1 + 0.8 * i as f64 / (EXISTING_CAPTION_SAMPLES - 1) as f64);
+        let output = TokioCommand::new(&ffmpeg_path)
+            .args(["-v", "error", "-ss", &format!("{:.3}", t), "-i", input,
+                   "-frames:v", "1", "-vf", "scale=360:-2,format=gray",
+                   "-f", "image2pipe", "-vcodec", "png", "-"])
+            .kill_on_drop(true)
+            .output()
+            .await?;
+        if !output.status.success() || output.stdout.is_empty() {
+            continue;
+        }
+        let Ok(frame) = image::load_from_memory(&output.stdout) else { continue; };
+        frames_sampled += 1;
+        if frame_has_text_band(&frame.to_luma8(), EXISTING_CAPTION_ZONE) {
+            frames_flagged += 1;
+        }
+    }
+
+    Ok(ExistingCaptionsReport {
+        likely: frames_sampled > 0 && frames_flagged * 2 >= frames_sampled,
+        frames_sampled,
+        frames_flagged,
+        moved_to_top: false,
+    })
+}
+
+/// Whether one frame of `input` at `t` seconds shows a text-like band inside `zone` (fractions
+/// of height, top to bottom). Same heuristic as existing-caption detection; None when no frame
+/// could be decoded there.
+pub async fn frame_has_text_at(input: &str, t: f64, zone: (f32, f32)) -> anyhow::Result<Option<bool>> {
+    let ffmpeg_path = find_ffmpeg_binary().await?;
+    let output = TokioCommand::new(&ffmpeg_path)
+        .args(["-v", "error", "-ss", &format!("{:.3}", t), "-i", input,
+               "-frames:v", "1", "-vf", "scale=360:-2,format=gray",
+               "-f", "image2pipe", "-vcodec", "png", "-"])
+        .kill_on_drop(true)
+        .output()
+        .await?;
+    if !output.status.success() || output.stdout.is_empty() {
+        return Ok(None);
+    }
+    Ok(image::load_from_memory(&output.stdout).ok().map(|frame| frame_has_text_band(&frame.to_luma8(), zone)))
+}
+
+fn frame_has_text_band(frame: &image::GrayImage, zone: (f32, f32)) -> bool {
+    let (w, h) = frame.dimensions();
+    if w < 2 || h == 0 { return false; }
+    let top = (h as f32 * zone.0) as u32;
+    let bottom = ((h as f32 * zone.1) as u32).min(h);
+    let min_band = ((h as f32 * TEXT_BAND_MIN_HEIGHT).ceil() as u32).max(1);
+
+    let mut run = 0;
+    for y in top..bottom {
+        let edges = (1..w)
+            .filter(|&x| {
+                let a = frame.get_pixel(x - 1, y)[0] as i16;
+                let b = frame.get_pixel(x, y)[0] as i16;
+                (a - b).abs() >= EDGE_DELTA
+            })
+            .count();
+        if edges as f32 / w as f32 >= TEXT_ROW_EDGE_RATIO {
+            run += 1;
+            if run >= min_band { return true; }
+        } else {
+            run = 0;
+        }
+    }
+    false
+}
 
 // ffmpeg sometimes reports frame rates as fractions (e.g., "30000/1001" for 29.97 fps)
 // This function handles both fraction and decimal formats
@@ -964,3 +1581,243 @@ fn parse_fps(s: &str) -> Option<f64> {
         s.parse().ok()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_even_rounds_odd_up() {
+        assert_eq!(round_even(1920), 1920);
+        assert_eq!(round_even(1921), 1922);
+        assert_eq!(round_even(0), 0);
+    }
+
+    #[test]
+    fn even_rounding_modes() {
+        assert_eq!(EvenRounding::Up.apply(1080.0), 1080);
+        assert_eq!(EvenRounding::Up.apply(1080.3), 1082);
+        assert_eq!(EvenRounding::Up.apply(1081.0), 1082);
+        assert_eq!(EvenRounding::Up.apply(1079.9999999), 1080);
+        assert_eq!(EvenRounding::Down.apply(1081.0), 1080);
+        assert_eq!(EvenRounding::Down.apply(1081.6), 1080);
+        assert_eq!(EvenRounding::Down.apply(1079.9999999), 1080);
+        assert_eq!(EvenRounding::Nearest.apply(1080.9), 1080);
+        assert_eq!(EvenRounding::Nearest.apply(1081.1), 1082);
+        assert_eq!(EvenRounding::Up.apply(0.0), 2);
+    }
+
+    #[test]
+    fn odd_sources_get_even_canvases() {
+        let modes = [EvenRounding::Up, EvenRounding::Down, EvenRounding::Nearest];
+        let ars = [TargetAR::AR9x16, TargetAR::AR16x9, TargetAR::AR4x5, TargetAR::AR1x1];
+        for &(w, h) in &[(1080, 1921), (1919, 1080), (721, 1279), (999, 999)] {
+            for &ar in &ars {
+                for &mode in &modes {
+                    let (cw, ch) = canvas_no_downscale_with(w, h, ar, mode);
+                    assert!(cw % 2 == 0 && ch % 2 == 0, "{}x{} -> {}x{} ({:?})", w, h, cw, ch, mode);
+                }
+            }
+        }
+        assert_eq!(canvas_no_downscale_with(1080, 1921, TargetAR::AR9x16, EvenRounding::Up), (1082, 1922));
+    }
+
+    fn export_params(extra: serde_json::Value) -> ExportParams {
+        let mut params = serde_json::json!({ "input": "in.mp4", "codec": "h264", "out": "out.mp4" });
+        params.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        serde_json::from_value(params).unwrap()
+    }
+
+    fn probe_1080x1920(codec: &str) -> ProbeResult {
+        serde_json::from_value(serde_json::json!({
+            "width": 1080, "height": 1920, "audio": true, "video": true, "videoCodec": codec
+        })).unwrap()
+    }
+
+    #[test]
+    fn copy_path_when_source_already_matches() {
+        let probe = probe_1080x1920("h264");
+        assert!(can_copy_video(&export_params(serde_json::json!({})), Some(&probe)));
+        assert!(can_copy_video(&export_params(serde_json::json!({ "format": "9:16" })), Some(&probe)));
+        assert!(can_copy_video(&export_params(serde_json::json!({ "width": 1080, "height": 1920 })), Some(&probe)));
+    }
+
+    #[test]
+    fn encode_path_when_anything_changes_the_picture() {
+        let probe = probe_1080x1920("h264");
+        assert!(!can_copy_video(&export_params(serde_json::json!({ "format": "16:9" })), Some(&probe)));
+        assert!(!can_copy_video(&export_params(serde_json::json!({ "width": 720, "height": 1280 })), Some(&probe)));
+        assert!(!can_copy_video(&export_params(serde_json::json!({ "codec": "hevc" })), Some(&probe)));
+        assert!(!can_copy_video(&export_params(serde_json::json!({})), Some(&probe_1080x1920("vp9"))));
+        assert!(!can_copy_video(&export_params(serde_json::json!({})), None));
+    }
+
+    #[test]
+    fn encode_path_when_quality_settings_are_given() {
+        let probe = probe_1080x1920("h264");
+        for extra in [serde_json::json!({ "crf": 23 }), serde_json::json!({ "preset": "fast" }), serde_json::json!({ "tune": "animation" })] {
+            assert!(!can_copy_video(&export_params(extra.clone()), Some(&probe)), "{}", extra);
+        }
+    }
+
+    #[test]
+    fn fit_pad_offsets_center_the_scaled_source() {
+        for &(src, canvas) in &[((1080, 1921), (1080, 1920)), ((1919, 1081), (1080, 1920)), ((721, 1279), (1080, 1350))] {
+            for &mode in &[EvenRounding::Up, EvenRounding::Down, EvenRounding::Nearest] {
+                let fit = FitPad::new(Some(src), canvas.0, canvas.1, mode);
+                let (w, h) = fit.scaled.unwrap();
+                assert!(w % 2 == 0 && h % 2 == 0 && w <= canvas.0 && h <= canvas.1);
+                let (_, pad) = fit.filters("lanczos");
+                let expected = format!("pad={}:{}:{}:{}:black", canvas.0, canvas.1, (canvas.0 - w) / 2, (canvas.1 - h) / 2);
+                assert_eq!(pad, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn cap_canvas_height_keeps_the_aspect_and_even_sizes() {
+        assert_eq!(cap_canvas_height(2160, 3840, 1920, EvenRounding::Up), (1080, 1920));
+        assert_eq!(cap_canvas_height(1080, 1920, 1920, EvenRounding::Up), (1080, 1920));
+        assert_eq!(cap_canvas_height(1080, 1920, 0, EvenRounding::Up), (1080, 1920));
+        // An odd cap rounds the height down so it is never exceeded
+        assert_eq!(cap_canvas_height(1080, 1920, 1281, EvenRounding::Nearest), (720, 1280));
+        let (w, h) = cap_canvas_height(1082, 1922, 1000, EvenRounding::Up);
+        assert!(w % 2 == 0 && h == 1000 && w >= 562, "{}x{}", w, h);
+    }
+
+    #[test]
+    fn vfr_needs_the_rates_to_disagree_by_more_than_a_percent() {
+        assert!(!is_variable_frame_rate(Some(29.97), Some(29.97)));
+        assert!(!is_variable_frame_rate(Some(29.8), Some(30.0)));
+        assert!(is_variable_frame_rate(Some(27.3), Some(30.0)));
+        assert!(!is_variable_frame_rate(None, Some(30.0)));
+        assert!(!is_variable_frame_rate(Some(0.0), Some(30.0)));
+    }
+
+    #[test]
+    fn gop_is_two_seconds_with_vfr_snapped_to_a_standard_rate() {
+        let mut probe = probe_1080x1920("h264");
+        assert_eq!(gop_size_for_probe(&probe), 48); // unknown fps
+        probe.fps = Some(29.97);
+        assert_eq!(gop_size_for_probe(&probe), 60);
+        probe.fps = Some(27.3);
+        assert_eq!(gop_size_for_probe(&probe), 55);
+        probe.vfr = true;
+        assert_eq!(gop_size_for_probe(&probe), 50); // snapped to 25fps
+        probe.fps = Some(f64::NAN);
+        assert_eq!(gop_size_for_probe(&probe), 48);
+    }
+
+    #[test]
+    fn df_output_gives_available_bytes() {
+        let out = "Filesystem     1024-blocks      Used Available Capacity Mounted on\n/dev/disk3s1    971350180 512345678 459004502      53% /\n";
+        assert_eq!(parse_df_available(out), Some(459004502 * 1024));
+        assert_eq!(parse_df_available("Filesystem 1024-blocks Used Available Capacity Mounted on\n"), None);
+        assert_eq!(parse_df_available("header\n/dev/x 10 5 n/a 50% /\n"), None);
+    }
+
+    #[test]
+    fn scale_algo_defaults_and_normalizes() {
+        assert_eq!(resolve_scale_algo(None).unwrap(), DEFAULT_SCALE_ALGO);
+        assert_eq!(resolve_scale_algo(Some(" Bicubic ")).unwrap(), "bicubic");
+        assert_eq!(resolve_scale_algo(Some("neighbor")).unwrap(), "neighbor");
+        let err = resolve_scale_algo(Some("lanczos:param0=3")).unwrap_err().to_string();
+        assert!(err.starts_with("Unknown scale algorithm: lanczos:param0=3. Supported: fast_bilinear,"), "{}", err);
+        assert!(resolve_scale_algo(Some("")).is_err());
+    }
+
+    #[test]
+    fn scale_algo_reaches_the_scale_filter() {
+        let fit = FitPad::new(Some((1920, 1080)), 1080, 1920, EvenRounding::default());
+        let (scale, _) = fit.filters("bicubic");
+        assert!(scale.contains("flags=bicubic"), "{}", scale);
+    }
+
+    #[test]
+    fn stream_rotation_reads_side_data_and_tags() {
+        use serde_json::json;
+        assert_eq!(stream_rotation(&json!({ "side_data_list": [{ "side_data_type": "Display Matrix", "rotation": -90 }] })), 90);
+        assert_eq!(stream_rotation(&json!({ "side_data_list": [{ "rotation": 90 }] })), 270);
+        assert_eq!(stream_rotation(&json!({ "side_data_list": [{ "rotation": 180 }] })), 180);
+        assert_eq!(stream_rotation(&json!({ "tags": { "rotate": "90" } })), 90);
+        assert_eq!(stream_rotation(&json!({ "tags": { "rotate": "-270" } })), 90);
+        assert_eq!(stream_rotation(&json!({ "tags": { "rotate": "89.6" } })), 90);
+        // Side data wins over a stale tag
+        assert_eq!(stream_rotation(&json!({ "side_data_list": [{ "rotation": -270 }], "tags": { "rotate": "180" } })), 270);
+        assert_eq!(stream_rotation(&json!({ "tags": { "rotate": "sideways" } })), 0);
+        assert_eq!(stream_rotation(&json!({})), 0);
+    }
+
+    #[test]
+    fn display_dimensions_swap_for_quarter_turns() {
+        assert_eq!(display_dimensions(Some(1920), Some(1080), 90), (Some(1080), Some(1920)));
+        assert_eq!(display_dimensions(Some(1920), Some(1080), 270), (Some(1080), Some(1920)));
+        assert_eq!(display_dimensions(Some(1920), Some(1080), 180), (Some(1920), Some(1080)));
+        assert_eq!(display_dimensions(Some(1920), None, 0), (Some(1920), None));
+    }
+
+    #[test]
+    fn filter_list_matches_the_name_column_only() {
+        let listing = "Filters:\n  T.. = Timeline support\n  ------\n \
+                       ... aresample         A->A       Resample audio data.\n \
+                       ... whisper           A->A       Transcribe audio using whisper.cpp.\n \
+                       T.C subtitles         V->V       Render text subtitles onto input video using the libass library.\n";
+        assert!(filter_list_has(listing, "whisper"));
+        assert!(filter_list_has(listing, "subtitles"));
+        assert!(!filter_list_has(listing, "libass")); // only mentioned in a description
+        assert!(!filter_list_has(listing, "whisp"));
+
+        let without = " ... aresample         A->A       Resample audio data (see whisper docs).\n";
+        assert!(!filter_list_has(without, "whisper"));
+        assert!(!filter_list_has("", "whisper"));
+    }
+
+    #[test]
+    fn clean_copy_graph_shares_the_fit_and_burns_only_one_output() {
+        let fit = FitPad::new(Some((1920, 1080)), 1080, 1920, EvenRounding::default());
+        let graph = build_fitpad_split_graph(1, &fit, "/tmp/captions.ass", None, "lanczos", HardwareEncoder::Software);
+        let chains: Vec<&str> = graph.split(';').map(str::trim).collect();
+        assert_eq!(chains.len(), 3, "{}", graph);
+
+        let (scale, pad) = fit.filters("lanczos");
+        assert_eq!(chains[0], format!("[0:v:1]format=yuv444p,{},{},split=2[fit_cap][fit_clean]", scale, pad));
+        assert!(chains[1].starts_with("[fit_cap]subtitles=") && chains[1].ends_with(",format=yuv420p[captioned]"), "{}", chains[1]);
+        assert_eq!(chains[2], "[fit_clean]format=yuv420p[clean]");
+        assert_eq!(graph.matches("subtitles=").count(), 1);
+    }
+
+    #[test]
+    fn export_takes_the_copy_path_unless_forced_to_encode() {
+        let probe = probe_1080x1920("h264");
+        assert!(use_copy_video(&export_params(serde_json::json!({})), Some(&probe)));
+        assert!(use_copy_video(&export_params(serde_json::json!({ "copyVideo": true })), Some(&probe)));
+        assert!(!use_copy_video(&export_params(serde_json::json!({ "copyVideo": false })), Some(&probe)));
+        assert!(!use_copy_video(&export_params(serde_json::json!({ "copyVideo": true, "format": "16:9" })), Some(&probe)));
+    }
+
+    #[tokio::test]
+    async fn export_video_copies_a_matching_source() {
+        let (Ok(ffmpeg), Ok(_)) = (find_ffmpeg_binary().await, find_ffprobe_binary().await) else {
+            eprintln!("skipping: ffmpeg/ffprobe not found");
+            return;
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.mkv");
+        let status = Command::new(&ffmpeg)
+            .args(["-y", "-f", "lavfi", "-i", "testsrc=size=64x64:rate=10", "-f", "lavfi", "-i", "sine=frequency=440",
+                   "-t", "1", "-c:v", "libx264", "-pix_fmt", "yuv420p", "-c:a", "ac3"])
+            .arg(&input)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "ffmpeg could not render the test clip");
+
+        let out = dir.path().join("out.mp4");
+        let params = export_params(serde_json::json!({ "input": input, "out": out }));
+        let mut logs = Vec::new();
+        export_video("t", params, |ev| if let RpcEvent::Log { message, .. } = ev { logs.push(message) }).await.unwrap();
+
+        assert!(out.exists());
+        assert!(logs.iter().any(|m| m.contains("copying video stream")), "{:?}", logs);
+    }
+}
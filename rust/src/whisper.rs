@@ -1,19 +1,41 @@
-use crate::{types::{CaptionSegment, WhisperResponse, WhisperCacheEntry, WhisperCacheIndex, TranscribeSegmentsParams, TranscribeSegmentsResult, WhisperWord}};
+use crate::{types::{CaptionSegment, WhisperResponse, WhisperCacheEntry, WhisperCacheIndex, TranscribeSegmentsParams, TranscribeSegmentsResult, TranscriptionExport, WhisperWord, PromptSpec}};
 use blake3;
 use tokio::fs;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt};
 use tokio::process::Command as TokioCommand;
 use std::path::PathBuf;
 use std::process::Stdio;
-use crate::rpc::RpcEvent;
+use crate::rpc::{CapslapError, RpcEvent};
 use crate::video::{is_ffmpeg_whisper_available, is_whisper_cpp_available};
 use regex::Regex;
 
-/// Transcribe audio using whisper.cpp CLI (preferred method)
+/// Model and decoding flags for a whisper.cpp run
+fn whisper_cpp_decode_args(model_path: &str, max_len: u32) -> Vec<String> {
+    vec![
+        "-m".into(), model_path.into(),
+        "--output-json-full".into(),            // Full JSON output
+        "--no-prints".into(),                   // Suppress progress output
+        "--word-thold".into(), "0.01".into(),   // Better word boundary detection
+        "--max-len".into(), max_len.to_string(), // Max chars per segment (0 = no limit)
+        "--output-words".into(),                // Enable word-level timestamps
+        "--entropy-thold".into(), "2.8".into(), // Anti-repetition
+        "--suppress-nst".into(),                // Suppress non-speech tokens
+    ]
+}
+
+/// Transcribe audio using whisper.cpp CLI (preferred method).
+/// `model_path` (already validated) is used as-is instead of looking up `model`.
+#[allow(clippy::too_many_arguments)]
 pub async fn transcribe_with_whisper_cpp(
     id: &str,
     audio_path: &str,
     model: Option<String>,
+    model_path: Option<&str>,
     language: Option<String>,
+    prompt: Option<String>,
+    max_len: u32,
+    stream_segments: bool,
+    (threads, processors): (Option<u32>, Option<u32>), // -t / -p (None = whisper.cpp default)
     mut emit: impl FnMut(RpcEvent)
 ) -> anyhow::Result<WhisperResponse> {
     // Use requested model or default to tiny
@@ -32,8 +54,11 @@ pub async fn transcribe_with_whisper_cpp(
         message: format!("Model requested: {}, DTW preset: disabled (testing without DTW)", whisper_model)
     });
 
-    // Find model with fallbacks
-    let (model_path, actual_model) = ensure_whisper_model(&whisper_model).await?;
+    // Find model with fallbacks, unless the caller brought their own file
+    let (model_path, actual_model) = match model_path {
+        Some(path) => (path.to_string(), whisper_model.clone()),
+        None => ensure_whisper_model(&whisper_model).await?,
+    };
 
     if actual_model != whisper_model {
         emit(RpcEvent::Log {
@@ -65,16 +90,32 @@ pub async fn transcribe_with_whisper_cpp(
     };
     let mut cmd = TokioCommand::new(&whisper_binary);
     // DTW disabled - causes timestamp issues for some audio files
-    let dtw_preset: Option<&str> = None;
+    let _dtw_preset: Option<&str> = None;
+
+    cmd.args(whisper_cpp_decode_args(&model_path, max_len));
+
+    if let Some(threads) = threads {
+        cmd.arg("-t").arg(threads.to_string());
+    }
+    if let Some(processors) = processors {
+        cmd.arg("-p").arg(processors.to_string());
+    }
+    if threads.is_some() || processors.is_some() {
+        emit(RpcEvent::Log {
+            id: id.into(),
+            message: format!(
+                "whisper.cpp CPU usage: threads={}, processors={}",
+                threads.map_or("default".to_string(), |t| t.to_string()),
+                processors.map_or("default".to_string(), |p| p.to_string())
+            )
+        });
+    }
 
-    cmd.arg("-m").arg(&model_path)
-       .arg("--output-json-full")    // Full JSON output
-       .arg("--no-prints")          // Suppress progress output
-       .arg("--word-thold").arg("0.01")   // Better word boundary detection
-       .arg("--max-len").arg("0")         // No segment length limit
-       .arg("--output-words")            // Enable word-level timestamps
-       .arg("--entropy-thold").arg("2.8") // Anti-repetition
-       .arg("--suppress-nst");           // Suppress non-speech tokens
+    // Per-job output name: the default `{audio}.json` side-car could be a stale file from
+    // another run (or another job transcribing the same audio)
+    let json_prefix = std::env::temp_dir().join(crate::rpc::job_scratch_name("capslap_whisper", id));
+    let json_file_path = format!("{}.json", json_prefix.to_string_lossy());
+    cmd.arg("-of").arg(&json_prefix);
 
     cmd.arg(audio_path);
 
@@ -82,24 +123,42 @@ pub async fn transcribe_with_whisper_cpp(
         cmd.arg("-l").arg(lang);
     }
 
+    if let Some(prompt) = &prompt {
+        cmd.arg("--prompt").arg(prompt);
+    }
+
     cmd.stdout(Stdio::piped())
        .stderr(Stdio::piped());
 
-    let output = cmd.output().await?;
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let stderr = String::from_utf8_lossy(&output.stderr);
+    // Segments are printed to stdout as they're decoded; read it live to stream them
+    cmd.kill_on_drop(true); // A cancelled job mustn't leave whisper.cpp running
+    let mut child = cmd.spawn()?;
+    let stderr_task = child.stderr.take().map(|mut pipe| tokio::spawn(async move {
+        let mut buf = Vec::new();
+        let _ = pipe.read_to_end(&mut buf).await;
+        buf
+    }));
+    let stdout = match child.stdout.take() {
+        Some(pipe) => read_printed_segments(id, tokio::io::BufReader::new(pipe), stream_segments, &mut emit).await?,
+        None => String::new(),
+    };
+    let status = child.wait().await?;
+    let stderr_bytes = match stderr_task {
+        Some(task) => task.await.unwrap_or_default(),
+        None => Vec::new(),
+    };
+    let stderr = String::from_utf8_lossy(&stderr_bytes);
     emit(RpcEvent::Log {
         id: id.into(),
-        message: format!("whisper.cpp stdout: {}", stdout.chars().take(500).collect::<String>())
+        message: format!("whisper.cpp stdout: {}", crate::rpc::preview(&stdout, 500))
     });
     emit(RpcEvent::Log {
         id: id.into(),
-        message: format!("whisper.cpp stderr: {}", stderr.chars().take(500).collect::<String>())
+        message: format!("whisper.cpp stderr: {}", crate::rpc::preview(&stderr, 500))
     });
 
-    if !output.status.success() {
-        return Err(anyhow::anyhow!("whisper.cpp failed with status {}: {}", output.status, stderr));
+    if !status.success() {
+        return Err(anyhow::anyhow!("whisper.cpp failed with status {}: {}", status, stderr));
     }
 
     emit(RpcEvent::Log {
@@ -107,9 +166,6 @@ pub async fn transcribe_with_whisper_cpp(
         message: "Parsing whisper.cpp output...".into()
     });
 
-    // whisper.cpp creates a JSON file next to the audio file
-    let json_file_path = format!("{}.json", audio_path);
-
     emit(RpcEvent::Log {
         id: id.into(),
         message: format!("Looking for JSON output at: {}", json_file_path)
@@ -122,12 +178,13 @@ pub async fn transcribe_with_whisper_cpp(
             message: format!("JSON file does not exist at: {}", json_file_path)
         });
 
-        // List files in the directory to see what was actually created
-        if let Some(parent_dir) = std::path::Path::new(audio_path).parent() {
+        // List whisper outputs in the directory to see what was actually created
+        if let Some(parent_dir) = json_prefix.parent() {
             if let Ok(entries) = std::fs::read_dir(parent_dir) {
                 let files: Vec<String> = entries
                     .filter_map(|e| e.ok())
                     .map(|e| e.file_name().to_string_lossy().to_string())
+                    .filter(|name| name.starts_with("capslap_whisper"))
                     .collect();
                 emit(RpcEvent::Log {
                     id: id.into(),
@@ -141,11 +198,12 @@ pub async fn transcribe_with_whisper_cpp(
 
     let json_content = std::fs::read_to_string(&json_file_path)
         .map_err(|e| anyhow::anyhow!("Failed to read whisper.cpp JSON output: {}", e))?;
+    let _ = std::fs::remove_file(&json_file_path);
 
-    // Debug: Log first 1000 chars of JSON to understand structure
+    // Debug: Log a preview of the JSON to understand structure
     emit(RpcEvent::Log {
         id: id.into(),
-        message: format!("whisper.cpp JSON preview: {}", &json_content.chars().take(1000).collect::<String>())
+        message: format!("whisper.cpp JSON preview: {}", crate::rpc::preview(&json_content, 1000))
     });
 
     // Parse the JSON output from file
@@ -207,7 +265,7 @@ async fn ensure_whisper_model(model: &str) -> anyhow::Result<(String, String)> {
     }
 
     // No models found locally - this will trigger OpenAI API fallback at higher level
-    Err(anyhow::anyhow!("No whisper models found locally. Tried fallback chain: {:?}", fallback_chain))
+    Err(CapslapError::ModelMissing(format!("No whisper models found locally. Tried fallback chain: {:?}", fallback_chain)).into())
 }
 
 /// Find whisper.cpp binary across different locations and platforms
@@ -245,7 +303,7 @@ pub async fn find_whisper_binary() -> anyhow::Result<String> {
         }
     }
 
-    Err(anyhow::anyhow!("whisper.cpp binary not found in any location"))
+    Err(CapslapError::WhisperBinaryNotFound("whisper.cpp binary not found in any location".into()).into())
 }
 
 /// Get possible bundled whisper binary paths (next to executable)
@@ -396,7 +454,7 @@ pub async fn find_ffmpeg_binary() -> anyhow::Result<String> {
         }
     }
 
-    Err(anyhow::anyhow!("FFmpeg binary not found in any location"))
+    Err(CapslapError::FfmpegNotFound("FFmpeg binary not found in any location".into()).into())
 }
 
 /// Find ffprobe binary using priority order (bundled > project > system)
@@ -441,7 +499,7 @@ pub async fn find_ffprobe_binary() -> anyhow::Result<String> {
         }
     }
 
-    Err(anyhow::anyhow!("ffprobe binary not found in any location"))
+    Err(CapslapError::FfmpegNotFound("ffprobe binary not found in any location".into()).into())
 }
 
 /// Get possible bundled FFmpeg binary paths (next to executable)
@@ -566,6 +624,7 @@ fn get_model_download_url(model_filename: &str) -> String {
 }
 
 /// Download whisper model from HuggingFace
+#[allow(dead_code)]
 async fn download_whisper_model(url: &str, path: &str) -> anyhow::Result<()> {
     use reqwest;
     use tokio::io::AsyncWriteExt;
@@ -610,6 +669,8 @@ pub async fn download_model_rpc(
     let models_dir = get_models_dir()
         .map_err(|e| anyhow::anyhow!("Cannot access models directory: {}. Please check app permissions.", e))?;
     let output_path = models_dir.join(model_filename);
+    // Partial downloads live next to the model until they complete
+    let part_path = models_dir.join(format!("{}.part", model_filename));
 
     emit(crate::rpc::RpcEvent::Log {
         id: id.into(),
@@ -621,24 +682,61 @@ pub async fn download_model_rpc(
         message: format!("Starting download of {} model from HuggingFace", params.model)
     });
 
+    // Resume from an earlier interrupted download if one is lying around
+    let mut existing = tokio::fs::metadata(&part_path).await.map(|m| m.len()).unwrap_or(0);
+
     // Download with progress
     let client = reqwest::Client::new();
-    let response = client.get(&url).send().await?;
+    let mut request = client.get(&url);
+    if existing > 0 {
+        request = request.header(reqwest::header::RANGE, format!("bytes={}-", existing));
+    }
+    let mut response = request.send().await?;
+
+    // 416: the partial file is already complete (or longer than the model), e.g. the process
+    // died before the rename. Its contents can't be trusted, so start over
+    if existing > 0 && response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+        emit(crate::rpc::RpcEvent::Log {
+            id: id.into(),
+            message: format!("Partial download of {} can't be resumed (HTTP 416), restarting", model_filename)
+        });
+        let _ = tokio::fs::remove_file(&part_path).await;
+        existing = 0;
+        response = client.get(&url).send().await?;
+    }
 
     if !response.status().is_success() {
         return Err(anyhow::anyhow!("Failed to download model: HTTP {}", response.status()));
     }
 
-    let total_size = response.content_length().unwrap_or(0);
+    // Only append when the server actually honoured the range; a plain 200 means the full file is coming again
+    let resumed = existing > 0 && response.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+    let mut downloaded = if resumed { existing } else { 0 };
+    let total_size = response.content_length().map(|len| len + downloaded).unwrap_or(0);
+
+    if resumed {
+        emit(crate::rpc::RpcEvent::Log {
+            id: id.into(),
+            message: format!("Resuming download of {} from {:.1} MB", model_filename, existing as f64 / 1024.0 / 1024.0)
+        });
+    } else if existing > 0 {
+        emit(crate::rpc::RpcEvent::Log {
+            id: id.into(),
+            message: format!("Server does not support resuming (HTTP {}), restarting download of {}", response.status(), model_filename)
+        });
+    }
 
     emit(crate::rpc::RpcEvent::Log {
         id: id.into(),
         message: format!("Downloading {} ({:.1} MB)...", model_filename, total_size as f64 / 1024.0 / 1024.0)
     });
 
-    let mut file = tokio::fs::File::create(&output_path).await
-        .map_err(|e| anyhow::anyhow!("Cannot create model file at {}: {}. Check app permissions in System Settings > Privacy & Security.", output_path.display(), e))?;
-    let mut downloaded = 0u64;
+    let mut file = if resumed {
+        tokio::fs::OpenOptions::new().append(true).open(&part_path).await
+    } else {
+        tokio::fs::File::create(&part_path).await
+    }
+    .map_err(|e| anyhow::anyhow!("Cannot create model file at {}: {}. Check app permissions in System Settings > Privacy & Security.", part_path.display(), e))?;
     let mut stream = response.bytes_stream();
 
     while let Some(chunk) = stream.next().await {
@@ -660,6 +758,22 @@ pub async fn download_model_rpc(
     }
 
     file.flush().await?;
+    drop(file);
+
+    if total_size > 0 && downloaded != total_size {
+        return Err(anyhow::anyhow!("Model download incomplete: got {} of {} bytes. Run the download again to resume.", downloaded, total_size));
+    }
+
+    // A corrupt download can't be resumed into a good one, so throw it away
+    let verified = hash_model_file(part_path.clone()).await
+        .and_then(|checksum| check_model_checksum(model_filename, &checksum).map_err(|e| anyhow::anyhow!(e)));
+    if let Err(e) = verified {
+        let _ = tokio::fs::remove_file(&part_path).await;
+        return Err(anyhow::anyhow!("Downloaded {} model is corrupt and was deleted: {}. Please download it again.", params.model, e));
+    }
+
+    tokio::fs::rename(&part_path, &output_path).await
+        .map_err(|e| anyhow::anyhow!("Cannot move downloaded model to {}: {}", output_path.display(), e))?;
 
     emit(crate::rpc::RpcEvent::Log {
         id: id.into(),
@@ -673,6 +787,180 @@ pub async fn download_model_rpc(
     })
 }
 
+/// Every ggml model file starts with the magic 0x67676d6c ("ggml") stored little-endian
+const GGML_MAGIC: [u8; 4] = *b"lmgg";
+
+/// SHA-1 digests published for the ggml models (whisper.cpp models/README.md)
+const MODEL_SHA1: [(&str, &str); 5] = [
+    ("ggml-tiny.bin", "bd577a113a864445d4c299885e0cb97d4ba92b5f"),
+    ("ggml-base.bin", "465707469ff3a37a2b9b8d8f89f2f99de7299dac"),
+    ("ggml-small.bin", "55356645c2b361a969dfd0ef2c5a50d530afd8d5"),
+    ("ggml-medium.bin", "fd9727b6e1217c2f614f9b698455c4ffd82463b4"),
+    ("ggml-large-v3.bin", "ad82bf6a9043ceed055076d0fd39f5f186ff8062"),
+];
+
+fn expected_model_checksum(model_filename: &str) -> Option<&'static str> {
+    MODEL_SHA1.iter().find(|(name, _)| *name == model_filename).map(|(_, sha1)| *sha1)
+}
+
+/// Compare a model file's digest with the published one
+fn check_model_checksum(model_filename: &str, checksum: &str) -> Result<(), String> {
+    match expected_model_checksum(model_filename) {
+        Some(expected) if !expected.eq_ignore_ascii_case(checksum) => Err(format!(
+            "Checksum mismatch: expected {}, got {}. Delete and re-download the model.", expected, checksum
+        )),
+        Some(_) => Ok(()),
+        None => Err(format!("No published checksum for {}", model_filename)),
+    }
+}
+
+/// SHA-1 hex digest of a model file, after checking it actually has a ggml header
+async fn hash_model_file(path: PathBuf) -> anyhow::Result<String> {
+    tokio::task::spawn_blocking(move || {
+        use sha1::Digest;
+        use std::io::{Read, Seek};
+
+        let mut file = std::fs::File::open(&path)?;
+        let mut magic = [0u8; 4];
+        file.read_exact(&mut magic)
+            .map_err(|_| anyhow::anyhow!("{} is too small to be a ggml model", path.display()))?;
+        if magic != GGML_MAGIC {
+            return Err(anyhow::anyhow!("{} is not a ggml model file (bad header)", path.display()));
+        }
+
+        file.rewind()?;
+        let mut hasher = sha1::Sha1::new();
+        std::io::copy(&mut file, &mut hasher)?;
+        Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+    }).await?
+}
+
+/// Public RPC method to re-check an installed model against its published checksum
+pub async fn verify_model_rpc(
+    id: &str,
+    params: crate::types::VerifyModelParams,
+    mut emit: impl FnMut(RpcEvent)
+) -> anyhow::Result<crate::types::VerifyModelResult> {
+    if !KNOWN_MODELS.contains(&params.model.as_str()) {
+        return Err(anyhow::anyhow!("Unknown model: {}. Supported: tiny, base, small, medium, large", params.model));
+    }
+
+    let models_dir = get_models_dir()
+        .map_err(|e| anyhow::anyhow!("Cannot access models directory: {}. Please check app permissions.", e))?;
+    let model_path = models_dir.join(model_file_name(&params.model));
+    if !model_path.exists() {
+        return Err(CapslapError::ModelMissing(format!("Model {} does not exist at {}", params.model, model_path.display())).into());
+    }
+
+    emit(RpcEvent::Log { id: id.into(), message: format!("Verifying {} model at {}", params.model, model_path.display()) });
+
+    let model_filename = model_file_name(&params.model);
+    let expected_checksum = expected_model_checksum(model_filename).map(str::to_string);
+
+    let (checksum, error) = match hash_model_file(model_path.clone()).await {
+        Ok(checksum) => {
+            let error = check_model_checksum(model_filename, &checksum).err();
+            (Some(checksum), error)
+        }
+        Err(e) => (None, Some(e.to_string())),
+    };
+
+    emit(RpcEvent::Log {
+        id: id.into(),
+        message: match &error {
+            Some(e) => format!("Model {} failed verification: {}", params.model, e),
+            None => format!("Model {} verified", params.model),
+        }
+    });
+
+    Ok(crate::types::VerifyModelResult {
+        model: params.model,
+        path: model_path.to_string_lossy().to_string(),
+        valid: error.is_none(),
+        checksum,
+        expected_checksum,
+        error,
+    })
+}
+
+/// Approximate on-disk size of each downloadable model, used for the disk space check
+fn model_download_size(model: &str) -> Option<u64> {
+    const MB: u64 = 1024 * 1024;
+    match model {
+        "tiny" => Some(75 * MB),
+        "base" => Some(142 * MB),
+        "small" => Some(466 * MB),
+        "medium" => Some(1_500 * MB),
+        "large" => Some(3_100 * MB),
+        _ => None,
+    }
+}
+
+/// Refuse a model download that wouldn't fit in `available` bytes
+fn check_download_space(model: &str, needed: u64, available: u64, models_dir: &std::path::Path) -> anyhow::Result<()> {
+    // Leave some headroom so the download doesn't fill the disk completely
+    let required = needed + needed / 10;
+    if available < required {
+        return Err(anyhow::anyhow!(
+            "Not enough disk space to download the {} model: need about {}, only {} free in {}",
+            model, format_bytes(required), format_bytes(available), models_dir.display()
+        ));
+    }
+    Ok(())
+}
+
+/// The built-in model to fetch before transcribing: only with autoDownloadModel, when the
+/// local backend will be tried with a built-in model, and only if that model is missing
+fn model_to_auto_download(
+    p: &TranscribeSegmentsParams,
+    use_openai_directly: bool,
+    has_custom_model: bool,
+    is_installed: impl Fn(&str) -> anyhow::Result<bool>
+) -> anyhow::Result<Option<&str>> {
+    if !p.auto_download_model || use_openai_directly || has_custom_model {
+        return Ok(None);
+    }
+    let requested = p.model.as_deref().unwrap_or("tiny");
+    if model_download_size(requested).is_none() || is_installed(requested)? {
+        return Ok(None);
+    }
+    Ok(Some(requested))
+}
+
+/// Download a missing model before transcription (autoDownloadModel)
+async fn auto_download_model(
+    id: &str,
+    model: &str,
+    mut emit: impl FnMut(RpcEvent)
+) -> anyhow::Result<()> {
+    let needed = model_download_size(model)
+        .ok_or_else(|| anyhow::anyhow!("Unknown model: {}. Supported: tiny, base, small, medium, large", model))?;
+    let models_dir = get_models_dir()
+        .map_err(|e| anyhow::anyhow!("Cannot access models directory: {}. Please check app permissions.", e))?;
+
+    match crate::video::available_disk_space(&models_dir) {
+        Some(available) => check_download_space(model, needed, available, &models_dir)?,
+        None => emit(RpcEvent::Log {
+            id: id.into(),
+            message: "Could not determine free disk space, downloading anyway".into()
+        }),
+    }
+
+    emit(RpcEvent::Log {
+        id: id.into(),
+        message: format!("Model '{}' is not installed, downloading it before transcription", model)
+    });
+
+    let params = crate::types::DownloadModelParams { model: model.to_string() };
+    if let Err(e) = download_model_rpc(id, params, &mut emit).await {
+        // Nothing is installed until the download is verified and renamed; an interrupted
+        // `.part` file is kept so the next attempt can resume it
+        return Err(anyhow::anyhow!("Failed to download the {} model: {}", model, e));
+    }
+
+    Ok(())
+}
+
 /// Check if a model exists
 pub fn check_model_exists(model_name: &str) -> anyhow::Result<bool> {
     let model_filename = match model_name {
@@ -715,7 +1003,7 @@ pub async fn delete_model_rpc(
     }
 
     if !model_path.exists() {
-        return Err(anyhow::anyhow!("Model {} does not exist at {}", params.model, model_path.display()));
+        return Err(CapslapError::ModelMissing(format!("Model {} does not exist at {}", params.model, model_path.display())).into());
     }
 
     emit(crate::rpc::RpcEvent::Log {
@@ -737,6 +1025,263 @@ pub async fn delete_model_rpc(
     })
 }
 
+/// Every model the download/delete RPCs know about
+const KNOWN_MODELS: [&str; 5] = ["tiny", "base", "small", "medium", "large"];
+
+/// ggml file name for a known model
+fn model_file_name(model: &str) -> &'static str {
+    match model {
+        "tiny" => "ggml-tiny.bin",
+        "base" => "ggml-base.bin",
+        "small" => "ggml-small.bin",
+        "medium" => "ggml-medium.bin",
+        _ => "ggml-large-v3.bin",
+    }
+}
+
+/// Models fetched by "download recommended" when the caller doesn't pick
+const RECOMMENDED_MODELS: [&str; 2] = ["base", "small"];
+
+/// Public RPC method to download several models with one combined progress bar
+pub async fn download_recommended_models_rpc(
+    id: &str,
+    params: crate::types::DownloadModelsParams,
+    mut emit: impl FnMut(RpcEvent)
+) -> anyhow::Result<crate::types::BulkModelResult> {
+    let models = params.models.unwrap_or_else(|| RECOMMENDED_MODELS.iter().map(|m| m.to_string()).collect());
+    let total = models.len().max(1) as f32;
+    let mut results = Vec::new();
+
+    for (idx, model) in models.into_iter().enumerate() {
+        if check_model_exists(&model).unwrap_or(false) {
+            emit(RpcEvent::Log { id: id.into(), message: format!("Model {} already installed, skipping", model) });
+            results.push(crate::types::ModelOperationResult { model, success: true, bytes: 0, error: None });
+            continue;
+        }
+
+        // Rescale each model's 0..1 download progress into its slice of the whole batch
+        let outcome = download_model_rpc(
+            id,
+            crate::types::DownloadModelParams { model: model.clone() },
+            |ev| match ev {
+                RpcEvent::Progress { id, status, progress } => emit(RpcEvent::Progress {
+                    id,
+                    status,
+                    progress: (idx as f32 + progress) / total,
+                }),
+                other => emit(other),
+            },
+        ).await;
+
+        results.push(match outcome {
+            Ok(downloaded) => crate::types::ModelOperationResult { model, success: true, bytes: downloaded.size, error: None },
+            Err(e) => {
+                emit(RpcEvent::Log { id: id.into(), message: format!("Failed to download {}: {}", model, e) });
+                crate::types::ModelOperationResult { model, success: false, bytes: 0, error: Some(e.to_string()) }
+            }
+        });
+    }
+
+    emit(RpcEvent::Progress { id: id.into(), status: "Downloads complete".into(), progress: 1.0 });
+    let total_bytes = results.iter().map(|r| r.bytes).sum();
+    Ok(crate::types::BulkModelResult { results, total_bytes })
+}
+
+/// Public RPC method to delete every installed whisper model
+pub async fn delete_all_models_rpc(
+    id: &str,
+    mut emit: impl FnMut(RpcEvent)
+) -> anyhow::Result<crate::types::BulkModelResult> {
+    let mut results = Vec::new();
+
+    for model in KNOWN_MODELS {
+        if !check_model_exists(model)? {
+            continue;
+        }
+        let size = get_models_dir().ok()
+            .and_then(|dir| std::fs::metadata(dir.join(model_file_name(model))).ok())
+            .map(|m| m.len())
+            .unwrap_or(0);
+
+        let outcome = delete_model_rpc(id, crate::types::DeleteModelParams { model: model.to_string() }, &mut emit).await;
+        results.push(match outcome {
+            Ok(_) => crate::types::ModelOperationResult { model: model.to_string(), success: true, bytes: size, error: None },
+            Err(e) => crate::types::ModelOperationResult { model: model.to_string(), success: false, bytes: 0, error: Some(e.to_string()) },
+        });
+    }
+
+    let total_bytes = results.iter().map(|r| r.bytes).sum();
+    emit(RpcEvent::Log { id: id.into(), message: format!("Freed {} from {} model(s)", format_bytes(total_bytes), results.len()) });
+    Ok(crate::types::BulkModelResult { results, total_bytes })
+}
+
+/// Public RPC method listing every known model with its install state, for the model picker
+pub fn list_models() -> anyhow::Result<Vec<crate::types::ModelInfo>> {
+    let models_dir = get_models_dir()
+        .map_err(|e| anyhow::anyhow!("Cannot access models directory: {}. Please check app permissions.", e))?;
+
+    Ok(KNOWN_MODELS.iter().map(|&model| {
+        let file_name = model_file_name(model);
+        let path = models_dir.join(file_name);
+        let installed_size = std::fs::metadata(&path).ok().filter(|m| m.is_file()).map(|m| m.len());
+        crate::types::ModelInfo {
+            name: model.to_string(),
+            installed: installed_size.is_some(),
+            path: path.to_string_lossy().to_string(),
+            size_bytes: installed_size.or_else(|| model_download_size(model)).unwrap_or(0),
+            download_url: get_model_download_url(file_name),
+        }
+    }).collect())
+}
+
+/// Default length of the clip analyzed by detectLanguage
+const DETECT_LANGUAGE_CLIP_SECS: u32 = 30;
+
+/// Public RPC method to detect the spoken language from the start of a file: local
+/// whisper.cpp `--detect-language` when available, otherwise the OpenAI API
+pub async fn detect_language_rpc(
+    id: &str,
+    params: crate::types::DetectLanguageParams,
+    mut emit: impl FnMut(RpcEvent)
+) -> anyhow::Result<crate::types::DetectLanguageResult> {
+    if let Some(path) = params.model_path.as_deref().filter(|p| !std::path::Path::new(p).is_file()) {
+        return Err(CapslapError::ModelMissing(format!("Model file not found: {}", path)).into());
+    }
+
+    // whisper.cpp wants 16 kHz mono WAV; the clip keeps both backends fast
+    let ffmpeg = find_ffmpeg_binary().await.map_err(|e| CapslapError::FfmpegNotFound(format!("FFmpeg not found: {}", e)))?;
+    let clip = std::env::temp_dir().join(format!("{}.wav", crate::rpc::job_scratch_name("capslap_detect", id)));
+    let clip_secs = params.clip_seconds.filter(|s| *s > 0).unwrap_or(DETECT_LANGUAGE_CLIP_SECS).to_string();
+    let output = TokioCommand::new(&ffmpeg)
+        .args(["-y", "-i", &params.input, "-t", &clip_secs, "-vn", "-ac", "1", "-ar", "16000", "-c:a", "pcm_s16le"])
+        .arg(&clip)
+        .kill_on_drop(true)
+        .output()
+        .await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(CapslapError::FfmpegFailed(format!("ffmpeg failed to extract a clip from {}: {}", params.input, crate::rpc::preview(&stderr, 500))).into());
+    }
+    let clip_path = clip.to_string_lossy().to_string();
+
+    let result = detect_language_with_fallback(id, &params, &clip_path, &mut emit).await;
+    let _ = fs::remove_file(&clip).await;
+    result
+}
+
+async fn detect_language_with_fallback(
+    id: &str,
+    params: &crate::types::DetectLanguageParams,
+    clip: &str,
+    emit: &mut impl FnMut(RpcEvent)
+) -> anyhow::Result<crate::types::DetectLanguageResult> {
+    if is_whisper_cpp_available().await {
+        match detect_language_with_whisper_cpp(params, clip).await {
+            Ok((language, confidence)) => {
+                return Ok(crate::types::DetectLanguageResult { language, confidence: Some(confidence), source: "whisper.cpp".into() });
+            }
+            Err(e) => emit(RpcEvent::Log {
+                id: id.into(),
+                message: format!("Local language detection failed: {}, falling back to OpenAI API", e)
+            }),
+        }
+    }
+
+    let api_key = params.api_key.as_deref().ok_or_else(|| CapslapError::OpenAiAuth("OpenAI API key not provided".into()))?;
+    let account = OpenAiAccount::new(api_key, params.api_base_url.as_deref(), params.api_org.as_deref())?;
+    let response = transcribe_openai_file(id, clip, &account, None, None, false, emit).await?;
+    let language = response.language
+        .ok_or_else(|| anyhow::anyhow!("OpenAI response did not include a language"))?;
+    Ok(crate::types::DetectLanguageResult {
+        language: crate::captions::language_code(&language),
+        confidence: None,
+        source: "openai".into(),
+    })
+}
+
+/// (language code, probability) from whisper.cpp's `--detect-language` run
+async fn detect_language_with_whisper_cpp(params: &crate::types::DetectLanguageParams, clip: &str) -> anyhow::Result<(String, f32)> {
+    let model_path = match params.model_path.as_deref() {
+        Some(path) => path.to_string(),
+        None => ensure_whisper_model(params.model.as_deref().unwrap_or("tiny")).await?.0,
+    };
+    let output = TokioCommand::new(find_whisper_binary().await?)
+        .arg("-m").arg(&model_path)
+        .arg("-l").arg("auto")
+        .arg("--detect-language")
+        .arg(clip)
+        .kill_on_drop(true)
+        .output()
+        .await?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("whisper.cpp failed with status {}: {}", output.status, crate::rpc::preview(&stderr, 500)));
+    }
+    // Logged to stderr, but some builds print it on stdout
+    parse_detected_language(&stderr)
+        .or_else(|| parse_detected_language(&String::from_utf8_lossy(&output.stdout)))
+        .ok_or_else(|| anyhow::anyhow!("whisper.cpp did not report a detected language"))
+}
+
+/// `... auto-detected language: en (p = 0.973145)` from whisper.cpp's log output
+fn parse_detected_language(log: &str) -> Option<(String, f32)> {
+    let re = Regex::new(r"auto-detected language: ([a-z]{2,3}) \(p = ([0-9.]+)\)").unwrap();
+    let caps = re.captures(log)?;
+    Some((caps[1].to_string(), caps[2].parse().ok()?))
+}
+
+/// OpenAI's published whisper-1 price in USD per audio minute
+const OPENAI_WHISPER_USD_PER_MINUTE: f64 = 0.006;
+
+/// Public RPC method to estimate what the OpenAI path would cost for a file
+pub async fn estimate_cost_rpc(
+    id: &str,
+    params: crate::types::EstimateCostParams,
+    mut emit: impl FnMut(RpcEvent)
+) -> anyhow::Result<crate::types::EstimateCostResult> {
+    let probe_result = crate::video::probe(id, &params.input, &mut emit).await?;
+    let duration_seconds = probe_result.duration
+        .ok_or_else(|| anyhow::anyhow!("Could not determine duration of {}", params.input))?;
+
+    // Rate: explicit param > CAPSLAP_OPENAI_USD_PER_MINUTE > published price
+    let rate_per_minute = params.rate_per_minute
+        .or_else(|| std::env::var("CAPSLAP_OPENAI_USD_PER_MINUTE").ok().and_then(|v| v.trim().parse().ok()))
+        .unwrap_or(OPENAI_WHISPER_USD_PER_MINUTE);
+    if !rate_per_minute.is_finite() || rate_per_minute < 0.0 {
+        return Err(anyhow::anyhow!("Invalid rate per minute: {}", rate_per_minute));
+    }
+
+    // Mirror the backend choice transcribeSegments would make
+    let mode = params.transcription_mode.as_deref().unwrap_or("auto");
+    let uses_openai = match mode {
+        "openai" => true,
+        "local" => false,
+        "auto" => {
+            let model = params.model.as_deref().unwrap_or("tiny");
+            model == "whisper-1"
+                || !((is_whisper_cpp_available().await && ensure_whisper_model(model).await.is_ok())
+                    || is_ffmpeg_whisper_available().await)
+        }
+        other => return Err(anyhow::anyhow!("Unknown transcription mode: {}. Supported: auto, local, openai", other)),
+    };
+
+    let minutes = duration_seconds / 60.0;
+    let estimated_usd = if uses_openai { minutes * rate_per_minute } else { 0.0 };
+
+    emit(RpcEvent::Log {
+        id: id.into(),
+        message: format!("{:.2} min of audio, estimated OpenAI cost ${:.4} (openai path: {})", minutes, estimated_usd, uses_openai)
+    });
+
+    Ok(crate::types::EstimateCostResult {
+        duration_seconds,
+        minutes,
+        rate_per_minute,
+        uses_openai,
+        estimated_usd,
+    })
+}
+
 /// Get the models directory path
 fn get_models_dir() -> anyhow::Result<std::path::PathBuf> {
     // Priority 1: Check if we're in development (project exists)
@@ -795,6 +1340,87 @@ fn get_models_dir() -> anyhow::Result<std::path::PathBuf> {
     Ok(fallback)
 }
 
+/// One timed token from whisper.cpp JSON as (raw text, start s, end s). Accepts both the
+/// `tokens` shape (`offsets.from/to` in ms) and the `words` shape (`start/end` in seconds);
+/// special tokens (`[_BEG_]`, `[_TT_123]`, `<|endoftext|>`) and empty ones are dropped.
+fn whisper_cpp_token(token: &serde_json::Value) -> Option<(String, f64, f64, Option<f32>)> {
+    let (text, start, end) = if let (Some(text), Some(from), Some(to)) = (
+        token.get("text").and_then(|t| t.as_str()),
+        token.get("offsets").and_then(|o| o.get("from")).and_then(|f| f.as_f64()),
+        token.get("offsets").and_then(|o| o.get("to")).and_then(|t| t.as_f64()),
+    ) {
+        (text, from / 1000.0, to / 1000.0)
+    } else {
+        (
+            token.get("word").and_then(|t| t.as_str())?,
+            token.get("start").and_then(|s| s.as_f64())?,
+            token.get("end").and_then(|e| e.as_f64())?,
+        )
+    };
+
+    let trimmed = text.trim();
+    let special = (trimmed.starts_with('[') && trimmed.ends_with(']'))
+        || trimmed.starts_with("[_")
+        || (trimmed.starts_with("<|") && trimmed.ends_with("|>"));
+    if trimmed.is_empty() || special || start >= end {
+        return None;
+    }
+    let confidence = token.get("p").and_then(|p| p.as_f64()).map(|p| p as f32);
+    Some((text.to_string(), start, end, confidence))
+}
+
+/// Collect whisper.cpp's stdout, emitting each printed segment as a `segment` event as soon
+/// as its line arrives (with streamSegments)
+async fn read_printed_segments(
+    id: &str,
+    reader: impl tokio::io::AsyncBufRead + Unpin,
+    stream_segments: bool,
+    emit: &mut impl FnMut(RpcEvent)
+) -> std::io::Result<String> {
+    let mut stdout = String::new();
+    let mut lines = reader.lines();
+    while let Some(line) = lines.next_line().await? {
+        if stream_segments {
+            if let Some(segment) = parse_printed_segment(&line) {
+                emit(RpcEvent::Segment { id: id.into(), segment });
+            }
+        }
+        stdout.push_str(&line);
+        stdout.push('\n');
+    }
+    Ok(stdout)
+}
+
+/// A segment from whisper.cpp's printed results: `[00:00:01.000 --> 00:00:04.200]  text`
+fn parse_printed_segment(line: &str) -> Option<CaptionSegment> {
+    static RE: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
+    let re = RE.get_or_init(|| Regex::new(r"^\[(\d+):(\d+):(\d+(?:\.\d+)?) --> (\d+):(\d+):(\d+(?:\.\d+)?)\]\s*(.*)$").unwrap());
+    let caps = re.captures(line.trim())?;
+    let ms = |h: usize| -> Option<u64> {
+        let secs = caps[h].parse::<f64>().ok()? * 3600.0 + caps[h + 1].parse::<f64>().ok()? * 60.0 + caps[h + 2].parse::<f64>().ok()?;
+        Some((secs * 1000.0).round() as u64)
+    };
+    let text = caps[7].trim();
+    if text.is_empty() {
+        return None;
+    }
+    Some(CaptionSegment {
+        start_ms: ms(1)?,
+        end_ms: ms(4)?,
+        text: text.to_string(),
+        words: Vec::new(),
+        speaker: None,
+        highlight: None,
+    })
+}
+
+/// streamSegments for backends without incremental output: a whole response's segments at once
+fn emit_segment_events(id: &str, response: &WhisperResponse, emit: &mut impl FnMut(RpcEvent)) {
+    for segment in whisper_to_caption_segments(response, false, None) {
+        emit(RpcEvent::Segment { id: id.into(), segment });
+    }
+}
+
 /// Parse whisper.cpp JSON output and convert to WhisperResponse
 fn parse_whisper_cpp_output(json_output: &str) -> anyhow::Result<WhisperResponse> {
     let json: serde_json::Value = serde_json::from_str(json_output)?;
@@ -827,92 +1453,34 @@ fn parse_whisper_cpp_output(json_output: &str) -> anyhow::Result<WhisperResponse
                         start: start_sec,
                         end: end_sec,
                         text: text.trim().to_string(),
+                        speaker: None,
                     });
 
-                    // TEMPORARILY DISABLE TOKEN PARSING - use only segment-level timing
-                    // This fixes sync issues with whisper.cpp tokens
-                    /*
-                    let tokens_array = segment.get("tokens")
-                        .and_then(|t| t.as_array())
-                        .or_else(|| segment.get("words").and_then(|w| w.as_array()));
-
-                    if let Some(tokens) = tokens_array {
-                        for token in tokens {
-                            // Try different JSON structures for token timing
-                            let (token_text, token_start, token_end) = if let (Some(text), Some(start), Some(end)) = (
-                                token.get("text").and_then(|t| t.as_str()),
-                                token.get("offsets").and_then(|o| o.get("from")).and_then(|f| f.as_f64()),
-                                token.get("offsets").and_then(|o| o.get("to")).and_then(|t| t.as_f64()),
-                            ) {
-                                (text, start, end)
-                            } else if let (Some(text), Some(start), Some(end)) = (
-                                token.get("word").and_then(|t| t.as_str()),
-                                token.get("start").and_then(|s| s.as_f64()),
-                                token.get("end").and_then(|e| e.as_f64()),
-                            ) {
-                                // Alternative JSON format: direct start/end fields in seconds
-                                (text, start * 1000.0, end * 1000.0) // Convert to ms for consistency
-                            } else {
-                                continue; // Skip if we can't parse this token
-                            };
-
-                            // Skip special tokens like [_BEG_] and empty/whitespace-only tokens
-                            let token_text_trimmed = token_text.trim();
-                            if !token_text_trimmed.is_empty()
-                                && !token_text_trimmed.starts_with('[')
-                                && !token_text_trimmed.ends_with(']')
-                                && token_start < token_end {
-
-                                words.push(crate::types::WhisperWord {
-                                    word: token_text_trimmed.to_string(),
-                                    start: token_start / 1000.0, // Convert ms to seconds
-                                    end: token_end / 1000.0,
-                                });
-                            }
-                        }
-                    }
-                    */
-
-                    // Parse word-level timestamps from tokens array
-                    let tokens_array = segment.get("tokens")
-                        .and_then(|t| t.as_array())
-                        .or_else(|| segment.get("words").and_then(|w| w.as_array()));
-
-                    if let Some(tokens) = tokens_array {
-                        for token in tokens {
-                            // Try different JSON structures for token timing
-                            let (token_text, token_start, token_end) = if let (Some(text), Some(start), Some(end)) = (
-                                token.get("text").and_then(|t| t.as_str()),
-                                token.get("offsets").and_then(|o| o.get("from")).and_then(|f| f.as_f64()),
-                                token.get("offsets").and_then(|o| o.get("to")).and_then(|t| t.as_f64()),
-                            ) {
-                                (text, start, end)
-                            } else if let (Some(text), Some(start), Some(end)) = (
-                                token.get("word").and_then(|t| t.as_str()),
-                                token.get("start").and_then(|s| s.as_f64()),
-                                token.get("end").and_then(|e| e.as_f64()),
-                            ) {
-                                // Alternative JSON format: direct start/end fields in seconds
-                                (text, start * 1000.0, end * 1000.0) // Convert to ms for consistency
-                            } else {
-                                continue; // Skip if we can't parse this token
-                            };
-
-                            // Skip special tokens like [_BEG_] and empty/whitespace-only tokens
-                            let token_text_trimmed = token_text.trim();
-                            if !token_text_trimmed.is_empty()
-                                && !token_text_trimmed.starts_with('[')
-                                && !token_text_trimmed.ends_with(']')
-                                && token_start < token_end {
-
-                                words.push(crate::types::WhisperWord {
-                                    word: token_text_trimmed.to_string(),
-                                    start: token_start / 1000.0, // Convert ms to seconds
-                                    end: token_end / 1000.0,
-                                });
+                    // Word-level timestamps from the segment's tokens (or ready-made words)
+                    let (tokens_array, subword_tokens) = match segment.get("tokens").and_then(|t| t.as_array()) {
+                        Some(tokens) => (Some(tokens), true),
+                        None => (segment.get("words").and_then(|w| w.as_array()), false),
+                    };
+
+                    let mut segment_words: Vec<crate::types::WhisperWord> = Vec::new();
+                    for (text, start, end, confidence) in tokens_array.into_iter().flatten().filter_map(whisper_cpp_token) {
+                        // BPE pieces without a leading space continue the previous word
+                        let continues_word = subword_tokens
+                            && !text.starts_with(char::is_whitespace)
+                            && !segment_words.is_empty();
+                        match segment_words.last_mut() {
+                            Some(prev) if continues_word => {
+                                prev.word.push_str(text.trim());
+                                prev.end = prev.end.max(end);
+                                prev.confidence = match (prev.confidence, confidence) {
+                                    (Some(a), Some(b)) => Some(a.min(b)),
+                                    (a, b) => a.or(b),
+                                };
                             }
+                            _ => segment_words.push(crate::types::WhisperWord { word: text.trim().to_string(), start, end, confidence }),
                         }
                     }
+                    words.extend(segment_words);
                 }
             }
         }
@@ -926,7 +1494,10 @@ fn parse_whisper_cpp_output(json_output: &str) -> anyhow::Result<WhisperResponse
 
     let response = WhisperResponse {
         task: Some("transcribe".to_string()),
-        language: None,
+        language: json.get("result")
+            .and_then(|r| r.get("language"))
+            .and_then(|l| l.as_str())
+            .map(|l| l.to_string()),
         duration: Some(duration),
         text: full_text,
         segments: Some(segments.clone()),
@@ -951,7 +1522,7 @@ pub async fn transcribe_with_ffmpeg_whisper(
         message: format!("Starting local FFmpeg Whisper transcription with model: {}", whisper_model)
     });
 
-    let ffmpeg_path = find_ffmpeg_binary().await.map_err(|e| anyhow::anyhow!("FFmpeg not found: {}", e))?;
+    let ffmpeg_path = find_ffmpeg_binary().await.map_err(|e| CapslapError::FfmpegNotFound(format!("FFmpeg not found: {}", e)))?;
     let mut cmd = TokioCommand::new(ffmpeg_path);
     cmd.arg("-y") // overwrite output
        .arg("-i").arg(audio_path)
@@ -975,11 +1546,12 @@ pub async fn transcribe_with_ffmpeg_whisper(
         message: "Running FFmpeg Whisper transcription...".into()
     });
 
+    cmd.kill_on_drop(true);
     let output = cmd.output().await?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(anyhow::anyhow!("FFmpeg Whisper failed: {}", stderr));
+        return Err(CapslapError::FfmpegFailed(format!("FFmpeg Whisper failed: {}", stderr)).into());
     }
 
     let stderr = String::from_utf8_lossy(&output.stderr);
@@ -1043,6 +1615,7 @@ fn parse_ffmpeg_whisper_output(stderr: &str) -> anyhow::Result<WhisperResponse>
                     start,
                     end,
                     text: text.clone(),
+                    speaker: None,
                 });
             }
         }
@@ -1065,18 +1638,10 @@ fn parse_ffmpeg_whisper_output(stderr: &str) -> anyhow::Result<WhisperResponse>
     })
 }
 
-/// Helper function to create transcription result with JSON file generation
-async fn create_transcription_result(
-    id: &str,
-    segments: &[CaptionSegment],
-    whisper_response: &WhisperResponse,
-    params: &TranscribeSegmentsParams,
-    temp_dir: Option<&std::path::PathBuf>
-) -> anyhow::Result<TranscribeSegmentsResult> {
-    use tokio::fs;
-
-    // Generate JSON file path based on temp directory (or video file location if no temp dir)
-    let json_path = if let Some(temp_dir) = temp_dir {
+/// Where the JSON export for a transcription is written
+fn transcription_json_path(id: &str, params: &TranscribeSegmentsParams, temp_dir: Option<&std::path::PathBuf>) -> String {
+    // Based on temp directory (or video file location if no temp dir)
+    if let Some(temp_dir) = temp_dir {
         let json_filename = format!("transcription_{}.json", id);
         temp_dir.join(json_filename).to_string_lossy().to_string()
     } else {
@@ -1088,30 +1653,80 @@ async fn create_transcription_result(
         let mut json_path = base_path.to_path_buf();
         json_path.set_extension("json");
         json_path.to_string_lossy().to_string()
+    }
+}
+
+/// Helper function to create transcription result with JSON file generation
+async fn create_transcription_result(
+    id: &str,
+    segments: &[CaptionSegment],
+    whisper_response: &WhisperResponse,
+    params: &TranscribeSegmentsParams,
+    temp_dir: Option<&std::path::PathBuf>,
+    emit: &mut impl FnMut(RpcEvent)
+) -> anyhow::Result<TranscribeSegmentsResult> {
+    use tokio::fs;
+
+    // Keep segment-level captions readable when the backend returns whole sentences
+    let segments = match params.max_words_per_caption {
+        Some(max_words) if !params.split_by_words => repack_long_segments(segments.to_vec(), max_words),
+        _ => segments.to_vec(),
+    };
+    let segments = match params.max_segment_ms {
+        Some(max_ms) => split_long_segments(segments, max_ms),
+        None => segments,
     };
 
-    // Create JSON export data
-    let json_data = serde_json::json!({
-        "segments": segments,
-        "fullText": whisper_response.text,
-        "duration": whisper_response.duration,
-        "splitByWords": params.split_by_words,
-        "model": params.model.clone().unwrap_or_else(|| "whisper-1".to_string()),
-        "language": params.language.clone(),
-        "generatedAt": std::time::SystemTime::now()
+    // Create JSON export data from the typed schema so it can't drift from the RPC result
+    let export = TranscriptionExport {
+        segments,
+        full_text: whisper_response.text.clone(),
+        duration: whisper_response.duration,
+        split_by_words: params.split_by_words,
+        model: params.model.clone().unwrap_or_else(|| "whisper-1".to_string()),
+        language: params.language.clone(),
+        generated_at: std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
-            .as_secs()
-    });
+            .as_secs(),
+    };
+
+    // API-style callers keep segments in memory and don't want files to clean up
+    let json_path = if params.write_transcript_json.unwrap_or(true) {
+        let json_path = crate::rpc::sanitize_output_path(&transcription_json_path(id, params, temp_dir))?
+            .to_string_lossy()
+            .to_string();
+        let json_content = serde_json::to_string_pretty(&export)?;
+        fs::write(&json_path, json_content).await?;
+        emit(RpcEvent::Artifact { id: id.into(), kind: "json".into(), path: json_path.clone() });
+        Some(json_path)
+    } else {
+        None
+    };
 
-    let json_content = serde_json::to_string_pretty(&json_data)?;
-    fs::write(&json_path, json_content).await?;
+    // Standard sidecars share the JSON export's location and stem
+    let (srt_path, vtt_path) = if params.export_subtitles {
+        let base = crate::rpc::sanitize_output_path(&transcription_json_path(id, params, temp_dir))?;
+        let srt_path = base.with_extension("srt");
+        let vtt_path = base.with_extension("vtt");
+        fs::write(&srt_path, crate::captions::build_srt(&export.segments)).await?;
+        fs::write(&vtt_path, crate::captions::build_segment_vtt(&export.segments, params.word_level_subtitles)).await?;
+        let (srt_path, vtt_path) = (srt_path.to_string_lossy().to_string(), vtt_path.to_string_lossy().to_string());
+        emit(RpcEvent::Artifact { id: id.into(), kind: "srt".into(), path: srt_path.clone() });
+        emit(RpcEvent::Artifact { id: id.into(), kind: "vtt".into(), path: vtt_path.clone() });
+        (Some(srt_path), Some(vtt_path))
+    } else {
+        (None, None)
+    };
 
     Ok(TranscribeSegmentsResult {
-        segments: segments.to_vec(),
-        full_text: whisper_response.text.clone(),
-        duration: whisper_response.duration,
+        segments: export.segments,
+        full_text: export.full_text,
+        duration: export.duration,
         json_file: json_path,
+        srt_file: srt_path,
+        vtt_file: vtt_path,
+        language: params.language.clone().or_else(|| whisper_response.language.clone()),
     })
 }
 
@@ -1120,60 +1735,55 @@ pub async fn transcribe_segments(id: &str, p: TranscribeSegmentsParams, emit: im
 }
 
 pub async fn transcribe_segments_with_temp(id: &str, p: TranscribeSegmentsParams, temp_dir: Option<&std::path::PathBuf>, mut emit: impl FnMut(RpcEvent)) -> anyhow::Result<TranscribeSegmentsResult> {
-    use reqwest::multipart;
-    use mime_guess::MimeGuess;
     use tokio::fs;
 
     // QUICK SWITCH: Set to false to force OpenAI API, true for local whisper
     const USE_LOCAL_WHISPER: bool = true;
 
-    // Check cache first
-    if let Ok(Some(cached_response)) = get_cached_whisper_response(&p.audio, &p).await {
-        let segments = whisper_to_caption_segments(&cached_response, p.split_by_words);
+    // Refuse oversized inputs up front instead of buffering them later
+    let audio_bytes = fs::metadata(&p.audio).await
+        .map_err(|e| anyhow::anyhow!("Cannot read audio file {}: {}", p.audio, e))?
+        .len();
+    check_audio_size(audio_bytes, max_audio_bytes(&p))?;
+
+    // A custom model that isn't there is an error, not a reason to fall back to another model
+    let custom_model = resolve_model_path(&p)?;
+    let cpu = (check_cpu_count("threads", p.threads)?, check_cpu_count("processors", p.processors)?);
+
+    // Resolve the effective prompt (per-language map / prompt file); a map needs the language,
+    // so it's detected first when the caller didn't give one
+    let prompt_language = match (&p.prompt, p.language.as_deref()) {
+        (Some(PromptSpec::PerLanguage(_)), None) => detect_prompt_language(id, &p, &mut emit).await,
+        (_, language) => language.map(str::to_string),
+    };
+    let prompt = resolve_prompt(&p, prompt_language.as_deref())?;
 
-        // generate JSON file path for cached response too
-        let json_path = if let Some(temp_dir) = temp_dir {
-            let json_filename = format!("transcription_{}.json", id);
-            temp_dir.join(json_filename).to_string_lossy().to_string()
-        } else {
-            let base_path = if let Some(ref video_file) = p.video_file {
-                std::path::Path::new(video_file)
-            } else {
-                std::path::Path::new(&p.audio)
-            };
-            let mut json_path = base_path.to_path_buf();
-            json_path.set_extension("json");
-            json_path.to_string_lossy().to_string()
-        };
+    // Check cache first
+    if let Ok(Some(cached_response)) = get_cached_whisper_response(&p.audio, &p, prompt.as_deref()).await {
+        if p.stream_segments {
+            emit_segment_events(id, &cached_response, &mut emit);
+        }
+        let segments = whisper_to_caption_segments(&cached_response, p.split_by_words, p.min_word_confidence);
 
-        // save JSON file for cached response as well
-        let json_data = serde_json::json!({
-            "segments": segments,
-            "fullText": cached_response.text,
-            "duration": cached_response.duration,
-            "splitByWords": p.split_by_words,
-            "model": p.model.clone().unwrap_or_else(|| "whisper-1".to_string()),
-            "language": p.language.clone(),
-            "generatedAt": std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs()
-        });
+        // write the JSON export for cached responses too
+        return create_transcription_result(id, &segments, &cached_response, &p, temp_dir, &mut emit).await;
+    }
 
-        let json_content = serde_json::to_string_pretty(&json_data)?;
-        fs::write(&json_path, json_content).await?;
+    // Explicit backend selection; "auto" keeps the whisper-1 model-name convention
+    let mode = p.transcription_mode.as_deref().unwrap_or("auto");
+    let (use_openai_directly, local_only) = match mode {
+        "auto" => (p.model.as_ref().map(|m| m == "whisper-1").unwrap_or(false), false),
+        "local" => (false, true),
+        "openai" => (true, false),
+        other => return Err(anyhow::anyhow!("Unknown transcription mode: {}. Supported: auto, local, openai", other)),
+    };
+    let mut local_error: Option<String> = None;
 
-        return Ok(TranscribeSegmentsResult {
-            segments,
-            full_text: cached_response.text,
-            duration: cached_response.duration,
-            json_file: json_path,
-        });
+    // Fetch a missing local model up front so the fallback chain doesn't quietly pick a smaller one
+    if let Some(model) = model_to_auto_download(&p, use_openai_directly, custom_model.is_some(), check_model_exists)? {
+        auto_download_model(id, model, &mut emit).await?;
     }
 
-    // Check if user explicitly selected OpenAI API (whisper-1)
-    let use_openai_directly = p.model.as_ref().map(|m| m == "whisper-1").unwrap_or(false);
-
     // Try local whisper.cpp first if available (unless whisper-1 is explicitly selected)
     if !use_openai_directly && USE_LOCAL_WHISPER && is_whisper_cpp_available().await {
         emit(RpcEvent::Log {
@@ -1181,14 +1791,14 @@ pub async fn transcribe_segments_with_temp(id: &str, p: TranscribeSegmentsParams
             message: "whisper.cpp detected, attempting local transcription...".into()
         });
 
-        match transcribe_with_whisper_cpp(id, &p.audio, p.model.clone(), p.language.clone(), &mut emit).await {
+        match transcribe_with_whisper_cpp(id, &p.audio, p.model.clone(), custom_model.as_deref(), p.language.clone(), prompt.clone(), p.whisper_max_len.unwrap_or(0), p.stream_segments, cpu, &mut emit).await {
             Ok(whisper_response) => {
                 emit(RpcEvent::Log {
                     id: id.into(),
                     message: "Local whisper.cpp transcription successful".into()
                 });
 
-                let segments = whisper_to_caption_segments(&whisper_response, p.split_by_words);
+                let segments = whisper_to_caption_segments(&whisper_response, p.split_by_words, p.min_word_confidence);
 
                 emit(RpcEvent::Log {
                     id: id.into(),
@@ -1197,15 +1807,16 @@ pub async fn transcribe_segments_with_temp(id: &str, p: TranscribeSegmentsParams
                 });
 
                 // Save to cache
-                if let Err(e) = save_cached_whisper_response(&p.audio, &p, &whisper_response).await {
+                if let Err(e) = save_cached_whisper_response(&p.audio, &p, prompt.as_deref(), &whisper_response).await {
                     emit(RpcEvent::Log { id: id.into(), message: format!("Failed to cache local transcription: {}", e) });
                 }
 
                 // Generate JSON file and return result
-                return create_transcription_result(id, &segments, &whisper_response, &p, temp_dir).await;
+                return create_transcription_result(id, &segments, &whisper_response, &p, temp_dir, &mut emit).await;
             }
             Err(e) => {
-                let error_msg = if e.to_string().contains("No whisper models found") {
+                local_error = Some(e.to_string());
+                let error_msg = if matches!(e.downcast_ref::<CapslapError>(), Some(CapslapError::ModelMissing(_))) {
                     format!("No local whisper models available, falling back to OpenAI API. ({})", e)
                 } else {
                     format!("Local whisper.cpp failed: {}, falling back to OpenAI API", e)
@@ -1232,18 +1843,22 @@ pub async fn transcribe_segments_with_temp(id: &str, p: TranscribeSegmentsParams
                     id: id.into(),
                     message: "Local FFmpeg Whisper transcription successful".into()
                 });
+                if p.stream_segments {
+                    emit_segment_events(id, &whisper_response, &mut emit);
+                }
 
-                let segments = whisper_to_caption_segments(&whisper_response, p.split_by_words);
+                let segments = whisper_to_caption_segments(&whisper_response, p.split_by_words, p.min_word_confidence);
 
                 // Save to cache
-                if let Err(e) = save_cached_whisper_response(&p.audio, &p, &whisper_response).await {
+                if let Err(e) = save_cached_whisper_response(&p.audio, &p, prompt.as_deref(), &whisper_response).await {
                     emit(RpcEvent::Log { id: id.into(), message: format!("Failed to cache local transcription: {}", e) });
                 }
 
                 // Generate JSON file and return result
-                return create_transcription_result(id, &segments, &whisper_response, &p, temp_dir).await;
+                return create_transcription_result(id, &segments, &whisper_response, &p, temp_dir, &mut emit).await;
             }
             Err(e) => {
+                local_error = Some(e.to_string());
                 emit(RpcEvent::Log {
                     id: id.into(),
                     message: format!("Local FFmpeg Whisper failed: {}, falling back to API", e)
@@ -1252,66 +1867,392 @@ pub async fn transcribe_segments_with_temp(id: &str, p: TranscribeSegmentsParams
         }
     }
 
+    // Local-only mode must never reach the paid API
+    if local_only {
+        return Err(anyhow::anyhow!(
+            "Local transcription failed and transcription mode is 'local': {}",
+            local_error.unwrap_or_else(|| "no local whisper backend available".into())
+        ));
+    }
+
     emit(RpcEvent::Log {
         id: id.into(),
         message: "No local Whisper available, using OpenAI API".into()
     });
 
     // Fallback to OpenAI API
-    let api_key = p.api_key.as_ref().ok_or_else(|| anyhow::anyhow!("OpenAI API key not provided"))?;
-    // Always use whisper-1 for OpenAI API (local model names like "tiny" are not valid for the API)
-    let model = "whisper-1".to_string();
+    let api_key = p.api_key.as_ref().ok_or_else(|| CapslapError::OpenAiAuth("OpenAI API key not provided".into()))?;
+    let account = OpenAiAccount::new(api_key, p.api_base_url.as_deref(), p.api_org.as_deref())?;
 
-    let bytes = fs::read(&p.audio).await?;
-    let filename = std::path::Path::new(&p.audio).file_name().unwrap_or_default().to_string_lossy().to_string();
-    let mime = MimeGuess::from_path(&p.audio).first_or_octet_stream();
+    // The API rejects uploads over 25MB: split long audio at silences and merge the results
+    let whisper_response = if audio_bytes > OPENAI_MAX_UPLOAD_BYTES {
+        emit(RpcEvent::Log {
+            id: id.into(),
+            message: format!("Audio is {} (API limit {}), splitting into chunks", format_bytes(audio_bytes), format_bytes(OPENAI_MAX_UPLOAD_BYTES))
+        });
+        transcribe_openai_chunked(id, &p, &account, prompt.as_deref(), &mut emit).await?
+    } else {
+        let response = transcribe_openai_file(id, &p.audio, &account, p.language.as_deref(), prompt.as_deref(), p.split_by_words, &mut emit).await?;
+        if p.stream_segments {
+            emit_segment_events(id, &response, &mut emit);
+        }
+        response
+    };
 
-    // build form for verbose_json with appropriate timestamp granularities
-    let mut form = multipart::Form::new()
-        .text("model", model.clone())
-        .part("file", multipart::Part::bytes(bytes.clone()).file_name(filename.clone()).mime_str(mime.as_ref()).unwrap())
-        .text("response_format", "verbose_json".to_string());
+    let segments = whisper_to_caption_segments(&whisper_response, p.split_by_words, p.min_word_confidence);
 
-    if let Some(lang) = &p.language {
-        form = form.text("language", lang.clone());
+    // Save to cache
+    if let Err(e) = save_cached_whisper_response(&p.audio, &p, prompt.as_deref(), &whisper_response).await {
+        emit(RpcEvent::Log { id: id.into(), message: format!("Failed to cache transcription: {}", e) });
     }
-    if let Some(prompt) = &p.prompt {
-        form = form.text("prompt", prompt.clone());
+
+    create_transcription_result(id, &segments, &whisper_response, &p, temp_dir, &mut emit).await
+}
+
+/// OpenAI transcription upload limit
+const OPENAI_MAX_UPLOAD_BYTES: u64 = 25 * 1024 * 1024;
+/// Longest chunk sent to the API; at the 64 kbps mono re-encode this is ~9.6MB, well under the limit
+const OPENAI_CHUNK_MAX_SECS: f64 = 20.0 * 60.0;
+
+/// API root used when apiBaseUrl is unset
+const OPENAI_DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// Credentials and endpoint for OpenAI transcription requests (apiKey, apiBaseUrl, apiOrg)
+struct OpenAiAccount<'a> {
+    api_key: &'a str,
+    endpoint: reqwest::Url,     // Full audio/transcriptions URL
+    org: Option<&'a str>,       // Sent as OpenAI-Organization when set
+}
+
+impl<'a> OpenAiAccount<'a> {
+    fn new(api_key: &'a str, base_url: Option<&str>, org: Option<&'a str>) -> anyhow::Result<Self> {
+        let base = base_url.unwrap_or(OPENAI_DEFAULT_BASE_URL);
+        let mut endpoint = reqwest::Url::parse(base)
+            .map_err(|e| anyhow::anyhow!("Invalid apiBaseUrl {}: {}", base, e))?;
+        if !matches!(endpoint.scheme(), "http" | "https") {
+            return Err(anyhow::anyhow!("apiBaseUrl must be an http(s) URL: {}", base));
+        }
+        // Keep any query (Azure's api-version) and append the route to the path
+        let path = format!("{}/audio/transcriptions", endpoint.path().trim_end_matches('/'));
+        endpoint.set_path(&path);
+        Ok(OpenAiAccount { api_key, endpoint, org: org.filter(|o| !o.trim().is_empty()) })
     }
 
-    // set timestamp granularities based on split_by_words preference
-    if p.split_by_words {
-        form = form.text("timestamp_granularities[]", "word".to_string());
-    } else {
-        form = form.text("timestamp_granularities[]", "segment".to_string());
+    /// Azure OpenAI takes the key in an `api-key` header instead of a bearer token
+    fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        let is_azure = self.endpoint.host_str().is_some_and(|h| h.ends_with(".openai.azure.com"));
+        let request = if is_azure {
+            request.header("api-key", self.api_key)
+        } else {
+            request.header("Authorization", format!("Bearer {}", self.api_key))
+        };
+        match self.org {
+            Some(org) => request.header("OpenAI-Organization", org),
+            None => request,
+        }
     }
+}
+
+/// Attempts per OpenAI transcription request (the first try included)
+const OPENAI_ATTEMPTS: u32 = 3;
+/// Backoff before the first retry; doubles each time, plus up to `OPENAI_RETRY_JITTER_MS`
+const OPENAI_RETRY_BASE_MS: u64 = 1000;
+const OPENAI_RETRY_JITTER_MS: u64 = 500;
+
+/// Rate limits and server-side hiccups are worth another try; other statuses are final
+fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+    matches!(status.as_u16(), 429 | 500 | 502 | 503 | 504)
+}
+
+/// Upload one audio file to the OpenAI transcription endpoint (verbose_json).
+/// Network errors and retryable statuses are retried with exponential backoff and jitter.
+async fn transcribe_openai_file(
+    id: &str,
+    audio: &str,
+    account: &OpenAiAccount<'_>,
+    language: Option<&str>,
+    prompt: Option<&str>,
+    split_by_words: bool,
+    emit: &mut impl FnMut(RpcEvent)
+) -> anyhow::Result<WhisperResponse> {
+    use reqwest::multipart;
+    use mime_guess::MimeGuess;
+
+    // Always use whisper-1 for OpenAI API (local model names like "tiny" are not valid for the API)
+    let model = "whisper-1".to_string();
+
+    let bytes = fs::read(audio).await?;
+    let filename = std::path::Path::new(audio).file_name().unwrap_or_default().to_string_lossy().to_string();
+    let mime = MimeGuess::from_path(audio).first_or_octet_stream();
+
+    // build form for verbose_json with appropriate timestamp granularities
+    // (a multipart body is consumed on send, so each attempt builds its own)
+    let build_form = || {
+        let mut form = multipart::Form::new()
+            .text("model", model.clone())
+            .part("file", multipart::Part::bytes(bytes.clone()).file_name(filename.clone()).mime_str(mime.as_ref()).unwrap())
+            .text("response_format", "verbose_json".to_string());
+
+        if let Some(lang) = language {
+            form = form.text("language", lang.to_string());
+        }
+        if let Some(prompt) = prompt {
+            form = form.text("prompt", prompt.to_string());
+        }
+
+        // set timestamp granularities based on split_by_words preference
+        if split_by_words {
+            form = form.text("timestamp_granularities[]", "word".to_string());
+        } else {
+            form = form.text("timestamp_granularities[]", "segment".to_string());
+        }
+        form
+    };
 
     let client = reqwest::Client::builder().user_agent("core/1.0.0").build()?;
 
-    let resp = client.post("https://api.openai.com/v1/audio/transcriptions")
-        .header("Authorization", format!("Bearer {}", api_key))
-        .multipart(form)
-        .send()
+    let mut attempt = 1;
+    loop {
+        let result = account.authorize(client.post(account.endpoint.clone()))
+            .multipart(build_form())
+            .send()
+            .await;
+
+        let failure = match result {
+            Ok(resp) if resp.status().is_success() => return Ok(resp.json().await?),
+            Ok(resp) => {
+                let status = resp.status();
+                let body = resp.text().await.unwrap_or_default();
+                let message = format!("OpenAI error {}: {}", status, body);
+                if status == reqwest::StatusCode::UNAUTHORIZED {
+                    return Err(CapslapError::OpenAiAuth(message).into());
+                }
+                if !is_retryable_status(status) || attempt >= OPENAI_ATTEMPTS {
+                    return Err(anyhow::anyhow!(message));
+                }
+                format!("HTTP {}", status)
+            }
+            Err(e) if attempt < OPENAI_ATTEMPTS => e.to_string(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let jitter = (uuid::Uuid::new_v4().as_u128() % (OPENAI_RETRY_JITTER_MS as u128 + 1)) as u64;
+        let delay_ms = OPENAI_RETRY_BASE_MS * (1 << (attempt - 1)) + jitter;
+        emit(RpcEvent::Log {
+            id: id.into(),
+            message: format!(
+                "OpenAI request failed ({}), retrying in {:.1}s (attempt {}/{})",
+                failure, delay_ms as f64 / 1000.0, attempt + 1, OPENAI_ATTEMPTS
+            )
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(delay_ms)).await;
+        attempt += 1;
+    }
+}
+
+/// Transcribe audio over the API upload limit: cut it at silences into re-encoded chunks,
+/// transcribe each one and merge the responses with timestamps shifted by the chunk start.
+async fn transcribe_openai_chunked(
+    id: &str,
+    p: &TranscribeSegmentsParams,
+    account: &OpenAiAccount<'_>,
+    prompt: Option<&str>,
+    emit: &mut impl FnMut(RpcEvent)
+) -> anyhow::Result<WhisperResponse> {
+    let ffmpeg = find_ffmpeg_binary().await?;
+    let (duration, silences) = detect_silences(&ffmpeg, &p.audio).await?;
+    let cuts = choose_chunk_cuts(duration, &silences, OPENAI_CHUNK_MAX_SECS);
+
+    let chunk_dir = std::env::temp_dir().join(crate::rpc::job_scratch_name("capslap_openai_chunks", id));
+    fs::create_dir_all(&chunk_dir).await?;
+
+    let result = async {
+        let total = cuts.len() - 1;
+        let mut merged = WhisperResponse {
+            task: Some("transcribe".into()),
+            language: None,
+            duration: Some(duration),
+            text: String::new(),
+            segments: None,
+            words: None,
+        };
+
+        for (i, window) in cuts.windows(2).enumerate() {
+            let (start, end) = (window[0], window[1]);
+            emit(RpcEvent::Log {
+                id: id.into(),
+                message: format!("Transcribing chunk {}/{} ({:.0}s - {:.0}s)", i + 1, total, start, end)
+            });
+
+            let chunk_path = chunk_dir.join(format!("chunk_{:03}.mp3", i));
+            let chunk = chunk_path.to_string_lossy().to_string();
+            let status = TokioCommand::new(&ffmpeg)
+                .args(["-y", "-v", "error",
+                       "-ss", &format!("{:.3}", start), "-t", &format!("{:.3}", end - start),
+                       "-i", &p.audio, "-vn", "-ac", "1", "-b:a", "64k", &chunk])
+                .kill_on_drop(true)
+                .status()
+                .await?;
+            if !status.success() {
+                return Err(CapslapError::FfmpegFailed(format!("ffmpeg failed to cut audio chunk {}/{}", i + 1, total)).into());
+            }
+
+            let response = transcribe_openai_file(id, &chunk, account, p.language.as_deref(), prompt, p.split_by_words, emit).await
+                .map_err(|e| anyhow::anyhow!("Chunk {}/{} failed: {}", i + 1, total, e))?;
+            if p.stream_segments {
+                // Shift first so streamed segments carry whole-file timestamps
+                let mut shifted = WhisperResponse { task: None, language: None, duration: None, text: String::new(), segments: None, words: None };
+                merge_offset_response(&mut shifted, response, start);
+                emit_segment_events(id, &shifted, emit);
+                merge_offset_response(&mut merged, shifted, 0.0);
+            } else {
+                merge_offset_response(&mut merged, response, start);
+            }
+        }
+        Ok(merged)
+    }.await;
+
+    let _ = fs::remove_dir_all(&chunk_dir).await;
+    result
+}
+
+/// Run ffmpeg silencedetect; returns (duration secs, silence midpoints secs)
+async fn detect_silences(ffmpeg: &str, audio: &str) -> anyhow::Result<(f64, Vec<f64>)> {
+    let output = TokioCommand::new(ffmpeg)
+        .args(["-hide_banner", "-i", audio, "-af", "silencedetect=noise=-35dB:d=0.4", "-f", "null", "-"])
+        .kill_on_drop(true)
+        .output()
         .await?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    let duration_re = Regex::new(r"Duration: (\d+):(\d+):(\d+(?:\.\d+)?)").unwrap();
+    let duration = duration_re.captures(&stderr)
+        .map(|c| c[1].parse::<f64>().unwrap_or(0.0) * 3600.0 + c[2].parse::<f64>().unwrap_or(0.0) * 60.0 + c[3].parse::<f64>().unwrap_or(0.0))
+        .filter(|d| *d > 0.0)
+        .ok_or_else(|| anyhow::anyhow!("Could not determine audio duration for chunking"))?;
+
+    let start_re = Regex::new(r"silence_start: (-?\d+(?:\.\d+)?)").unwrap();
+    let end_re = Regex::new(r"silence_end: (\d+(?:\.\d+)?)").unwrap();
+    let starts: Vec<f64> = start_re.captures_iter(&stderr).filter_map(|c| c[1].parse().ok()).collect();
+    let ends: Vec<f64> = end_re.captures_iter(&stderr).filter_map(|c| c[1].parse().ok()).collect();
+    let midpoints = starts.iter().zip(ends.iter()).map(|(s, e)| (s.max(0.0) + e) / 2.0).collect();
 
-    if !resp.status().is_success() {
-        let status = resp.status();
-        let body = resp.text().await.unwrap_or_default();
-        return Err(anyhow::anyhow!("OpenAI error {}: {}", status, body));
+    Ok((duration, midpoints))
+}
+
+/// Pick chunk boundaries (including 0 and the duration) no more than `max_secs` apart,
+/// preferring the latest silence in the back half of each window over a hard cut.
+fn choose_chunk_cuts(duration: f64, silences: &[f64], max_secs: f64) -> Vec<f64> {
+    let mut cuts = vec![0.0];
+    let mut last = 0.0;
+    while duration - last > max_secs {
+        let limit = last + max_secs;
+        let cut = silences.iter().copied()
+            .filter(|&t| t > last + max_secs / 2.0 && t <= limit)
+            .fold(None, |best: Option<f64>, t| Some(best.map_or(t, |b| b.max(t))))
+            .unwrap_or(limit);
+        cuts.push(cut);
+        last = cut;
+    }
+    cuts.push(duration);
+    cuts
+}
+
+/// Append a chunk's response to the merged one, shifting its timestamps by `offset` seconds
+fn merge_offset_response(merged: &mut WhisperResponse, chunk: WhisperResponse, offset: f64) {
+    if merged.language.is_none() {
+        merged.language = chunk.language;
+    }
+    let text = chunk.text.trim();
+    if !text.is_empty() {
+        if !merged.text.is_empty() { merged.text.push(' '); }
+        merged.text.push_str(text);
+    }
+    if let Some(segments) = chunk.segments {
+        let out = merged.segments.get_or_insert_with(Vec::new);
+        for mut seg in segments {
+            seg.id = out.len() as u32;
+            seg.start += offset;
+            seg.end += offset;
+            out.push(seg);
+        }
+    }
+    if let Some(words) = chunk.words {
+        let out = merged.words.get_or_insert_with(Vec::new);
+        out.extend(words.into_iter().map(|mut w| { w.start += offset; w.end += offset; w }));
     }
+}
+
+/// Per-request audio size limit: the param wins, then the CAPSLAP_MAX_AUDIO_BYTES env var
+fn max_audio_bytes(p: &TranscribeSegmentsParams) -> Option<u64> {
+    p.max_audio_bytes.or_else(|| {
+        std::env::var("CAPSLAP_MAX_AUDIO_BYTES").ok().and_then(|v| v.trim().parse().ok())
+    })
+}
 
-    let whisper_response: WhisperResponse = resp.json().await?;
+fn check_audio_size(audio_bytes: u64, limit: Option<u64>) -> anyhow::Result<()> {
+    match limit {
+        Some(limit) if audio_bytes > limit => Err(anyhow::anyhow!(
+            "Audio file is {} which exceeds the configured limit of {}",
+            format_bytes(audio_bytes), format_bytes(limit)
+        )),
+        _ => Ok(()),
+    }
+}
 
-    let segments = whisper_to_caption_segments(&whisper_response, p.split_by_words);
+fn format_bytes(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+}
 
-    // Save to cache
-    if let Err(e) = save_cached_whisper_response(&p.audio, &p, &whisper_response).await {
-        emit(RpcEvent::Log { id: id.into(), message: format!("Failed to cache transcription: {}", e) });
+/// Language for a per-language prompt when the caller didn't give one; None uses the "default" entry
+async fn detect_prompt_language(id: &str, p: &TranscribeSegmentsParams, emit: &mut impl FnMut(RpcEvent)) -> Option<String> {
+    let params = crate::types::DetectLanguageParams {
+        input: p.audio.clone(),
+        // "whisper-1" names the OpenAI backend, not a local model
+        model: p.model.clone().filter(|m| model_download_size(m).is_some()),
+        model_path: p.model_path.clone(),
+        api_key: p.api_key.clone(),
+        api_base_url: p.api_base_url.clone(),
+        api_org: p.api_org.clone(),
+        clip_seconds: None,
+    };
+    match detect_language_rpc(id, params, &mut *emit).await {
+        Ok(detected) => Some(detected.language),
+        Err(e) => {
+            eprintln!("[whisper] language detection for the prompt failed, using its default entry: {}", e);
+            None
+        }
     }
+}
 
-    create_transcription_result(id, &segments, &whisper_response, &p, temp_dir).await
+/// Per-language prompt keys and languages compare case-insensitively, with '_' as '-' ("pt_BR" == "pt-br")
+fn prompt_language_key(lang: &str) -> String {
+    lang.trim().to_lowercase().replace('_', "-")
 }
 
+/// Resolve the effective transcription prompt for `language` (given or detected).
+/// A per-language map is looked up by the full tag, then its base code, then its "default" entry;
+/// `prompt_file` is read when `prompt` is absent or has no entry for the language.
+pub fn resolve_prompt(p: &TranscribeSegmentsParams, language: Option<&str>) -> anyhow::Result<Option<String>> {
+    let from_prompt = match &p.prompt {
+        Some(PromptSpec::Text(text)) => Some(text.clone()),
+        Some(PromptSpec::PerLanguage(map)) => {
+            let entry = |key: &str| map.iter().find(|(k, _)| prompt_language_key(k) == key).map(|(_, v)| v.clone());
+            language.and_then(|l| entry(&prompt_language_key(l)).or_else(|| entry(&crate::captions::language_code(l))))
+                .or_else(|| entry("default"))
+        }
+        None => None,
+    };
+
+    let resolved = match (from_prompt, &p.prompt_file) {
+        (Some(prompt), _) => Some(prompt),
+        (None, Some(path)) => Some(std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read prompt file {}: {}", path, e))?),
+        (None, None) => None,
+    };
+
+    Ok(resolved.map(|s| s.trim().to_string()).filter(|s| !s.is_empty()))
+}
 
 fn is_digits(s: &str) -> bool {
     !s.is_empty() && s.chars().all(|c| c.is_ascii_digit())
@@ -1320,15 +2261,19 @@ fn is_digits(s: &str) -> bool {
 fn format_with_thousands(digits: String) -> String {
     // insert commas every 3 from right
     let mut out = String::new();
-    let mut cnt = 0;
-    for ch in digits.chars().rev() {
+    for (cnt, ch) in digits.chars().rev().enumerate() {
         if cnt > 0 && cnt % 3 == 0 { out.push(','); }
         out.push(ch);
-        cnt += 1;
     }
     out.chars().rev().collect()
 }
 
+/// Words starting at most this far past the clip duration are pulled back in, not dropped
+/// (backend timestamps overshoot the decoded audio slightly)
+const DURATION_CLAMP_TOLERANCE_MS: u64 = 250;
+/// Shortest on-screen time for a word pulled back inside the duration
+const CLAMPED_WORD_MIN_MS: u64 = 100;
+
 /// Merge currency symbols, thousand-groups, and decimals into single tokens.
 /// Handles patterns like ["$", "225", "000"] → "$225,000" and ["19", ".", "99"] → "19.99"
 /// Returns (text, start_ms, end_ms) tuples ready for CaptionSegment mapping.
@@ -1342,11 +2287,18 @@ fn merge_numbers_and_currency(
     while i < words.len() {
         let cur = words[i].word.trim();
         // FIX: words[i].start is already in seconds, convert to ms properly
-        let start_ms = (words[i].start * 1000.0) as u64;
+        let mut start_ms = (words[i].start * 1000.0) as u64;
         let mut end_ms   = (words[i].end   * 1000.0) as u64;
 
         if let Some(max_ms) = max_duration_ms {
-            if start_ms > max_ms { break; }
+            if start_ms > max_ms + DURATION_CLAMP_TOLERANCE_MS { break; }
+            // keep a word starting at/just past the end visible instead of collapsing it to nothing,
+            // without pulling it back over the word before it (short files: max_ms < the minimum)
+            let pulled_back = max_ms.saturating_sub(CLAMPED_WORD_MIN_MS);
+            if start_ms > pulled_back {
+                let prev_end = out.last().map(|w: &(String, u64, u64)| w.2).unwrap_or(0);
+                start_ms = pulled_back.max(prev_end).min(start_ms).min(max_ms);
+            }
             end_ms = end_ms.min(max_ms);
         }
 
@@ -1437,13 +2389,194 @@ fn merge_numbers_and_currency(
     out
 }
 
-pub fn whisper_to_caption_segments(response: &WhisperResponse, split_by_words: bool) -> Vec<CaptionSegment> {
-    let max_duration_ms = response.duration.map(|d| (d * 1000.0) as u64);
+/// Per-word (start, end) times spread over a segment by word length, with at least 100ms per
+/// word; the last word runs to `final_end_ms`. Words that end up with no time get start == end.
+fn char_weighted_word_timings(words: &[&str], start_ms: u64, duration_ms: u64, final_end_ms: u64) -> Vec<(u64, u64)> {
+    let word_lengths: Vec<usize> = words.iter().map(|w| w.len()).collect();
+    let total_chars: usize = word_lengths.iter().sum();
+    let base_time = duration_ms as f64;
+
+    let mut timings = Vec::with_capacity(words.len());
+    let mut cumulative_time = 0.0;
+    for (i, &len) in word_lengths.iter().enumerate() {
+        let word_start_ms = start_ms + cumulative_time as u64;
+
+        // Allocate time based on word length ratio with minimum duration
+        let char_ratio = if total_chars > 0 {
+            len as f64 / total_chars as f64
+        } else {
+            1.0 / words.len() as f64 // Fallback to equal distribution
+        };
+
+        // Ensure minimum 100ms per word, but don't exceed segment duration
+        let word_duration = (base_time * char_ratio).max(100.0);
+        cumulative_time += word_duration;
+
+        let word_end_ms = if i == words.len() - 1 {
+            final_end_ms // Last word gets remaining time
+        } else {
+            (word_start_ms as f64 + word_duration).min(final_end_ms as f64) as u64
+        };
+        timings.push((word_start_ms, word_end_ms.max(word_start_ms)));
+    }
+    timings
+}
+
+/// Split segment-level captions longer than `max_words` into consecutive chunks whose timing
+/// follows the char-weighted word distribution; chunks share boundaries so timing stays continuous.
+pub fn repack_long_segments(segments: Vec<CaptionSegment>, max_words: usize) -> Vec<CaptionSegment> {
+    let max_words = max_words.max(1);
+    let mut out = Vec::with_capacity(segments.len());
+    for seg in segments {
+        let words: Vec<&str> = seg.text.split_whitespace().collect();
+        if words.len() <= max_words || !seg.words.is_empty() {
+            out.push(seg);
+            continue;
+        }
+
+        let duration_ms = seg.end_ms.saturating_sub(seg.start_ms);
+        let timings = char_weighted_word_timings(&words, seg.start_ms, duration_ms, seg.end_ms);
+        let chunk_count = words.len().div_ceil(max_words);
+        // Even out chunk sizes (15 words at max 6 -> 5/5/5 instead of 6/6/3)
+        let per_chunk = words.len().div_ceil(chunk_count);
+
+        let mut start_ms = seg.start_ms;
+        for (c, chunk) in words.chunks(per_chunk).enumerate() {
+            let last_idx = (c * per_chunk + chunk.len() - 1).min(timings.len() - 1);
+            let end_ms = if (c + 1) * per_chunk >= words.len() {
+                seg.end_ms
+            } else {
+                timings[last_idx].1.clamp(start_ms, seg.end_ms)
+            };
+            out.push(CaptionSegment {
+                start_ms,
+                end_ms,
+                text: chunk.join(" "),
+                words: Vec::new(),
+                speaker: seg.speaker,
+                highlight: seg.highlight,
+            });
+            start_ms = end_ms;
+        }
+    }
+    out
+}
+
+/// Split captions lasting longer than `max_ms` into consecutive pieces. With word timing the
+/// cuts fall between words, preferring the longest pause that keeps each piece at least half
+/// full; without it the text is split evenly and timed like `repack_long_segments`.
+pub fn split_long_segments(segments: Vec<CaptionSegment>, max_ms: u64) -> Vec<CaptionSegment> {
+    let max_ms = max_ms.max(1);
+    let mut out = Vec::with_capacity(segments.len());
+    for seg in segments {
+        let duration_ms = seg.end_ms.saturating_sub(seg.start_ms);
+        if duration_ms <= max_ms {
+            out.push(seg);
+        } else if seg.words.len() > 1 {
+            split_segment_at_pauses(seg, max_ms, &mut out);
+        } else if seg.words.is_empty() {
+            let word_count = seg.text.split_whitespace().count();
+            let pieces = (duration_ms.div_ceil(max_ms) as usize).clamp(1, word_count.max(1));
+            out.extend(repack_long_segments(vec![seg], word_count.div_ceil(pieces)));
+        } else {
+            out.push(seg); // a single timed word can't be split
+        }
+    }
+    out
+}
+
+fn split_segment_at_pauses(seg: CaptionSegment, max_ms: u64, out: &mut Vec<CaptionSegment>) {
+    let words = &seg.words;
+    let mut first = 0;
+    let mut prev_end_ms = seg.start_ms;
+    while first < words.len() {
+        let rest = &words[first..];
+        let start_ms = if first == 0 { seg.start_ms } else { rest[0].start_ms.max(prev_end_ms) };
+
+        let last = if rest.len() == 1 || seg.end_ms.saturating_sub(start_ms) <= max_ms {
+            rest.len() - 1
+        } else {
+            // Cut after word i: the piece must fit, and must leave words for the next one
+            let piece_ms = |i: usize| rest[i].end_ms.saturating_sub(start_ms);
+            let fits: Vec<usize> = (0..rest.len() - 1).filter(|&i| piece_ms(i) <= max_ms).collect();
+            let roomy: Vec<usize> = fits.iter().copied().filter(|&i| piece_ms(i) >= max_ms / 2).collect();
+            let candidates = if roomy.is_empty() { &fits } else { &roomy };
+            candidates.iter().copied()
+                .max_by_key(|&i| rest[i + 1].start_ms.saturating_sub(rest[i].end_ms))
+                .unwrap_or(0) // even the first word is too long: it becomes its own piece
+        };
 
-    if split_by_words && response.words.is_some() {
-        let words = response.words.as_ref().unwrap();
+        let piece = &rest[..=last];
+        let end_ms = if first + last + 1 == words.len() { seg.end_ms } else { piece[last].end_ms.max(start_ms) };
+        out.push(CaptionSegment {
+            start_ms,
+            end_ms,
+            text: piece.iter().map(|w| w.text.trim()).filter(|t| !t.is_empty()).collect::<Vec<_>>().join(" "),
+            words: piece.to_vec(),
+            speaker: seg.speaker,
+            highlight: seg.highlight,
+        });
+        prev_end_ms = end_ms;
+        first += last + 1;
+    }
+}
+
+/// Speaker labels from a diarizing backend, numbered in order of first appearance
+fn speaker_indices(response: &WhisperResponse) -> std::collections::HashMap<String, u32> {
+    let mut indices = std::collections::HashMap::new();
+    for label in response.segments.iter().flatten().filter_map(|seg| seg.speaker.as_ref()) {
+        let next = indices.len() as u32;
+        indices.entry(label.clone()).or_insert(next);
+    }
+    indices
+}
+
+/// Drop words scored below `min_confidence`. A dropped word's time goes to the previous kept
+/// word (or the next one at the start) so the remaining timing stays contiguous.
+fn drop_low_confidence_words(words: &[WhisperWord], min_confidence: f32) -> Vec<WhisperWord> {
+    let mut kept: Vec<WhisperWord> = Vec::with_capacity(words.len());
+    let mut orphan_start: Option<f64> = None; // dropped leading words waiting for a neighbor
+    for word in words {
+        if word.confidence.is_some_and(|c| c < min_confidence) {
+            match kept.last_mut() {
+                Some(prev) => prev.end = prev.end.max(word.end),
+                None => { orphan_start.get_or_insert(word.start); }
+            }
+            continue;
+        }
+        let mut word = word.clone();
+        if let Some(start) = orphan_start.take() {
+            word.start = word.start.min(start);
+        }
+        kept.push(word);
+    }
+    kept
+}
+
+pub fn whisper_to_caption_segments(response: &WhisperResponse, split_by_words: bool, min_word_confidence: Option<f32>) -> Vec<CaptionSegment> {
+    let max_duration_ms = response.duration.map(|d| (d * 1000.0) as u64);
+    let speakers = speaker_indices(response);
+    let speaker_of = |seg: &crate::types::WhisperSegment| seg.speaker.as_ref().and_then(|l| speakers.get(l).copied());
+
+    if let (true, Some(words)) = (split_by_words, response.words.as_ref()) {
+        let filtered;
+        let words = match min_word_confidence {
+            Some(min) => {
+                filtered = drop_low_confidence_words(words, min);
+                &filtered
+            }
+            None => words,
+        };
         let merged = merge_numbers_and_currency(words, max_duration_ms);
 
+        // Word-level timestamps carry no speaker; take it from the segment around the word
+        let speaker_at = |start_ms: u64, end_ms: u64| {
+            let mid = (start_ms + end_ms) as f64 / 2000.0;
+            response.segments.iter().flatten()
+                .find(|seg| seg.start <= mid && mid < seg.end)
+                .and_then(speaker_of)
+        };
+
         merged.into_iter()
             .filter_map(|(text, start_ms, end_ms)| {
                 if end_ms <= start_ms { return None; }
@@ -1452,12 +2585,13 @@ pub fn whisper_to_caption_segments(response: &WhisperResponse, split_by_words: b
                     end_ms,
                     text,
                     words: Vec::new(),
+                    speaker: speaker_at(start_ms, end_ms),
+                    highlight: None,
                 })
             })
             .collect()
-    } else if split_by_words && response.segments.is_some() {
+    } else if let (true, Some(segments)) = (split_by_words, response.segments.as_ref()) {
         // Auto-split segments into words when word-level timestamps are not available
-        let segments = response.segments.as_ref().unwrap();
         let mut word_segments = Vec::new();
 
         for seg in segments {
@@ -1485,31 +2619,8 @@ pub fn whisper_to_caption_segments(response: &WhisperResponse, split_by_words: b
             }
 
             // Distribute time based on word length (better than linear distribution)
-            let word_lengths: Vec<usize> = words.iter().map(|w| w.len()).collect();
-            let total_chars: usize = word_lengths.iter().sum();
-            let base_time = segment_duration_ms as f64;
-
-            let mut cumulative_time = 0.0;
-            for (i, word) in words.iter().enumerate() {
-                let word_start_ms = start_ms + cumulative_time as u64;
-
-                // Allocate time based on word length ratio with minimum duration
-                let char_ratio = if total_chars > 0 {
-                    word_lengths[i] as f64 / total_chars as f64
-                } else {
-                    1.0 / words.len() as f64 // Fallback to equal distribution
-                };
-
-                // Ensure minimum 100ms per word, but don't exceed segment duration
-                let word_duration = (base_time * char_ratio).max(100.0);
-                cumulative_time += word_duration;
-
-                let word_end_ms = if i == words.len() - 1 {
-                    final_end_ms // Last word gets remaining time
-                } else {
-                    (word_start_ms as f64 + word_duration).min(final_end_ms as f64) as u64
-                };
-
+            let timings = char_weighted_word_timings(&words, start_ms, segment_duration_ms, final_end_ms);
+            for (word, (word_start_ms, word_end_ms)) in words.iter().zip(timings) {
                 if word_end_ms <= word_start_ms {
                     continue;
                 }
@@ -1519,6 +2630,8 @@ pub fn whisper_to_caption_segments(response: &WhisperResponse, split_by_words: b
                     end_ms: word_end_ms,
                     text: word.to_string(),
                     words: Vec::new(),
+                    speaker: speaker_of(seg),
+                    highlight: None,
                 });
             }
         }
@@ -1555,6 +2668,8 @@ pub fn whisper_to_caption_segments(response: &WhisperResponse, split_by_words: b
                     end_ms: final_end_ms,
                     text: seg.text.clone(),
                     words: Vec::new(), // srt-style segments don't include word timing
+                    speaker: speaker_of(seg),
+                    highlight: None,
                 })
             })
             .collect()
@@ -1566,29 +2681,31 @@ pub fn whisper_to_caption_segments(response: &WhisperResponse, split_by_words: b
             end_ms: duration as u64,
             text: response.text.clone(),
             words: Vec::new(),
+            speaker: None,
+            highlight: None,
         }]
     }
 }
 
 
-pub async fn get_cached_whisper_response(audio_path: &str, params: &TranscribeSegmentsParams) -> anyhow::Result<Option<WhisperResponse>> {
-    let (audio_hash, params_hash) = compute_segments_cache_key(audio_path, params)?;
+pub async fn get_cached_whisper_response(audio_path: &str, params: &TranscribeSegmentsParams, prompt: Option<&str>) -> anyhow::Result<Option<WhisperResponse>> {
+    let (audio_hash, params_hash) = compute_segments_cache_key(audio_path, params, prompt)?;
     let index = load_cache_index().await?;
 
     for entry in &index.entries {
-        if entry.audio_hash == audio_hash && entry.params_hash == params_hash {
-            if std::path::Path::new(&entry.response_path).exists() {
-                let content = fs::read_to_string(&entry.response_path).await?;
-                let response: WhisperResponse = serde_json::from_str(&content)?;
-                return Ok(Some(response));
-            }
+        if entry.audio_hash == audio_hash && entry.params_hash == params_hash
+            && std::path::Path::new(&entry.response_path).exists()
+        {
+            let content = fs::read_to_string(&entry.response_path).await?;
+            let response: WhisperResponse = serde_json::from_str(&content)?;
+            return Ok(Some(response));
         }
     }
     Ok(None)
 }
 
-pub async fn save_cached_whisper_response(audio_path: &str, params: &TranscribeSegmentsParams, response: &WhisperResponse) -> anyhow::Result<()> {
-    let (audio_hash, params_hash) = compute_segments_cache_key(audio_path, params)?;
+pub async fn save_cached_whisper_response(audio_path: &str, params: &TranscribeSegmentsParams, prompt: Option<&str>, response: &WhisperResponse) -> anyhow::Result<()> {
+    let (audio_hash, params_hash) = compute_segments_cache_key(audio_path, params, prompt)?;
     let mut index = load_cache_index().await?;
     let cache_dir = get_cache_dir()?;
     let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs();
@@ -1613,37 +2730,240 @@ pub async fn save_cached_whisper_response(audio_path: &str, params: &TranscribeS
     // add new entry
     index.entries.push(new_entry);
 
-    // keep only 4 most recent entries (LRU eviction)
-    if index.entries.len() > 4 {
+    evict_lru_entries(&mut index).await;
+
+    save_cache_index(&index).await?;
+    Ok(())
+}
+
+/// Default number of cached transcriptions kept on disk
+const CACHE_MAX_ENTRIES: usize = 4;
+
+/// Cache retention: the CAPSLAP_CACHE_MAX_ENTRIES env var, or the default
+fn cache_max_entries() -> usize {
+    std::env::var("CAPSLAP_CACHE_MAX_ENTRIES").ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(CACHE_MAX_ENTRIES)
+}
+
+/// Keep only the most recent entries (LRU eviction), deleting evicted response files
+async fn evict_lru_entries(index: &mut WhisperCacheIndex) {
+    let max_entries = cache_max_entries();
+    if index.entries.len() > max_entries {
         index.entries.sort_by_key(|e| e.timestamp);
-        let to_remove = index.entries.drain(0..index.entries.len() - 4).collect::<Vec<_>>();
+        let to_remove = index.entries.drain(0..index.entries.len() - max_entries).collect::<Vec<_>>();
 
         // delete old cached files
         for entry in to_remove {
             let _ = fs::remove_file(&entry.response_path).await;
         }
     }
+}
+
+/// Public RPC method to delete every cached transcription and reset the index
+pub async fn clear_cache_rpc(
+    id: &str,
+    mut emit: impl FnMut(RpcEvent)
+) -> anyhow::Result<crate::types::ClearCacheResult> {
+    let entries_removed = load_cache_index().await?.entries.len();
+    let cache_dir = get_cache_dir()?;
+
+    // Everything under the cache dir goes, including responses the index lost track of
+    let mut bytes_freed = 0;
+    let mut dir = fs::read_dir(&cache_dir).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        let path = entry.path();
+        let meta = entry.metadata().await?;
+        if meta.is_dir() {
+            fs::remove_dir_all(&path).await?;
+        } else {
+            fs::remove_file(&path).await?;
+            bytes_freed += meta.len();
+        }
+    }
+    save_cache_index(&WhisperCacheIndex { entries: Vec::new() }).await?;
+
+    emit(RpcEvent::Log {
+        id: id.into(),
+        message: format!("Cleared {} cache entries, freed {}", entries_removed, format_bytes(bytes_freed))
+    });
+
+    Ok(crate::types::ClearCacheResult { entries_removed, bytes_freed })
+}
+
+/// Current portable cache archive format
+const CACHE_ARCHIVE_VERSION: u32 = 1;
+
+/// Public RPC method to export the whisper cache into a single portable archive file
+/// Responses are inlined so the archive does not depend on local cache paths
+pub async fn export_cache_rpc(
+    id: &str,
+    mut params: crate::types::ExportCacheParams,
+    mut emit: impl FnMut(RpcEvent)
+) -> anyhow::Result<crate::types::ExportCacheResult> {
+    params.out = crate::rpc::sanitize_output_path(&params.out)?.to_string_lossy().to_string();
+    let index = load_cache_index().await?;
+    let mut entries = Vec::new();
+
+    for entry in index.entries {
+        let response = match fs::read_to_string(&entry.response_path).await
+            .map_err(anyhow::Error::from)
+            .and_then(|content| serde_json::from_str::<WhisperResponse>(&content).map_err(anyhow::Error::from))
+        {
+            Ok(response) => response,
+            Err(e) => {
+                emit(RpcEvent::Log { id: id.into(), message: format!("Skipping unreadable cache entry {}: {}", entry.response_path, e) });
+                continue;
+            }
+        };
+        entries.push(crate::types::WhisperCacheArchiveEntry {
+            audio_hash: entry.audio_hash,
+            params_hash: entry.params_hash,
+            timestamp: entry.timestamp,
+            response,
+        });
+    }
+
+    let archive = crate::types::WhisperCacheArchive { version: CACHE_ARCHIVE_VERSION, entries };
+    fs::write(&params.out, serde_json::to_string(&archive)?).await
+        .map_err(|e| anyhow::anyhow!("Failed to write cache archive to {}: {}", params.out, e))?;
+
+    emit(RpcEvent::Log {
+        id: id.into(),
+        message: format!("Exported {} cache entries to {}", archive.entries.len(), params.out)
+    });
+
+    Ok(crate::types::ExportCacheResult { path: params.out, entries: archive.entries.len() })
+}
+
+/// Public RPC method to merge a portable cache archive into the local whisper cache
+/// Entries are matched purely by (audio_hash, params_hash), so they hit on any machine
+/// that has the same audio; the newer of the local and imported copies wins before LRU eviction
+pub async fn import_cache_rpc(
+    id: &str,
+    params: crate::types::ImportCacheParams,
+    mut emit: impl FnMut(RpcEvent)
+) -> anyhow::Result<crate::types::ImportCacheResult> {
+    let content = fs::read_to_string(&params.input).await
+        .map_err(|e| anyhow::anyhow!("Failed to read cache archive {}: {}", params.input, e))?;
+    let archive: crate::types::WhisperCacheArchive = serde_json::from_str(&content)
+        .map_err(|e| anyhow::anyhow!("Invalid cache archive {}: {}", params.input, e))?;
+    if archive.version != CACHE_ARCHIVE_VERSION {
+        return Err(anyhow::anyhow!("Unsupported cache archive version {} (expected {})", archive.version, CACHE_ARCHIVE_VERSION));
+    }
+
+    let mut index = load_cache_index().await?;
+    let cache_dir = get_cache_dir()?;
+    let mut imported = 0;
+    let mut skipped = 0;
+
+    for entry in archive.entries {
+        // hashes become file names, so only accept well-formed hex digests
+        if entry.audio_hash.len() < 8 || entry.params_hash.len() < 8
+            || !entry.audio_hash.chars().chain(entry.params_hash.chars()).all(|c| c.is_ascii_hexdigit())
+        {
+            skipped += 1;
+            continue;
+        }
+
+        let existing = index.entries.iter()
+            .find(|e| e.audio_hash == entry.audio_hash && e.params_hash == entry.params_hash);
+        if existing.map(|e| e.timestamp >= entry.timestamp).unwrap_or(false) {
+            skipped += 1;
+            continue;
+        }
+
+        // remap the response into the local cache dir
+        let cache_filename = format!("{}_{}.json", &entry.audio_hash[..8], &entry.params_hash[..8]);
+        let cached_json_path = cache_dir.join(cache_filename);
+        fs::write(&cached_json_path, serde_json::to_string_pretty(&entry.response)?).await?;
+
+        index.entries.retain(|e| !(e.audio_hash == entry.audio_hash && e.params_hash == entry.params_hash));
+        index.entries.push(WhisperCacheEntry {
+            audio_hash: entry.audio_hash,
+            params_hash: entry.params_hash,
+            response_path: cached_json_path.to_string_lossy().to_string(),
+            timestamp: entry.timestamp,
+        });
+        imported += 1;
+    }
 
+    evict_lru_entries(&mut index).await;
     save_cache_index(&index).await?;
-    Ok(())
+
+    emit(RpcEvent::Log {
+        id: id.into(),
+        message: format!("Imported {} cache entries ({} skipped), cache now holds {}", imported, skipped, index.entries.len())
+    });
+
+    Ok(crate::types::ImportCacheResult { imported, skipped, total: index.entries.len() })
 }
 
 
-pub fn compute_segments_cache_key(audio_path: &str, params: &TranscribeSegmentsParams) -> anyhow::Result<(String, String)> {
-    // hash audio file content
-    let audio_bytes = std::fs::read(audio_path)?;
-    let audio_hash = blake3::hash(&audio_bytes).to_hex().to_string();
+/// Validate a whisper.cpp thread/processor count: positive and at most the available CPUs
+fn check_cpu_count(field: &str, value: Option<u32>) -> anyhow::Result<Option<u32>> {
+    let Some(n) = value else { return Ok(None) };
+    let cpus = std::thread::available_parallelism().map(|n| n.get() as u32).unwrap_or(1);
+    if n == 0 || n > cpus {
+        return Err(anyhow::anyhow!("{} must be between 1 and {} (available CPUs), got {}", field, cpus, n));
+    }
+    Ok(Some(n))
+}
+
+/// Canonical path of the params' custom model file; errors when it's missing or not a file
+fn resolve_model_path(params: &TranscribeSegmentsParams) -> anyhow::Result<Option<String>> {
+    let Some(path) = params.model_path.as_deref() else { return Ok(None) };
+    let path = std::path::Path::new(path);
+    if !path.is_file() {
+        return Err(CapslapError::ModelMissing(format!("Model file not found: {}", path.display())).into());
+    }
+    Ok(Some(path.canonicalize()?.to_string_lossy().to_string()))
+}
+
+/// Bump when the cached response format or the key derivation changes
+const CACHE_KEY_VERSION: u32 = 4;
+
+/// Params that can't change what the backend transcribes: inputs hashed separately, output
+/// and post-processing options, limits and credentials. The prompt and model file are keyed
+/// by their resolved forms instead. Every other field is keyed, so a new transcription
+/// option invalidates stale entries unless it's added here.
+const CACHE_KEY_EXCLUDED: &[&str] = &[
+    "audio", "videoFile", "apiKey", "apiBaseUrl", "apiOrg", "transcriptionMode", "maxAudioBytes", "autoDownloadModel",
+    "maxWordsPerCaption", "maxSegmentMs", "writeTranscriptJson", "exportSubtitles", "wordLevelSubtitles",
+    "prompt", "promptFile", "modelPath", "threads", "processors", "minWordConfidence", "streamSegments",
+];
+
+/// `prompt` is the resolved prompt the transcription actually used
+pub fn compute_segments_cache_key(audio_path: &str, params: &TranscribeSegmentsParams, prompt: Option<&str>) -> anyhow::Result<(String, String)> {
+    // hash audio file content (streamed so large files aren't buffered in memory)
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(std::fs::File::open(audio_path)?)?;
+    let audio_hash = hasher.finalize().to_hex().to_string();
+
+    Ok((audio_hash, cache_params_hash(params, prompt)?))
+}
 
-    // hash relevant parameters (excluding video_file as it doesn't affect transcription)
-    let params_for_hash = serde_json::json!({
-        "model": params.model,
-        "language": params.language,
-        "split_by_words": params.split_by_words,
-        "prompt": params.prompt,
+/// Hash of the canonical (sorted-key JSON, versioned) form of the transcription-affecting params
+fn cache_params_hash(params: &TranscribeSegmentsParams, prompt: Option<&str>) -> anyhow::Result<String> {
+    let serde_json::Value::Object(mut fields) = serde_json::to_value(params)? else {
+        return Err(anyhow::anyhow!("Transcription params did not serialize to an object"));
+    };
+    // unset options (and whisperMaxLen's 0 = unlimited) key the same as before they existed
+    fields.retain(|name, value| {
+        let unset = value.is_null() || (name == "whisperMaxLen" && value.as_u64() == Some(0));
+        !unset && !CACHE_KEY_EXCLUDED.contains(&name.as_str())
     });
-    let params_hash = blake3::hash(params_for_hash.to_string().as_bytes()).to_hex().to_string();
 
-    Ok((audio_hash, params_hash))
+    // the prompt is hashed after resolution so editing a prompt file invalidates the entry
+    if let Some(prompt) = prompt {
+        fields.insert("prompt".into(), blake3::hash(prompt.as_bytes()).to_hex().to_string().into());
+    }
+    if let Some(model_path) = resolve_model_path(params)? {
+        fields.insert("modelPath".into(), model_path.into());
+    }
+
+    let canonical = format!("v{}:{}", CACHE_KEY_VERSION, serde_json::Value::Object(fields));
+    Ok(blake3::hash(canonical.as_bytes()).to_hex().to_string())
 }
 
 pub async fn save_cache_index(index: &WhisperCacheIndex) -> anyhow::Result<()> {
@@ -1672,3 +2992,394 @@ pub fn get_cache_dir() -> std::io::Result<PathBuf> {
     std::fs::create_dir_all(&cache_dir)?;
     Ok(cache_dir)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// whisper.cpp `--output-json-full` shape: per-segment `tokens` with ms offsets
+    const TOKENS_FIXTURE: &str = r#"{
+        "result": { "language": "en" },
+        "transcription": [{
+            "offsets": { "from": 0, "to": 2400 },
+            "text": " Unbelievable results today",
+            "tokens": [
+                { "text": "[_BEG_]", "offsets": { "from": 0, "to": 0 }, "p": 0.99 },
+                { "text": " Un", "offsets": { "from": 0, "to": 300 }, "p": 0.91 },
+                { "text": "belie", "offsets": { "from": 300, "to": 600 }, "p": 0.72 },
+                { "text": "vable", "offsets": { "from": 600, "to": 900 }, "p": 0.88 },
+                { "text": " results", "offsets": { "from": 900, "to": 1500 }, "p": 0.95 },
+                { "text": " today", "offsets": { "from": 1500, "to": 2400 }, "p": 0.97 },
+                { "text": "[_TT_120]", "offsets": { "from": 2400, "to": 2400 }, "p": 0.5 },
+                { "text": "<|endoftext|>", "offsets": { "from": 2400, "to": 2500 }, "p": 0.5 }
+            ]
+        }]
+    }"#;
+
+    /// Builds that print ready-made `words` with start/end in seconds
+    const WORDS_FIXTURE: &str = r#"{
+        "transcription": [{
+            "offsets": { "from": 1000, "to": 2000 },
+            "text": " hello world",
+            "words": [
+                { "word": "hello", "start": 1.0, "end": 1.4 },
+                { "word": "world", "start": 1.4, "end": 2.0 }
+            ]
+        }]
+    }"#;
+
+    #[test]
+    fn whisper_cpp_token_reads_both_shapes() {
+        let token = serde_json::json!({ "text": " Hi", "offsets": { "from": 500, "to": 900 }, "p": 0.8 });
+        assert_eq!(whisper_cpp_token(&token), Some((" Hi".to_string(), 0.5, 0.9, Some(0.8))));
+        let word = serde_json::json!({ "word": "Hi", "start": 0.5, "end": 0.9 });
+        assert_eq!(whisper_cpp_token(&word), Some(("Hi".to_string(), 0.5, 0.9, None)));
+    }
+
+    #[test]
+    fn whisper_cpp_token_drops_special_and_empty_tokens() {
+        for text in ["[_BEG_]", "[_TT_42]", "<|endoftext|>", "[MUSIC]", "  "] {
+            let token = serde_json::json!({ "text": text, "offsets": { "from": 0, "to": 100 } });
+            assert_eq!(whisper_cpp_token(&token), None, "{:?}", text);
+        }
+        let zero_length = serde_json::json!({ "text": " hi", "offsets": { "from": 100, "to": 100 } });
+        assert_eq!(whisper_cpp_token(&zero_length), None);
+    }
+
+    #[test]
+    fn parse_whisper_cpp_output_merges_bpe_pieces() {
+        let response = parse_whisper_cpp_output(TOKENS_FIXTURE).unwrap();
+        assert_eq!(response.language.as_deref(), Some("en"));
+        assert_eq!(response.text, "Unbelievable results today");
+        let words = response.words.unwrap();
+        let texts: Vec<&str> = words.iter().map(|w| w.word.as_str()).collect();
+        assert_eq!(texts, ["Unbelievable", "results", "today"]);
+        assert_eq!((words[0].start, words[0].end), (0.0, 0.9));
+        assert_eq!(words[0].confidence, Some(0.72)); // lowest piece
+        assert_eq!(response.segments.unwrap()[0].end, 2.4);
+    }
+
+    #[test]
+    fn parse_whisper_cpp_output_keeps_ready_made_words() {
+        let response = parse_whisper_cpp_output(WORDS_FIXTURE).unwrap();
+        let words = response.words.unwrap();
+        let texts: Vec<&str> = words.iter().map(|w| w.word.as_str()).collect();
+        assert_eq!(texts, ["hello", "world"]);
+        assert_eq!((words[1].start, words[1].end), (1.4, 2.0));
+    }
+
+    fn words(list: &[(&str, f64, f64)]) -> Vec<WhisperWord> {
+        list.iter().map(|&(word, start, end)| WhisperWord { word: word.into(), start, end, confidence: None }).collect()
+    }
+
+    #[test]
+    fn currency_merge_keeps_the_last_token_when_clamped() {
+        // "$500" spoken past the decoded duration: pulled back inside instead of swallowed
+        let merged = merge_numbers_and_currency(&words(&[("we", 0.0, 0.4), ("made", 0.4, 0.8), ("$", 1.0, 1.1), ("500", 1.1, 1.3)]), Some(1000));
+        assert_eq!(merged.last().unwrap(), &("$500".to_string(), 900, 1000));
+    }
+
+    #[test]
+    fn clamp_drops_words_past_the_tolerance() {
+        let merged = merge_numbers_and_currency(&words(&[("hi", 0.0, 0.5), ("late", 1.3, 1.6)]), Some(1000));
+        assert_eq!(merged, vec![("hi".to_string(), 0, 500)]);
+        // Within the tolerance the word stays, clamped to the duration
+        let merged = merge_numbers_and_currency(&words(&[("hi", 0.0, 0.5), ("late", 1.2, 1.6)]), Some(1000));
+        assert_eq!(merged.last().unwrap(), &("late".to_string(), 900, 1000));
+    }
+
+    #[test]
+    fn clamp_never_overlaps_the_previous_word() {
+        // Shorter than the minimum on-screen time: no pulling back to 0 over earlier words
+        let merged = merge_numbers_and_currency(&words(&[("a", 0.0, 0.06), ("b", 0.085, 0.12)]), Some(80));
+        assert_eq!(merged, vec![("a".to_string(), 0, 60), ("b".to_string(), 60, 80)]);
+        let merged = merge_numbers_and_currency(&words(&[("one", 0.0, 0.95), ("two", 1.1, 1.3)]), Some(1000));
+        assert_eq!(merged, vec![("one".to_string(), 0, 950), ("two".to_string(), 950, 1000)]);
+    }
+
+    #[tokio::test]
+    async fn transcription_export_round_trips_into_the_typed_struct() {
+        let dir = tempfile::tempdir().unwrap();
+        let params: TranscribeSegmentsParams = serde_json::from_value(serde_json::json!({
+            "audio": "clip.mp3", "splitByWords": true, "model": "base", "language": "en"
+        })).unwrap();
+        let response = parse_whisper_cpp_output(TOKENS_FIXTURE).unwrap();
+        let segments = whisper_to_caption_segments(&response, true, None);
+        let dir_path = dir.path().to_path_buf();
+        let result = create_transcription_result("t", &segments, &response, &params, Some(&dir_path), &mut |_| {}).await.unwrap();
+
+        let written = std::fs::read_to_string(result.json_file.as_deref().unwrap()).unwrap();
+        let raw: serde_json::Value = serde_json::from_str(&written).unwrap();
+        for key in ["segments", "fullText", "duration", "splitByWords", "model", "language", "generatedAt"] {
+            assert!(raw.get(key).is_some(), "missing {} in {}", key, written);
+        }
+        let export: TranscriptionExport = serde_json::from_str(&written).unwrap();
+        assert_eq!(export.full_text, result.full_text);
+        assert_eq!(export.model, "base");
+        assert!(export.split_by_words);
+        assert_eq!(serde_json::to_value(&export.segments).unwrap(), serde_json::to_value(&result.segments).unwrap());
+        assert_eq!(serde_json::to_string_pretty(&export).unwrap(), written);
+    }
+
+    #[test]
+    fn parse_whisper_cpp_output_rejects_empty_transcripts() {
+        assert!(parse_whisper_cpp_output(r#"{ "transcription": [] }"#).is_err());
+    }
+
+    #[test]
+    fn audio_size_limit_is_inclusive_and_optional() {
+        assert!(check_audio_size(10 * 1024 * 1024, None).is_ok());
+        assert!(check_audio_size(1024, Some(1024)).is_ok());
+        let err = check_audio_size(3 * 1024 * 1024, Some(1024 * 1024)).unwrap_err().to_string();
+        assert_eq!(err, "Audio file is 3.0 MB which exceeds the configured limit of 1.0 MB");
+    }
+
+    #[test]
+    fn audio_size_param_wins_over_the_env() {
+        let params: TranscribeSegmentsParams = serde_json::from_value(serde_json::json!({
+            "audio": "clip.mp3", "splitByWords": true, "maxAudioBytes": 2048
+        })).unwrap();
+        assert_eq!(max_audio_bytes(&params), Some(2048));
+    }
+
+    fn sentence(text: &str, start_ms: u64, end_ms: u64) -> CaptionSegment {
+        CaptionSegment { start_ms, end_ms, text: text.into(), words: Vec::new(), speaker: None, highlight: None }
+    }
+
+    #[test]
+    fn repack_evens_out_chunks_and_keeps_timing_continuous() {
+        let text = "one two three four five six seven eight nine ten eleven twelve thirteen fourteen fifteen";
+        let out = repack_long_segments(vec![sentence(text, 1000, 8500)], 6);
+        let sizes: Vec<usize> = out.iter().map(|s| s.text.split_whitespace().count()).collect();
+        assert_eq!(sizes, [5, 5, 5]);
+        assert_eq!(out.iter().map(|s| s.text.as_str()).collect::<Vec<_>>().join(" "), text);
+        assert_eq!((out[0].start_ms, out[2].end_ms), (1000, 8500));
+        for pair in out.windows(2) {
+            assert_eq!(pair[0].end_ms, pair[1].start_ms);
+            assert!(pair[0].start_ms < pair[0].end_ms);
+        }
+    }
+
+    #[test]
+    fn repack_leaves_short_and_word_timed_segments_alone() {
+        let short = sentence("just four words here", 0, 1000);
+        assert_eq!(repack_long_segments(vec![short], 6).len(), 1);
+
+        let mut timed = sentence("one two three four five six seven eight", 0, 2400);
+        timed.words = (0..8).map(|i| crate::types::WordSpan { start_ms: i * 300, end_ms: (i + 1) * 300, text: "w".into(), speaker: None, highlight: None }).collect();
+        assert_eq!(repack_long_segments(vec![timed], 3).len(), 1);
+    }
+
+    #[test]
+    fn model_download_needs_ten_percent_headroom() {
+        let dir = std::path::Path::new("/models");
+        let needed = model_download_size("base").unwrap();
+        assert!(check_download_space("base", needed, needed * 2, dir).is_ok());
+        assert!(check_download_space("base", needed, needed + needed / 10, dir).is_ok());
+        let err = check_download_space("base", needed, needed, dir).unwrap_err().to_string();
+        assert_eq!(err, "Not enough disk space to download the base model: need about 156.2 MB, only 142.0 MB free in /models");
+        assert_eq!(model_download_size("huge"), None);
+    }
+
+    #[test]
+    fn whisper_cpp_max_len_is_passed_through() {
+        let arg_after = |args: &[String], flag: &str| args.iter().position(|a| a == flag).and_then(|i| args.get(i + 1)).cloned();
+        let args = whisper_cpp_decode_args("/models/ggml-base.bin", 42);
+        assert_eq!(arg_after(&args, "--max-len").as_deref(), Some("42"));
+        assert_eq!(arg_after(&args, "-m").as_deref(), Some("/models/ggml-base.bin"));
+        assert_eq!(arg_after(&whisper_cpp_decode_args("m.bin", 0), "--max-len").as_deref(), Some("0"));
+
+        let params: TranscribeSegmentsParams = serde_json::from_value(serde_json::json!({
+            "audio": "clip.mp3", "splitByWords": false, "whisperMaxLen": 60
+        })).unwrap();
+        assert_eq!(params.whisper_max_len, Some(60));
+    }
+
+    fn hash_of(json: serde_json::Value) -> String {
+        let mut params = serde_json::json!({ "audio": "clip.mp3", "splitByWords": true, "model": "base", "language": "en" });
+        params.as_object_mut().unwrap().extend(json.as_object().unwrap().clone());
+        let params: TranscribeSegmentsParams = serde_json::from_value(params).unwrap();
+        let prompt = resolve_prompt(&params, params.language.as_deref()).unwrap();
+        cache_params_hash(&params, prompt.as_deref()).unwrap()
+    }
+
+    #[test]
+    fn cache_hash_is_stable_and_ignores_output_options() {
+        let base = hash_of(serde_json::json!({}));
+        assert_eq!(base, hash_of(serde_json::json!({})));
+        assert_eq!(base, hash_of(serde_json::json!({ "whisperMaxLen": 0 })));
+        for extra in [
+            serde_json::json!({ "audio": "/tmp/other.mp3" }),
+            serde_json::json!({ "apiKey": "sk-test", "apiOrg": "org" }),
+            serde_json::json!({ "maxWordsPerCaption": 4, "maxSegmentMs": 3000, "minWordConfidence": 0.5 }),
+            serde_json::json!({ "writeTranscriptJson": false, "exportSubtitles": true, "streamSegments": true }),
+            serde_json::json!({ "threads": 8, "processors": 2, "transcriptionMode": "local" }),
+        ] {
+            assert_eq!(base, hash_of(extra.clone()), "{}", extra);
+        }
+    }
+
+    #[test]
+    fn cache_hash_changes_with_what_is_transcribed() {
+        let base = hash_of(serde_json::json!({}));
+        for extra in [
+            serde_json::json!({ "language": "de" }),
+            serde_json::json!({ "model": "small" }),
+            serde_json::json!({ "splitByWords": false }),
+            serde_json::json!({ "whisperMaxLen": 60 }),
+            serde_json::json!({ "prompt": "Capslap, FFmpeg" }),
+        ] {
+            assert_ne!(base, hash_of(extra.clone()), "{}", extra);
+        }
+
+        // A prompt file is keyed by its contents, not its path
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("prompt.txt");
+        std::fs::write(&file, "Capslap, FFmpeg").unwrap();
+        let from_file = hash_of(serde_json::json!({ "promptFile": file }));
+        assert_eq!(from_file, hash_of(serde_json::json!({ "prompt": "Capslap, FFmpeg" })));
+        std::fs::write(&file, "something else").unwrap();
+        assert_ne!(from_file, hash_of(serde_json::json!({ "promptFile": file })));
+    }
+
+    #[test]
+    fn cache_exclusions_name_real_params() {
+        let params: TranscribeSegmentsParams = serde_json::from_value(serde_json::json!({ "audio": "clip.mp3", "splitByWords": true })).unwrap();
+        let serde_json::Value::Object(fields) = serde_json::to_value(&params).unwrap() else { panic!("params must be an object") };
+        for name in CACHE_KEY_EXCLUDED {
+            assert!(fields.contains_key(*name), "{} is not a TranscribeSegmentsParams field", name);
+        }
+    }
+
+    #[test]
+    fn every_model_has_a_published_checksum() {
+        for model in KNOWN_MODELS {
+            let sha1 = expected_model_checksum(model_file_name(model)).unwrap();
+            assert!(sha1.len() == 40 && sha1.chars().all(|c| c.is_ascii_hexdigit()), "{}", model);
+        }
+        assert!(check_model_checksum("ggml-base.bin", "465707469FF3A37A2B9B8D8F89F2F99DE7299DAC").is_ok());
+        let err = check_model_checksum("ggml-base.bin", "0000000000000000000000000000000000000000").unwrap_err();
+        assert!(err.starts_with("Checksum mismatch: expected 465707469ff3a37a2b9b8d8f89f2f99de7299dac"), "{}", err);
+        assert!(check_model_checksum("ggml-custom.bin", "465707469ff3a37a2b9b8d8f89f2f99de7299dac").is_err());
+    }
+
+    #[tokio::test]
+    async fn model_hash_is_sha1_of_a_ggml_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let model = dir.path().join("ggml-test.bin");
+        let mut bytes = GGML_MAGIC.to_vec();
+        bytes.extend([0u8; 60]);
+        std::fs::write(&model, &bytes).unwrap();
+        assert_eq!(hash_model_file(model.clone()).await.unwrap(), "ed5349a74664ad0a369a92d60b216e98f11a5ba6");
+
+        std::fs::write(&model, b"<html>Not Found</html>").unwrap();
+        assert!(hash_model_file(model).await.unwrap_err().to_string().contains("bad header"));
+    }
+
+    #[test]
+    fn auto_download_only_fetches_a_missing_built_in_model() {
+        let params = |json: serde_json::Value| -> TranscribeSegmentsParams {
+            let mut base = serde_json::json!({ "audio": "clip.mp3", "splitByWords": true, "autoDownloadModel": true });
+            base.as_object_mut().unwrap().extend(json.as_object().unwrap().clone());
+            serde_json::from_value(base).unwrap()
+        };
+        let missing = |_: &str| Ok(false);
+        let installed = |_: &str| Ok(true);
+
+        let p = params(serde_json::json!({ "model": "base" }));
+        assert_eq!(model_to_auto_download(&p, false, false, missing).unwrap(), Some("base"));
+        assert_eq!(model_to_auto_download(&p, false, false, installed).unwrap(), None);
+        assert_eq!(model_to_auto_download(&p, true, false, missing).unwrap(), None);  // OpenAI backend
+        assert_eq!(model_to_auto_download(&p, false, true, missing).unwrap(), None);  // modelPath given
+        assert_eq!(model_to_auto_download(&params(serde_json::json!({})), false, false, missing).unwrap(), Some("tiny"));
+        assert_eq!(model_to_auto_download(&params(serde_json::json!({ "model": "whisper-1" })), false, false, missing).unwrap(), None);
+
+        let off = params(serde_json::json!({ "model": "base", "autoDownloadModel": false }));
+        assert_eq!(model_to_auto_download(&off, false, false, missing).unwrap(), None);
+    }
+
+    #[test]
+    fn printed_segments_parse_timestamps_and_text() {
+        let seg = parse_printed_segment("[00:01:02.345 --> 01:00:00.000]   Hello there.  ").unwrap();
+        assert_eq!((seg.start_ms, seg.end_ms, seg.text.as_str()), (62_345, 3_600_000, "Hello there."));
+        let seg = parse_printed_segment("[00:00:01 --> 00:00:04.2] whole seconds").unwrap();
+        assert_eq!((seg.start_ms, seg.end_ms), (1000, 4200));
+        assert!(parse_printed_segment("[00:00:01.000 --> 00:00:02.000]   ").is_none());
+        assert!(parse_printed_segment("whisper_init_from_file: loading model").is_none());
+        assert!(parse_printed_segment("[00:00:01.000] no range").is_none());
+    }
+
+    #[tokio::test]
+    async fn segments_stream_one_per_line_before_the_output_ends() {
+        use tokio::io::AsyncWriteExt;
+        let (mut writer, reader) = tokio::io::duplex(1024);
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+        // The scripted backend only prints its next line once the previous segment came out
+        let backend = tokio::spawn(async move {
+            let lines = ["whisper_full: progress", "[00:00:00.000 --> 00:00:01.500]  First line.", "[00:00:01.500 --> 00:00:03.000]  Second line."];
+            let mut seen = Vec::new();
+            for line in lines {
+                writer.write_all(format!("{}\n", line).as_bytes()).await.unwrap();
+                if line.starts_with('[') {
+                    match rx.recv().await {
+                        Some(RpcEvent::Segment { segment, .. }) => seen.push(segment.text),
+                        other => panic!("expected a segment event, got {:?}", other),
+                    }
+                }
+            }
+            seen
+        });
+
+        let mut emit = |event: RpcEvent| { let _ = tx.send(event); };
+        let read = read_printed_segments("job", tokio::io::BufReader::new(reader), true, &mut emit);
+        let stdout = tokio::time::timeout(std::time::Duration::from_secs(5), read).await
+            .expect("segments must be emitted while the output is still open")
+            .unwrap();
+
+        assert_eq!(backend.await.unwrap(), ["First line.", "Second line."]);
+        assert_eq!(stdout.lines().count(), 3);
+    }
+
+    #[test]
+    fn whole_responses_emit_one_event_per_segment() {
+        let response = parse_whisper_cpp_output(TOKENS_FIXTURE).unwrap();
+        let mut events = Vec::new();
+        emit_segment_events("job", &response, &mut |event| events.push(event));
+        assert!(!events.is_empty());
+        assert_eq!(events.len(), response.segments.as_ref().map_or(0, |s| s.len()));
+        assert!(events.iter().all(|e| matches!(e, RpcEvent::Segment { id, .. } if id == "job")));
+    }
+
+    #[test]
+    fn per_language_prompts_match_normalized_tags_then_base_code() {
+        let params: TranscribeSegmentsParams = serde_json::from_value(serde_json::json!({
+            "audio": "clip.mp3", "splitByWords": true,
+            "prompt": { "pt-BR": "Olá, Capslap", "ES": "Hola", "zh_Hant": "字幕", "default": "Capslap" }
+        })).unwrap();
+        let prompt = |lang: Option<&str>| resolve_prompt(&params, lang).unwrap();
+
+        assert_eq!(prompt(Some("pt-BR")).as_deref(), Some("Olá, Capslap"));
+        assert_eq!(prompt(Some("pt_br")).as_deref(), Some("Olá, Capslap"));
+        assert_eq!(prompt(Some("zh-Hant")).as_deref(), Some("字幕"));
+        assert_eq!(prompt(Some("es")).as_deref(), Some("Hola"));
+        // a regional tag falls back to its base code's entry
+        assert_eq!(prompt(Some("es-MX")).as_deref(), Some("Hola"));
+        assert_eq!(prompt(Some("pt")).as_deref(), Some("Capslap"));
+        assert_eq!(prompt(None).as_deref(), Some("Capslap"));
+    }
+
+    #[test]
+    fn cache_hash_follows_the_prompt_resolved_for_the_detected_language() {
+        // No language given: the key depends on which entry the detected language picked
+        let params: TranscribeSegmentsParams = serde_json::from_value(serde_json::json!({
+            "audio": "clip.mp3", "splitByWords": true, "prompt": { "de": "Guten Tag", "default": "Hello" }
+        })).unwrap();
+        let hash_for = |detected: Option<&str>| {
+            let prompt = resolve_prompt(&params, detected).unwrap();
+            cache_params_hash(&params, prompt.as_deref()).unwrap()
+        };
+        assert_ne!(hash_for(Some("de")), hash_for(Some("en")));
+        assert_eq!(hash_for(Some("en")), hash_for(None));
+    }
+}